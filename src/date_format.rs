@@ -0,0 +1,518 @@
+use std::fmt;
+use std::str::FromStr;
+use lazy_static::lazy_static;
+use regex::Regex;
+use crate::jahis::*;
+use crate::era::*;
+
+const KANJI_DIGITS: [char; 10] = ['〇', '一', '二', '三', '四', '五', '六', '七', '八', '九'];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+/// Calendar a date is formatted in
+pub enum Calendar {
+    #[default]
+    AsIs, // 保持している暦のまま
+    Seireki, // 西暦
+    Wareki, // 和暦
+}
+
+impl fmt::Display for Calendar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::AsIs => write!(f, "as-is"),
+            Self::Seireki => write!(f, "seireki"),
+            Self::Wareki => write!(f, "wareki"),
+        }
+    }
+}
+
+impl FromStr for Calendar {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "as-is" | "AsIs" => Ok(Self::AsIs),
+            "seireki" | "Seireki" | "西暦" => Ok(Self::Seireki),
+            "wareki" | "Wareki" | "和暦" => Ok(Self::Wareki),
+            _ => Err(Error::InvalidArgument(
+                format!("Cannot convert str to Calendar, got \"{}\"", s)
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+/// Layout of a formatted date
+pub enum DateStyle {
+    #[default]
+    Long, // 令和2年5月1日
+    Short, // R2.5.1
+}
+
+impl fmt::Display for DateStyle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Long => write!(f, "long"),
+            Self::Short => write!(f, "short"),
+        }
+    }
+}
+
+impl FromStr for DateStyle {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "long" | "Long" => Ok(Self::Long),
+            "short" | "Short" => Ok(Self::Short),
+            _ => Err(Error::InvalidArgument(
+                format!("Cannot convert str to DateStyle, got \"{}\"", s)
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+/// How the era of a wareki date is written
+pub enum EraStyle {
+    #[default]
+    Name, // 令和
+    Ligature, // ㋿
+    Code, // R
+}
+
+impl fmt::Display for EraStyle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Name => write!(f, "name"),
+            Self::Ligature => write!(f, "ligature"),
+            Self::Code => write!(f, "code"),
+        }
+    }
+}
+
+impl FromStr for EraStyle {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" | "Name" => Ok(Self::Name),
+            "ligature" | "Ligature" => Ok(Self::Ligature),
+            "code" | "Code" => Ok(Self::Code),
+            _ => Err(Error::InvalidArgument(
+                format!("Cannot convert str to EraStyle, got \"{}\"", s)
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+/// Digits the numbers of a date are written in
+pub enum Numerals {
+    #[default]
+    HalfWidth, // 2
+    FullWidth, // ２
+    Kanji, // 二
+}
+
+impl fmt::Display for Numerals {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::HalfWidth => write!(f, "half-width"),
+            Self::FullWidth => write!(f, "full-width"),
+            Self::Kanji => write!(f, "kanji"),
+        }
+    }
+}
+
+impl FromStr for Numerals {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "half-width" | "HalfWidth" | "halfwidth" => Ok(Self::HalfWidth),
+            "full-width" | "FullWidth" | "fullwidth" => Ok(Self::FullWidth),
+            "kanji" | "Kanji" => Ok(Self::Kanji),
+            _ => Err(Error::InvalidArgument(
+                format!("Cannot convert str to Numerals, got \"{}\"", s)
+            )),
+        }
+    }
+}
+
+/// Formats a date in the styles of printed labels and letters, and reads
+/// them back.
+///
+/// The default formatter writes the same text as `Display`, e.g.
+/// `令和元年5月1日`. `calendar` converts the date before formatting, so a
+/// seireki date can be shown in wareki. The short style joins the numbers
+/// with `separator`, e.g. `R2.5.1`, and `zero_pad` writes the month, the day
+/// and an era year in two digits. `gannen` writes the first year of an era
+/// as 元 in the long style.
+///
+/// `parse` accepts any of the styles regardless of the settings, as well as
+/// the JAHIS codes such as `R020501`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DateFormatter {
+    calendar: Calendar,
+    style: DateStyle,
+    era_style: EraStyle,
+    numerals: Numerals,
+    separator: char,
+    zero_pad: bool,
+    gannen: bool,
+}
+
+impl DateFormatter {
+    pub fn new() -> Self {
+        Self {
+            calendar: Calendar::default(),
+            style: DateStyle::default(),
+            era_style: EraStyle::default(),
+            numerals: Numerals::default(),
+            separator: '.',
+            zero_pad: false,
+            gannen: true,
+        }
+    }
+
+    pub fn calendar(mut self, calendar: Calendar) -> Self {
+        self.calendar = calendar;
+        self
+    }
+
+    pub fn style(mut self, style: DateStyle) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// Writes the era as its name, its ligature or its code. An era without
+    /// a ligature is written as its name.
+    pub fn era_style(mut self, era_style: EraStyle) -> Self {
+        self.era_style = era_style;
+        self
+    }
+
+    pub fn numerals(mut self, numerals: Numerals) -> Self {
+        self.numerals = numerals;
+        self
+    }
+
+    pub fn separator(mut self, separator: char) -> Self {
+        self.separator = separator;
+        self
+    }
+
+    pub fn zero_pad(mut self, zero_pad: bool) -> Self {
+        self.zero_pad = zero_pad;
+        self
+    }
+
+    pub fn gannen(mut self, gannen: bool) -> Self {
+        self.gannen = gannen;
+        self
+    }
+
+    /// Formats a date, failing if it cannot be converted to the calendar.
+    pub fn format(&self, date: &Date) -> Result<String, Error> {
+        let date = match self.calendar {
+            Calendar::AsIs => *date,
+            Calendar::Seireki => date.to_seireki()?,
+            Calendar::Wareki => date.to_wareki()?,
+        };
+        let (gengo_year, year, month, day) = match date {
+            Date::Seireki{year, month, day} => (None, year, Some(month), Some(day)),
+            Date::Wareki{gengo_year, month, day} => (Some(gengo_year), gengo_year.year(), Some(month), Some(day)),
+            Date::SeirekiYearMonth{year, month} => (None, year, Some(month), None),
+            Date::WarekiYearMonth{gengo_year, month} => (Some(gengo_year), gengo_year.year(), Some(month), None),
+            Date::SeirekiYear{year} => (None, year, None, None),
+            Date::WarekiYear{gengo_year} => (Some(gengo_year), gengo_year.year(), None, None),
+        };
+
+        let mut s = gengo_year.map(|gy| self.era_text(&gy)).unwrap_or_default();
+        let year_width = if gengo_year.is_some() {2} else {4};
+        match self.style {
+            DateStyle::Long => {
+                if gengo_year.is_some() && year == 1 && self.gannen {
+                    s.push('元');
+                } else {
+                    s.push_str(&self.number(year, year_width));
+                }
+                s.push('年');
+                if let Some(m) = month {
+                    s.push_str(&self.number(m as i32, 2));
+                    s.push('月');
+                }
+                if let Some(d) = day {
+                    s.push_str(&self.number(d as i32, 2));
+                    s.push('日');
+                }
+            },
+            DateStyle::Short => {
+                s.push_str(&self.number(year, year_width));
+                for n in month.iter().chain(day.iter()) {
+                    s.push(self.separator);
+                    s.push_str(&self.number(*n as i32, 2));
+                }
+            },
+        }
+        Ok(s)
+    }
+
+    /// Reads a date written in any style, with half-width, full-width or
+    /// kanji numerals, ignoring white space.
+    pub fn parse(&self, s: &str) -> Result<Date, Error> {
+        lazy_static! {
+            static ref RE_LONG: Regex = Regex::new(r"^(\d+|元)年(?:(\d+)月(?:(\d+)日)?)?$").unwrap();
+            static ref RE_SHORT: Regex = Regex::new(r"^(\d+)(?:[./\-](\d+)(?:[./\-](\d+))?)?$").unwrap();
+        }
+        let error = || Error::InvalidArgument(
+            format!("Cannot convert str to Date, got \"{}\"", s)
+        );
+        let normalized: String = s.chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| match c {
+                '０'..='９' | 'Ａ'..='Ｚ' | 'ａ'..='ｚ' | '．' | '／' | '－' =>
+                    char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+                '−' | 'ー' => '-',
+                c if c == self.separator => '.',
+                c => c,
+            })
+            .collect();
+        if let Ok(date) = normalized.parse::<Date>() {
+            return Ok(date);
+        }
+
+        let (era, rest) = split_era(&normalized);
+        let rest = kanji_to_digits(rest);
+        let (year, month, day) = if let Some(cap) = RE_LONG.captures(&rest) {
+            let year = if &cap[1] == "元" {1} else {cap[1].parse()?};
+            (year, cap.get(2), cap.get(3))
+        } else if let Some(cap) = RE_SHORT.captures(&rest) {
+            (cap[1].parse()?, cap.get(2), cap.get(3))
+        } else {
+            return Err(error());
+        };
+        let month: Option<u32> = month.map(|m| m.as_str().parse()).transpose()?;
+        let day: Option<u32> = day.map(|d| d.as_str().parse()).transpose()?;
+        match (era, month, day) {
+            (Some(era), Some(m), Some(d)) => Date::new_wareki(GengoYear::new(era.code, year), m, d),
+            (Some(era), Some(m), None) => Date::new_wareki_year_month(GengoYear::new(era.code, year), m),
+            (Some(era), None, None) => Date::new_wareki_year(GengoYear::new(era.code, year)),
+            (None, Some(m), Some(d)) => Date::new_seireki(year, m, d),
+            (None, Some(m), None) => Date::new_seireki_year_month(year, m),
+            (None, None, None) => Date::new_seireki_year(year),
+            _ => Err(error()),
+        }
+    }
+
+    fn era_text(&self, gengo_year: &GengoYear) -> String {
+        let era = match gengo_year.era() {
+            Some(era) => era,
+            None => return gengo_year.code().to_string(),
+        };
+        match self.era_style {
+            EraStyle::Ligature => era.ligature.map_or(era.name, |l| l.to_string()),
+            EraStyle::Name => era.name,
+            EraStyle::Code => era.code.to_string(),
+        }
+    }
+
+    fn number(&self, n: i32, width: usize) -> String {
+        let width = if self.zero_pad {width} else {0};
+        match self.numerals {
+            Numerals::HalfWidth => format!("{:0width$}", n, width = width),
+            Numerals::FullWidth => format!("{:0width$}", n, width = width).chars()
+                .map(|c| if c.is_ascii_digit() {char::from_u32(c as u32 + 0xFEE0).unwrap_or(c)} else {c})
+                .collect(),
+            Numerals::Kanji => digits_to_kanji(n),
+        }
+    }
+}
+
+impl Default for DateFormatter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Date {
+    pub fn format_with(&self, formatter: &DateFormatter) -> Result<String, Error> {
+        formatter.format(self)
+    }
+
+    /// Reads a date typed as free text in any style of `DateFormatter`,
+    /// e.g. `令和二年五月一日`, `R2.5.1` or `２０２０年５月１日`.
+    pub fn parse_text(s: &str) -> Result<Self, Error> {
+        DateFormatter::default().parse(s)
+    }
+}
+
+/// Splits the era from the head of a date, which is `None` in seireki.
+/// Names are tried before codes, so that no era name is read as numerals.
+fn split_era(s: &str) -> (Option<Era>, &str) {
+    if let Some(rest) = s.strip_prefix("西暦") {
+        return (None, rest);
+    }
+    for era in eras().into_iter().rev() {
+        if let Some(rest) = s.strip_prefix(era.name.as_str()) {
+            return (Some(era), rest);
+        }
+        if let Some(rest) = era.ligature.and_then(|l| s.strip_prefix(l)) {
+            return (Some(era), rest);
+        }
+    }
+    match s.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() => match era_by_code(c) {
+            Some(era) => (Some(era), &s[1..]),
+            None => (None, s),
+        },
+        _ => (None, s),
+    }
+}
+
+/// Writes a number of 100 or more digit by digit (二〇二〇), and a smaller
+/// one with 十 (三十一).
+fn digits_to_kanji(n: i32) -> String {
+    if n < 0 {
+        return n.to_string();
+    }
+    if n >= 100 || n == 0 {
+        return n.to_string().chars()
+            .map(|c| KANJI_DIGITS[c.to_digit(10).unwrap_or(0) as usize])
+            .collect();
+    }
+    let (tens, ones) = (n / 10, n % 10);
+    let mut s = String::new();
+    if tens > 1 {
+        s.push(KANJI_DIGITS[tens as usize]);
+    }
+    if tens > 0 {
+        s.push('十');
+    }
+    if ones > 0 {
+        s.push(KANJI_DIGITS[ones as usize]);
+    }
+    s
+}
+
+/// Replaces each run of kanji numerals with half-width digits, reading it
+/// positionally if it has 十, 百 or 千 and digit by digit otherwise.
+fn kanji_to_digits(s: &str) -> String {
+    let is_kanji = |c: char| KANJI_DIGITS.contains(&c) || c == '零' || "十百千".contains(c);
+    let digit = |c: char| if c == '零' {Some(0)} else {KANJI_DIGITS.iter().position(|&k| k == c).map(|d| d as u32)};
+    let mut out = String::new();
+    let mut run: Vec<char> = Vec::new();
+    let flush = |run: &mut Vec<char>, out: &mut String| {
+        if run.is_empty() {
+            return;
+        }
+        if run.iter().any(|c| "十百千".contains(*c)) {
+            let (mut total, mut current) = (0, 0);
+            for &c in run.iter() {
+                let unit = match c {
+                    '十' => 10,
+                    '百' => 100,
+                    '千' => 1000,
+                    c => {
+                        current = digit(c).unwrap_or(0);
+                        continue;
+                    },
+                };
+                total += if current == 0 {1} else {current} * unit;
+                current = 0;
+            }
+            out.push_str(&(total + current).to_string());
+        } else {
+            out.extend(run.iter().filter_map(|&c| digit(c)).map(|d| char::from(b'0' + d as u8)));
+        }
+        run.clear();
+    };
+    for c in s.chars() {
+        if is_kanji(c) {
+            run.push(c);
+        } else {
+            flush(&mut run, &mut out);
+            out.push(c);
+        }
+    }
+    flush(&mut run, &mut out);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wareki(code: char, year: i32, month: u32, day: u32) -> Date {
+        Date::new_wareki(GengoYear::new(code, year), month, day).unwrap()
+    }
+
+    #[test]
+    fn formats_in_each_style() {
+        let date = wareki('R', 1, 5, 1);
+        let format = |formatter: DateFormatter| formatter.format(&date).unwrap();
+        assert_eq!(format(DateFormatter::new()), date.to_string());
+        assert_eq!(format(DateFormatter::new()), "令和元年5月1日");
+        assert_eq!(format(DateFormatter::new().gannen(false)), "令和1年5月1日");
+        assert_eq!(format(DateFormatter::new().era_style(EraStyle::Ligature)), "㋿元年5月1日");
+        assert_eq!(format(DateFormatter::new().numerals(Numerals::FullWidth).zero_pad(true).gannen(false)),
+            "令和０１年０５月０１日");
+        assert_eq!(format(DateFormatter::new().numerals(Numerals::Kanji)), "令和元年五月一日");
+        assert_eq!(format(DateFormatter::new().style(DateStyle::Short).era_style(EraStyle::Code)), "R1.5.1");
+        assert_eq!(format(DateFormatter::new().style(DateStyle::Short).era_style(EraStyle::Code)
+            .separator('/').zero_pad(true)), "R01/05/01");
+        assert_eq!(format(DateFormatter::new().calendar(Calendar::Seireki)), "2019年5月1日");
+        assert_eq!(format(DateFormatter::new().calendar(Calendar::Seireki).numerals(Numerals::Kanji)),
+            "二〇一九年五月一日");
+        assert_eq!(format(DateFormatter::new().calendar(Calendar::Seireki).style(DateStyle::Short)
+            .separator('-').zero_pad(true)), "2019-05-01");
+
+        let month = Date::new_seireki_year_month(2020, 12).unwrap();
+        assert_eq!(DateFormatter::new().calendar(Calendar::Wareki).format(&month).unwrap(), "令和2年12月");
+        assert_eq!(DateFormatter::new().numerals(Numerals::Kanji).format(&month).unwrap(), "二〇二〇年十二月");
+        assert!(DateFormatter::new().calendar(Calendar::Wareki)
+            .format(&Date::new_seireki(1868, 1, 1).unwrap()).is_err());
+    }
+
+    #[test]
+    fn parses_text_in_any_style() {
+        let date = wareki('R', 2, 5, 1);
+        for s in ["令和2年5月1日", "令和二年五月一日", "㋿2年5月1日", "令和 02年 05月 01日", "R2.5.1", "r02/05/01",
+                "Ｒ２．５．１", "R020501", "令和2-5-1"] {
+            assert_eq!(Date::parse_text(s), Ok(date), "{}", s);
+        }
+        assert_eq!(Date::parse_text("令和元年5月1日"), Ok(wareki('R', 1, 5, 1)));
+        assert_eq!(Date::parse_text("平成三十一年四月三十日"), Ok(wareki('H', 31, 4, 30)));
+
+        let date = Date::new_seireki(2020, 5, 1).unwrap();
+        for s in ["2020年5月1日", "２０２０年５月１日", "二〇二〇年五月一日", "西暦2020年5月1日", "2020/5/1",
+                "20200501"] {
+            assert_eq!(Date::parse_text(s), Ok(date), "{}", s);
+        }
+        assert_eq!(Date::parse_text("令和2年5月"), Date::new_wareki_year_month(GengoYear::new('R', 2), 5));
+        assert_eq!(Date::parse_text("2020年"), Date::new_seireki_year(2020));
+        assert_eq!(DateFormatter::new().separator('_').parse("2020_5_1"), Ok(date));
+
+        for s in ["", "令和", "2020年5月32日", "平成31年5月1日", "令和2年1日", "2020.5.1.1", "明日"] {
+            assert!(Date::parse_text(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn reads_back_what_it_formats() {
+        let dates = [
+            wareki('H', 1, 1, 8),
+            wareki('S', 64, 1, 7),
+            Date::new_seireki(2020, 12, 31).unwrap(),
+            Date::new_wareki_year(GengoYear::new('R', 1)).unwrap(),
+        ];
+        for date in &dates {
+            for formatter in [
+                DateFormatter::new(),
+                DateFormatter::new().numerals(Numerals::Kanji),
+                DateFormatter::new().era_style(EraStyle::Ligature).numerals(Numerals::FullWidth),
+                DateFormatter::new().style(DateStyle::Short).era_style(EraStyle::Code).zero_pad(true),
+            ] {
+                let s = formatter.format(date).unwrap();
+                assert_eq!(formatter.parse(&s).as_ref(), Ok(date), "{}", s);
+            }
+        }
+    }
+}
@@ -0,0 +1,1209 @@
+// A QR code reader for clean printed symbols, as found on medicine notebook
+// stickers and receipts: finder patterns are located in a binarized image,
+// the modules are sampled through a perspective transform, and the
+// codewords are corrected with Reed-Solomon before the segments are read.
+//
+// ISO/IEC 18004:2015
+
+use std::collections::HashSet;
+use lazy_static::lazy_static;
+use crate::jahis::Error;
+
+/// Structured append header of a symbol (構造的連接)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct StructuredAppend {
+    pub index: u8, // 0-based
+    pub total: u8,
+    pub parity: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct DecodedSymbol {
+    pub payload: Vec<u8>,
+    pub structured_append: Option<StructuredAppend>,
+}
+
+/// Finds and decodes every QR code in a PNG or JPEG image. The errors are
+/// those of symbols which were located but could not be decoded.
+pub(crate) fn decode_image(bytes: &[u8]) -> Result<(Vec<DecodedSymbol>, Vec<Error>), Error> {
+    let image = Luma::load(bytes)?;
+    let mut symbols: Vec<DecodedSymbol> = Vec::new();
+    let mut errors: Option<Vec<Error>> = None;
+    for bitmap in [image.binarize_global(), image.binarize_local()] {
+        let (found, failed) = decode_bitmap(&bitmap);
+        for symbol in found {
+            if !symbols.contains(&symbol) {
+                symbols.push(symbol);
+            }
+        }
+        if errors.as_ref().is_none_or(|e| failed.len() < e.len()) {
+            errors = Some(failed);
+        }
+        if errors.as_ref().is_some_and(|e| e.is_empty()) && !symbols.is_empty() {
+            break;
+        }
+    }
+    let mut errors = errors.unwrap_or_default();
+    if symbols.is_empty() && errors.is_empty() {
+        errors.push(Error::QrCodeError("no QR code found".to_string()));
+    }
+    Ok((symbols, errors))
+}
+
+//------------------------------------------------------------------------------
+// Images
+
+/// Grayscale image
+struct Luma {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Luma {
+    fn load(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.starts_with(b"\x89PNG") {
+            Self::load_png(bytes)
+        } else if bytes.starts_with(b"\xFF\xD8") {
+            Self::load_jpeg(bytes)
+        } else {
+            Err(Error::ImageError("unsupported image format, expected PNG or JPEG".to_string()))
+        }
+    }
+
+    fn load_png(bytes: &[u8]) -> Result<Self, Error> {
+        let mut decoder = png::Decoder::new(bytes);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info().map_err(|e| Error::ImageError(e.to_string()))?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).map_err(|e| Error::ImageError(e.to_string()))?;
+        let channels = match info.color_type {
+            png::ColorType::Grayscale => 1,
+            png::ColorType::GrayscaleAlpha => 2,
+            png::ColorType::Rgb | png::ColorType::Indexed => 3,
+            png::ColorType::Rgba => 4,
+        };
+        let (width, height) = (info.width as usize, info.height as usize);
+        let mut pixels = Vec::with_capacity(width * height);
+        for row in buf[..info.buffer_size()].chunks(info.line_size).take(height) {
+            for px in row.chunks(channels).take(width) {
+                let (luma, alpha) = match px {
+                    [l] => (*l as u32, 255),
+                    [l, a] => (*l as u32, *a as u32),
+                    [r, g, b] => (rgb_luma(*r, *g, *b), 255),
+                    [r, g, b, a, ..] => (rgb_luma(*r, *g, *b), *a as u32),
+                    _ => (255, 255),
+                };
+                // Transparent pixels are taken as white paper.
+                pixels.push(((luma * alpha + 255 * (255 - alpha)) / 255) as u8);
+            }
+        }
+        Ok(Self {width, height, pixels})
+    }
+
+    fn load_jpeg(bytes: &[u8]) -> Result<Self, Error> {
+        let mut decoder = jpeg_decoder::Decoder::new(bytes);
+        let data = decoder.decode().map_err(|e| Error::ImageError(e.to_string()))?;
+        let info = decoder.info()
+            .ok_or_else(|| Error::ImageError("missing JPEG header".to_string()))?;
+        let pixels = match info.pixel_format {
+            jpeg_decoder::PixelFormat::L8 => data,
+            jpeg_decoder::PixelFormat::L16 => data.chunks(2).map(|px| px[0]).collect(),
+            jpeg_decoder::PixelFormat::RGB24 => data.chunks(3)
+                .map(|px| rgb_luma(px[0], px[1], px[2]) as u8)
+                .collect(),
+            jpeg_decoder::PixelFormat::CMYK32 => data.chunks(4)
+                .map(|px| {
+                    let k = 255 - px[3] as u32;
+                    let [r, g, b] = [px[0], px[1], px[2]].map(|c| ((255 - c as u32) * k / 255) as u8);
+                    rgb_luma(r, g, b) as u8
+                })
+                .collect(),
+        };
+        Ok(Self {width: info.width as usize, height: info.height as usize, pixels})
+    }
+
+    /// Binarizes with a single threshold chosen by Otsu's method, which
+    /// suits evenly lit scans.
+    fn binarize_global(&self) -> Bitmap {
+        let mut histogram = [0usize; 256];
+        for &p in &self.pixels {
+            histogram[p as usize] += 1;
+        }
+        let total = self.pixels.len() as f64;
+        let sum: f64 = histogram.iter().enumerate().map(|(i, &n)| i as f64 * n as f64).sum();
+        let (mut weight, mut sum_below, mut best, mut threshold) = (0.0, 0.0, 0.0, 128);
+        for (i, &n) in histogram.iter().enumerate() {
+            weight += n as f64;
+            sum_below += i as f64 * n as f64;
+            if weight == 0.0 || weight == total {
+                continue;
+            }
+            let mean_below = sum_below / weight;
+            let mean_above = (sum - sum_below) / (total - weight);
+            let variance = weight * (total - weight) * (mean_below - mean_above).powi(2);
+            if variance > best {
+                best = variance;
+                threshold = i;
+            }
+        }
+        Bitmap {
+            width: self.width,
+            height: self.height,
+            dark: self.pixels.iter().map(|&p| (p as usize) <= threshold).collect(),
+        }
+    }
+
+    /// Binarizes against the mean of the surrounding pixels, which suits
+    /// photographs with uneven lighting.
+    fn binarize_local(&self) -> Bitmap {
+        let (w, h) = (self.width, self.height);
+        let mut integral = vec![0u64; (w + 1) * (h + 1)];
+        for y in 0..h {
+            let mut row = 0u64;
+            for x in 0..w {
+                row += self.pixels[y * w + x] as u64;
+                integral[(y + 1) * (w + 1) + x + 1] = integral[y * (w + 1) + x + 1] + row;
+            }
+        }
+        let radius = (w.min(h) / 8).max(8);
+        let mut dark = Vec::with_capacity(w * h);
+        for y in 0..h {
+            let (y0, y1) = (y.saturating_sub(radius), (y + radius + 1).min(h));
+            for x in 0..w {
+                let (x0, x1) = (x.saturating_sub(radius), (x + radius + 1).min(w));
+                let sum = integral[y1 * (w + 1) + x1] + integral[y0 * (w + 1) + x0]
+                    - integral[y0 * (w + 1) + x1] - integral[y1 * (w + 1) + x0];
+                let mean = sum / ((x1 - x0) * (y1 - y0)) as u64;
+                dark.push((self.pixels[y * w + x] as u64) * 100 < mean * 90);
+            }
+        }
+        Bitmap {width: w, height: h, dark}
+    }
+}
+
+fn rgb_luma(r: u8, g: u8, b: u8) -> u32 {
+    (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000
+}
+
+struct Bitmap {
+    width: usize,
+    height: usize,
+    dark: Vec<bool>,
+}
+
+impl Bitmap {
+    fn is_dark(&self, x: usize, y: usize) -> bool {
+        self.dark[y * self.width + x]
+    }
+
+    /// Samples the pixel at a point; outside of the image is light.
+    fn sample(&self, x: f64, y: f64) -> bool {
+        x >= 0.0 && y >= 0.0 && (x as usize) < self.width && (y as usize) < self.height
+            && self.is_dark(x as usize, y as usize)
+    }
+}
+
+//------------------------------------------------------------------------------
+// Finder patterns
+
+#[derive(Debug, Clone, Copy)]
+struct Finder {
+    x: f64,
+    y: f64,
+    module_size: f64,
+    count: usize,
+}
+
+fn distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
+}
+
+/// Whether runs of dark, light, dark, light and dark pixels are in the
+/// ratio 1:1:3:1:1 of a finder pattern.
+fn is_finder_ratio(counts: &[usize; 5]) -> bool {
+    let total: usize = counts.iter().sum();
+    if total < 7 || counts.contains(&0) {
+        return false;
+    }
+    let module_size = total as f64 / 7.0;
+    let max_variance = module_size / 2.0;
+    (module_size - counts[0] as f64).abs() < max_variance
+        && (module_size - counts[1] as f64).abs() < max_variance
+        && (3.0 * module_size - counts[2] as f64).abs() < 3.0 * max_variance
+        && (module_size - counts[3] as f64).abs() < max_variance
+        && (module_size - counts[4] as f64).abs() < max_variance
+}
+
+/// Measures the runs of a finder pattern through a point along a line,
+/// returning the center of the pattern on the line and the total length.
+fn cross_check(bitmap: &Bitmap, x: usize, y: usize, vertical: bool, max_count: usize) -> Option<(f64, usize)> {
+    let (len, pos) = if vertical { (bitmap.height, y) } else { (bitmap.width, x) };
+    let dark = |i: usize| if vertical { bitmap.is_dark(x, i) } else { bitmap.is_dark(i, y) };
+    let mut counts = [0usize; 5];
+
+    let mut i = pos as isize;
+    while i >= 0 && dark(i as usize) {
+        counts[2] += 1;
+        i -= 1;
+    }
+    for (state, want_dark) in [(1, false), (0, true)] {
+        while i >= 0 && dark(i as usize) == want_dark && counts[state] <= max_count {
+            counts[state] += 1;
+            i -= 1;
+        }
+        if counts[state] == 0 || counts[state] > max_count {
+            return None;
+        }
+    }
+
+    let mut i = pos + 1;
+    while i < len && dark(i) {
+        counts[2] += 1;
+        i += 1;
+    }
+    for (state, want_dark) in [(3, false), (4, true)] {
+        while i < len && dark(i) == want_dark && counts[state] <= max_count {
+            counts[state] += 1;
+            i += 1;
+        }
+        if counts[state] == 0 || counts[state] > max_count {
+            return None;
+        }
+    }
+
+    if !is_finder_ratio(&counts) {
+        return None;
+    }
+    let center = i as f64 - counts[4] as f64 - counts[3] as f64 - counts[2] as f64 / 2.0;
+    Some((center, counts.iter().sum()))
+}
+
+fn find_finders(bitmap: &Bitmap) -> Vec<Finder> {
+    let mut finders: Vec<Finder> = Vec::new();
+    let mut add = |counts: &[usize; 5], end: usize, y: usize| {
+        let total: usize = counts.iter().sum();
+        let x = end as f64 - counts[4] as f64 - counts[3] as f64 - counts[2] as f64 / 2.0;
+        let Some((cy, v_total)) = cross_check(bitmap, x as usize, y, true, counts[2] * 2) else { return };
+        let Some((cx, h_total)) = cross_check(bitmap, x as usize, cy as usize, false, counts[2] * 2) else { return };
+        let module_size = (total + v_total + h_total) as f64 / 21.0;
+        match finders.iter_mut().find(|f| (f.x - cx).abs() <= f.module_size
+                && (f.y - cy).abs() <= f.module_size
+                && (f.module_size - module_size).abs() <= f.module_size.max(1.0)) {
+            Some(f) => {
+                let n = f.count as f64;
+                f.x = (f.x * n + cx) / (n + 1.0);
+                f.y = (f.y * n + cy) / (n + 1.0);
+                f.module_size = (f.module_size * n + module_size) / (n + 1.0);
+                f.count += 1;
+            },
+            None => finders.push(Finder {x: cx, y: cy, module_size, count: 1}),
+        }
+    };
+
+    for y in 0..bitmap.height {
+        let mut counts = [0usize; 5];
+        let mut state = 0;
+        for x in 0..bitmap.width {
+            if bitmap.is_dark(x, y) {
+                if state % 2 == 1 {
+                    state += 1;
+                }
+                counts[state] += 1;
+            } else if state % 2 == 1 {
+                counts[state] += 1;
+            } else if state == 4 {
+                if is_finder_ratio(&counts) {
+                    add(&counts, x, y);
+                }
+                counts = [counts[2], counts[3], counts[4], 1, 0];
+                state = 3;
+            } else {
+                state += 1;
+                counts[state] += 1;
+            }
+        }
+        if state == 4 && is_finder_ratio(&counts) {
+            add(&counts, bitmap.width, y);
+        }
+    }
+    finders.retain(|f| f.count >= 2);
+    finders.sort_by_key(|f| std::cmp::Reverse(f.count));
+    finders.truncate(30);
+    finders
+}
+
+/// Finds sets of three finder patterns which may be the corners of a
+/// symbol, as top-left, top-right and bottom-left, best first.
+fn group_finders(finders: &[Finder]) -> Vec<[usize; 3]> {
+    let mut groups: Vec<(f64, [usize; 3])> = Vec::new();
+    for i in 0..finders.len() {
+        for j in i + 1..finders.len() {
+            for k in j + 1..finders.len() {
+                let sizes = [finders[i].module_size, finders[j].module_size, finders[k].module_size];
+                let (min, max) = (sizes.iter().cloned().fold(f64::MAX, f64::min), sizes.iter().cloned().fold(0.0, f64::max));
+                if max > min * 1.4 {
+                    continue;
+                }
+                // The corner at the right angle is opposite the longest side.
+                let p = |n: usize| (finders[n].x, finders[n].y);
+                let mut corners = [(distance(p(j), p(k)), i, j, k), (distance(p(i), p(k)), j, i, k),
+                                   (distance(p(i), p(j)), k, i, j)];
+                corners.sort_by(|a, b| b.0.total_cmp(&a.0));
+                let (hypotenuse, a, b, c) = corners[0];
+                let (ab, ac) = (distance(p(a), p(b)), distance(p(a), p(c)));
+                let legs_ratio = ab.max(ac) / ab.min(ac);
+                let hypotenuse_ratio = hypotenuse / ((ab * ab + ac * ac).sqrt());
+                let modules = (ab + ac) / 2.0 / ((min + max) / 2.0) + 7.0;
+                if legs_ratio > 1.3 || (hypotenuse_ratio - 1.0).abs() > 0.15 || !(17.0..=185.0).contains(&modules) {
+                    continue;
+                }
+                // Order the other corners so that top-right comes first in
+                // image coordinates, whose y axis points down.
+                let cross = (p(b).0 - p(a).0) * (p(c).1 - p(a).1) - (p(b).1 - p(a).1) * (p(c).0 - p(a).0);
+                let (tr, bl) = if cross > 0.0 { (b, c) } else { (c, b) };
+                groups.push(((legs_ratio - 1.0) + (hypotenuse_ratio - 1.0).abs(), [a, tr, bl]));
+            }
+        }
+    }
+    groups.sort_by(|a, b| a.0.total_cmp(&b.0));
+    groups.into_iter().map(|(_, g)| g).collect()
+}
+
+fn decode_bitmap(bitmap: &Bitmap) -> (Vec<DecodedSymbol>, Vec<Error>) {
+    let finders = find_finders(bitmap);
+    let mut symbols = Vec::new();
+    let mut regions: Vec<[Finder; 3]> = Vec::new();
+    let mut used: HashSet<usize> = HashSet::new();
+    let mut failed: Vec<([usize; 3], Error)> = Vec::new();
+    for group in group_finders(&finders) {
+        if group.iter().any(|i| used.contains(i)) {
+            continue;
+        }
+        let [tl, tr, bl] = group.map(|i| finders[i]);
+        match decode_at(bitmap, tl, tr, bl) {
+            Ok(symbol) => {
+                used.extend(group);
+                regions.push([tl, tr, bl]);
+                symbols.push(symbol);
+            },
+            Err(e) => failed.push((group, e)),
+        }
+    }
+    // Report a failure only once for each finder pattern which is neither
+    // part nor inside of a decoded symbol, as most failures are of wrong
+    // combinations or of finder-like shapes in the data.
+    let mut errors = Vec::new();
+    for (group, e) in failed {
+        if group.iter().all(|i| !used.contains(i) && !regions.iter().any(|r| is_inside(r, &finders[*i]))) {
+            used.extend(group);
+            errors.push(e);
+        }
+    }
+    (symbols, errors)
+}
+
+/// Whether a finder pattern lies within the symbol of the given corners.
+fn is_inside([tl, tr, bl]: &[Finder; 3], f: &Finder) -> bool {
+    let (ux, uy, vx, vy) = (tr.x - tl.x, tr.y - tl.y, bl.x - tl.x, bl.y - tl.y);
+    let det = ux * vy - uy * vx;
+    let (px, py) = (f.x - tl.x, f.y - tl.y);
+    let u = (px * vy - py * vx) / det;
+    let v = (ux * py - uy * px) / det;
+    (-0.1..=1.1).contains(&u) && (-0.1..=1.1).contains(&v)
+}
+
+//------------------------------------------------------------------------------
+// Sampling
+
+/// Projective transform from module coordinates to pixel coordinates.
+#[derive(Debug, Clone, Copy)]
+struct Transform([f64; 9]);
+
+impl Transform {
+    fn square_to_quad(q: [(f64, f64); 4]) -> Self {
+        let [(x0, y0), (x1, y1), (x2, y2), (x3, y3)] = q;
+        let (dx3, dy3) = (x0 - x1 + x2 - x3, y0 - y1 + y2 - y3);
+        if dx3 == 0.0 && dy3 == 0.0 {
+            return Self([x1 - x0, x2 - x1, x0, y1 - y0, y2 - y1, y0, 0.0, 0.0, 1.0]);
+        }
+        let (dx1, dx2, dy1, dy2) = (x1 - x2, x3 - x2, y1 - y2, y3 - y2);
+        let denominator = dx1 * dy2 - dx2 * dy1;
+        let a13 = (dx3 * dy2 - dx2 * dy3) / denominator;
+        let a23 = (dx1 * dy3 - dx3 * dy1) / denominator;
+        Self([x1 - x0 + a13 * x1, x3 - x0 + a23 * x3, x0,
+              y1 - y0 + a13 * y1, y3 - y0 + a23 * y3, y0,
+              a13, a23, 1.0])
+    }
+
+    fn adjoint(&self) -> Self {
+        let [a11, a12, a13, a21, a22, a23, a31, a32, a33] = self.0;
+        Self([a22 * a33 - a23 * a32, a13 * a32 - a12 * a33, a12 * a23 - a13 * a22,
+              a23 * a31 - a21 * a33, a11 * a33 - a13 * a31, a13 * a21 - a11 * a23,
+              a21 * a32 - a22 * a31, a12 * a31 - a11 * a32, a11 * a22 - a12 * a21])
+    }
+
+    fn times(&self, other: &Self) -> Self {
+        let (a, b) = (self.0, other.0);
+        let mut m = [0.0; 9];
+        for r in 0..3 {
+            for c in 0..3 {
+                m[r * 3 + c] = (0..3).map(|k| a[r * 3 + k] * b[k * 3 + c]).sum();
+            }
+        }
+        Self(m)
+    }
+
+    /// Maps the corners of the quadrilateral `from` onto those of `to`.
+    fn quad_to_quad(from: [(f64, f64); 4], to: [(f64, f64); 4]) -> Self {
+        Self::square_to_quad(to).times(&Self::square_to_quad(from).adjoint())
+    }
+
+    fn apply(&self, x: f64, y: f64) -> (f64, f64) {
+        let m = self.0;
+        let w = m[6] * x + m[7] * y + m[8];
+        ((m[0] * x + m[1] * y + m[2]) / w, (m[3] * x + m[4] * y + m[5]) / w)
+    }
+}
+
+fn decode_at(bitmap: &Bitmap, tl: Finder, tr: Finder, bl: Finder) -> Result<DecodedSymbol, Error> {
+    let module_size = (tl.module_size + tr.module_size + bl.module_size) / 3.0;
+    let modules = (distance((tl.x, tl.y), (tr.x, tr.y)) + distance((tl.x, tl.y), (bl.x, bl.y)))
+        / 2.0 / module_size + 7.0;
+    let estimate = ((modules - 17.0) / 4.0).round().clamp(1.0, 40.0) as usize;
+    let mut versions = vec![estimate];
+    for d in 1..=2 {
+        versions.extend([estimate + d, estimate.saturating_sub(d)].iter().filter(|&&v| (1..=40).contains(&v)));
+    }
+
+    let mut last_error = Error::QrCodeError("cannot read the symbol".to_string());
+    let mut tried = HashSet::new();
+    while let Some(version) = versions.first().copied() {
+        versions.remove(0);
+        if !tried.insert(version) {
+            continue;
+        }
+        let size = version * 4 + 17;
+        let affine = affine_transform(tl, tr, bl, size);
+        // The version information next to the top-right finder pattern can be
+        // read even when the estimated size is slightly wrong.
+        if version >= 7 {
+            if let Some(v) = read_version(&sample_grid(bitmap, &affine, size)) {
+                if v != version && !tried.contains(&v) {
+                    versions.insert(0, v);
+                    continue;
+                }
+            }
+        }
+        let mut transforms = Vec::new();
+        if version >= 2 {
+            let estimate = affine.apply(size as f64 - 6.5, size as f64 - 6.5);
+            transforms.extend(find_alignments(bitmap, estimate, module_size).into_iter()
+                .map(|alignment| perspective_transform(tl, tr, bl, alignment, size)));
+        }
+        transforms.push(affine);
+        for transform in &transforms {
+            match sample_grid(bitmap, transform, size).decode() {
+                Ok(symbol) => return Ok(symbol),
+                Err(e) => last_error = e,
+            }
+        }
+    }
+    Err(last_error)
+}
+
+fn affine_transform(tl: Finder, tr: Finder, bl: Finder, size: usize) -> Transform {
+    let s = size as f64;
+    Transform::quad_to_quad(
+        [(3.5, 3.5), (s - 3.5, 3.5), (s - 3.5, s - 3.5), (3.5, s - 3.5)],
+        [(tl.x, tl.y), (tr.x, tr.y), (tr.x + bl.x - tl.x, tr.y + bl.y - tl.y), (bl.x, bl.y)])
+}
+
+/// Estimates the transform from the finder patterns and the bottom-right
+/// alignment pattern, which corrects the perspective.
+fn perspective_transform(tl: Finder, tr: Finder, bl: Finder, alignment: (f64, f64), size: usize) -> Transform {
+    let s = size as f64;
+    Transform::quad_to_quad(
+        [(3.5, 3.5), (s - 3.5, 3.5), (s - 6.5, s - 6.5), (3.5, s - 3.5)],
+        [(tl.x, tl.y), (tr.x, tr.y), alignment, (bl.x, bl.y)])
+}
+
+fn sample_grid(bitmap: &Bitmap, transform: &Transform, size: usize) -> Grid {
+    let mut modules = Vec::with_capacity(size * size);
+    for y in 0..size {
+        for x in 0..size {
+            let (px, py) = transform.apply(x as f64 + 0.5, y as f64 + 0.5);
+            modules.push(bitmap.sample(px, py));
+        }
+    }
+    Grid {size, modules}
+}
+
+/// Looks for the bottom-right alignment pattern around its estimated
+/// position, widening the search until a match is found. Returns the
+/// centers of the matches, closest to the estimate first, as the patterns
+/// next to it may also be found when the perspective is strong.
+fn find_alignments(bitmap: &Bitmap, (ex, ey): (f64, f64), module_size: f64) -> Vec<(f64, f64)> {
+    let m = module_size;
+    let light = [(m, 0.0), (-m, 0.0), (0.0, m), (0.0, -m), (m, m), (m, -m), (-m, m), (-m, -m)];
+    let dark = [(2.0 * m, 0.0), (-2.0 * m, 0.0), (0.0, 2.0 * m), (0.0, -2.0 * m),
+                (2.0 * m, 2.0 * m), (2.0 * m, -2.0 * m), (-2.0 * m, 2.0 * m), (-2.0 * m, -2.0 * m)];
+    let is_center = |x: f64, y: f64| bitmap.sample(x, y)
+        && light.iter().all(|(ox, oy)| !bitmap.sample(x + ox, y + oy))
+        && dark.iter().all(|(ox, oy)| bitmap.sample(x + ox, y + oy));
+    for allowance in [4.0, 8.0, 16.0] {
+        let radius = (module_size * allowance) as isize;
+        let mut matches: Vec<(f64, f64)> = Vec::new();
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                let (x, y) = (ex + dx as f64, ey + dy as f64);
+                if is_center(x, y) {
+                    matches.push((x, y));
+                }
+            }
+        }
+        matches.sort_by(|a, b| distance(*a, (ex, ey)).total_cmp(&distance(*b, (ex, ey))));
+        // Average the points of each cluster.
+        let mut centers: Vec<(f64, f64)> = Vec::new();
+        while let Some(&first) = matches.first() {
+            let (cluster, rest): (Vec<(f64, f64)>, _) = matches.into_iter()
+                .partition(|p| distance(*p, first) <= m);
+            let n = cluster.len() as f64;
+            centers.push((cluster.iter().map(|p| p.0).sum::<f64>() / n, cluster.iter().map(|p| p.1).sum::<f64>() / n));
+            matches = rest;
+        }
+        if !centers.is_empty() {
+            centers.truncate(4);
+            return centers;
+        }
+    }
+    Vec::new()
+}
+
+//------------------------------------------------------------------------------
+// Symbol structure
+
+/// Modules of a symbol, with x to the right and y downwards.
+struct Grid {
+    size: usize,
+    modules: Vec<bool>,
+}
+
+const EC_LEVELS: [&str; 4] = ["L", "M", "Q", "H"];
+
+/// Number of error correction codewords per block, by version and level
+/// (L, M, Q, H).
+static EC_CODEWORDS_PER_BLOCK: [[usize; 4]; 40] = [
+    [7, 10, 13, 17], [10, 16, 22, 28], [15, 26, 18, 22], [20, 18, 26, 16], [26, 24, 18, 22],
+    [18, 16, 24, 28], [20, 18, 18, 26], [24, 22, 22, 26], [30, 22, 20, 24], [18, 26, 24, 28],
+    [20, 30, 28, 24], [24, 22, 26, 28], [26, 22, 24, 22], [30, 24, 20, 24], [22, 24, 30, 24],
+    [24, 28, 24, 30], [28, 28, 28, 28], [30, 26, 28, 28], [28, 26, 26, 26], [28, 26, 30, 28],
+    [28, 26, 28, 30], [28, 28, 30, 24], [30, 28, 30, 30], [30, 28, 30, 30], [26, 28, 30, 30],
+    [28, 28, 28, 30], [30, 28, 30, 30], [30, 28, 30, 30], [30, 28, 30, 30], [30, 28, 30, 30],
+    [30, 28, 30, 30], [30, 28, 30, 30], [30, 28, 30, 30], [30, 28, 30, 30], [30, 28, 30, 30],
+    [30, 28, 30, 30], [30, 28, 30, 30], [30, 28, 30, 30], [30, 28, 30, 30], [30, 28, 30, 30],
+];
+
+/// Number of error correction blocks, by version and level (L, M, Q, H).
+static BLOCKS: [[usize; 4]; 40] = [
+    [1, 1, 1, 1], [1, 1, 1, 1], [1, 1, 2, 2], [1, 2, 2, 4], [1, 2, 4, 4],
+    [2, 4, 4, 4], [2, 4, 6, 5], [2, 4, 6, 6], [2, 5, 8, 8], [4, 5, 8, 8],
+    [4, 5, 8, 11], [4, 8, 10, 11], [4, 9, 12, 16], [4, 9, 16, 16], [6, 10, 12, 18],
+    [6, 10, 17, 16], [6, 11, 16, 19], [6, 13, 18, 21], [7, 14, 21, 25], [8, 16, 20, 25],
+    [8, 17, 23, 25], [9, 17, 23, 34], [9, 18, 25, 30], [10, 20, 27, 32], [12, 21, 29, 35],
+    [12, 23, 34, 37], [12, 25, 34, 40], [13, 26, 35, 42], [14, 28, 38, 45], [15, 29, 40, 48],
+    [16, 31, 43, 51], [17, 33, 45, 54], [18, 35, 48, 57], [19, 37, 51, 60], [19, 38, 53, 63],
+    [20, 40, 56, 66], [21, 43, 59, 70], [22, 45, 62, 74], [24, 47, 65, 77], [25, 49, 68, 81],
+];
+
+fn alignment_positions(version: usize) -> Vec<usize> {
+    if version == 1 {
+        return Vec::new();
+    }
+    let count = version / 7 + 2;
+    let step = if version == 32 { 26 } else { (version * 4 + count * 2 + 1) / (count * 2 - 2) * 2 };
+    let mut positions: Vec<usize> = (0..count - 1).map(|i| version * 4 + 10 - i * step).collect();
+    positions.push(6);
+    positions.reverse();
+    positions
+}
+
+fn format_word(data: u32) -> u32 {
+    let mut rem = data;
+    for _ in 0..10 {
+        rem = (rem << 1) ^ ((rem >> 9) * 0x537);
+    }
+    ((data << 10) | rem) ^ 0x5412
+}
+
+fn version_word(version: u32) -> u32 {
+    let mut rem = version;
+    for _ in 0..12 {
+        rem = (rem << 1) ^ ((rem >> 11) * 0x1F25);
+    }
+    (version << 12) | rem
+}
+
+fn read_version(grid: &Grid) -> Option<usize> {
+    let s = grid.size;
+    let (mut a, mut b) = (0u32, 0u32);
+    for i in 0..18 {
+        let (u, v) = (s - 11 + i % 3, i / 3);
+        a |= (grid.get(u, v) as u32) << i;
+        b |= (grid.get(v, u) as u32) << i;
+    }
+    (7..=40u32)
+        .map(|v| (v, (version_word(v) ^ a).count_ones().min((version_word(v) ^ b).count_ones())))
+        .filter(|&(_, d)| d <= 3)
+        .min_by_key(|&(_, d)| d)
+        .map(|(v, _)| v as usize)
+}
+
+impl Grid {
+    fn get(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.size + x]
+    }
+
+    /// Reads the error correction level (index into `EC_LEVELS`) and the
+    /// mask pattern.
+    fn read_format(&self) -> Option<(usize, u32)> {
+        let s = self.size;
+        let (mut a, mut b) = (0u32, 0u32);
+        for i in 0..15 {
+            let (x, y) = match i {
+                0..=5 => (8, i),
+                6 => (8, 7),
+                7 => (8, 8),
+                8 => (7, 8),
+                _ => (14 - i, 8),
+            };
+            a |= (self.get(x, y) as u32) << i;
+            let (x, y) = if i < 8 { (s - 1 - i, 8) } else { (8, s - 15 + i) };
+            b |= (self.get(x, y) as u32) << i;
+        }
+        let (data, d) = (0..32u32)
+            .map(|data| (data, (format_word(data) ^ a).count_ones().min((format_word(data) ^ b).count_ones())))
+            .min_by_key(|&(_, d)| d)?;
+        if d > 3 {
+            return None;
+        }
+        // The format information holds 01, 00, 11, 10 for L, M, Q, H.
+        let level = [1, 0, 3, 2][(data >> 3) as usize];
+        Some((level, data & 7))
+    }
+
+    fn function_modules(&self) -> Vec<bool> {
+        let s = self.size;
+        let version = (s - 17) / 4;
+        let mut function = vec![false; s * s];
+        let mut mark = |x0: usize, y0: usize, w: usize, h: usize| {
+            for y in y0..y0 + h {
+                for x in x0..x0 + w {
+                    function[y * s + x] = true;
+                }
+            }
+        };
+        // Finder patterns with separators and format information
+        mark(0, 0, 9, 9);
+        mark(s - 8, 0, 8, 9);
+        mark(0, s - 8, 9, 8);
+        // Timing patterns
+        mark(6, 0, 1, s);
+        mark(0, 6, s, 1);
+        let positions = alignment_positions(version);
+        let last = positions.len().saturating_sub(1);
+        for (i, &y) in positions.iter().enumerate() {
+            for (j, &x) in positions.iter().enumerate() {
+                // No alignment patterns overlap the finder patterns.
+                if matches!((i, j), (0, 0)) || (i == 0 && j == last) || (i == last && j == 0) {
+                    continue;
+                }
+                mark(x - 2, y - 2, 5, 5);
+            }
+        }
+        if version >= 7 {
+            mark(s - 11, 0, 3, 6);
+            mark(0, s - 11, 6, 3);
+        }
+        function
+    }
+
+    fn decode(&self) -> Result<DecodedSymbol, Error> {
+        let version = (self.size - 17) / 4;
+        let (level, mask) = self.read_format()
+            .ok_or_else(|| Error::QrCodeError("cannot read the format information".to_string()))?;
+        let function = self.function_modules();
+
+        let mut codewords: Vec<u8> = Vec::new();
+        let (mut byte, mut bits) = (0u8, 0);
+        let s = self.size;
+        let mut right = s - 1;
+        loop {
+            if right == 6 {
+                right = 5;
+            }
+            let upward = (right + 1) & 2 == 0;
+            for vert in 0..s {
+                let y = if upward { s - 1 - vert } else { vert };
+                for x in [right, right - 1] {
+                    if function[y * s + x] {
+                        continue;
+                    }
+                    let masked = match mask {
+                        0 => (x + y) % 2 == 0,
+                        1 => y % 2 == 0,
+                        2 => x % 3 == 0,
+                        3 => (x + y) % 3 == 0,
+                        4 => (x / 3 + y / 2) % 2 == 0,
+                        5 => x * y % 2 + x * y % 3 == 0,
+                        6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                        _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                    };
+                    byte = (byte << 1) | (self.get(x, y) ^ masked) as u8;
+                    bits += 1;
+                    if bits == 8 {
+                        codewords.push(byte);
+                        bits = 0;
+                    }
+                }
+            }
+            if right < 2 {
+                break;
+            }
+            right -= 2;
+        }
+
+        let ec = EC_CODEWORDS_PER_BLOCK[version - 1][level];
+        let blocks = BLOCKS[version - 1][level];
+        let data_total = codewords.len() - ec * blocks;
+        let short_len = data_total / blocks;
+        let long_blocks = data_total % blocks;
+        let data_len = |b: usize| if b >= blocks - long_blocks { short_len + 1 } else { short_len };
+
+        let mut interleaved = codewords.into_iter();
+        let mut block_data: Vec<Vec<u8>> = vec![Vec::new(); blocks];
+        for i in 0..=short_len {
+            for (b, block) in block_data.iter_mut().enumerate() {
+                if i < data_len(b) {
+                    block.extend(interleaved.next());
+                }
+            }
+        }
+        for _ in 0..ec {
+            for block in block_data.iter_mut() {
+                block.extend(interleaved.next());
+            }
+        }
+        let mut data = Vec::with_capacity(data_total);
+        for (b, block) in block_data.iter_mut().enumerate() {
+            correct_errors(block, ec).ok_or_else(|| Error::QrCodeError(format!(
+                "too many errors in block {} of a version {}-{} symbol", b + 1, version, EC_LEVELS[level]
+            )))?;
+            data.extend_from_slice(&block[..data_len(b)]);
+        }
+        read_segments(&data, version)
+    }
+}
+
+//------------------------------------------------------------------------------
+// Reed-Solomon error correction over GF(2^8) with the polynomial 0x11D
+
+lazy_static! {
+    static ref GF_EXP: [u8; 512] = {
+        let mut exp = [0u8; 512];
+        let mut x: u16 = 1;
+        for e in exp.iter_mut() {
+            *e = x as u8;
+            x <<= 1;
+            if x & 0x100 != 0 {
+                x ^= 0x11D;
+            }
+        }
+        exp
+    };
+    static ref GF_LOG: [usize; 256] = {
+        let mut log = [0usize; 256];
+        for i in 0..255 {
+            log[GF_EXP[i] as usize] = i;
+        }
+        log
+    };
+}
+
+fn gf_mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 { 0 } else { GF_EXP[GF_LOG[a as usize] + GF_LOG[b as usize]] }
+}
+
+fn gf_div(a: u8, b: u8) -> u8 {
+    if a == 0 { 0 } else { GF_EXP[GF_LOG[a as usize] + 255 - GF_LOG[b as usize]] }
+}
+
+/// Evaluates a polynomial whose coefficients are in ascending order.
+fn poly_eval(poly: &[u8], x: u8) -> u8 {
+    poly.iter().rev().fold(0, |acc, &c| gf_mul(acc, x) ^ c)
+}
+
+/// Corrects a block of data followed by `ec` error correction codewords in
+/// place, returning the number of corrected codewords.
+fn correct_errors(block: &mut [u8], ec: usize) -> Option<usize> {
+    let n = block.len();
+    let syndromes: Vec<u8> = (0..ec)
+        .map(|j| block.iter().fold(0, |acc, &c| gf_mul(acc, GF_EXP[j]) ^ c))
+        .collect();
+    if syndromes.iter().all(|&s| s == 0) {
+        return Some(0);
+    }
+
+    // Berlekamp-Massey
+    let (mut lambda, mut prev) = (vec![1u8], vec![1u8]);
+    let (mut errors, mut shift, mut prev_delta) = (0, 1, 1u8);
+    for r in 0..ec {
+        let delta = (1..=errors).fold(syndromes[r], |acc, i| {
+            acc ^ gf_mul(*lambda.get(i).unwrap_or(&0), syndromes[r - i])
+        });
+        if delta == 0 {
+            shift += 1;
+            continue;
+        }
+        let coef = gf_div(delta, prev_delta);
+        let mut next = lambda.clone();
+        next.resize(next.len().max(prev.len() + shift), 0);
+        for (i, &p) in prev.iter().enumerate() {
+            next[i + shift] ^= gf_mul(coef, p);
+        }
+        if 2 * errors <= r {
+            prev = std::mem::replace(&mut lambda, next);
+            errors = r + 1 - errors;
+            prev_delta = delta;
+            shift = 1;
+        } else {
+            lambda = next;
+            shift += 1;
+        }
+    }
+    if errors * 2 > ec {
+        return None;
+    }
+
+    // Chien search and Forney's algorithm
+    let mut omega = vec![0u8; ec];
+    for (i, &l) in lambda.iter().enumerate() {
+        for (j, &s) in syndromes.iter().enumerate() {
+            if i + j < ec {
+                omega[i + j] ^= gf_mul(l, s);
+            }
+        }
+    }
+    let derivative: Vec<u8> = lambda.iter().enumerate().skip(1)
+        .map(|(i, &l)| if i % 2 == 1 { l } else { 0 })
+        .collect();
+    let mut corrected = 0;
+    for (k, codeword) in block.iter_mut().enumerate() {
+        let power = (n - 1 - k) % 255;
+        let x_inv = GF_EXP[255 - power];
+        if poly_eval(&lambda, x_inv) != 0 {
+            continue;
+        }
+        let denominator = poly_eval(&derivative, x_inv);
+        if denominator == 0 {
+            return None;
+        }
+        *codeword ^= gf_mul(GF_EXP[power], gf_div(poly_eval(&omega, x_inv), denominator));
+        corrected += 1;
+    }
+    if corrected != errors {
+        return None;
+    }
+    Some(corrected)
+}
+
+//------------------------------------------------------------------------------
+// Data segments
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl BitReader<'_> {
+    fn available(&self) -> usize {
+        self.data.len() * 8 - self.pos
+    }
+
+    fn read(&mut self, n: usize) -> Option<u32> {
+        if n > self.available() {
+            return None;
+        }
+        let mut value = 0;
+        for _ in 0..n {
+            let bit = (self.data[self.pos / 8] >> (7 - self.pos % 8)) & 1;
+            value = (value << 1) | bit as u32;
+            self.pos += 1;
+        }
+        Some(value)
+    }
+}
+
+const ALPHANUMERIC: &[u8; 45] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:";
+
+fn read_segments(data: &[u8], version: usize) -> Result<DecodedSymbol, Error> {
+    let truncated = || Error::QrCodeError("truncated data segment".to_string());
+    let group = if version <= 9 { 0 } else if version <= 26 { 1 } else { 2 };
+    let mut reader = BitReader {data, pos: 0};
+    let mut payload = Vec::new();
+    let mut structured_append = None;
+    while reader.available() >= 4 {
+        match reader.read(4).ok_or_else(truncated)? {
+            0 => break,
+            // Numeric
+            1 => {
+                let mut count = reader.read([10, 12, 14][group]).ok_or_else(truncated)? as usize;
+                while count > 0 {
+                    let digits = count.min(3);
+                    let value = reader.read([0, 4, 7, 10][digits]).ok_or_else(truncated)?;
+                    if value >= [1, 10, 100, 1000][digits] {
+                        return Err(Error::QrCodeError(format!("invalid {}-digit numeric value {}", digits, value)));
+                    }
+                    payload.extend(format!("{:0width$}", value, width = digits).bytes());
+                    count -= digits;
+                }
+            },
+            // Alphanumeric
+            2 => {
+                let mut count = reader.read([9, 11, 13][group]).ok_or_else(truncated)? as usize;
+                while count > 0 {
+                    let chars = count.min(2);
+                    let value = reader.read(if chars == 2 { 11 } else { 6 }).ok_or_else(truncated)? as usize;
+                    if value >= [1, 45, 45 * 45][chars] {
+                        return Err(Error::QrCodeError(format!("invalid {}-character alphanumeric value {}", chars, value)));
+                    }
+                    if chars == 2 {
+                        payload.push(ALPHANUMERIC[value / 45]);
+                    }
+                    payload.push(ALPHANUMERIC[value % 45]);
+                    count -= chars;
+                }
+            },
+            // Structured append
+            3 => {
+                let index = reader.read(4).ok_or_else(truncated)? as u8;
+                let total = reader.read(4).ok_or_else(truncated)? as u8 + 1;
+                let parity = reader.read(8).ok_or_else(truncated)? as u8;
+                structured_append = Some(StructuredAppend {index, total, parity});
+            },
+            // 8-bit byte
+            4 => {
+                let count = reader.read([8, 16, 16][group]).ok_or_else(truncated)?;
+                for _ in 0..count {
+                    payload.push(reader.read(8).ok_or_else(truncated)? as u8);
+                }
+            },
+            // FNC1 in the first position
+            5 => {},
+            // ECI, whose designator is ignored as Shift_JIS is assumed
+            7 => {
+                let first = reader.read(8).ok_or_else(truncated)?;
+                let rest = if first & 0x80 == 0 { 0 } else if first & 0x40 == 0 { 8 } else { 16 };
+                reader.read(rest).ok_or_else(truncated)?;
+            },
+            // Kanji, converted back to Shift_JIS bytes
+            8 => {
+                let count = reader.read([8, 10, 12][group]).ok_or_else(truncated)?;
+                for _ in 0..count {
+                    let value = reader.read(13).ok_or_else(truncated)?;
+                    let code = ((value / 0xC0) << 8) | (value % 0xC0);
+                    let code = code + if code < 0x1F00 { 0x8140 } else { 0xC140 };
+                    payload.extend([(code >> 8) as u8, code as u8]);
+                }
+            },
+            // FNC1 in the second position
+            9 => {
+                reader.read(8).ok_or_else(truncated)?;
+            },
+            mode => return Err(Error::QrCodeError(format!("unsupported mode indicator {:04b}", mode))),
+        }
+    }
+    Ok(DecodedSymbol {payload, structured_append})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "qr")]
+    use crate::{ErrorCorrectionLevel, MedicineNotebook, QrEncoder, QrSymbol};
+    #[cfg(feature = "qr")]
+    use crate::test_util::SAMPLE;
+
+    /// Packs fields of (number of bits, value) into bytes, padding the last
+    /// byte with zeros.
+    fn pack(fields: &[(usize, u32)]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut pos = 0;
+        for &(n, value) in fields {
+            for i in (0..n).rev() {
+                if pos % 8 == 0 {
+                    bytes.push(0);
+                }
+                bytes[pos / 8] |= (((value >> i) & 1) as u8) << (7 - pos % 8);
+                pos += 1;
+            }
+        }
+        bytes
+    }
+
+    /// Draws a symbol with modules of three pixels inside a quiet zone of
+    /// four modules, inverting the modules for which `flip` holds.
+    #[cfg(feature = "qr")]
+    fn render(symbol: &QrSymbol, flip: impl Fn(usize, usize) -> bool) -> Bitmap {
+        let (scale, quiet, width) = (3, 4, symbol.width());
+        let size = (width + quiet * 2) * scale;
+        let mut dark = vec![false; size * size];
+        for y in 0..size {
+            for x in 0..size {
+                let (mx, my) = ((x / scale).wrapping_sub(quiet), (y / scale).wrapping_sub(quiet));
+                if mx < width && my < width {
+                    dark[y * size + x] = symbol.is_dark(mx, my) ^ flip(mx, my);
+                }
+            }
+        }
+        Bitmap {width: size, height: size, dark}
+    }
+
+    #[test]
+    fn reads_numeric_and_alphanumeric_segments() {
+        let data = pack(&[
+            (4, 1), (10, 5), (10, 123), (7, 45),
+            (4, 2), (9, 3), (11, 10 * 45 + 11), (6, 36),
+            (4, 0),
+        ]);
+        assert_eq!(read_segments(&data, 1).unwrap().payload, b"12345AB ");
+    }
+
+    #[test]
+    fn rejects_values_out_of_range() {
+        for fields in [
+            [(4, 1), (10, 3), (10, 1000)],
+            [(4, 1), (10, 2), (7, 100)],
+            [(4, 1), (10, 1), (4, 10)],
+            [(4, 2), (9, 2), (11, 45 * 45)],
+            [(4, 2), (9, 1), (6, 45)],
+        ] {
+            assert!(matches!(read_segments(&pack(&fields), 1), Err(Error::QrCodeError(_))), "{:?}", fields);
+        }
+    }
+
+    #[test]
+    fn reads_structured_append_header() {
+        let data = pack(&[(4, 3), (4, 1), (4, 2), (8, 0x5A), (4, 4), (8, 2), (8, b'O' as u32), (8, b'K' as u32), (4, 0)]);
+        let symbol = read_segments(&data, 1).unwrap();
+        assert_eq!(symbol.payload, b"OK");
+        assert_eq!(symbol.structured_append, Some(StructuredAppend {index: 1, total: 3, parity: 0x5A}));
+    }
+
+    #[test]
+    fn corrects_errors_of_a_block() {
+        // "Hello" as a version 1-L symbol, whose block has 7 error correction codewords
+        let data = [0x40, 0x54, 0x86, 0x56, 0xC6, 0xC6, 0xF0, 0xEC, 0x11, 0xEC, 0x11, 0xEC, 0x11, 0xEC,
+            0x11, 0xEC, 0x11, 0xEC, 0x11];
+        let mut block = data.to_vec();
+        block.extend(encode_ec(&data, 7));
+        let original = block.clone();
+        assert_eq!(correct_errors(&mut block, 7), Some(0));
+
+        for (i, c) in [(0, 0xFF), (9, 0x01), (22, 0x80)] {
+            block[i] ^= c;
+        }
+        assert_eq!(correct_errors(&mut block, 7), Some(3));
+        assert_eq!(block, original);
+
+        for i in [1, 3, 5, 7] {
+            block[i] ^= 0x55;
+        }
+        assert_eq!(correct_errors(&mut block, 7), None);
+    }
+
+    /// Computes the error correction codewords of a block.
+    fn encode_ec(data: &[u8], ec: usize) -> Vec<u8> {
+        let mut generator = vec![1u8];
+        for i in 0..ec {
+            let mut next = vec![0u8; generator.len() + 1];
+            for (j, &g) in generator.iter().enumerate() {
+                next[j] ^= g;
+                next[j + 1] ^= gf_mul(g, GF_EXP[i]);
+            }
+            generator = next;
+        }
+        let mut remainder = vec![0u8; ec];
+        for &d in data {
+            let factor = d ^ remainder[0];
+            remainder.remove(0);
+            remainder.push(0);
+            for (r, &g) in remainder.iter_mut().zip(&generator[1..]) {
+                *r ^= gf_mul(g, factor);
+            }
+        }
+        remainder
+    }
+
+    #[cfg(feature = "qr")]
+    #[test]
+    fn decodes_symbols_of_each_level() {
+        let notebook: MedicineNotebook = SAMPLE.parse().unwrap();
+        let mut versions = HashSet::new();
+        for level in [ErrorCorrectionLevel::Low, ErrorCorrectionLevel::Medium,
+                ErrorCorrectionLevel::Quartile, ErrorCorrectionLevel::High] {
+            let symbols = QrEncoder::new().error_correction(level).encode_notebook(&notebook).unwrap();
+            assert_eq!(symbols.len(), 1);
+            let (decoded, errors) = decode_image(&symbols[0].to_png(4).unwrap()).unwrap();
+            assert!(errors.is_empty(), "{}: {:?}", level, errors);
+            assert_eq!(decoded, vec![DecodedSymbol {payload: symbols[0].payload().to_vec(), structured_append: None}]);
+            versions.insert(symbols[0].version());
+        }
+        assert!(versions.len() >= 3, "{:?}", versions);
+    }
+
+    #[cfg(feature = "qr")]
+    #[test]
+    fn decodes_symbols_of_several_versions() {
+        let notebook: MedicineNotebook = SAMPLE.parse().unwrap();
+        let encoder = QrEncoder::new().max_version(8).data_id("20200401000000");
+        let symbols = encoder.encode_notebook(&notebook).unwrap();
+        assert!(symbols.len() > 1);
+        let small = MedicineNotebook {dispensing_information: Vec::new(), .. notebook};
+        let small = QrEncoder::new().error_correction(ErrorCorrectionLevel::Low).encode_notebook(&small).unwrap();
+
+        let mut versions = HashSet::new();
+        for symbol in symbols.iter().chain(&small) {
+            let (decoded, errors) = decode_bitmap(&render(symbol, |_, _| false));
+            assert!(errors.is_empty(), "version {}: {:?}", symbol.version(), errors);
+            assert_eq!(decoded.len(), 1);
+            assert_eq!(decoded[0].payload, symbol.payload());
+            versions.insert(symbol.version());
+        }
+        assert!(versions.len() >= 2, "{:?}", versions);
+    }
+
+    #[cfg(feature = "qr")]
+    #[test]
+    fn corrects_corrupted_codewords() {
+        let notebook: MedicineNotebook = SAMPLE.parse().unwrap();
+        let symbols = QrEncoder::new().error_correction(ErrorCorrectionLevel::Low).encode_notebook(&notebook).unwrap();
+        let symbol = &symbols[0];
+        let w = symbol.width();
+
+        // The first codeword lies in the two columns and four rows at the bottom right.
+        let (decoded, errors) = decode_bitmap(&render(symbol, |x, y| x >= w - 2 && y >= w - 4));
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(decoded[0].payload, symbol.payload());
+
+        // A quarter of the modules is more than the 7% of level L can restore.
+        let grid = Grid {
+            size: w,
+            modules: (0..w * w).map(|i| symbol.is_dark(i % w, i / w) ^ (i % w >= w / 2 && i / w >= w / 2)).collect(),
+        };
+        match grid.decode() {
+            Err(Error::QrCodeError(message)) => assert!(message.starts_with("too many errors"), "{}", message),
+            result => panic!("{:?}", result),
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+use crate::jahis::*;
+use crate::reader::MedicineNotebookReader;
+use crate::writer::MedicineNotebookWriter;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+/// Text encoding of serialized notebook data
+pub enum TextEncoding {
+    #[default]
+    Utf8, // UTF-8
+    ShiftJis, // Shift_JIS (CP932)
+}
+
+impl TextEncoding {
+    pub fn encode<'a>(&self, s: &'a str) -> Result<Cow<'a, [u8]>, Error> {
+        match *self {
+            Self::Utf8 => Ok(Cow::Borrowed(s.as_bytes())),
+            Self::ShiftJis => {
+                let (bytes, _, had_errors) = encoding_rs::SHIFT_JIS.encode(s);
+                if had_errors {
+                    Err(Error::EncodingError(
+                        format!("Cannot encode str in Shift_JIS, got \"{}\"", s)
+                    ))
+                } else {
+                    Ok(bytes)
+                }
+            },
+        }
+    }
+
+    pub fn decode<'a>(&self, bytes: &'a [u8]) -> Result<Cow<'a, str>, Error> {
+        match *self {
+            Self::Utf8 => std::str::from_utf8(bytes)
+                .map(Cow::Borrowed)
+                .map_err(|e| Error::EncodingError(
+                    format!("Cannot decode bytes as UTF-8: {}", e)
+                )),
+            Self::ShiftJis => encoding_rs::SHIFT_JIS
+                .decode_without_bom_handling_and_without_replacement(bytes)
+                .ok_or_else(|| Error::EncodingError(
+                    "Cannot decode bytes as Shift_JIS".to_string()
+                )),
+        }
+    }
+
+    /// Decodes bytes, replacing malformed sequences with U+FFFD.
+    pub fn decode_lossy<'a>(&self, bytes: &'a [u8]) -> Cow<'a, str> {
+        match *self {
+            Self::Utf8 => String::from_utf8_lossy(bytes),
+            Self::ShiftJis => encoding_rs::SHIFT_JIS.decode_without_bom_handling(bytes).0,
+        }
+    }
+
+    /// Encodes a record line, naming the field which holds the first
+    /// character that cannot be represented in this encoding.
+    pub fn encode_record_line<'a>(&self, line: &'a str) -> Result<Cow<'a, [u8]>, Error> {
+        self.encode(line).map_err(|e| self.locate_unmappable(line).unwrap_or(e))
+    }
+
+    fn locate_unmappable(&self, line: &str) -> Option<Error> {
+        let mut cols = line.split(',');
+        let record_number: u32 = cols.next()?.parse().ok()?;
+        let names = field_names(record_number)?;
+        for (i, col) in cols.enumerate() {
+            if self.encode(col).is_err() {
+                let mut buf = [0; 4];
+                let character = col.chars()
+                    .find(|c| self.encode(c.encode_utf8(&mut buf)).is_err())?;
+                return Some(Error::UnmappableCharacter {
+                    record_number,
+                    field_name: names.get(i).copied().unwrap_or("追加項目"),
+                    character,
+                });
+            }
+        }
+        None
+    }
+}
+
+/// Returns the length of a string in Shift_JIS (CP932) bytes, counting a
+/// character which cannot be encoded as two bytes.
+pub fn cp932_len(s: &str) -> usize {
+    match encoding_rs::SHIFT_JIS.encode(s) {
+        (bytes, _, false) => bytes.len(),
+        _ => s.chars().map(|c| {
+            let mut buf = [0; 4];
+            match encoding_rs::SHIFT_JIS.encode(c.encode_utf8(&mut buf)) {
+                (bytes, _, false) => bytes.len(),
+                _ => 2,
+            }
+        }).sum(),
+    }
+}
+
+/// Checks the fields of a record line against `field_max_bytes`, returning
+/// an error for every field over its limit.
+pub fn field_length_errors(line: &str) -> Vec<Error> {
+    let mut cols = line.split(',');
+    let record_number: u32 = match cols.next().map(|s| s.parse()) {
+        Some(Ok(n)) => n,
+        _ => return Vec::new(),
+    };
+    let (limits, names) = match (field_max_bytes(record_number), field_names(record_number)) {
+        (Some(limits), Some(names)) => (limits, names),
+        _ => return Vec::new(),
+    };
+    cols.zip(limits.iter().zip(names))
+        .filter_map(|(col, (&max_bytes, &field_name))| {
+            let bytes = cp932_len(col);
+            if bytes > max_bytes {
+                Some(Error::FieldTooLong {record_number, field_name, max_bytes, bytes})
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+impl fmt::Display for TextEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Utf8 => write!(f, "UTF-8"),
+            Self::ShiftJis => write!(f, "Shift_JIS"),
+        }
+    }
+}
+
+impl FromStr for TextEncoding {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "UTF-8" | "utf-8" | "UTF8" | "utf8" => Ok(Self::Utf8),
+            "Shift_JIS" | "shift_jis" | "SJIS" | "sjis" | "CP932" | "cp932"
+                | "Windows-31J" | "windows-31j" => Ok(Self::ShiftJis),
+            _ => Err(Error::InvalidArgument(
+                format!("Cannot convert str to TextEncoding, got \"{}\"", s)
+            )),
+        }
+    }
+}
+
+impl MedicineNotebook {
+    pub fn from_bytes(bytes: &[u8], encoding: TextEncoding) -> Result<Self, Error> {
+        MedicineNotebookReader::with_encoding(bytes, encoding)?.into_notebook()
+    }
+
+    pub fn from_sjis_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        Self::from_bytes(bytes, TextEncoding::ShiftJis)
+    }
+
+    pub fn to_bytes(&self, encoding: TextEncoding) -> Result<Vec<u8>, Error> {
+        let mut writer = MedicineNotebookWriter::new(Vec::new()).encoding(encoding);
+        writer.write_notebook(self)?;
+        writer.finish()
+    }
+
+    pub fn to_sjis_bytes(&self) -> Result<Vec<u8>, Error> {
+        self.to_bytes(TextEncoding::ShiftJis)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::SAMPLE;
+
+    #[test]
+    fn round_trips_through_shift_jis() {
+        // ① is one of the NEC special characters of CP932.
+        let code = SAMPLE.replace("ロキソプロフェン錠60mg", "ロキソプロフェン錠60mg①");
+        let notebook: MedicineNotebook = code.parse().unwrap();
+        let bytes = notebook.to_sjis_bytes().unwrap();
+        assert_eq!(bytes, encoding_rs::SHIFT_JIS.encode(&code).0.into_owned());
+        assert_eq!(MedicineNotebook::from_sjis_bytes(&bytes).unwrap(), notebook);
+        assert!(MedicineNotebook::from_bytes(&bytes, TextEncoding::Utf8).is_err());
+    }
+
+    #[test]
+    fn names_the_field_of_an_unmappable_character() {
+        let mut notebook: MedicineNotebook = SAMPLE.parse().unwrap();
+        notebook.patient.name = "𠮷田太郎".to_string();
+        assert_eq!(notebook.to_sjis_bytes().unwrap_err(), Error::UnmappableCharacter {
+            record_number: 1,
+            field_name: "患者氏名",
+            character: '𠮷',
+        });
+        assert!(notebook.to_bytes(TextEncoding::Utf8).is_ok());
+
+        let mut notebook: MedicineNotebook = SAMPLE.parse().unwrap();
+        notebook.dispensing_information[0].prescriptions[0].rps[0].usage.name = "1日3回毎食後💊".to_string();
+        assert_eq!(notebook.to_sjis_bytes().unwrap_err(), Error::UnmappableCharacter {
+            record_number: 301,
+            field_name: "用法名称",
+            character: '💊',
+        });
+    }
+
+    #[test]
+    fn counts_shift_jis_bytes() {
+        assert_eq!(cp932_len("山田"), 4);
+        assert_eq!(cp932_len("ﾔﾏﾀﾞ"), 4);
+        assert_eq!(cp932_len("A𠮷"), 3);
+    }
+}
@@ -0,0 +1,151 @@
+use std::fmt;
+use std::sync::RwLock;
+use lazy_static::lazy_static;
+use chrono::NaiveDate;
+use crate::jahis::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// A Japanese era (元号)
+pub struct Era {
+    pub code: char, // 略号 (R)
+    pub name: String, // 元号名 (令和)
+    pub ligature: Option<char>, // 合字 (㋿)
+    pub start: NaiveDate, // 開始日
+}
+
+impl Era {
+    pub fn new(code: char, name: &str, ligature: Option<char>, start: NaiveDate) -> Self {
+        Self {code, name: name.to_string(), ligature, start}
+    }
+}
+
+impl fmt::Display for Era {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({}, from {})", self.name, self.code, self.start)
+    }
+}
+
+fn default_eras() -> Vec<Era> {
+    let ymd = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+    vec![
+        Era::new('M', "明治", Some('㍾'), ymd(1868, 10, 23)),
+        Era::new('T', "大正", Some('㍽'), ymd(1912, 7, 30)),
+        Era::new('S', "昭和", Some('㍼'), ymd(1926, 12, 25)),
+        Era::new('H', "平成", Some('㍻'), ymd(1989, 1, 8)),
+        Era::new('R', "令和", Some('㋿'), ymd(2019, 5, 1)),
+    ]
+}
+
+lazy_static! {
+    static ref ERAS: RwLock<Vec<Era>> = RwLock::new(default_eras());
+}
+
+/// Eras known to the library, in ascending order of their start dates.
+///
+/// Each era lasts until the day before the next one starts, and the last
+/// one has not ended.
+pub fn eras() -> Vec<Era> {
+    ERAS.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Adds an era which starts after the last known one, e.g. on the next
+/// 改元, so that dates in it are read and written without a new release.
+///
+/// The code must be an upper case ASCII letter, and neither the code, the
+/// name nor the ligature may be used by another era.
+pub fn register_era(era: Era) -> Result<(), Error> {
+    let mut eras = ERAS.write().unwrap_or_else(|e| e.into_inner());
+    if !era.code.is_ascii_uppercase() {
+        return Err(Error::InvalidArgument(
+            format!("Cannot use \"{}\" as the code of an era", era.code)
+        ));
+    }
+    if era.name.is_empty() {
+        return Err(Error::InvalidArgument("Cannot register an era without a name".to_string()));
+    }
+    if let Some(other) = eras.iter().find(|e| e.code == era.code || e.name == era.name
+            || era.ligature.is_some() && e.ligature == era.ligature) {
+        return Err(Error::InvalidArgument(
+            format!("Cannot register {}, which conflicts with {}", era, other)
+        ));
+    }
+    if let Some(last) = eras.last().filter(|last| last.start >= era.start) {
+        return Err(Error::InvalidArgument(
+            format!("Cannot register {}, which does not start after {}", era, last)
+        ));
+    }
+    eras.push(era);
+    Ok(())
+}
+
+/// Returns the era of a code, e.g. 'R', ignoring case.
+pub fn era_by_code(code: char) -> Option<Era> {
+    let code = code.to_ascii_uppercase();
+    ERAS.read().unwrap_or_else(|e| e.into_inner()).iter().find(|e| e.code == code).cloned()
+}
+
+/// Returns the era of a name such as "令和" or a ligature such as "㋿".
+pub fn era_by_name(name: &str) -> Option<Era> {
+    ERAS.read().unwrap_or_else(|e| e.into_inner()).iter()
+        .find(|e| e.name == name || e.ligature.is_some_and(|l| name.chars().eq(std::iter::once(l))))
+        .cloned()
+}
+
+/// Returns the era a day belongs to, if it is not before the first one.
+pub fn era_of(date: NaiveDate) -> Option<Era> {
+    ERAS.read().unwrap_or_else(|e| e.into_inner()).iter().rev().find(|e| e.start <= date).cloned()
+}
+
+/// Returns the last day of an era, or `None` if it has not ended.
+pub fn era_end(era: &Era) -> Option<NaiveDate> {
+    ERAS.read().unwrap_or_else(|e| e.into_inner()).iter()
+        .find(|e| e.start > era.start)
+        .and_then(|next| next.start.pred_opt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jahis::{Date, GengoYear};
+
+    fn ymd(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    // The eras are shared by the whole process, so the era registered here
+    // starts long after the dates of the other tests.
+    #[test]
+    fn registers_an_era_after_the_last_one() {
+        let era = Era::new('X', "試験", Some('㍿'), ymd(2100, 1, 1));
+        assert!("X010101".parse::<Date>().is_err());
+        assert!("R810101".parse::<Date>().is_ok());
+
+        for invalid in [
+            Era::new('x', "試験", None, ymd(2100, 1, 1)),
+            Era::new('X', "", None, ymd(2100, 1, 1)),
+            Era::new('R', "試験", None, ymd(2100, 1, 1)),
+            Era::new('X', "令和", None, ymd(2100, 1, 1)),
+            Era::new('X', "試験", Some('㋿'), ymd(2100, 1, 1)),
+            Era::new('X', "試験", None, ymd(2019, 5, 1)),
+        ] {
+            assert!(register_era(invalid).is_err());
+        }
+        assert_eq!(eras().len(), 5);
+
+        register_era(era.clone()).unwrap();
+        assert_eq!(eras().last(), Some(&era));
+        assert!(register_era(era.clone()).is_err());
+        assert_eq!(era_by_code('x'), Some(era.clone()));
+        assert_eq!(era_by_name("試験"), Some(era.clone()));
+        assert_eq!(era_by_name("㍿"), Some(era.clone()));
+        assert_eq!(era_of(ymd(2100, 1, 1)), Some(era.clone()));
+        assert_eq!(era_end(&era), None);
+        assert_eq!(era_end(&era_by_code('R').unwrap()), Some(ymd(2099, 12, 31)));
+
+        let date: Date = "X010101".parse().unwrap();
+        assert_eq!(date.first_day(), Ok(ymd(2100, 1, 1)));
+        assert_eq!(Date::from(ymd(2100, 1, 2)).to_wareki(), Date::new_wareki(GengoYear::new('X', 1), 1, 2));
+        assert!("R811231".parse::<Date>().is_ok());
+        assert!("R820101".parse::<Date>().is_err());
+    }
+}
@@ -0,0 +1,102 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+use crate::jahis::*;
+
+/// Characters which cannot appear in a field, as they separate fields and
+/// record lines
+pub const FORBIDDEN_CHARACTERS: [char; 3] = [',', '\r', '\n'];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+/// How commas and line breaks in free-text fields are handled
+pub enum EscapePolicy {
+    #[default]
+    Reject, // エラーとする
+    FullWidth, // 全角文字に置き換える
+}
+
+impl EscapePolicy {
+    /// Escapes the value of a field. `FullWidth` replaces a comma with a
+    /// full-width comma and a line break with a full-width space, while
+    /// `Reject` returns the first forbidden character.
+    pub fn escape<'a>(&self, s: &'a str) -> Result<Cow<'a, str>, char> {
+        let character = match s.chars().find(|c| FORBIDDEN_CHARACTERS.contains(c)) {
+            Some(c) => c,
+            None => return Ok(Cow::Borrowed(s)),
+        };
+        match *self {
+            Self::Reject => Err(character),
+            Self::FullWidth => Ok(Cow::Owned(
+                s.replace("\r\n", "\n")
+                    .replace(',', "，")
+                    .replace(['\r', '\n'], "　")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for EscapePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Reject => write!(f, "reject"),
+            Self::FullWidth => write!(f, "full-width"),
+        }
+    }
+}
+
+impl FromStr for EscapePolicy {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" | "Reject" => Ok(Self::Reject),
+            "full-width" | "FullWidth" | "fullwidth" => Ok(Self::FullWidth),
+            _ => Err(Error::InvalidArgument(
+                format!("Cannot convert str to EscapePolicy, got \"{}\"", s)
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer::MedicineNotebookWriter;
+    use crate::test_util::SAMPLE;
+
+    #[test]
+    fn escapes_forbidden_characters() {
+        assert_eq!(EscapePolicy::Reject.escape("メモ"), Ok(Cow::Borrowed("メモ")));
+        assert_eq!(EscapePolicy::Reject.escape("朝,夕\r\n"), Err(','));
+        assert_eq!(EscapePolicy::Reject.escape("朝\n夕"), Err('\n'));
+        assert_eq!(EscapePolicy::FullWidth.escape("朝,夕\r\n就寝前\r"),
+            Ok(Cow::Owned("朝，夕　就寝前　".to_string())));
+    }
+
+    #[test]
+    fn writes_an_escaped_memo_which_reads_back() {
+        let mut notebook: MedicineNotebook = SAMPLE.parse().unwrap();
+        notebook.memos.push(MemoRecord {
+            content: "朝,夕\r\n就寝前".to_string(),
+            created_at: None,
+            created_by: RecordCreator::Patient,
+            extra: Vec::new(),
+        });
+        assert_eq!(notebook.to_code_with(EscapePolicy::Reject), Err(Error::ForbiddenCharacter {
+            record_number: 4,
+            field_name: "手帳メモ情報",
+            character: ',',
+        }));
+        let mut writer = MedicineNotebookWriter::new(Vec::new());
+        assert!(writer.write_notebook(&notebook).is_err());
+
+        let code = notebook.to_code_with(EscapePolicy::FullWidth).unwrap();
+        assert!(code.contains("\r\n4,朝，夕　就寝前,,2\r\n"));
+        let mut writer = MedicineNotebookWriter::new(Vec::new()).escape_policy(EscapePolicy::FullWidth);
+        writer.write_notebook(&notebook).unwrap();
+        assert_eq!(String::from_utf8(writer.finish().unwrap()).unwrap(), code);
+
+        let read: MedicineNotebook = code.parse().unwrap();
+        assert_eq!(read.memos[1].content, "朝，夕　就寝前");
+        assert_eq!(read.dispensing_information, notebook.dispensing_information);
+    }
+}
@@ -0,0 +1,1152 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_json::{json, Value};
+use uuid::Uuid;
+use crate::jahis::*;
+
+// https://jpfhir.jp/fhir/core/
+const PROFILE_BASE: &str = "http://jpfhir.jp/fhir/core/StructureDefinition/";
+const EXTENSION_BASE: &str = "http://jpfhir.jp/fhir/core/Extension/StructureDefinition/";
+
+/// Code systems of the drug codes, by `DrugCodeType`
+pub const DRUG_CODE_SYSTEM_RECEIPT: &str = "urn:oid:1.2.392.100495.20.2.71"; // レセプト電算処理システム用コード
+pub const DRUG_CODE_SYSTEM_MHLW: &str = "urn:oid:1.2.392.100495.20.2.73"; // 薬価基準収載医薬品コード
+pub const DRUG_CODE_SYSTEM_YJ: &str = "urn:oid:1.2.392.100495.20.2.74"; // 個別医薬品コード (YJコード)
+pub const DRUG_CODE_SYSTEM_HOT: &str = "urn:oid:1.2.392.200119.4.403.1"; // HOTコード
+/// Code system of the JAMI usage codes (JAMI用法コード)
+pub const USAGE_CODE_SYSTEM_JAMI: &str = "urn:oid:1.2.392.200250.2.2.20.20";
+/// Identifier system of the RP numbers (剤グループ番号)
+pub const RP_NUMBER_SYSTEM: &str = "urn:oid:1.2.392.100495.20.3.81";
+/// Identifier system of the 10 digit codes of medical institutions and
+/// pharmacies (都道府県番号, 点数表番号 and 医療機関等コード)
+pub const INSTITUTION_NUMBER_SYSTEM: &str = "http://jpfhir.jp/fhir/core/IdSystem/insurance-medical-institution-no";
+const PREFECTURE_SYSTEM: &str = "urn:oid:1.2.392.100495.20.3.21";
+const FEE_TABLE_SYSTEM: &str = "urn:oid:1.2.392.100495.20.3.22";
+const INSTITUTION_CODE_SYSTEM: &str = "urn:oid:1.2.392.100495.20.3.23";
+const NAME_REPRESENTATION: &str = "http://hl7.org/fhir/StructureDefinition/iso21090-EN-representation";
+
+impl DrugCodeType {
+    /// FHIR code system of the drug codes, or `None` for `DrugCodeType::None`.
+    pub fn fhir_system(&self) -> Option<&'static str> {
+        match *self {
+            Self::None => None,
+            Self::Receipt => Some(DRUG_CODE_SYSTEM_RECEIPT),
+            Self::Mhlw => Some(DRUG_CODE_SYSTEM_MHLW),
+            Self::Yj => Some(DRUG_CODE_SYSTEM_YJ),
+            Self::Hot => Some(DRUG_CODE_SYSTEM_HOT),
+        }
+    }
+}
+
+/// Collects the resources of a bundle, giving each one an ID of its type and
+/// its position, and a full URL of a random UUID which references point to.
+struct BundleBuilder {
+    entries: Vec<(String, Value)>,
+}
+
+impl BundleBuilder {
+    fn new() -> Self {
+        Self {entries: Vec::new()}
+    }
+
+    /// Adds a resource without `id` and returns a reference to it.
+    fn add(&mut self, resource_type: &str, mut resource: Value) -> Value {
+        let n = self.entries.iter().filter(|(_, r)| r["resourceType"] == resource_type).count();
+        resource["resourceType"] = json!(resource_type);
+        resource["id"] = json!(format!("{}-{}", resource_type.to_lowercase(), n + 1));
+        let full_url = format!("urn:uuid:{}", Uuid::new_v4());
+        self.entries.push((full_url.clone(), resource));
+        json!({"reference": full_url})
+    }
+
+    /// Adds a resource unless the same one has been added, so that a
+    /// pharmacy dispensing several times appears once.
+    fn share(&mut self, resource_type: &str, resource: Value) -> Value {
+        let same = self.entries.iter().find(|(_, r)| {
+            let mut r = r.clone();
+            if let Some(r) = r.as_object_mut() {
+                r.remove("id");
+                r.remove("resourceType").is_some_and(|t| t == resource_type)
+                    && *r == resource.as_object().cloned().unwrap_or_default()
+            } else {
+                false
+            }
+        });
+        match same {
+            Some((full_url, _)) => json!({"reference": full_url}),
+            None => self.add(resource_type, resource),
+        }
+    }
+
+    fn build(self) -> Value {
+        let entry: Vec<Value> = self.entries.into_iter()
+            .map(|(full_url, resource)| json!({"fullUrl": full_url, "resource": resource}))
+            .collect();
+        json!({
+            "resourceType": "Bundle",
+            "type": "collection",
+            "entry": entry,
+        })
+    }
+}
+
+fn profile(name: &str) -> Value {
+    json!({"profile": [format!("{}{}", PROFILE_BASE, name)]})
+}
+
+/// Formats a date as a FHIR date, which is YYYY-MM-DD, YYYY-MM or YYYY.
+pub fn fhir_date(date: &Date) -> Result<String, Error> {
+    let first = date.first_day()?;
+    Ok(match date.precision() {
+        DatePrecision::Day => first.format("%Y-%m-%d").to_string(),
+        DatePrecision::Month => first.format("%Y-%m").to_string(),
+        DatePrecision::Year => first.format("%Y").to_string(),
+    })
+}
+
+/// Parses a number written in a field such as 用量, which may be written in
+/// full-width digits.
+fn decimal(s: &str) -> Option<f64> {
+    let s: String = s.trim().chars()
+        .map(|c| match c {
+            '０'..='９' | '．' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+            c => c,
+        })
+        .collect();
+    s.parse().ok()
+}
+
+fn telecom(value: Option<&String>) -> Option<Value> {
+    value.map(|v| json!([{"system": "phone", "value": v}]))
+}
+
+fn insert_some(resource: &mut Value, key: &str, value: Option<Value>) {
+    if let Some(value) = value {
+        resource[key] = value;
+    }
+}
+
+fn patient_resource(record: &PatientRecord) -> Result<Value, Error> {
+    let mut names = vec![json!({
+        "extension": [{"url": NAME_REPRESENTATION, "valueCode": "IDE"}],
+        "use": "official",
+        "text": record.name,
+    })];
+    if let Some(kana) = &record.name_in_kana {
+        names.push(json!({
+            "extension": [{"url": NAME_REPRESENTATION, "valueCode": "SYL"}],
+            "use": "official",
+            "text": kana,
+        }));
+    }
+    let mut resource = json!({
+        "meta": profile("JP_Patient"),
+        "name": names,
+        "gender": match record.gender {
+            Gender::Male => "male",
+            Gender::Female => "female",
+        },
+        "birthDate": fhir_date(&record.day_of_birth)?,
+    });
+    insert_some(&mut resource, "telecom", telecom(record.telephone.as_ref()));
+    if record.address.is_some() || record.zip_code.is_some() {
+        let mut address = json!({});
+        insert_some(&mut address, "text", record.address.as_ref().map(|a| json!(a)));
+        insert_some(&mut address, "postalCode", record.zip_code.as_ref().map(|z| json!(z)));
+        resource["address"] = json!([address]);
+    }
+    Ok(resource)
+}
+
+fn organization_resource(name: &str, prefecture: Option<Prefecture>, fee_table: Option<FeeTable>,
+        institution_code: Option<&String>) -> Value {
+    let mut extensions: Vec<Value> = Vec::new();
+    if let Some(prefecture) = prefecture {
+        extensions.push(json!({
+            "url": format!("{}JP_Organization_PrefectureNo", EXTENSION_BASE),
+            "valueCoding": {"system": PREFECTURE_SYSTEM, "code": prefecture.to_code(), "display": prefecture.to_string()},
+        }));
+    }
+    if let Some(fee_table) = fee_table {
+        extensions.push(json!({
+            "url": format!("{}JP_Organization_InsuranceOrganizationCategory", EXTENSION_BASE),
+            "valueCoding": {"system": FEE_TABLE_SYSTEM, "code": fee_table.to_code(), "display": fee_table.to_string()},
+        }));
+    }
+    if let Some(code) = institution_code {
+        extensions.push(json!({
+            "url": format!("{}JP_Organization_InsuranceOrganizationNo", EXTENSION_BASE),
+            "valueIdentifier": {"system": INSTITUTION_CODE_SYSTEM, "value": code},
+        }));
+    }
+    let mut resource = json!({
+        "meta": profile("JP_Organization"),
+        "name": name,
+    });
+    if !extensions.is_empty() {
+        resource["extension"] = json!(extensions);
+    }
+    if let (Some(prefecture), Some(fee_table), Some(code)) = (prefecture, fee_table, institution_code) {
+        resource["identifier"] = json!([{
+            "system": INSTITUTION_NUMBER_SYSTEM,
+            "value": format!("{}{}{}", prefecture.to_code(), fee_table.to_code(), code),
+        }]);
+    }
+    resource
+}
+
+fn pharmacy_resource(record: &PharmacyRecord) -> Value {
+    let mut resource = organization_resource(&record.name, record.prefecture, record.fee_table,
+        record.institution_code.as_ref());
+    insert_some(&mut resource, "telecom", telecom(record.telephone.as_ref()));
+    if record.address.is_some() || record.zip_code.is_some() {
+        let mut address = json!({});
+        insert_some(&mut address, "text", record.address.as_ref().map(|a| json!(a)));
+        insert_some(&mut address, "postalCode", record.zip_code.as_ref().map(|z| json!(z)));
+        resource["address"] = json!([address]);
+    }
+    resource
+}
+
+fn practitioner_resource(name: &str, contact_information: Option<&String>) -> Value {
+    let mut resource = json!({
+        "meta": profile("JP_Practitioner"),
+        "name": [{
+            "extension": [{"url": NAME_REPRESENTATION, "valueCode": "IDE"}],
+            "use": "official",
+            "text": name,
+        }],
+    });
+    insert_some(&mut resource, "telecom", telecom(contact_information));
+    resource
+}
+
+fn medication(record: &DrugRecord) -> Value {
+    let mut medication = json!({"text": record.name});
+    if let (Some(system), Some(code)) = (record.drug_code_type.fhir_system(), &record.drug_code) {
+        medication["coding"] = json!([{"system": system, "code": code, "display": record.name}]);
+    }
+    medication
+}
+
+/// Quantity of days in UCUM, as a number of 日分.
+fn days(quantity: u32) -> Value {
+    json!({"value": quantity, "unit": "日", "system": "http://unitsofmeasure.org", "code": "d"})
+}
+
+/// Dosage of the RP, with the daily dose of the drug if given.
+fn dosage_instruction(drug: Option<&DrugRecord>, rp: &RpBlock) -> Value {
+    let usage = &rp.usage;
+    let mut text = usage.name.clone();
+    for record in &rp.usage_supplementary {
+        text.push(' ');
+        text.push_str(&record.content);
+    }
+    let mut timing = json!({"text": usage.name});
+    if let (Some(UsageCodeType::Jami), Some(code)) = (usage.usage_code_type, &usage.usage_code) {
+        timing["coding"] = json!([{"system": USAGE_CODE_SYSTEM_JAMI, "code": code, "display": usage.name}]);
+    }
+    let mut dosage = json!({
+        "text": text,
+        "timing": {"code": timing},
+    });
+    if !rp.usage_supplementary.is_empty() {
+        let instructions: Vec<Value> = rp.usage_supplementary.iter().map(|r| json!({"text": r.content})).collect();
+        dosage["additionalInstruction"] = json!(instructions);
+    }
+    if let Some(drug) = drug {
+        let dose = match decimal(&drug.dosage) {
+            Some(value) => json!({"value": value, "unit": drug.unit}),
+            None => json!({"unit": format!("{}{}", drug.dosage, drug.unit)}),
+        };
+        dosage["doseAndRate"] = json!([{"type": {"text": "1日量"}, "doseQuantity": dose}]);
+    }
+    json!([dosage])
+}
+
+impl MedicineNotebook {
+    /// Converts the notebook to a FHIR R4 Bundle of type collection, whose
+    /// resources follow the JP Core profiles.
+    ///
+    /// The patient record becomes a Patient, pharmacies and medical
+    /// institutions become Organizations, and pharmacists and physicians
+    /// become Practitioners, with a PractitionerRole holding the specialty of
+    /// a physician. Each RP becomes a MedicationRequest, requested by the
+    /// PractitionerRole of the physician at the medical institution, or by
+    /// the institution itself, and each of its drugs a MedicationDispense
+    /// which the request authorizes, whose dosage is taken from the usage
+    /// record of the RP. Allergy and adverse event notes become
+    /// AllergyIntolerances.
+    ///
+    /// Every entry has a full URL of `urn:uuid:`, which resources refer to
+    /// each other by, and the performer of a MedicationDispense is
+    /// a PractitionerRole of the pharmacist at the pharmacy. A physician or
+    /// an institution of a block without RPs is left out, as nothing would
+    /// refer to it. Fails if a date cannot be converted to seireki.
+    pub fn to_fhir_bundle(&self) -> Result<Value, Error> {
+        let mut bundle = BundleBuilder::new();
+        let patient = bundle.add("Patient", patient_resource(&self.patient)?);
+
+        for note in &self.special_patient_notes {
+            let kind = match note.category {
+                SpecialPatientNoteCategory::Allergy => "allergy",
+                SpecialPatientNoteCategory::AdverseEvent => "intolerance",
+                _ => continue,
+            };
+            bundle.add("AllergyIntolerance", json!({
+                "meta": profile("JP_AllergyIntolerance"),
+                "type": kind,
+                "code": {"text": note.content},
+                "patient": patient,
+            }));
+        }
+
+        for block in &self.dispensing_information {
+            let date = fhir_date(&block.date.created_at)?;
+            let mut dispenser = json!({
+                "meta": profile("JP_PractitionerRole"),
+                "organization": bundle.share("Organization", pharmacy_resource(&block.pharmacy)),
+            });
+            insert_some(&mut dispenser, "practitioner", block.pharmacist.as_ref().map(|record| bundle.share("Practitioner",
+                practitioner_resource(&record.name, record.contact_information.as_ref()))));
+            let performers = json!([{"actor": bundle.share("PractitionerRole", dispenser)}]);
+            let institution = block.medical_institute.as_ref().map(|record|
+                organization_resource(&record.name, record.prefecture, record.fee_table, record.institution_code.as_ref()));
+
+            for prescription in &block.prescriptions {
+                if prescription.rps.is_empty() {
+                    continue;
+                }
+                let institution = institution.clone().map(|resource| bundle.share("Organization", resource));
+                let requester = match &prescription.physician {
+                    Some(record) => {
+                        let physician = bundle.share("Practitioner", practitioner_resource(&record.name, None));
+                        let mut role = json!({
+                            "meta": profile("JP_PractitionerRole"),
+                            "practitioner": physician,
+                        });
+                        insert_some(&mut role, "organization", institution);
+                        insert_some(&mut role, "specialty", record.specialty.as_ref().map(|s| json!([{"text": s}])));
+                        Some(bundle.share("PractitionerRole", role))
+                    },
+                    None => institution,
+                };
+
+                for rp in &prescription.rps {
+                    let identifier = json!([{"system": RP_NUMBER_SYSTEM, "value": rp.usage.rp_number.to_string()}]);
+                    let (requested, drug) = match rp.drugs.as_slice() {
+                        [drug] => (medication(&drug.drug), Some(&drug.drug)),
+                        drugs => {
+                            let names: Vec<&str> = drugs.iter().map(|d| d.drug.name.as_str()).collect();
+                            (json!({"text": names.join("、")}), None)
+                        },
+                    };
+                    let mut request = json!({
+                        "meta": profile("JP_MedicationRequest"),
+                        "identifier": identifier,
+                        "status": "completed",
+                        "intent": "order",
+                        "medicationCodeableConcept": requested,
+                        "subject": patient,
+                        "dosageInstruction": dosage_instruction(drug, rp),
+                    });
+                    insert_some(&mut request, "requester", requester.clone());
+                    if let (Some(quantity), Some("日分")) = (rp.usage.quantity, rp.usage.unit.as_deref()) {
+                        request["dispenseRequest"] = json!({"expectedSupplyDuration": days(quantity)});
+                    }
+                    let request = bundle.add("MedicationRequest", request);
+
+                    for drug in &rp.drugs {
+                        let mut dispense = json!({
+                            "meta": profile("JP_MedicationDispense"),
+                            "identifier": identifier,
+                            "status": "completed",
+                            "medicationCodeableConcept": medication(&drug.drug),
+                            "subject": patient,
+                            "performer": performers,
+                            "authorizingPrescription": [request],
+                            "whenHandedOver": date,
+                            "dosageInstruction": dosage_instruction(Some(&drug.drug), rp),
+                        });
+                        insert_some(&mut dispense, "category", rp.usage.dosage_form.map(|form| json!({"text": form.to_string()})));
+                        if let (Some(quantity), Some(unit)) = (rp.usage.quantity, &rp.usage.unit) {
+                            if unit == "日分" {
+                                dispense["daysSupply"] = days(quantity);
+                            } else {
+                                dispense["quantity"] = json!({"value": quantity, "unit": unit});
+                            }
+                        }
+                        let notes: Vec<Value> = drug.drug_supplementary.iter().map(|r| &r.content)
+                            .chain(drug.drug_notice.iter().map(|r| &r.content))
+                            .chain(rp.rp_notice.iter().map(|r| &r.content))
+                            .map(|content| json!({"text": content}))
+                            .collect();
+                        if !notes.is_empty() {
+                            dispense["note"] = json!(notes);
+                        }
+                        bundle.add("MedicationDispense", dispense);
+                    }
+                }
+            }
+        }
+        Ok(bundle.build())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Something in a FHIR bundle which `from_fhir_bundle` could not represent
+pub struct FhirImportIssue {
+    pub resource: String, // リソース (Type/id)
+    pub message: String,
+}
+
+impl fmt::Display for FhirImportIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.resource, self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+/// Report of what `from_fhir_bundle` left out of the notebook
+pub struct FhirImportReport {
+    pub issues: Vec<FhirImportIssue>,
+}
+
+impl FhirImportReport {
+    /// Whether the whole bundle is represented in the notebook.
+    pub fn is_complete(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    fn push(&mut self, resource: &Value, message: String) {
+        self.issues.push(FhirImportIssue {resource: label(resource), message});
+    }
+}
+
+impl fmt::Display for FhirImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} issues", self.issues.len())?;
+        for issue in &self.issues {
+            write!(f, "\n{}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a FHIR date or dateTime, keeping only the date and its precision.
+pub fn parse_fhir_date(s: &str) -> Result<Date, Error> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"^(\d{4})(?:-(\d{2})(?:-(\d{2}))?)?(?:T.*)?$").unwrap();
+    }
+    if let Some(cap) = RE.captures(s) {
+        let year: i32 = cap[1].parse()?;
+        return match (cap.get(2), cap.get(3)) {
+            (Some(m), Some(d)) => Date::new_seireki(year, m.as_str().parse()?, d.as_str().parse()?),
+            (Some(m), None) => Date::new_seireki_year_month(year, m.as_str().parse()?),
+            _ => Date::new_seireki_year(year),
+        };
+    }
+    Err(Error::InvalidArgument(
+        format!("Cannot convert FHIR date to Date, got \"{}\"", s)
+    ))
+}
+
+/// Parses a FHIR date or dateTime which has a day, as the dates of the
+/// records which do not hold a year or a month alone.
+pub fn parse_fhir_full_date(s: &str) -> Result<Date, Error> {
+    let date = parse_fhir_date(s)?;
+    if date.precision() != DatePrecision::Day {
+        return Err(Error::InvalidArgument(
+            format!("Cannot convert FHIR date without a day to Date, got \"{}\"", s)
+        ));
+    }
+    Ok(date)
+}
+
+fn label(resource: &Value) -> String {
+    format!("{}/{}", resource["resourceType"].as_str().unwrap_or("Resource"), resource["id"].as_str().unwrap_or("?"))
+}
+
+fn string(value: &Value) -> Option<String> {
+    value.as_str().filter(|s| !s.is_empty()).map(|s| s.to_string())
+}
+
+fn array(value: &Value) -> &[Value] {
+    value.as_array().map_or(&[], |a| a.as_slice())
+}
+
+/// Text of a CodeableConcept, or the display of its first coding.
+fn concept_text(concept: &Value) -> Option<String> {
+    string(&concept["text"]).or_else(|| array(&concept["coding"]).iter().find_map(|c| string(&c["display"])))
+}
+
+fn number(value: &Value) -> Option<String> {
+    let n = value.as_f64()?;
+    Some(if n.fract() == 0.0 {format!("{}", n as i64)} else {n.to_string()})
+}
+
+/// Name of a person, preferring the one in the representation, e.g. "SYL"
+/// for kana, or else one which is not kana.
+fn human_name(names: &Value, representation: Option<&str>) -> Option<String> {
+    let representation_of = |name: &Value| array(&name["extension"]).iter()
+        .find(|e| e["url"] == NAME_REPRESENTATION)
+        .and_then(|e| e["valueCode"].as_str().map(|s| s.to_string()));
+    let text = |name: &Value| string(&name["text"]).or_else(|| {
+        let parts: Vec<String> = std::iter::once(&name["family"]).chain(array(&name["given"]).iter())
+            .filter_map(string).collect();
+        if parts.is_empty() {None} else {Some(parts.join("\u{3000}"))}
+    });
+    let names = array(names);
+    match representation {
+        Some(r) => names.iter().find(|n| representation_of(n).as_deref() == Some(r)).and_then(text),
+        None => names.iter().find(|n| representation_of(n).as_deref() == Some("IDE"))
+            .or_else(|| names.iter().find(|n| representation_of(n).as_deref() != Some("SYL")))
+            .and_then(text),
+    }
+}
+
+fn phone(resource: &Value) -> Option<String> {
+    array(&resource["telecom"]).iter().find(|t| t["system"] == "phone").and_then(|t| string(&t["value"]))
+}
+
+fn address(resource: &Value) -> (Option<String>, Option<String>) {
+    let address = match array(&resource["address"]).first() {
+        Some(address) => address,
+        None => return (None, None),
+    };
+    let text = string(&address["text"]).or_else(|| {
+        let parts: Vec<String> = [&address["state"], &address["city"]].iter().copied()
+            .chain(array(&address["line"]).iter())
+            .filter_map(string).collect();
+        if parts.is_empty() {None} else {Some(parts.concat())}
+    });
+    (string(&address["postalCode"]), text)
+}
+
+/// The prefecture, the fee table and the code of an Organization, read from
+/// its 10 digit identifier or else from its extensions.
+fn institution_codes(resource: &Value) -> (Option<Prefecture>, Option<FeeTable>, Option<String>) {
+    let number = array(&resource["identifier"]).iter()
+        .find(|i| i["system"] == INSTITUTION_NUMBER_SYSTEM)
+        .and_then(|i| string(&i["value"]))
+        .filter(|v| v.len() == 10 && v.chars().all(|c| c.is_ascii_digit()));
+    if let Some(number) = number {
+        return (
+            number[..2].parse::<u32>().ok().and_then(|n| Prefecture::try_from(n).ok()),
+            number[2..3].parse::<u32>().ok().and_then(|n| FeeTable::try_from(n).ok()),
+            Some(number[3..].to_string()),
+        );
+    }
+    let extension = |name: &str| array(&resource["extension"]).iter()
+        .find(|e| e["url"].as_str() == Some(&format!("{}{}", EXTENSION_BASE, name)))
+        .cloned();
+    (
+        extension("JP_Organization_PrefectureNo").and_then(|e| e["valueCoding"]["code"].as_str().and_then(|c| c.parse().ok())),
+        extension("JP_Organization_InsuranceOrganizationCategory").and_then(|e| e["valueCoding"]["code"].as_str().and_then(|c| c.parse().ok())),
+        extension("JP_Organization_InsuranceOrganizationNo").and_then(|e| string(&e["valueIdentifier"]["value"])),
+    )
+}
+
+fn pharmacy_record(resource: &Value) -> PharmacyRecord {
+    let (prefecture, fee_table, institution_code) = institution_codes(resource);
+    let (zip_code, address) = address(resource);
+    PharmacyRecord {
+        name: string(&resource["name"]).unwrap_or_default(),
+        prefecture,
+        fee_table,
+        institution_code,
+        zip_code,
+        address,
+        telephone: phone(resource),
+        created_by: RecordCreator::MedicalExpert,
+        extra: Vec::new(),
+    }
+}
+
+fn medical_institution_record(resource: &Value) -> MedicalInstitutionRecord {
+    let (prefecture, fee_table, institution_code) = institution_codes(resource);
+    MedicalInstitutionRecord {
+        name: string(&resource["name"]).unwrap_or_default(),
+        prefecture,
+        fee_table,
+        institution_code,
+        created_by: RecordCreator::MedicalExpert,
+        extra: Vec::new(),
+    }
+}
+
+/// The resources of a bundle, found by their references, remembering which
+/// ones have been referred to.
+struct Resources<'a> {
+    entries: Vec<(Option<&'a str>, &'a Value)>,
+    resolved: RefCell<Vec<usize>>,
+}
+
+impl<'a> Resources<'a> {
+    fn new(bundle: &'a Value) -> Self {
+        Self {
+            entries: array(&bundle["entry"]).iter()
+                .filter(|e| e["resource"].is_object())
+                .map(|e| (e["fullUrl"].as_str(), &e["resource"]))
+                .collect(),
+            resolved: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn of_type(&self, resource_type: &'static str) -> impl Iterator<Item = &'a Value> + '_ {
+        self.entries.iter().map(|(_, r)| *r).filter(move |r| r["resourceType"] == resource_type)
+    }
+
+    /// Resolves a Reference by the full URL or by a relative reference,
+    /// which may also be the end of an absolute one.
+    fn resolve(&self, reference: &Value) -> Option<&'a Value> {
+        let reference = reference["reference"].as_str()?;
+        let i = self.entries.iter().position(|(full_url, resource)| *full_url == Some(reference) || {
+            let relative = label(resource);
+            reference == relative || reference.ends_with(&format!("/{}", relative))
+        })?;
+        self.resolved.borrow_mut().push(i);
+        Some(self.entries[i].1)
+    }
+
+    fn is_resolved(&self, i: usize) -> bool {
+        self.resolved.borrow().contains(&i)
+    }
+}
+
+/// A physician and the institution they work at, found from the requester
+/// of a MedicationRequest.
+fn prescriber(resources: &Resources, requester: &Value) -> (Option<PhysicianRecord>, Option<MedicalInstitutionRecord>) {
+    let resource = match resources.resolve(requester) {
+        Some(resource) => resource,
+        None => return (None, None),
+    };
+    match resource["resourceType"].as_str() {
+        Some("Practitioner") => (
+            human_name(&resource["name"], None).map(|name| PhysicianRecord::new(name, None, RecordCreator::MedicalExpert)),
+            None,
+        ),
+        Some("PractitionerRole") => (
+            resources.resolve(&resource["practitioner"])
+                .and_then(|p| human_name(&p["name"], None))
+                .map(|name| PhysicianRecord::new(name,
+                    array(&resource["specialty"]).first().and_then(concept_text), RecordCreator::MedicalExpert)),
+            resources.resolve(&resource["organization"]).map(medical_institution_record),
+        ),
+        Some("Organization") => (None, Some(medical_institution_record(resource))),
+        _ => (None, None),
+    }
+}
+
+/// Where a medication is placed in the notebook.
+struct Placement {
+    date: Date,
+    pharmacy: PharmacyRecord,
+    pharmacist: Option<PharmacistRecord>,
+    physician: Option<PhysicianRecord>,
+    medical_institution: Option<MedicalInstitutionRecord>,
+    rp_key: Option<String>,
+}
+
+fn drug_record(resources: &Resources, resource: &Value, report: &mut FhirImportReport) -> DrugRecord {
+    let concept = match resources.resolve(&resource["medicationReference"]) {
+        Some(medication) => &medication["code"],
+        None => &resource["medicationCodeableConcept"],
+    };
+    let name = concept_text(concept).unwrap_or_else(|| {
+        report.push(resource, "has no name of the medication".to_string());
+        String::new()
+    });
+    let codings = array(&concept["coding"]);
+    let code = codings.iter().find_map(|coding| {
+        let system = coding["system"].as_str()?;
+        let code_type = [DrugCodeType::Yj, DrugCodeType::Hot, DrugCodeType::Receipt, DrugCodeType::Mhlw].iter()
+            .find(|t| t.fhir_system() == Some(system))?;
+        Some((*code_type, string(&coding["code"])?))
+    });
+    let (drug_code_type, drug_code) = match code {
+        Some((code_type, code)) => (code_type, Some(code)),
+        None => {
+            for coding in codings {
+                report.push(resource, format!("drops the drug code \"{}\" of the system \"{}\", which is not a JAHIS drug code",
+                    coding["code"].as_str().unwrap_or_default(), coding["system"].as_str().unwrap_or_default()));
+            }
+            (DrugCodeType::None, None)
+        },
+    };
+
+    let dose = &resource["dosageInstruction"][0]["doseAndRate"][0]["doseQuantity"];
+    let (dosage, unit) = match (number(&dose["value"]), string(&dose["unit"]).or_else(|| string(&dose["code"]))) {
+        (Some(value), unit) => (value, unit.unwrap_or_default()),
+        (None, Some(unit)) => (String::new(), unit),
+        (None, None) => {
+            report.push(resource, "has no dose quantity".to_string());
+            (String::new(), String::new())
+        },
+    };
+    DrugRecord::new(1, name, dosage, unit, drug_code_type, drug_code, RecordCreator::MedicalExpert)
+}
+
+fn rp_block(resource: &Value, report: &mut FhirImportReport) -> RpBlock {
+    let instructions = array(&resource["dosageInstruction"]);
+    if instructions.len() > 1 {
+        report.push(resource, format!("drops {} dosage instructions after the first one", instructions.len() - 1));
+    }
+    let dosage = &resource["dosageInstruction"][0];
+    let timing = &dosage["timing"]["code"];
+    let name = concept_text(timing).or_else(|| string(&dosage["text"])).unwrap_or_else(|| {
+        report.push(resource, "has no usage".to_string());
+        String::new()
+    });
+    let jami = array(&timing["coding"]).iter()
+        .find(|c| c["system"] == USAGE_CODE_SYSTEM_JAMI)
+        .and_then(|c| string(&c["code"]));
+
+    let days = resource["daysSupply"]["value"].as_f64()
+        .or_else(|| resource["dispenseRequest"]["expectedSupplyDuration"]["value"].as_f64());
+    let (quantity, unit) = match (days, string(&resource["quantity"]["unit"])) {
+        (Some(days), _) => (Some(days as u32), Some("日分".to_string())),
+        (None, Some(unit)) if unit.ends_with("回分") => (resource["quantity"]["value"].as_f64().map(|n| n as u32), Some(unit)),
+        _ => (None, None),
+    };
+    let dosage_form = match array(&resource["category"]).first().or(Some(&resource["category"])).and_then(concept_text) {
+        Some(text) => match text.parse::<DosageForm>() {
+            Ok(form) => Some(form),
+            Err(_) => {
+                report.push(resource, format!("drops the category \"{}\", which is not a dosage form", text));
+                None
+            },
+        },
+        None => None,
+    };
+
+    let mut rp = RpBlock::new(UsageRecord::new(1, name, quantity, unit, dosage_form,
+        jami.as_ref().map(|_| UsageCodeType::Jami), jami, RecordCreator::MedicalExpert));
+    rp.usage_supplementary = array(&dosage["additionalInstruction"]).iter()
+        .filter_map(concept_text)
+        .map(|text| UsageSupplementaryRecord::new(1, text, RecordCreator::MedicalExpert))
+        .collect();
+    rp
+}
+
+/// Finds the block of the date and the pharmacy, the prescription of the
+/// physician and the RP of the key, adding each one which is missing.
+fn place(notebook: &mut MedicineNotebook, rps: &mut HashMap<(usize, usize, String), usize>,
+        placement: Placement, resource: &Value, rp: RpBlock, drug: DrugBlock, report: &mut FhirImportReport) {
+    let Placement {date, pharmacy, pharmacist, physician, medical_institution, rp_key} = placement;
+    let i = match notebook.dispensing_information.iter()
+            .position(|b| b.date.created_at == date && b.pharmacy == pharmacy) {
+        Some(i) => i,
+        None => {
+            notebook.dispensing_information.push(DispensingInformationBlock::new(
+                DateRecord::new(date, RecordCreator::MedicalExpert), pharmacy));
+            notebook.dispensing_information.len() - 1
+        },
+    };
+    let block = &mut notebook.dispensing_information[i];
+    if block.pharmacist.is_none() {
+        block.pharmacist = pharmacist;
+    } else if pharmacist.is_some() && block.pharmacist != pharmacist {
+        report.push(resource, "drops its pharmacist, who differs from the one of the same dispensing".to_string());
+    }
+    if block.medical_institute.is_none() {
+        block.medical_institute = medical_institution;
+    } else if medical_institution.is_some() && block.medical_institute != medical_institution {
+        report.push(resource, "drops its medical institution, which differs from the one of the same dispensing".to_string());
+    }
+
+    let j = match block.prescriptions.iter().position(|p| p.physician == physician) {
+        Some(j) => j,
+        None => {
+            block.prescriptions.push(PrescriptionBlock {physician, .. PrescriptionBlock::default()});
+            block.prescriptions.len() - 1
+        },
+    };
+    let prescription = &mut block.prescriptions[j];
+    let k = match rp_key {
+        Some(key) => match rps.get(&(i, j, key.clone())) {
+            Some(&k) => k,
+            None => {
+                prescription.rps.push(rp);
+                rps.insert((i, j, key), prescription.rps.len() - 1);
+                prescription.rps.len() - 1
+            },
+        },
+        None => {
+            prescription.rps.push(rp);
+            prescription.rps.len() - 1
+        },
+    };
+    prescription.rps[k].drugs.push(drug);
+}
+
+impl MedicineNotebook {
+    /// Builds a notebook from a FHIR R4 Bundle of a Patient and their
+    /// MedicationDispenses or MedicationRequests, with the Organizations,
+    /// Practitioners, PractitionerRoles and Medications they refer to.
+    ///
+    /// Medications are grouped into a dispensing information block by the
+    /// date they were handed over, or prescribed, and the organization which
+    /// dispensed them, then into prescriptions by the prescribing physician,
+    /// and into RPs by their RP number identifiers. Drug codes are read from
+    /// the YJ, HOT, レセプト電算 and 薬価基準 code systems, and
+    /// AllergyIntolerances become special patient notes.
+    ///
+    /// Fails if the bundle has no Patient; everything else which cannot be
+    /// represented in the notebook is listed in the report. A medication
+    /// whose date has no day is dropped, and a birth date without one is
+    /// left as the default, as the records hold full dates only.
+    pub fn from_fhir_bundle(bundle: &Value) -> Result<(Self, FhirImportReport), Error> {
+        if bundle["resourceType"] != "Bundle" {
+            return Err(Error::InvalidArgument("Cannot convert a FHIR resource other than Bundle to MedicineNotebook".to_string()));
+        }
+        let resources = Resources::new(bundle);
+        let mut report = FhirImportReport::default();
+
+        let mut patients = resources.of_type("Patient");
+        let patient = patients.next().ok_or_else(|| Error::InvalidArgument(
+            "Cannot find a Patient in the FHIR bundle".to_string()
+        ))?;
+        for other in patients {
+            report.push(other, "is dropped, as a notebook has a single patient".to_string());
+        }
+        let mut notebook = MedicineNotebook::new(patient_record(patient, &mut report));
+
+        for resource in resources.of_type("AllergyIntolerance") {
+            if matches!(resource["verificationStatus"]["coding"][0]["code"].as_str(), Some("refuted") | Some("entered-in-error")) {
+                report.push(resource, "is dropped, as it is refuted or entered in error".to_string());
+                continue;
+            }
+            let category = if resource["type"] == "intolerance" {
+                SpecialPatientNoteCategory::AdverseEvent
+            } else {
+                SpecialPatientNoteCategory::Allergy
+            };
+            match concept_text(&resource["code"]) {
+                Some(content) => notebook.special_patient_notes.push(
+                    SpecialPatientNoteRecord::new(category, content, RecordCreator::MedicalExpert)),
+                None => report.push(resource, "is dropped, as it has no substance".to_string()),
+            }
+        }
+
+        let authorizing: Vec<&Value> = resources.of_type("MedicationDispense")
+            .flat_map(|r| array(&r["authorizingPrescription"]).iter())
+            .filter_map(|reference| resources.resolve(reference))
+            .collect();
+        let mut rps: HashMap<(usize, usize, String), usize> = HashMap::new();
+        let medications = resources.of_type("MedicationDispense")
+            .chain(resources.of_type("MedicationRequest").filter(|r| !authorizing.iter().any(|a| std::ptr::eq(*a, *r))));
+        for resource in medications {
+            let is_dispense = resource["resourceType"] == "MedicationDispense";
+            let status = resource["status"].as_str().unwrap_or_default();
+            if ["cancelled", "entered-in-error", "declined", "draft"].contains(&status) {
+                report.push(resource, format!("is dropped, as its status is {}", status));
+                continue;
+            }
+            let date = if is_dispense {
+                string(&resource["whenHandedOver"]).or_else(|| string(&resource["whenPrepared"]))
+            } else {
+                string(&resource["authoredOn"])
+            };
+            let date = match date.as_deref().map(parse_fhir_full_date) {
+                Some(Ok(date)) => date,
+                Some(Err(e)) => {
+                    report.push(resource, format!("is dropped, as its date is invalid: {}", e));
+                    continue;
+                },
+                None => {
+                    report.push(resource, "is dropped, as it has no date".to_string());
+                    continue;
+                },
+            };
+
+            let request = if is_dispense {
+                array(&resource["authorizingPrescription"]).first().and_then(|r| resources.resolve(r))
+            } else {
+                Some(resource)
+            };
+            let (physician, medical_institution) = request
+                .map_or((None, None), |request| prescriber(&resources, &request["requester"]));
+            let mut pharmacist = None;
+            let mut pharmacy = None;
+            if is_dispense {
+                for actor in array(&resource["performer"]).iter().filter_map(|p| resources.resolve(&p["actor"])) {
+                    match actor["resourceType"].as_str() {
+                        Some("Practitioner") => pharmacist = pharmacist.or_else(|| human_name(&actor["name"], None)
+                            .map(|name| PharmacistRecord::new(name, phone(actor), RecordCreator::MedicalExpert))),
+                        Some("Organization") => pharmacy = pharmacy.or_else(|| Some(pharmacy_record(actor))),
+                        Some("PractitionerRole") => {
+                            pharmacist = pharmacist.or_else(|| resources.resolve(&actor["practitioner"])
+                                .and_then(|p| human_name(&p["name"], None)
+                                    .map(|name| PharmacistRecord::new(name, phone(p), RecordCreator::MedicalExpert))));
+                            pharmacy = pharmacy.or_else(|| resources.resolve(&actor["organization"]).map(pharmacy_record));
+                        },
+                        _ => {},
+                    }
+                }
+            } else {
+                let requester = resources.resolve(&resource["requester"]);
+                pharmacy = requester.and_then(|r| match r["resourceType"].as_str() {
+                    Some("Organization") => Some(r),
+                    Some("PractitionerRole") => resources.resolve(&r["organization"]),
+                    _ => None,
+                }).map(pharmacy_record);
+            }
+            let pharmacy = pharmacy.unwrap_or_else(|| {
+                report.push(resource, "has no dispensing organization".to_string());
+                PharmacyRecord {created_by: RecordCreator::MedicalExpert, .. PharmacyRecord::default()}
+            });
+            let rp_key = array(&resource["identifier"]).iter()
+                .find(|i| i["system"] == RP_NUMBER_SYSTEM)
+                .and_then(|i| string(&i["value"]));
+
+            let mut drug = DrugBlock::new(drug_record(&resources, resource, &mut report));
+            drug.drug_supplementary = array(&resource["note"]).iter()
+                .filter_map(|n| string(&n["text"]))
+                .map(|text| DrugSupplementaryRecord::new(1, text, RecordCreator::MedicalExpert))
+                .collect();
+            let rp = rp_block(resource, &mut report);
+            let placement = Placement {date, pharmacy, pharmacist, physician, medical_institution, rp_key};
+            place(&mut notebook, &mut rps, placement, resource, rp, drug, &mut report);
+        }
+
+        for block in &mut notebook.dispensing_information {
+            for prescription in &mut block.prescriptions {
+                for (n, rp) in prescription.rps.iter_mut().enumerate() {
+                    let rp_number = n as u32 + 1;
+                    rp.usage.rp_number = rp_number;
+                    for drug in &mut rp.drugs {
+                        drug.drug.rp_number = rp_number;
+                        drug.drug_supplementary.iter_mut().for_each(|r| r.rp_number = rp_number);
+                    }
+                    rp.usage_supplementary.iter_mut().for_each(|r| r.rp_number = rp_number);
+                }
+            }
+        }
+        notebook.dispensing_information.sort_by_key(|b| b.date.created_at);
+
+        let imported = ["Patient", "AllergyIntolerance", "MedicationDispense", "MedicationRequest"];
+        let referred = ["Medication", "Organization", "Practitioner", "PractitionerRole"];
+        for (i, (_, resource)) in resources.entries.iter().enumerate() {
+            let resource_type = resource["resourceType"].as_str().unwrap_or_default();
+            if referred.contains(&resource_type) {
+                if !resources.is_resolved(i) {
+                    report.push(resource, "is dropped, as no imported medication refers to it".to_string());
+                }
+            } else if !imported.contains(&resource_type) {
+                report.push(resource, "is not represented in a notebook".to_string());
+            }
+        }
+        Ok((notebook, report))
+    }
+}
+
+fn patient_record(resource: &Value, report: &mut FhirImportReport) -> PatientRecord {
+    let name = human_name(&resource["name"], None).unwrap_or_else(|| {
+        report.push(resource, "has no name".to_string());
+        String::new()
+    });
+    let gender = match resource["gender"].as_str() {
+        Some("male") => Gender::Male,
+        Some("female") => Gender::Female,
+        gender => {
+            report.push(resource, format!("has the gender {}, which a notebook cannot hold", gender.unwrap_or("of none")));
+            Gender::Male
+        },
+    };
+    let day_of_birth = match resource["birthDate"].as_str().map(parse_fhir_full_date) {
+        Some(Ok(date)) => date,
+        Some(Err(e)) => {
+            report.push(resource, format!("has an invalid birth date, which is left as the default: {}", e));
+            PatientRecord::default().day_of_birth
+        },
+        None => {
+            report.push(resource, "has no birth date".to_string());
+            PatientRecord::default().day_of_birth
+        },
+    };
+    let (zip_code, address) = address(resource);
+    PatientRecord {
+        zip_code,
+        address,
+        telephone: phone(resource),
+        name_in_kana: human_name(&resource["name"], Some("SYL")),
+        .. PatientRecord::new(name, gender, day_of_birth)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two blocks like those of the shared sample, with patient notes, a pharmacist, a physician and a HOT code.
+    const SAMPLE: &str = "JAHISTC07,1\r\n\
+        1,山田太郎,1,19700101,1000001,東京都千代田区,03-0000-0000,,A,60.5,ﾔﾏﾀﾞﾀﾛｳ\r\n\
+        2,1,卵アレルギー,1\r\n\
+        2,2,ペニシリンで発疹,1\r\n\
+        5,20200401,1\r\n\
+        11,テスト薬局,13,4,1234567,1000001,東京都,03-1111-1111,1\r\n\
+        15,薬剤師花子,03-1111-1111,1\r\n\
+        51,テスト病院,13,1,7654321,1\r\n\
+        55,医師一郎,内科,1\r\n\
+        201,1,ロキソプロフェン錠60mg,1,錠,2,620098801,1\r\n\
+        301,1,1日3回毎食後,7,日分,1,1,,1\r\n\
+        5,20200501,1\r\n\
+        11,テスト薬局,13,4,1234567,1000001,東京都,03-1111-1111,1\r\n\
+        15,薬剤師花子,03-1111-1111,1\r\n\
+        201,1,ムコスタ錠100mg,3,錠,4,2329021F1021,1\r\n\
+        201,1,アムロジピン錠5mg,1,錠,6,1171234010101,1\r\n\
+        301,1,1日3回毎食後,7,日分,1,,,1";
+
+    #[test]
+    fn refers_to_entries_by_full_url() {
+        let notebook: MedicineNotebook = SAMPLE.parse().unwrap();
+        let bundle = notebook.to_fhir_bundle().unwrap();
+        let entries = array(&bundle["entry"]);
+        let full_urls: Vec<&str> = entries.iter().map(|e| e["fullUrl"].as_str().unwrap()).collect();
+        assert!(full_urls.iter().all(|u| u.starts_with("urn:uuid:")));
+        let find = |reference: &Value| {
+            let i = full_urls.iter().position(|u| reference["reference"] == *u).unwrap();
+            &entries[i]["resource"]
+        };
+
+        let dispenses: Vec<&Value> = entries.iter().map(|e| &e["resource"])
+            .filter(|r| r["resourceType"] == "MedicationDispense")
+            .collect();
+        assert_eq!(dispenses.len(), 3);
+        for dispense in &dispenses {
+            assert_eq!(find(&dispense["subject"])["resourceType"], "Patient");
+            let role = find(&dispense["performer"][0]["actor"]);
+            assert_eq!(role["resourceType"], "PractitionerRole");
+            assert_eq!(find(&role["practitioner"])["name"][0]["text"], "薬剤師花子");
+            assert_eq!(find(&role["organization"])["name"], "テスト薬局");
+        }
+        assert_eq!(dispenses[0]["performer"], dispenses[1]["performer"]);
+
+        let request = find(&dispenses[0]["authorizingPrescription"][0]);
+        assert_eq!(request["resourceType"], "MedicationRequest");
+        assert_eq!(request["identifier"], dispenses[0]["identifier"]);
+        let role = find(&request["requester"]);
+        assert_eq!(role["resourceType"], "PractitionerRole");
+        assert_eq!(find(&role["practitioner"])["name"][0]["text"], "医師一郎");
+        assert_eq!(find(&role["organization"])["name"], "テスト病院");
+        assert!(find(&dispenses[1]["authorizingPrescription"][0])["requester"].is_null());
+    }
+
+    #[test]
+    fn takes_allergies_and_dosages_from_their_records() {
+        let notebook: MedicineNotebook = SAMPLE.parse().unwrap();
+        let bundle = notebook.to_fhir_bundle().unwrap();
+        let entries = array(&bundle["entry"]);
+        let patient = entries.iter().find(|e| e["resource"]["resourceType"] == "Patient").unwrap();
+        let resources = |resource_type: &str| -> Vec<&Value> {
+            entries.iter().map(|e| &e["resource"]).filter(|r| r["resourceType"] == resource_type).collect()
+        };
+
+        let allergies = resources("AllergyIntolerance");
+        assert_eq!(allergies.len(), 2);
+        assert_eq!((&allergies[0]["type"], &allergies[0]["code"]["text"]), (&json!("allergy"), &json!("卵アレルギー")));
+        assert_eq!((&allergies[1]["type"], &allergies[1]["code"]["text"]), (&json!("intolerance"), &json!("ペニシリンで発疹")));
+        for allergy in &allergies {
+            assert_eq!(allergy["patient"]["reference"], patient["fullUrl"]);
+        }
+
+        let dispense = resources("MedicationDispense")[0];
+        let dosage = &dispense["dosageInstruction"][0];
+        assert_eq!(dosage["text"], "1日3回毎食後");
+        assert_eq!(dosage["timing"]["code"]["text"], "1日3回毎食後");
+        assert_eq!(dosage["doseAndRate"][0]["doseQuantity"]["unit"], "錠");
+        assert_eq!(dispense["daysSupply"], json!({"value": 7, "unit": "日", "system": "http://unitsofmeasure.org", "code": "d"}));
+        assert!(dispense["quantity"].is_null());
+        assert_eq!(resources("MedicationRequest")[0]["dispenseRequest"]["expectedSupplyDuration"], dispense["daysSupply"]);
+    }
+
+    #[test]
+    fn reads_back_its_own_bundle() {
+        let notebook: MedicineNotebook = SAMPLE.parse().unwrap();
+        let (imported, report) = MedicineNotebook::from_fhir_bundle(&notebook.to_fhir_bundle().unwrap()).unwrap();
+        assert!(report.is_complete(), "{}", report);
+        assert_eq!(imported.special_patient_notes, notebook.special_patient_notes);
+        assert_eq!(imported.dispensing_information.len(), 2);
+        for block in &imported.dispensing_information {
+            assert_eq!(block.pharmacy.name, "テスト薬局");
+            assert_eq!(block.pharmacist.as_ref().map(|p| p.name.as_str()), Some("薬剤師花子"));
+        }
+        let block = &imported.dispensing_information[0];
+        assert_eq!(block.medical_institute, notebook.dispensing_information[0].medical_institute);
+        assert_eq!(block.prescriptions[0].physician, notebook.dispensing_information[0].prescriptions[0].physician);
+        assert!(imported.dispensing_information[1].medical_institute.is_none());
+
+        let codes: Vec<(DrugCodeType, Option<&str>)> = imported.dispensing_information.iter()
+            .flat_map(|b| &b.prescriptions)
+            .flat_map(|p| &p.rps)
+            .flat_map(|rp| &rp.drugs)
+            .map(|d| (d.drug.drug_code_type, d.drug.drug_code.as_deref()))
+            .collect();
+        assert_eq!(codes, vec![
+            (DrugCodeType::Receipt, Some("620098801")),
+            (DrugCodeType::Yj, Some("2329021F1021")),
+            (DrugCodeType::Hot, Some("1171234010101")),
+        ]);
+    }
+
+    #[test]
+    fn reports_drug_codes_of_other_systems() {
+        let notebook: MedicineNotebook = SAMPLE.parse().unwrap();
+        let mut bundle = notebook.to_fhir_bundle().unwrap();
+        for entry in bundle["entry"].as_array_mut().unwrap() {
+            let resource = &mut entry["resource"];
+            if resource["resourceType"] == "MedicationDispense" && resource["whenHandedOver"] == "2020-04-01" {
+                resource["medicationCodeableConcept"]["coding"] = json!([
+                    {"system": "http://example.com/drug-code", "code": "X-0001", "display": "ロキソプロフェン錠60mg"},
+                ]);
+            }
+        }
+
+        let (imported, report) = MedicineNotebook::from_fhir_bundle(&bundle).unwrap();
+        let drug = &imported.dispensing_information[0].prescriptions[0].rps[0].drugs[0].drug;
+        assert_eq!(drug.name, "ロキソプロフェン錠60mg");
+        assert_eq!(drug.drug_code_type, DrugCodeType::None);
+        assert_eq!(drug.drug_code, None);
+        let messages: Vec<String> = report.issues.iter().map(|i| i.to_string()).collect();
+        assert_eq!(messages, vec![
+            "MedicationDispense/medicationdispense-1: drops the drug code \"X-0001\" of the system \"http://example.com/drug-code\", which is not a JAHIS drug code",
+        ]);
+        assert!(imported.to_code().parse::<MedicineNotebook>().is_ok());
+    }
+
+    #[test]
+    fn drops_medications_without_a_day() {
+        let notebook: MedicineNotebook = SAMPLE.parse().unwrap();
+        let mut bundle = notebook.to_fhir_bundle().unwrap();
+        for entry in bundle["entry"].as_array_mut().unwrap() {
+            let resource = &mut entry["resource"];
+            match resource["resourceType"].as_str() {
+                Some("Patient") => resource["birthDate"] = json!("1970"),
+                Some("MedicationDispense") if resource["whenHandedOver"] == "2020-05-01" => resource["whenHandedOver"] = json!("2020-05"),
+                _ => {},
+            }
+        }
+
+        let (imported, report) = MedicineNotebook::from_fhir_bundle(&bundle).unwrap();
+        assert_eq!(imported.patient.day_of_birth, PatientRecord::default().day_of_birth);
+        assert_eq!(imported.dispensing_information.len(), 1);
+        assert_eq!(imported.dispensing_information[0].date.created_at, Date::new_seireki(2020, 4, 1).unwrap());
+        let messages: Vec<String> = report.issues.iter().map(|i| i.to_string()).collect();
+        assert!(messages.iter().any(|m| m.starts_with("Patient/patient-1: has an invalid birth date")), "{:?}", messages);
+        assert!(messages.iter().any(|m| m.starts_with("MedicationDispense/medicationdispense-2: is dropped, as its date is invalid")), "{:?}", messages);
+        assert!(imported.to_code().parse::<MedicineNotebook>().is_ok());
+    }
+
+    #[test]
+    fn parses_full_dates_only_where_a_day_is_required() {
+        assert_eq!(parse_fhir_date("2024-03").unwrap().precision(), DatePrecision::Month);
+        assert_eq!(parse_fhir_full_date("2024-03-05T10:00:00+09:00").unwrap(), Date::new_seireki(2024, 3, 5).unwrap());
+        assert!(parse_fhir_full_date("2024-03").is_err());
+        assert!(parse_fhir_full_date("2024").is_err());
+    }
+}
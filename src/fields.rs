@@ -0,0 +1,62 @@
+// Fields of the record lines of the formats read along with notebooks, such
+// as outpatient prescriptions and UKE files, whose records are not described
+// by `field_names`.
+
+use std::str::FromStr;
+use crate::jahis::{Error, ParseError};
+
+/// The fields of a record line, which fail with the line and the index and
+/// the name of the field which could not be read.
+pub(crate) struct Fields<'a> {
+    pub line: usize, // 1-based line number
+    pub cols: Vec<&'a str>,
+    record: String, // e.g. "UKE record SH"
+}
+
+impl<'a> Fields<'a> {
+    /// Splits a line of a format, e.g. "UKE", into the record identifier and
+    /// its fields.
+    pub fn new(line: usize, format: &str, s: &'a str) -> Self {
+        let cols: Vec<&str> = s.split(',').collect();
+        let record = format!("{} record {}", format, cols[0]);
+        Self {line, cols, record}
+    }
+
+    pub fn get(&self, i: usize) -> Option<String> {
+        self.cols.get(i).map(|s| s.trim()).filter(|s| !s.is_empty()).map(|s| s.to_string())
+    }
+
+    fn error(&self, i: usize, field_name: &'static str, expected: String) -> Error {
+        Error::InvalidRecordLine(ParseError {
+            line: Some(self.line),
+            field_index: Some(i),
+            field_name: Some(field_name),
+            expected,
+            found: self.cols.get(i).copied().unwrap_or_default().to_string(),
+            .. Default::default()
+        })
+    }
+
+    /// An error of a field whose value cannot be read.
+    pub fn invalid(&self, i: usize, field_name: &'static str) -> Error {
+        self.error(i, field_name, format!("a valid value of {}", self.record))
+    }
+
+    /// An error of a required field which is empty.
+    pub fn missing(&self, i: usize, field_name: &'static str) -> Error {
+        self.error(i, field_name, format!("a value of {}", self.record))
+    }
+
+    pub fn parse_with<T, E, F>(&self, i: usize, field_name: &'static str, parse: F) -> Result<Option<T>, Error>
+            where F: FnOnce(&str) -> Result<T, E> {
+        self.get(i).map(|col| parse(&col).map_err(|_| self.invalid(i, field_name))).transpose()
+    }
+
+    pub fn parse<T: FromStr>(&self, i: usize, field_name: &'static str) -> Result<Option<T>, Error> {
+        self.parse_with(i, field_name, str::parse)
+    }
+
+    pub fn require<T: FromStr>(&self, i: usize, field_name: &'static str) -> Result<T, Error> {
+        self.parse(i, field_name)?.ok_or_else(|| self.missing(i, field_name))
+    }
+}
@@ -4,6 +4,7 @@
 #![allow(dead_code)]
 
 use std::num;
+use std::collections::HashMap;
 use std::fmt;
 use std::str::FromStr;
 use std::convert::From;
@@ -12,50 +13,259 @@ use lazy_static::lazy_static;
 use chrono;
 use chrono::Datelike;
 use regex::Regex;
+use crate::reader::MedicineNotebookReader;
+use crate::escape::EscapePolicy;
+use crate::profile::VersionProfile;
+use crate::era::*;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 /// An error which can be return when parsing a date string.
 pub enum Error {
     InvalidArgument(String),
-    InvalidRecordLine(String),
-    GotUnexpectedRecordLine(String),
-    MissingRequiredRecord(String),
+    InvalidRecordLine(ParseError),
+    GotUnexpectedRecordLine(ParseError),
+    MissingRequiredRecord(ParseError),
     Unreachable(String),
     ParseIntError(num::ParseIntError),
     ParseFloatError(num::ParseFloatError),
+    IoError(String),
+    EncodingError(String),
+    UnmappableCharacter{record_number: u32, field_name: &'static str, character: char},
+    ForbiddenCharacter{record_number: u32, field_name: &'static str, character: char},
+    NotInVersion{version: u32, record_number: u32, field_name: Option<&'static str>},
+    MissingSplitParts{data_id: String, missing: Vec<u32>},
+    DuplicateSplitPart{data_id: String, index: u32},
+    ImageError(String),
+    QrCodeError(String),
+    InvalidDate(String),
+    FieldTooLong{record_number: u32, field_name: &'static str, max_bytes: usize, bytes: usize},
+}
+
+impl Error {
+    /// Sets the line number of a parse error unless it is already known.
+    pub fn at_line(self, line: usize) -> Self {
+        self.map_line(|n| n.or(Some(line)))
+    }
+
+    /// Maps the line number of a parse error, e.g. from a line of a block
+    /// to the corresponding line of the whole notebook.
+    pub fn map_line<F: FnOnce(Option<usize>) -> Option<usize>>(self, f: F) -> Self {
+        match self {
+            Self::InvalidRecordLine(e) => Self::InvalidRecordLine(ParseError {line: f(e.line), .. e}),
+            Self::GotUnexpectedRecordLine(e) => Self::GotUnexpectedRecordLine(ParseError {line: f(e.line), .. e}),
+            Self::MissingRequiredRecord(e) => Self::MissingRequiredRecord(ParseError {line: f(e.line), .. e}),
+            e => e,
+        }
+    }
+
+    /// Returns the details of the parse error, if any.
+    pub fn parse_error(&self) -> Option<&ParseError> {
+        match self {
+            Self::InvalidRecordLine(e) | Self::GotUnexpectedRecordLine(e)
+                | Self::MissingRequiredRecord(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidArgument(s) => write!(f, "invalid argument: {}", s),
+            Self::InvalidRecordLine(e) => write!(f, "invalid record line: {}", e),
+            Self::GotUnexpectedRecordLine(e) => write!(f, "unexpected record line: {}", e),
+            Self::MissingRequiredRecord(e) => write!(f, "missing required record: {}", e),
+            Self::Unreachable(s) => write!(f, "unreachable: {}", s),
+            Self::ParseIntError(e) => write!(f, "{}", e),
+            Self::ParseFloatError(e) => write!(f, "{}", e),
+            Self::IoError(s) => write!(f, "I/O error: {}", s),
+            Self::EncodingError(s) => write!(f, "encoding error: {}", s),
+            Self::UnmappableCharacter{record_number, field_name, character} =>
+                write!(f, "cannot encode '{}' (U+{:04X}) in {} of record {}",
+                    character, *character as u32, field_name, record_number),
+            Self::ForbiddenCharacter{record_number, field_name, character} =>
+                write!(f, "cannot write {:?} in {} of record {}",
+                    character, field_name, record_number),
+            Self::NotInVersion{version, record_number, field_name: Some(field_name)} =>
+                write!(f, "{} of record {} is not defined in JAHISTC{:>02}",
+                    field_name, record_number, version),
+            Self::NotInVersion{version, record_number, field_name: None} =>
+                write!(f, "record {} is not defined in JAHISTC{:>02}", record_number, version),
+            Self::MissingSplitParts{data_id, missing} =>
+                write!(f, "parts {:?} of split data \"{}\" are missing", missing, data_id),
+            Self::DuplicateSplitPart{data_id, index} =>
+                write!(f, "part {} of split data \"{}\" is given twice with different contents",
+                    index, data_id),
+            Self::ImageError(s) => write!(f, "image error: {}", s),
+            Self::QrCodeError(s) => write!(f, "QR code error: {}", s),
+            Self::InvalidDate(s) => write!(f, "invalid date: {}", s),
+            Self::FieldTooLong{record_number, field_name, max_bytes, bytes} =>
+                write!(f, "{} of record {} is {} bytes long, over the limit of {} bytes",
+                    field_name, record_number, bytes, max_bytes),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::ParseIntError(e) => Some(e),
+            Self::ParseFloatError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<num::ParseIntError> for Error {
+    fn from(e: num::ParseIntError) -> Self {
+        Self::ParseIntError(e)
+    }
+}
+
+impl From<num::ParseFloatError> for Error {
+    fn from(e: num::ParseFloatError) -> Self {
+        Self::ParseFloatError(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+/// Where and why a record line failed to parse
+pub struct ParseError {
+    pub line: Option<usize>, // 1-based line number
+    pub record_number: Option<u32>, // レコード番号
+    pub field_index: Option<usize>, // 1-based field index, not counting the record number
+    pub field_name: Option<&'static str>, // 項目名
+    pub expected: String,
+    pub found: String,
+}
+
+impl ParseError {
+    /// A record line which is not allowed at this position.
+    pub fn unexpected(line: usize, s: &str, expected: &str) -> Self {
+        Self {
+            line: Some(line),
+            record_number: s.split(',').next().and_then(|v| v.parse().ok()),
+            expected: expected.to_string(),
+            found: s.to_string(),
+            .. Default::default()
+        }
+    }
+
+    /// A block which lacks a required record, located at the first line of the block.
+    pub fn missing(s: &str, expected: &str) -> Self {
+        Self::missing_in(&numbered_lines(s), expected)
+    }
+
+    /// A block of numbered lines which lacks a required record, located at
+    /// the first line of the block.
+    pub(crate) fn missing_in(lines: &[(usize, &str)], expected: &str) -> Self {
+        match lines.iter().find(|(_, line)| !line.is_empty()) {
+            Some(&(n, line)) => Self::unexpected(n, line, expected),
+            None => Self {
+                expected: expected.to_string(),
+                .. Default::default()
+            },
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "line {}: ", line)?;
+        }
+        if let Some(n) = self.record_number {
+            write!(f, "record {}", n)?;
+            if let Some(name) = record_name(n) {
+                write!(f, " ({})", name)?;
+            }
+        }
+        if let Some(i) = self.field_index {
+            write!(f, "{}field {}", if self.record_number.is_some() {", "} else {""}, i)?;
+            if let Some(name) = self.field_name {
+                write!(f, " ({})", name)?;
+            }
+        }
+        if self.record_number.is_some() || self.field_index.is_some() {
+            write!(f, ": ")?;
+        }
+        write!(f, "expected {}, found \"{}\"", self.expected, self.found)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 /// Appended Table 1: Japanese era calendar scheme (Gengo)
+///
+/// An era added to the registry after the release, see `register_era`, is
+/// held by `Other` with its code. With the `serde` feature, it is serialized
+/// as its code, e.g. `"R02"`.
 pub enum GengoYear {
     Reiwa(i32), // 令和
     Heisei(i32), // 平成
     Showa(i32), // 昭和
     Taisho(i32), // 大正
     Meiji(i32), // 明示
+    Other(char, i32), // 略号, 年
 }
 
 impl GengoYear {
-    pub fn to_code(&self) -> String {
+    /// Creates a year of the era of a code, using `Other` only for the
+    /// eras which have no variant.
+    pub fn new(code: char, year: i32) -> Self {
+        match code.to_ascii_uppercase() {
+            'R' => Self::Reiwa(year),
+            'H' => Self::Heisei(year),
+            'S' => Self::Showa(year),
+            'T' => Self::Taisho(year),
+            'M' => Self::Meiji(year),
+            c => Self::Other(c, year),
+        }
+    }
+
+    /// Code of the era, e.g. 'R'.
+    pub fn code(&self) -> char {
+        match *self {
+            Self::Reiwa(_) => 'R',
+            Self::Heisei(_) => 'H',
+            Self::Showa(_) => 'S',
+            Self::Taisho(_) => 'T',
+            Self::Meiji(_) => 'M',
+            Self::Other(c, _) => c,
+        }
+    }
+
+    /// Year of the era, where 1 is 元年.
+    pub fn year(&self) -> i32 {
         match *self {
-            Self::Reiwa(y) => format!("R{:>02}", y),
-            Self::Heisei(y) => format!("H{:>02}", y),
-            Self::Showa(y) => format!("S{:>02}", y),
-            Self::Taisho(y) => format!("T{:>02}", y),
-            Self::Meiji(y) => format!("M{:>02}", y),
+            Self::Reiwa(y) | Self::Heisei(y) | Self::Showa(y) | Self::Taisho(y) | Self::Meiji(y)
+                | Self::Other(_, y) => y,
         }
     }
+
+    /// The era in the registry, if it is registered.
+    pub fn era(&self) -> Option<Era> {
+        era_by_code(self.code())
+    }
+
+    /// Year in seireki, failing if the era is not registered.
+    pub fn to_seireki_year(&self) -> Result<i32, Error> {
+        self.era()
+            .map(|era| era.start.year() + self.year() - 1)
+            .ok_or_else(|| Error::InvalidArgument(
+                format!("Cannot find the era of code \"{}\"", self.code())
+            ))
+    }
+
+    pub fn to_code(&self) -> String {
+        format!("{}{:>02}", self.code(), self.year())
+    }
 }
 
 impl fmt::Display for GengoYear {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            Self::Reiwa(y) => write!(f, "令和{}年", if y == 1 {"元".to_string()} else {y.to_string()}),
-            Self::Heisei(y) => write!(f, "平成{}年", if y == 1 {"元".to_string()} else {y.to_string()}),
-            Self::Showa(y) => write!(f, "昭和{}年", if y == 1 {"元".to_string()} else {y.to_string()}),
-            Self::Taisho(y) => write!(f, "大正{}年", if y == 1 {"元".to_string()} else {y.to_string()}),
-            Self::Meiji(y) => write!(f, "明治{}年", if y == 1 {"元".to_string()} else {y.to_string()}),
-        }
+        let name = self.era().map_or_else(|| self.code().to_string(), |era| era.name);
+        let y = self.year();
+        write!(f, "{}{}年", name, if y == 1 {"元".to_string()} else {y.to_string()})
     }
 }
 
@@ -63,19 +273,17 @@ impl FromStr for GengoYear {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         lazy_static! {
-            static ref RE: Regex = Regex::new(r"^([RrHhSsTtMm㋿㍻㍼㍽㍾]|令和|平成|昭和|大正|明治)(\d+|元)年?$").unwrap();
-        }
-        for cap in RE.captures_iter(s) {
-            let year: i32 = if &cap[2] == "元" {1} else {(&cap[2]).parse().map_err(Error::ParseIntError)?};
-            match &cap[1] {
-                "令和" | "㋿" | "R" | "r" => return Ok(Self::Reiwa(year)),
-                "平成" | "㍻" | "H" | "h" => return Ok(Self::Heisei(year)),
-                "昭和" | "㍼" | "S" | "s" => return Ok(Self::Showa(year)),
-                "大正" | "㍽" | "T" | "t" => return Ok(Self::Taisho(year)),
-                "明治" | "㍾" | "M" | "m" => return Ok(Self::Meiji(year)),
-                _ => return Err(Error::Unreachable(
-                    format!("Unreachable code in GengoYear, got \"{}\"", s)
-                )),
+            static ref RE: Regex = Regex::new(r"^(\D+?)(\d+|元)年?$").unwrap();
+        }
+        if let Some(cap) = RE.captures(s) {
+            let year: i32 = if &cap[2] == "元" {1} else {cap[2].parse().map_err(Error::ParseIntError)?};
+            let mut chars = cap[1].chars();
+            let era = match (chars.next(), chars.next()) {
+                (Some(c), None) if c.is_ascii_alphabetic() => era_by_code(c),
+                _ => era_by_name(&cap[1]),
+            };
+            if let Some(era) = era {
+                return Ok(Self::new(era.code, year));
             }
         }
         Err(Error::InvalidArgument(
@@ -84,79 +292,249 @@ impl FromStr for GengoYear {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+/// How precisely a date is known
+pub enum DatePrecision {
+    Year, // 年
+    Month, // 年月
+    Day, // 年月日
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 /// A struct that holds date in seireki or in wareki
+///
+/// A date may be known only to the month or to the year, e.g. when a patient
+/// remembers only the month they started an OTC drug. Dates are ordered by
+/// the first day they may be.
+///
+/// With the `serde` feature, a date is serialized as its code in the record,
+/// e.g. `"20200501"`, `"R020501"`, `"202005"` or `"R02"`, which keeps the
+/// calendar and the precision.
 pub enum Date {
     Seireki{year: i32, month: u32, day: u32},
     Wareki{gengo_year: GengoYear, month: u32, day: u32},
+    SeirekiYearMonth{year: i32, month: u32},
+    WarekiYearMonth{gengo_year: GengoYear, month: u32},
+    SeirekiYear{year: i32},
+    WarekiYear{gengo_year: GengoYear},
 }
 
+/// Era year (`None` in seireki), seireki year, month and day of a date
+type DateParts = (Option<GengoYear>, i32, Option<u32>, Option<u32>);
+
 impl Date {
-    pub fn to_naivedate(&self) -> chrono::NaiveDate {
+    /// Creates a date in seireki, failing if there is no such day.
+    pub fn new_seireki(year: i32, month: u32, day: u32) -> Result<Self, Error> {
+        let date = Self::Seireki{year, month, day};
+        date.first_day()?;
+        Ok(date)
+    }
+
+    /// Creates a date in wareki, failing if there is no such day or the day
+    /// is not in the era.
+    pub fn new_wareki(gengo_year: GengoYear, month: u32, day: u32) -> Result<Self, Error> {
+        let date = Self::Wareki{gengo_year, month, day};
+        date.first_day()?;
+        Ok(date)
+    }
+
+    /// Creates a month in seireki, failing if there is no such month.
+    pub fn new_seireki_year_month(year: i32, month: u32) -> Result<Self, Error> {
+        let date = Self::SeirekiYearMonth{year, month};
+        date.first_day()?;
+        Ok(date)
+    }
+
+    /// Creates a month in wareki, failing if there is no such month or no
+    /// day of it is in the era.
+    pub fn new_wareki_year_month(gengo_year: GengoYear, month: u32) -> Result<Self, Error> {
+        let date = Self::WarekiYearMonth{gengo_year, month};
+        date.first_day()?;
+        Ok(date)
+    }
+
+    pub fn new_seireki_year(year: i32) -> Result<Self, Error> {
+        let date = Self::SeirekiYear{year};
+        date.first_day()?;
+        Ok(date)
+    }
+
+    /// Creates a year in wareki, failing if the era has no such year.
+    pub fn new_wareki_year(gengo_year: GengoYear) -> Result<Self, Error> {
+        let date = Self::WarekiYear{gengo_year};
+        date.first_day()?;
+        Ok(date)
+    }
+
+    pub fn precision(&self) -> DatePrecision {
         match *self {
-            Self::Seireki{year: y, month: m, day: d} => chrono::NaiveDate::from_ymd(y, m, d),
-            Self::Wareki{gengo_year: gy, month: m, day: d} => {
-                match gy {
-                    GengoYear::Reiwa(y) => chrono::NaiveDate::from_ymd(y + 2018, m, d),
-                    GengoYear::Heisei(y) => chrono::NaiveDate::from_ymd(y + 1988, m, d),
-                    GengoYear::Showa(y) => chrono::NaiveDate::from_ymd(y + 1925, m, d),
-                    GengoYear::Taisho(y) => chrono::NaiveDate::from_ymd(y + 1911, m, d),
-                    GengoYear::Meiji(y) => chrono::NaiveDate::from_ymd(y + 1867, m, d),
-                }
-            }
+            Self::Seireki{..} | Self::Wareki{..} => DatePrecision::Day,
+            Self::SeirekiYearMonth{..} | Self::WarekiYearMonth{..} => DatePrecision::Month,
+            Self::SeirekiYear{..} | Self::WarekiYear{..} => DatePrecision::Year,
+        }
+    }
+
+    /// Returns the era year, the seireki year, the month and the day, of
+    /// which the era year is `None` in seireki, failing if the era is not
+    /// registered.
+    fn parts(&self) -> Result<DateParts, Error> {
+        let seireki_year = |gy: GengoYear| gy.to_seireki_year().map_err(|_| Error::InvalidDate(
+            format!("there is no era of code \"{}\"", gy.code())
+        ));
+        Ok(match *self {
+            Self::Seireki{year: y, month: m, day: d} => (None, y, Some(m), Some(d)),
+            Self::Wareki{gengo_year: gy, month: m, day: d} => (Some(gy), seireki_year(gy)?, Some(m), Some(d)),
+            Self::SeirekiYearMonth{year: y, month: m} => (None, y, Some(m), None),
+            Self::WarekiYearMonth{gengo_year: gy, month: m} => (Some(gy), seireki_year(gy)?, Some(m), None),
+            Self::SeirekiYear{year: y} => (None, y, None, None),
+            Self::WarekiYear{gengo_year: gy} => (Some(gy), seireki_year(gy)?, None, None),
+        })
+    }
+
+    /// Returns the first and the last days the date may be, failing if there
+    /// is no such date or a date in wareki is not in its era. The days of a
+    /// month or a year in wareki are limited to those in the era.
+    fn period(&self) -> Result<(chrono::NaiveDate, chrono::NaiveDate), Error> {
+        let (gengo_year, year, month, day) = self.parts()?;
+        let no_such_date = || Error::InvalidDate(format!("there is no such date as {}", self));
+        let (first_month, last_month) = month.map_or((1, 12), |m| (m, m));
+        let first = chrono::NaiveDate::from_ymd_opt(year, first_month, day.unwrap_or(1))
+            .ok_or_else(no_such_date)?;
+        let last = match day {
+            Some(_) => first,
+            None if last_month == 12 => chrono::NaiveDate::from_ymd_opt(year, 12, 31)
+                .ok_or_else(no_such_date)?,
+            None => chrono::NaiveDate::from_ymd_opt(year, last_month + 1, 1)
+                .and_then(|d| d.pred_opt())
+                .ok_or_else(no_such_date)?,
+        };
+        let gy = match gengo_year {
+            Some(gy) => gy,
+            None => return Ok((first, last)),
+        };
+
+        let era = gy.era().ok_or_else(|| Error::InvalidDate(
+            format!("there is no era of code \"{}\"", gy.code())
+        ))?;
+        if gy.year() < 1 {
+            return Err(Error::InvalidDate(format!("{} is not a valid year", gy)));
+        }
+        if last < era.start {
+            return Err(Error::InvalidDate(
+                format!("{} is before {} began on {}", self, era.name, era.start)
+            ));
+        }
+        match era_end(&era) {
+            Some(end) if first > end => Err(Error::InvalidDate(
+                format!("{} is after {} ended on {}", self, era.name, end)
+            )),
+            end => Ok((first.max(era.start), end.map_or(last, |end| last.min(end)))),
+        }
+    }
+
+    /// The first day the date may be, failing if there is no such date or
+    /// a date in wareki is not in its era.
+    pub fn first_day(&self) -> Result<chrono::NaiveDate, Error> {
+        self.period().map(|(first, _)| first)
+    }
+
+    /// The last day the date may be, failing if there is no such date or
+    /// a date in wareki is not in its era.
+    pub fn last_day(&self) -> Result<chrono::NaiveDate, Error> {
+        self.period().map(|(_, last)| last)
+    }
+
+    /// Converts to `chrono::NaiveDate`, failing if there is no such day, a
+    /// date in wareki is not in its era, or the day is not known.
+    pub fn try_to_naivedate(&self) -> Result<chrono::NaiveDate, Error> {
+        let first = self.first_day()?;
+        if self.precision() == DatePrecision::Day {
+            Ok(first)
+        } else {
+            Err(Error::InvalidDate(format!("the day of {} is not known", self)))
         }
     }
 
+    /// Converts to `chrono::NaiveDate`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no such day, a date in wareki is not in its era, or
+    /// the day is not known.
+    #[deprecated(note = "use `try_to_naivedate`, which returns an error instead of panicking")]
+    pub fn to_naivedate(&self) -> chrono::NaiveDate {
+        self.try_to_naivedate().unwrap()
+    }
+
     pub fn to_code(&self) -> String {
         match *self {
             Self::Seireki{year: y, month: m, day: d} => format!("{:>04}{:>02}{:>02}", y, m, d),
             Self::Wareki{gengo_year: gy, month: m, day: d} => format!("{}{:>02}{:>02}", gy.to_code(), m, d),
+            Self::SeirekiYearMonth{year: y, month: m} => format!("{:>04}{:>02}", y, m),
+            Self::WarekiYearMonth{gengo_year: gy, month: m} => format!("{}{:>02}", gy.to_code(), m),
+            Self::SeirekiYear{year: y} => format!("{:>04}", y),
+            Self::WarekiYear{gengo_year: gy} => gy.to_code(),
         }
     }
 
-    pub fn to_seireki8(&self) -> String {
-        match *self {
-            Self::Seireki{year: y, month: m, day: d} => format!("{:>04}{:>02}{:>02}", y, m, d),
-            Self::Wareki{gengo_year: gy, month: m, day: d} => {
-                match gy {
-                    GengoYear::Reiwa(y) => format!("{:>04}{:>02}{:>02}", y + 2018, m, d),
-                    GengoYear::Heisei(y) => format!("{:>04}{:>02}{:>02}", y + 1988, m, d),
-                    GengoYear::Showa(y) => format!("{:>04}{:>02}{:>02}", y + 1925, m, d),
-                    GengoYear::Taisho(y) => format!("{:>04}{:>02}{:>02}", y + 1911, m, d),
-                    GengoYear::Meiji(y) => format!("{:>04}{:>02}{:>02}", y + 1867, m, d),
-                }
-            }
+    /// Formats the date as YYYYMMDD, or as YYYYMM or YYYY for a month or
+    /// a year, failing if the era of a date in wareki is not registered.
+    pub fn try_to_seireki8(&self) -> Result<String, Error> {
+        let (_, y, m, d) = self.parts()?;
+        let mut code = format!("{:>04}", y);
+        for n in [m, d].iter().flatten() {
+            code.push_str(&format!("{:>02}", n));
         }
+        Ok(code)
+    }
+
+    /// Formats the date as YYYYMMDD, or as YYYYMM or YYYY for a month or
+    /// a year.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the era of a date in wareki is not registered.
+    #[deprecated(note = "use `try_to_seireki8`, which returns an error instead of panicking")]
+    pub fn to_seireki8(&self) -> String {
+        self.try_to_seireki8().unwrap()
     }
 
+    /// Formats the date as GYYMMDD, or as GYYMM or GYY for a month or a
+    /// year. A month or a year in seireki is in the era of its first day.
     pub fn try_to_wareki7(&self) -> Result<String, Error> {
+        Ok(self.to_wareki()?.to_code())
+    }
+
+    /// Converts to the same date in seireki, failing if the era is not
+    /// registered.
+    pub fn to_seireki(&self) -> Result<Self, Error> {
         match *self {
-            Self::Seireki{year: y, month: m, day: d} => {
-                if y > 2019 || y == 2019 && m >= 5 {
-                    return Ok(format!("R{:>02}{:>02}{:>02}", y - 2018, m, d));
-                } else if y > 1989 || y == 1989 && m > 1 || y == 1989 && m == 1 && d >= 8 {
-                    return Ok(format!("H{:>02}{:>02}{:>02}", y - 1988, m, d));
-                } else if y > 1926 || y == 1926 && m == 12 && d >= 25 {
-                    return Ok(format!("S{:>02}{:>02}{:>02}", y - 1925, m, d));
-                } else if y > 1912 || y == 1912 && m > 7 || y == 1912 && m == 7 && d >= 30 {
-                    return Ok(format!("T{:>02}{:>02}{:>02}", y - 1911, m, d));
-                } else if y > 1872 {
-                    return Ok(format!("M{:>02}{:>02}{:>02}", y - 1867, m, d));
-                } else {
-                    return Err(Error::InvalidArgument(
-                        format!("Cannot convert seireki8 to wareki7, got \"{:?}\"", *self)
-                    ));
-                }
-            },
-            Self::Wareki{gengo_year: gy, month: m, day: d} => {
-                match gy {
-                    GengoYear::Reiwa(y) => Ok(format!("R{:>02}{:>02}{:>02}", y, m, d)),
-                    GengoYear::Heisei(y) => Ok(format!("H{:>02}{:>02}{:>02}", y, m, d)),
-                    GengoYear::Showa(y) => Ok(format!("S{:>02}{:>02}{:>02}", y, m, d)),
-                    GengoYear::Taisho(y) => Ok(format!("T{:>02}{:>02}{:>02}", y, m, d)),
-                    GengoYear::Meiji(y) => Ok(format!("M{:>02}{:>02}{:>02}", y, m, d)),
-                }
-            },
+            Self::Wareki{gengo_year: gy, month, day} => Ok(Self::Seireki{year: gy.to_seireki_year()?, month, day}),
+            Self::WarekiYearMonth{gengo_year: gy, month} => Ok(Self::SeirekiYearMonth{year: gy.to_seireki_year()?, month}),
+            Self::WarekiYear{gengo_year: gy} => Ok(Self::SeirekiYear{year: gy.to_seireki_year()?}),
+            date => Ok(date),
+        }
+    }
+
+    /// Converts to the same date in wareki, failing if there is no such day
+    /// or it is before the first era. A month or a year is in the era of its
+    /// first day.
+    pub fn to_wareki(&self) -> Result<Self, Error> {
+        let (gengo_year, y, _, _) = self.parts()?;
+        if gengo_year.is_some() {
+            return Ok(*self);
+        }
+        let first = self.first_day()?;
+        let era = era_of(first).ok_or_else(|| Error::InvalidArgument(
+            format!("Cannot convert seireki8 to wareki7, got \"{:?}\"", *self)
+        ))?;
+        let gy = GengoYear::new(era.code, y - era.start.year() + 1);
+        match *self {
+            Self::Seireki{month, day, ..} => Ok(Self::Wareki{gengo_year: gy, month, day}),
+            Self::SeirekiYearMonth{month, ..} => Ok(Self::WarekiYearMonth{gengo_year: gy, month}),
+            _ => Ok(Self::WarekiYear{gengo_year: gy}),
         }
     }
 }
@@ -166,31 +544,60 @@ impl fmt::Display for Date {
         match *self {
             Self::Seireki{year: y, month: m, day: d} => write!(f, "{}年{}月{}日", y, m, d),
             Self::Wareki{gengo_year: gy, month: m, day: d} => write!(f, "{}{}月{}日", gy, m, d),
+            Self::SeirekiYearMonth{year: y, month: m} => write!(f, "{}年{}月", y, m),
+            Self::WarekiYearMonth{gengo_year: gy, month: m} => write!(f, "{}{}月", gy, m),
+            Self::SeirekiYear{year: y} => write!(f, "{}年", y),
+            Self::WarekiYear{gengo_year: gy} => write!(f, "{}", gy),
         }
     }
 }
 
+impl PartialOrd for Date {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Dates are compared by their first days, then a less precise date comes
+/// first, then seireki comes before wareki. Dates in unregistered eras come
+/// first, ordered by their codes.
+impl Ord for Date {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        let key = |date: &Self| {
+            let parts = date.parts().ok().map(|(_, y, m, d)| (y, m, d));
+            let gengo_year = match *date {
+                Self::Wareki{gengo_year: gy, ..} | Self::WarekiYearMonth{gengo_year: gy, ..}
+                    | Self::WarekiYear{gengo_year: gy} => Some((gy.code(), gy.year())),
+                _ => None,
+            };
+            (date.first_day().ok(), parts, date.precision(), gengo_year, date.to_code())
+        };
+        key(self).cmp(&key(other))
+    }
+}
+
 impl FromStr for Date {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         lazy_static! {
-            static ref RE_SEIREKI8: Regex = Regex::new(r"^(\d{4})(\d{2})(\d{2})?$").unwrap();
-            static ref RE_WAREKI7: Regex = Regex::new(r"^([RHSTM]\d{2})(\d{2})(\d{2})$").unwrap();
-        }
-        if RE_SEIREKI8.is_match(s) {
-            for cap in RE_SEIREKI8.captures_iter(s) {
-                let y: i32 = (&cap[1]).parse().unwrap();
-                let m: u32 = (&cap[2]).parse().unwrap();
-                let d: u32 = (&cap[3]).parse().unwrap();
-                return Ok(Date::Seireki{year: y, month: m, day: d})
-            }
-        } else if RE_WAREKI7.is_match(s) {
-            for cap in RE_WAREKI7.captures_iter(s) {
-                let gy: &str = &cap[1];
-                let m: u32 = (&cap[2]).parse().unwrap();
-                let d: u32 = (&cap[3]).parse().unwrap();
-                return Ok(Date::Wareki{gengo_year: gy.parse::<GengoYear>().unwrap(), month: m, day: d})
-            }
+            static ref RE_SEIREKI: Regex = Regex::new(r"^(\d{4})(?:(\d{2})(\d{2})?)?$").unwrap();
+            static ref RE_WAREKI: Regex = Regex::new(r"^([A-Z]\d{2})(?:(\d{2})(\d{2})?)?$").unwrap();
+        }
+        if let Some(cap) = RE_SEIREKI.captures(s) {
+            let year: i32 = cap[1].parse()?;
+            return match (cap.get(2), cap.get(3)) {
+                (Some(m), Some(d)) => Self::new_seireki(year, m.as_str().parse()?, d.as_str().parse()?),
+                (Some(m), None) => Self::new_seireki_year_month(year, m.as_str().parse()?),
+                _ => Self::new_seireki_year(year),
+            };
+        }
+        if let Some(cap) = RE_WAREKI.captures(s) {
+            let gengo_year: GengoYear = cap[1].parse()?;
+            return match (cap.get(2), cap.get(3)) {
+                (Some(m), Some(d)) => Self::new_wareki(gengo_year, m.as_str().parse()?, d.as_str().parse()?),
+                (Some(m), None) => Self::new_wareki_year_month(gengo_year, m.as_str().parse()?),
+                _ => Self::new_wareki_year(gengo_year),
+            };
         }
         Err(Error::InvalidArgument(
             format!("Cannot convert str to Date, got \"{}\"", s)
@@ -203,24 +610,19 @@ impl From<chrono::NaiveDate> for Date {
         Self::Seireki{year: d.year(), month: d.month(), day: d.day()}
     }
 }
+/// # Panics
+///
+/// Panics if there is no such day, a date in wareki is not in its era, or
+/// the day is not known; use `Date::try_to_naivedate` to get an error
+/// instead.
 impl From<Date> for chrono::NaiveDate {
     fn from(d: Date) -> Self {
-        match d {
-            Date::Seireki{year: y, month: m, day: d} => chrono::NaiveDate::from_ymd(y, m, d),
-            Date::Wareki{gengo_year: gy, month: m, day: d} => {
-                match gy {
-                    GengoYear::Reiwa(y) => chrono::NaiveDate::from_ymd(y + 2018, m, d),
-                    GengoYear::Heisei(y) => chrono::NaiveDate::from_ymd(y + 1988, m, d),
-                    GengoYear::Showa(y) => chrono::NaiveDate::from_ymd(y + 1925, m, d),
-                    GengoYear::Taisho(y) => chrono::NaiveDate::from_ymd(y + 1911, m, d),
-                    GengoYear::Meiji(y) => chrono::NaiveDate::from_ymd(y + 1867, m, d),
-                }
-            }
-        }
+        d.try_to_naivedate().unwrap()
     }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// Appended Table 2: Prefecture Code, which confirms to JIS X 0401:1973 and ISO 3166-2:JP
 pub enum Prefecture {
     Hokkaido = 1,
@@ -449,6 +851,7 @@ impl TryFrom<u32> for Prefecture {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// Appended Table 3: Type of Medical Fee Table
 pub enum FeeTable {
     Medicine = 1, // 医科
@@ -501,6 +904,7 @@ impl TryFrom<u32> for FeeTable {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// Appended Table 4: Type of dosage form
 pub enum DosageForm {
     OralAdministration = 1, // 内服
@@ -577,6 +981,7 @@ impl TryFrom<u32> for DosageForm {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RecordCreator {
     MedicalExpert = 1, // 医療関係者
     Patient = 2, // 患者等
@@ -632,8 +1037,10 @@ impl TryFrom<u32> for RecordCreator {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum OutputCategory {
     ToPatient = 1, // 医療機関・薬局から患者等に情報を提供する場合
+    #[cfg_attr(feature = "serde", serde(rename = "FromPatient", alias = "FromPatinet"))]
     FromPatinet = 2, // 患者等から医療機関・薬局に情報を提供する場合
 }
 
@@ -679,6 +1086,7 @@ impl TryFrom<u32> for OutputCategory {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Gender {
     Male = 1, // 男性
     Female = 2, // 女性
@@ -726,6 +1134,7 @@ impl TryFrom<u32> for Gender {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SpecialPatientNoteCategory {
     Allergy = 1, // アレルギー歴
     AdverseEvent = 2, // 副作用歴
@@ -781,6 +1190,7 @@ impl TryFrom<u32> for SpecialPatientNoteCategory {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DrugCodeType {
     None, // コードなし
     Receipt, // レセプト電算コード
@@ -846,6 +1256,7 @@ impl TryFrom<u32> for DrugCodeType {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum UsageCodeType {
     None, // コードなし
     Jami, // JAMI用法コード
@@ -896,6 +1307,7 @@ impl TryFrom<u32> for UsageCodeType {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ProvidedInformationType {
     AdverseEventInHospital, // 30: 入院中に副作用が発現した薬剤に関する情報
     PostDischargeCare, // 31: 退院後の療養を担う保険医療機関での投薬又は
@@ -958,27 +1370,312 @@ impl TryFrom<u32> for ProvidedInformationType {
 pub trait Record {
     fn record_number(&self) -> u32;
     fn cols(&self) -> u32;
+
+    /// Maximum length of each field in Shift_JIS (CP932) bytes.
+    fn field_max_bytes(&self) -> &'static [usize] {
+        field_max_bytes(self.record_number()).unwrap_or(&[])
+    }
+}
+
+/// Returns the name of the record type for a record number.
+pub fn record_name(record_number: u32) -> Option<&'static str> {
+    match record_number {
+        1 => Some("PatientRecord"),
+        2 => Some("SpecialPatientNoteRecord"),
+        3 => Some("OtcDrugRecord"),
+        4 => Some("MemoRecord"),
+        5 => Some("DateRecord"),
+        11 => Some("PharmacyRecord"),
+        15 => Some("PharmacistRecord"),
+        51 => Some("MedicalInstitutionRecord"),
+        55 => Some("PhysicianRecord"),
+        201 => Some("DrugRecord"),
+        281 => Some("DrugSupplementaryRecord"),
+        291 => Some("DrugNoticeRecord"),
+        301 => Some("UsageRecord"),
+        311 => Some("UsageSupplementaryRecord"),
+        391 => Some("RpNoticeRecord"),
+        401 => Some("NoticeRecord"),
+        411 => Some("InformationProvisionRecord"),
+        421 => Some("RemainingMedicineRecord"),
+        501 => Some("NoteRecord"),
+        601 => Some("FromPatientRecord"),
+        701 => Some("FamilyPharmacistRecord"),
+        911 => Some("SplitControlRecord"),
+        _ => None,
+    }
+}
+
+/// Returns the names of the fields following the record number,
+/// as written in the specification.
+pub fn field_names(record_number: u32) -> Option<&'static [&'static str]> {
+    match record_number {
+        1 => Some(&["患者氏名", "患者性別", "患者生年月日", "患者郵便番号", "患者住所",
+                    "患者電話番号", "緊急連絡先", "血液型", "体重", "患者氏名カナ"]),
+        2 => Some(&["患者特記種別", "患者特記内容", "レコード作成者"]),
+        3 => Some(&["薬品名称", "服用開始年月日", "服用終了年月日", "レコード作成者"]),
+        4 => Some(&["手帳メモ情報", "メモ入力年月日", "レコード作成者"]),
+        5 => Some(&["調剤等年月日", "レコード作成者"]),
+        11 => Some(&["医療機関等名称", "医療機関等都道府県", "医療機関等点数表", "医療機関等コード",
+                     "医療機関等郵便番号", "医療機関等住所", "医療機関等電話番号", "レコード作成者"]),
+        15 => Some(&["医師・薬剤師氏名", "医師・薬剤師連絡先", "レコード作成者"]),
+        51 => Some(&["医療機関名称", "医療機関都道府県", "医療機関点数表", "医療機関コード",
+                     "レコード作成者"]),
+        55 => Some(&["医師氏名", "診療科名", "レコード作成者"]),
+        201 => Some(&["RP番号", "薬品名称", "用量", "単位名", "薬品コード種別", "薬品コード",
+                      "レコード作成者"]),
+        281 => Some(&["RP番号", "薬品補足情報", "レコード作成者"]),
+        291 => Some(&["RP番号", "内容", "レコード作成者"]),
+        301 => Some(&["RP番号", "用法名称", "調剤数量", "調剤単位", "剤型コード", "用法コード種別",
+                      "用法コード", "レコード作成者"]),
+        311 => Some(&["RP番号", "用法補足情報", "レコード作成者"]),
+        391 => Some(&["RP番号", "内容", "レコード作成者"]),
+        401 => Some(&["内容", "レコード作成者"]),
+        411 => Some(&["内容", "提供情報種別", "レコード作成者"]),
+        421 => Some(&["残薬確認内容", "レコード作成者"]),
+        501 => Some(&["備考情報", "レコード作成者"]),
+        601 => Some(&["患者等記入情報", "入力年月日"]),
+        701 => Some(&["かかりつけ薬剤師氏名", "勤務先薬局名称", "連絡先", "担当開始年月日",
+                      "担当終了年月日", "レコード作成者"]),
+        911 => Some(&["データID", "最大分割数", "分割番号"]),
+        _ => None,
+    }
+}
+
+/// Returns the maximum length in Shift_JIS (CP932) bytes of each field
+/// following the record number.
+pub fn field_max_bytes(record_number: u32) -> Option<&'static [usize]> {
+    match record_number {
+        1 => Some(&[40, 1, 8, 8, 200, 15, 200, 10, 5, 40]),
+        2 => Some(&[1, 400, 1]),
+        3 => Some(&[200, 8, 8, 1]),
+        4 => Some(&[400, 8, 1]),
+        5 => Some(&[8, 1]),
+        11 => Some(&[200, 2, 1, 7, 8, 200, 15, 1]),
+        15 => Some(&[40, 15, 1]),
+        51 => Some(&[200, 2, 1, 7, 1]),
+        55 => Some(&[40, 80, 1]),
+        201 => Some(&[3, 200, 20, 20, 1, 20, 1]),
+        281 | 291 | 311 | 391 => Some(&[3, 400, 1]),
+        301 => Some(&[3, 200, 5, 20, 1, 1, 20, 1]),
+        401 | 421 | 501 => Some(&[400, 1]),
+        411 => Some(&[400, 2, 1]),
+        601 => Some(&[400, 8]),
+        701 => Some(&[40, 200, 200, 8, 8, 1]),
+        911 => Some(&[40, 3, 3]),
+        _ => None,
+    }
+}
+
+const TEXT: &str = r"[^,\r\n]*";
+const DATE: &str = r"\d{8}|\w\d{6}";
+const OPTIONAL_DATE: &str = r"(?:\d{8}|\w\d{6})?";
+const OPTIONAL_PARTIAL_DATE: &str = r"(?:\d{4}(?:\d{2}){0,2}|\w\d{2}(?:\d{2}){0,2})?";
+const CREATOR: &str = r"\d";
+
+/// Returns the patterns of the fields following the record number.
+fn field_patterns(record_number: u32) -> Option<&'static [&'static str]> {
+    match record_number {
+        1 => Some(&[TEXT, r"\d", DATE, TEXT, TEXT, TEXT, TEXT, TEXT,
+                    r"(?:[0-9]+(?:[.][0-9]*)?|[.][0-9]+)?", TEXT]),
+        2 => Some(&[r"\d", TEXT, CREATOR]),
+        3 => Some(&[TEXT, OPTIONAL_PARTIAL_DATE, OPTIONAL_PARTIAL_DATE, CREATOR]),
+        4 => Some(&[TEXT, OPTIONAL_PARTIAL_DATE, CREATOR]),
+        5 => Some(&[DATE, CREATOR]),
+        11 => Some(&[TEXT, r"\d{0,2}", r"\d?", TEXT, TEXT, TEXT, TEXT, CREATOR]),
+        15 => Some(&[TEXT, TEXT, CREATOR]),
+        51 => Some(&[TEXT, r"\d{0,2}", r"\d?", TEXT, CREATOR]),
+        55 => Some(&[TEXT, TEXT, CREATOR]),
+        201 => Some(&[r"\d+", TEXT, TEXT, TEXT, r"\d?", TEXT, CREATOR]),
+        281 | 291 | 311 | 391 => Some(&[r"\d+", TEXT, CREATOR]),
+        301 => Some(&[r"\d+", TEXT, r"\d*", TEXT, r"\d*", r"\d?", TEXT, CREATOR]),
+        401 | 421 | 501 => Some(&[TEXT, CREATOR]),
+        411 => Some(&[TEXT, r"\d{1,2}", CREATOR]),
+        601 => Some(&[TEXT, OPTIONAL_DATE]),
+        701 => Some(&[TEXT, TEXT, TEXT, OPTIONAL_DATE, OPTIONAL_DATE, CREATOR]),
+        911 => Some(&[TEXT, r"\d+", r"\d+"]),
+        _ => None,
+    }
+}
+
+/// Returns whether a field may be left empty.
+pub(crate) fn is_optional_field(record_number: u32, field_index: usize) -> bool {
+    field_patterns(record_number)
+        .and_then(|patterns| patterns.get(field_index - 1))
+        .is_none_or(|pattern| field_regex(pattern).is_match(""))
+}
+
+/// Returns the compiled regex of a whole field for a pattern of
+/// `field_patterns`.
+fn field_regex(pattern: &str) -> &'static Regex {
+    lazy_static! {
+        static ref RES: HashMap<&'static str, Regex> = (0..1000)
+            .filter_map(field_patterns)
+            .flatten()
+            .map(|&p| (p, Regex::new(&format!("^(?:{})$", p)).unwrap()))
+            .collect();
+    }
+    &RES[pattern]
+}
+
+fn describe_pattern(pattern: &str) -> String {
+    match pattern {
+        TEXT => "text without commas or line breaks".to_string(),
+        DATE => "a date as YYYYMMDD or GYYMMDD".to_string(),
+        OPTIONAL_DATE => "an empty field or a date as YYYYMMDD or GYYMMDD".to_string(),
+        OPTIONAL_PARTIAL_DATE => "an empty field or a date as YYYYMMDD, YYYYMM, YYYY, GYYMMDD, GYYMM or GYY".to_string(),
+        r"\d" => "a one-digit code".to_string(),
+        r"\d?" => "an empty field or a one-digit code".to_string(),
+        r"\d+" => "a number".to_string(),
+        r"\d*" => "an empty field or a number".to_string(),
+        r"\d{0,2}" => "an empty field or a code of up to two digits".to_string(),
+        r"\d{1,2}" => "a code of one or two digits".to_string(),
+        _ => format!("a value matching /{}/", pattern),
+    }
+}
+
+/// Builds the regex of a whole record line from its field patterns. The
+/// last capture holds the columns following the defined fields.
+fn record_regex(record_number: u32) -> Regex {
+    let fields: Vec<String> = field_patterns(record_number).unwrap_or(&[])
+        .iter()
+        .map(|p| format!("({})", p))
+        .collect();
+    Regex::new(&format!(r"^(\d+),{}((?:,[^,]*)*)$", fields.join(","))).unwrap()
+}
+
+/// Collects the columns following the defined fields of a record line,
+/// which are kept so that they are written back unchanged.
+fn extra_fields(cap: &regex::Captures) -> Vec<String> {
+    match cap.get(cap.len() - 1).map(|m| m.as_str()) {
+        Some(extra) if !extra.is_empty() => extra[1..].split(',').map(String::from).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Escapes the value of the `field_index`-th field of a record by `policy`,
+/// naming the field when a character is rejected.
+fn escape_field(value: &str, policy: EscapePolicy, record_number: u32, field_index: usize) -> Result<String, Error> {
+    let field_name = field_names(record_number)
+        .and_then(|names| names.get(field_index - 1).copied())
+        .unwrap_or("追加項目");
+    match policy.escape(value) {
+        Ok(s) => Ok(s.into_owned()),
+        Err(character) => Err(Error::ForbiddenCharacter {record_number, field_name, character}),
+    }
+}
+
+/// Unwraps a serialization by `EscapePolicy::FullWidth`, which replaces the
+/// forbidden characters instead of rejecting them and so cannot fail.
+pub(crate) fn full_width<T>(result: Result<T, Error>) -> T {
+    result.expect("EscapePolicy::FullWidth does not reject any character")
+}
+
+/// Formats the extra columns of a record, each preceded by a comma.
+/// `first_index` is the field index of the first one.
+fn extra_code(extra: &[String], policy: EscapePolicy, record_number: u32, first_index: usize) -> Result<String, Error> {
+    let mut code = String::new();
+    for (i, col) in extra.iter().enumerate() {
+        code.push(',');
+        code.push_str(&escape_field(col, policy, record_number, first_index + i)?);
+    }
+    Ok(code)
+}
+
+/// Explains why a line does not match the record it was parsed as.
+fn record_line_error(s: &str, record_number: u32) -> Error {
+    let cols: Vec<&str> = s.split(',').collect();
+    let patterns = field_patterns(record_number).unwrap_or(&[]);
+    let mut e = ParseError {
+        record_number: Some(record_number),
+        found: s.to_string(),
+        .. Default::default()
+    };
+    if cols[0] != record_number.to_string() {
+        e.expected = format!("record number {}", record_number);
+    } else if cols.len() - 1 < patterns.len() {
+        e.expected = format!("at least {} fields after the record number, but there are {}",
+            patterns.len(), cols.len() - 1);
+    } else {
+        for (i, (col, pattern)) in cols[1..].iter().zip(patterns).enumerate() {
+            if !field_regex(pattern).is_match(col) {
+                e.field_index = Some(i + 1);
+                e.field_name = field_names(record_number).and_then(|names| names.get(i).copied());
+                e.expected = describe_pattern(pattern);
+                e.found = col.to_string();
+                break;
+            }
+        }
+    }
+    Error::InvalidRecordLine(e)
+}
+
+/// Parses the `i`-th capture of a record line, reporting the field on failure.
+fn parse_field<T>(cap: &regex::Captures, i: usize, record_number: u32) -> Result<T, Error>
+        where T: FromStr, Error: From<T::Err> {
+    cap[i].parse().map_err(|e| {
+        let type_name = std::any::type_name::<T>();
+        let type_name = type_name.rsplit("::").next().unwrap_or(type_name);
+        Error::InvalidRecordLine(ParseError {
+            record_number: Some(record_number),
+            field_index: Some(i - 1),
+            field_name: field_names(record_number).and_then(|names| names.get(i - 2).copied()),
+            expected: match Error::from(e) {
+                Error::InvalidDate(reason) => format!("a valid {} ({})", type_name, reason),
+                _ => format!("a valid {}", type_name),
+            },
+            found: cap[i].to_string(),
+            .. Default::default()
+        })
+    })
+}
+
+/// Parses one line of a block, attaching its line number to any error.
+pub(crate) fn parse_line<T: FromStr<Err = Error>>(s: &str, line: usize) -> Result<T, Error> {
+    s.parse().map_err(|e: Error| e.at_line(line))
+}
+
+/// Splits a string into its lines, each with its 1-based line number.
+pub(crate) fn numbered_lines(s: &str) -> Vec<(usize, &str)> {
+    s.lines().enumerate().map(|(i, line)| (i + 1, line)).collect()
+}
+
+/// A block parsed from its record lines, each given with its 1-based line
+/// number, so that the lines collected for a nested block are parsed as they
+/// are and errors point at the lines as they were read.
+pub(crate) trait FromLines: Sized {
+    fn from_lines(lines: &[(usize, &str)]) -> Result<Self, Error>;
 }
 
 /// Version record (バージョンレコード)
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct VersionRecord {
     pub number: u32,
     pub output_category: OutputCategory, 
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub extra: Vec<String>, // 追加項目
 }
 
 impl VersionRecord {
     pub fn new(number: u32, output_category: OutputCategory) -> Self {
-        Self {number: number, output_category: output_category}
+        Self {number, output_category, extra: Vec::new()}
     }
+    /// Serializes the record by `EscapePolicy::FullWidth`, which is lossy.
     pub fn to_code(&self) -> String {
-        format!("JAHISTC{:>02},{}", self.number, self.output_category.to_code())
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+    /// The version record has no record number, so errors report it as 0.
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        Ok(format!("JAHISTC{:>02},{}{}", self.number, self.output_category.to_code(),
+            extra_code(&self.extra, policy, 0, 2)?))
     }
 }
 
 impl Default for VersionRecord {
     fn default() -> Self {
-        Self {number: 6, output_category: OutputCategory::ToPatient}
+        Self {number: 6, output_category: OutputCategory::ToPatient, extra: Vec::new()}
     }
 }
 
@@ -986,22 +1683,27 @@ impl FromStr for VersionRecord {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         lazy_static! {
-            static ref RE: Regex = Regex::new(r"^JAHISTC(\d\d),(\d)$").unwrap();
+            static ref RE: Regex = Regex::new(r"^JAHISTC(\d\d),(\d)((?:,[^,]*)*)$").unwrap();
         }
         for cap in RE.captures_iter(s) {
             return Ok(Self {
                 number: (&cap[1]).parse().map_err(Error::ParseIntError)?,
                 output_category: (&cap[2]).parse()?,
+                extra: extra_fields(&cap),
             })
         }
-        Err(Error::InvalidRecordLine(
-            format!("Cannot convert str to VersionRecord, got \"{}\"", s)
-        ))
+        Err(Error::InvalidRecordLine(ParseError {
+            expected: "a version record as JAHISTCnn,n".to_string(),
+            found: s.to_string(),
+            .. Default::default()
+        }))
     }
 }
 
 /// No. 1: Patient record (患者情報レコード)
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct PatientRecord {
     pub name: String, // 患者氏名
     pub gender: Gender, // 患者性別
@@ -1013,6 +1715,8 @@ pub struct PatientRecord {
     pub blood_type: Option<String>, // 血液型
     pub body_weight: Option<f32>, // 体重
     pub name_in_kana: Option<String>, // 患者氏名カナ
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub extra: Vec<String>, // 追加項目
 }
 
 impl PatientRecord {
@@ -1025,20 +1729,26 @@ impl PatientRecord {
         }
     }
 
+    /// Serializes the record by `EscapePolicy::FullWidth`, which is lossy.
     pub fn to_code(&self) -> String {
-        format!("{},{},{},{},{},{},{},{},{},{},{}",
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        Ok(format!("{},{},{},{},{},{},{},{},{},{},{}{}",
             self.record_number().to_string(), // 1
-            self.name,
+            escape_field(&self.name, policy, 1, 1)?,
             self.gender.to_code(),
             self.day_of_birth.to_code(),
-            self.zip_code.as_ref().map(|s| s.clone()).unwrap_or_default(),
-            self.address.as_ref().map(|s| s.clone()).unwrap_or_default(),
-            self.telephone.as_ref().map(|s| s.clone()).unwrap_or_default(),
-            self.emergency_contact_information.as_ref().map(|s| s.clone()).unwrap_or_default(),
-            self.blood_type.as_ref().map(|s| s.clone()).unwrap_or_default(),
+            escape_field(self.zip_code.as_deref().unwrap_or_default(), policy, 1, 4)?,
+            escape_field(self.address.as_deref().unwrap_or_default(), policy, 1, 5)?,
+            escape_field(self.telephone.as_deref().unwrap_or_default(), policy, 1, 6)?,
+            escape_field(self.emergency_contact_information.as_deref().unwrap_or_default(), policy, 1, 7)?,
+            escape_field(self.blood_type.as_deref().unwrap_or_default(), policy, 1, 8)?,
             self.body_weight.map(|v| v.to_string()).unwrap_or_default(),
-            self.name_in_kana.as_ref().map(|s| s.clone()).unwrap_or_default()
-        )
+            escape_field(self.name_in_kana.as_deref().unwrap_or_default(), policy, 1, 10)?,
+            extra_code(&self.extra, policy, 1, 11)?
+        ))
     }
 
     pub fn create_medicine_notebook(self) -> MedicineNotebook {
@@ -1071,6 +1781,7 @@ impl Default for PatientRecord {
             blood_type: None,
             body_weight: None,
             name_in_kana: None,
+            extra: Vec::new(),
         }
     }
 }
@@ -1079,36 +1790,39 @@ impl FromStr for PatientRecord {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         lazy_static! {
-            static ref RE: Regex = Regex::new(r"^(\d+),([^,]*),(\d),(\d{8}|\w\d{6}),([^,]*),([^,]*),([^,]*),([^,]*),([^,]*),((?:[0-9]+(?:[.][0-9]*)?|[.][0-9]+)?),([^,]*)$").unwrap();
+            static ref RE: Regex = record_regex(1);
         }
         for cap in RE.captures_iter(s) {
             if (&cap[1]) == "1" {
                 return Ok(Self {
                     name: (&cap[2]).to_string(),
-                    gender: (&cap[3]).parse()?,
-                    day_of_birth: (&cap[4]).parse()?,
+                    gender: parse_field(&cap, 3, 1)?,
+                    day_of_birth: parse_field(&cap, 4, 1)?,
                     zip_code: if (&cap[5]).is_empty() {None} else {Some((&cap[5]).to_string())},
                     address: if (&cap[6]).is_empty() {None} else {Some((&cap[6]).to_string())},
                     telephone: if (&cap[7]).is_empty() {None} else {Some((&cap[7]).to_string())},
                     emergency_contact_information: if (&cap[8]).is_empty() {None} else {Some((&cap[8]).to_string())},
                     blood_type: if (&cap[9]).is_empty() {None} else {Some((&cap[9]).to_string())},
-                    body_weight: if (&cap[10]).is_empty() {None} else {Some((&cap[10]).parse().map_err(Error::ParseFloatError)?)},
+                    body_weight: if cap[10].is_empty() {None} else {Some(parse_field(&cap, 10, 1)?)},
                     name_in_kana: if (&cap[11]).is_empty() {None} else {Some((&cap[11]).to_string())},
+                    extra: extra_fields(&cap),
                 })
             }
         }
-        Err(Error::InvalidRecordLine(
-            format!("Cannot convert str to PatientRecord, got \"{}\"", s)
-        ))
+        Err(record_line_error(s, 1))
     }
 }
 
 /// No 2. Special patient note record (患者特記レコード)
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct SpecialPatientNoteRecord {
     pub category: SpecialPatientNoteCategory, // 患者特記種別
     pub content: String, // 患者特記内容
     pub created_by: RecordCreator, // レコード作成者
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub extra: Vec<String>, // 追加項目
 }
 
 impl SpecialPatientNoteRecord {
@@ -1118,16 +1832,23 @@ impl SpecialPatientNoteRecord {
             category: category,
             content: content,
             created_by: created_by,
+            extra: Vec::new(),
         }
     }
 
+    /// Serializes the record by `EscapePolicy::FullWidth`, which is lossy.
     pub fn to_code(&self) -> String {
-        format!("{},{},{},{}",
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        Ok(format!("{},{},{},{}{}",
             self.record_number().to_string(), // 2
             self.category.to_code(),
-            self.content,
-            self.created_by.to_code()
-        )
+            escape_field(&self.content, policy, 2, 2)?,
+            self.created_by.to_code(),
+            extra_code(&self.extra, policy, 2, 4)?
+        ))
     }
 }
 
@@ -1146,6 +1867,7 @@ impl Default for SpecialPatientNoteRecord {
             category: SpecialPatientNoteCategory::Other,
             content: "".to_string(),
             created_by: RecordCreator::Unknown,
+            extra: Vec::new(),
         }
     }
 }
@@ -1154,30 +1876,33 @@ impl FromStr for SpecialPatientNoteRecord {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         lazy_static! {
-            static ref RE: Regex = Regex::new(r"^(\d+),(\d),([^,]*),(\d)$").unwrap();
+            static ref RE: Regex = record_regex(2);
         }
         for cap in RE.captures_iter(s) {
             if (&cap[1]) == "2" {
                 return Ok(Self {
-                    category: (&cap[2]).parse()?,
+                    category: parse_field(&cap, 2, 2)?,
                     content: (&cap[3]).to_string(),
-                    created_by: (&cap[4]).parse()?,
+                    created_by: parse_field(&cap, 4, 2)?,
+                    extra: extra_fields(&cap),
                 })
             }
         }
-        Err(Error::InvalidRecordLine(
-            format!("Cannot convert str to SpecialPatientNoteRecord, got \"{}\"", s)
-        ))
+        Err(record_line_error(s, 2))
     }
 }
 
 /// No 3. OTC medicine record (一般用医薬品服用レコード )
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct OtcDrugRecord {
     pub drug_name: String, // 薬品名称
     pub start_date: Option<Date>, // 服用開始年月日
     pub end_date: Option<Date>, // 服用終了年月日
     pub created_by: RecordCreator, // レコード作成者
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub extra: Vec<String>, // 追加項目
 }
 
 impl OtcDrugRecord {
@@ -1188,17 +1913,24 @@ impl OtcDrugRecord {
             start_date: start_date,
             end_date: end_date,
             created_by: created_by,
+            extra: Vec::new(),
         }
     }
 
+    /// Serializes the record by `EscapePolicy::FullWidth`, which is lossy.
     pub fn to_code(&self) -> String {
-        format!("{},{},{},{},{}",
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        Ok(format!("{},{},{},{},{}{}",
             self.record_number().to_string(), // 3
-            self.drug_name,
+            escape_field(&self.drug_name, policy, 3, 1)?,
             self.start_date.map(|v| v.to_code()).unwrap_or_default(),
             self.end_date.map(|v| v.to_code()).unwrap_or_default(),
-            self.created_by.to_code()
-        )
+            self.created_by.to_code(),
+            extra_code(&self.extra, policy, 3, 5)?
+        ))
     }
 }
 
@@ -1218,6 +1950,7 @@ impl Default for OtcDrugRecord {
             start_date: None,
             end_date: None,
             created_by: RecordCreator::Unknown,
+            extra: Vec::new(),
         }
     }
 }
@@ -1226,30 +1959,33 @@ impl FromStr for OtcDrugRecord {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         lazy_static! {
-            static ref RE: Regex = Regex::new(r"^(\d+),([^,]*),((?:\d{8}|\w\d{6})?),((?:\d{8}|\w\d{6})?),(\d)$").unwrap();
+            static ref RE: Regex = record_regex(3);
         }
         for cap in RE.captures_iter(s) {
             if (&cap[1]) == "3" {
                 return Ok(Self {
                     drug_name: (&cap[2]).to_string(),
-                    start_date: if (&cap[3]).is_empty() {None} else {Some((&cap[3]).parse()?)},
-                    end_date: if (&cap[4]).is_empty() {None} else {Some((&cap[4]).parse()?)},
-                    created_by: (&cap[5]).parse()?,
+                    start_date: if cap[3].is_empty() {None} else {Some(parse_field(&cap, 3, 3)?)},
+                    end_date: if cap[4].is_empty() {None} else {Some(parse_field(&cap, 4, 3)?)},
+                    created_by: parse_field(&cap, 5, 3)?,
+                    extra: extra_fields(&cap),
                 })
             }
         }
-        Err(Error::InvalidRecordLine(
-            format!("Cannot convert str to OtcDrugRecord, got \"{}\"", s)
-        ))
+        Err(record_line_error(s, 3))
     }
 }
 
 /// No 4. Memo record (手帳メモレコード)
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct MemoRecord {
     pub content: String, // 手帳メモ情報
     pub created_at: Option<Date>, // メモ入力年月日
     pub created_by: RecordCreator, // レコード作成者
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub extra: Vec<String>, // 追加項目
 }
 
 impl MemoRecord {
@@ -1259,16 +1995,23 @@ impl MemoRecord {
             content: content,
             created_at: created_at,
             created_by: created_by,
+            extra: Vec::new(),
         }
     }
 
+    /// Serializes the record by `EscapePolicy::FullWidth`, which is lossy.
     pub fn to_code(&self) -> String {
-        format!("{},{},{},{}",
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        Ok(format!("{},{},{},{}{}",
             self.record_number().to_string(), // 4
-            self.content,
+            escape_field(&self.content, policy, 4, 1)?,
             self.created_at.map(|v| v.to_code()).unwrap_or_default(),
-            self.created_by.to_code()
-        )
+            self.created_by.to_code(),
+            extra_code(&self.extra, policy, 4, 4)?
+        ))
     }
 }
 
@@ -1287,6 +2030,7 @@ impl Default for MemoRecord {
             content: "".to_string(),
             created_at: None,
             created_by: RecordCreator::Unknown,
+            extra: Vec::new(),
         }
     }
 }
@@ -1295,41 +2039,50 @@ impl FromStr for MemoRecord {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         lazy_static! {
-            static ref RE: Regex = Regex::new(r"^(\d+),([^,]*),((?:\d{8}|\w\d{6})?),(\d)$").unwrap();
+            static ref RE: Regex = record_regex(4);
         }
         for cap in RE.captures_iter(s) {
             if (&cap[1]) == "4" {
                 return Ok(Self {
                     content: (&cap[2]).to_string(),
-                    created_at: if (&cap[3]).is_empty() {None} else {Some((&cap[3]).parse()?)},
-                    created_by: (&cap[4]).parse()?,
+                    created_at: if cap[3].is_empty() {None} else {Some(parse_field(&cap, 3, 4)?)},
+                    created_by: parse_field(&cap, 4, 4)?,
+                    extra: extra_fields(&cap),
                 })
             }
         }
-        Err(Error::InvalidRecordLine(
-            format!("Cannot convert str to MemoRecord, got \"{}\"", s)
-        ))
+        Err(record_line_error(s, 4))
     }
 }
 
 /// No 5. Date record (調剤等年月日レコード)
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct DateRecord {
     pub created_at: Date, // 調剤等年月日
     pub created_by: RecordCreator, // レコード作成者
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub extra: Vec<String>, // 追加項目
 }
 
 impl DateRecord {
     pub fn new(created_at: Date, created_by: RecordCreator) -> Self {
-        Self {created_at: created_at, created_by: created_by}
+        Self {created_at, created_by, extra: Vec::new()}
     }
 
+    /// Serializes the record by `EscapePolicy::FullWidth`, which is lossy.
     pub fn to_code(&self) -> String {
-        format!("{},{},{}",
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        Ok(format!("{},{},{}{}",
             self.record_number().to_string(), // 5
             self.created_at.to_code(),
-            self.created_by.to_code()
-        )
+            self.created_by.to_code(),
+            extra_code(&self.extra, policy, 5, 3)?
+        ))
     }
 }
 
@@ -1347,6 +2100,7 @@ impl Default for DateRecord {
         Self {
             created_at: Date::Seireki{year: 1970, month: 1, day: 1},
             created_by: RecordCreator::Unknown,
+            extra: Vec::new(),
         }
     }
 }
@@ -1355,24 +2109,25 @@ impl FromStr for DateRecord {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         lazy_static! {
-            static ref RE: Regex = Regex::new(r"^(\d+),(\d{8}|\w\d{6}),(\d)$").unwrap();
+            static ref RE: Regex = record_regex(5);
         }
         for cap in RE.captures_iter(s) {
             if (&cap[1]) == "5" {
                 return Ok(Self {
-                    created_at: (&cap[2]).parse()?,
-                    created_by: (&cap[3]).parse()?,
+                    created_at: parse_field(&cap, 2, 5)?,
+                    created_by: parse_field(&cap, 3, 5)?,
+                    extra: extra_fields(&cap),
                 })
             }
         }
-        Err(Error::InvalidRecordLine(
-            format!("Cannot convert str to DateRecord, got \"{}\"", s)
-        ))
+        Err(record_line_error(s, 5))
     }
 }
 
 /// No 11. Pharmacy record (調剤－医療機関等レコード)
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct PharmacyRecord {
     pub name: String, // 医療機関等名称
     pub prefecture: Option<Prefecture>, // 医療機関等都道府県
@@ -1382,6 +2137,8 @@ pub struct PharmacyRecord {
     pub address: Option<String>, // 医療機関等住所
     pub telephone: Option<String>, // 医療機関等電話番号
     pub created_by: RecordCreator, // レコード作成者
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub extra: Vec<String>, // 追加項目
 }
 
 impl PharmacyRecord {
@@ -1398,21 +2155,28 @@ impl PharmacyRecord {
             address: address,
             telephone: telephone,
             created_by: created_by,
+            extra: Vec::new(),
         }
     }
 
+    /// Serializes the record by `EscapePolicy::FullWidth`, which is lossy.
     pub fn to_code(&self) -> String {
-        format!("{},{},{},{},{},{},{},{},{}",
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        Ok(format!("{},{},{},{},{},{},{},{},{}{}",
             self.record_number().to_string(), // 11
-            self.name,
+            escape_field(&self.name, policy, 11, 1)?,
             self.prefecture.map(|v| v.to_code()).unwrap_or_default(),
             self.fee_table.map(|v| v.to_code()).unwrap_or_default(),
-            self.institution_code.as_ref().map(|s| s.clone()).unwrap_or_default(),
-            self.zip_code.as_ref().map(|s| s.clone()).unwrap_or_default(),
-            self.address.as_ref().map(|s| s.clone()).unwrap_or_default(),
-            self.telephone.as_ref().map(|s| s.clone()).unwrap_or_default(),
-            self.created_by.to_code()
-        )
+            escape_field(self.institution_code.as_deref().unwrap_or_default(), policy, 11, 4)?,
+            escape_field(self.zip_code.as_deref().unwrap_or_default(), policy, 11, 5)?,
+            escape_field(self.address.as_deref().unwrap_or_default(), policy, 11, 6)?,
+            escape_field(self.telephone.as_deref().unwrap_or_default(), policy, 11, 7)?,
+            self.created_by.to_code(),
+            extra_code(&self.extra, policy, 11, 9)?
+        ))
     }
 }
 
@@ -1436,6 +2200,7 @@ impl Default for PharmacyRecord {
             address: None,
             telephone: None,
             created_by: RecordCreator::Unknown,
+            extra: Vec::new(),
         }
     }
 }
@@ -1444,34 +2209,37 @@ impl FromStr for PharmacyRecord {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         lazy_static! {
-            static ref RE: Regex = Regex::new(r"^(\d+),([^,]*),(\d{0,2}),(\d?),([^,]*),([^,]*),([^,]*),([^,]*),(\d)$").unwrap();
+            static ref RE: Regex = record_regex(11);
         }
         for cap in RE.captures_iter(s) {
             if (&cap[1]) == "11" {
                 return Ok(Self {
                     name: (&cap[2]).to_string(),
-                    prefecture: if (&cap[3]).is_empty() {None} else {Some((&cap[3]).parse()?)},
-                    fee_table: if (&cap[4]).is_empty() {None} else {Some((&cap[4]).parse()?)},
+                    prefecture: if cap[3].is_empty() {None} else {Some(parse_field(&cap, 3, 11)?)},
+                    fee_table: if cap[4].is_empty() {None} else {Some(parse_field(&cap, 4, 11)?)},
                     institution_code: if (&cap[5]).is_empty() {None} else {Some((&cap[5]).to_string())},
                     zip_code: if (&cap[6]).is_empty() {None} else {Some((&cap[6]).to_string())},
                     address: if (&cap[7]).is_empty() {None} else {Some((&cap[7]).to_string())},
                     telephone: if (&cap[8]).is_empty() {None} else {Some((&cap[8]).to_string())},
-                    created_by: (&cap[9]).parse()?,
+                    created_by: parse_field(&cap, 9, 11)?,
+                    extra: extra_fields(&cap),
                 })
             }
         }
-        Err(Error::InvalidRecordLine(
-            format!("Cannot convert str to PharmacyRecord, got \"{}\"", s)
-        ))
+        Err(record_line_error(s, 11))
     }
 }
 
 /// No 15. Pharmacist record (調剤－医師・薬剤師レコード)
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct PharmacistRecord {
     pub name: String, // 医師・薬剤師氏名
     pub contact_information: Option<String>, // 医師・薬剤師連絡先
     pub created_by: RecordCreator, // レコード作成者
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub extra: Vec<String>, // 追加項目
 }
 
 impl PharmacistRecord {
@@ -1481,16 +2249,23 @@ impl PharmacistRecord {
             name: name,
             contact_information: contact_information,
             created_by: created_by,
+            extra: Vec::new(),
         }
     }
 
+    /// Serializes the record by `EscapePolicy::FullWidth`, which is lossy.
     pub fn to_code(&self) -> String {
-        format!("{},{},{},{}",
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        Ok(format!("{},{},{},{}{}",
             self.record_number().to_string(), // 15
-            self.name,
-            self.contact_information.as_ref().map(|s| s.clone()).unwrap_or_default(),
-            self.created_by.to_code()
-        )
+            escape_field(&self.name, policy, 15, 1)?,
+            escape_field(self.contact_information.as_deref().unwrap_or_default(), policy, 15, 2)?,
+            self.created_by.to_code(),
+            extra_code(&self.extra, policy, 15, 4)?
+        ))
     }
 }
 
@@ -1509,6 +2284,7 @@ impl Default for PharmacistRecord {
             name: "".to_string(),
             contact_information: None,
             created_by: RecordCreator::Unknown,
+            extra: Vec::new(),
         }
     }
 }
@@ -1517,31 +2293,34 @@ impl FromStr for PharmacistRecord {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         lazy_static! {
-            static ref RE: Regex = Regex::new(r"^(\d+),([^,]*),([^,]*),(\d)$").unwrap();
+            static ref RE: Regex = record_regex(15);
         }
         for cap in RE.captures_iter(s) {
             if (&cap[1]) == "15" {
                 return Ok(Self {
                     name: (&cap[2]).to_string(),
                     contact_information: if (&cap[3]).is_empty() {None} else {Some((&cap[3]).to_string())},
-                    created_by: (&cap[4]).parse()?,
+                    created_by: parse_field(&cap, 4, 15)?,
+                    extra: extra_fields(&cap),
                 })
             }
         }
-        Err(Error::InvalidRecordLine(
-            format!("Cannot convert str to PharmacistRecord, got \"{}\"", s)
-        ))
+        Err(record_line_error(s, 15))
     }
 }
 
 /// No 51. Medical institution record (処方－医療機関レコード)
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct MedicalInstitutionRecord {
     pub name: String, // 医療機関名称
     pub prefecture: Option<Prefecture>, // 医療機関都道府県
     pub fee_table: Option<FeeTable>, // 医療機関点数表
     pub institution_code: Option<String>, // 医療機関コード
     pub created_by: RecordCreator, // レコード作成者
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub extra: Vec<String>, // 追加項目
 }
 
 impl MedicalInstitutionRecord {
@@ -1554,18 +2333,25 @@ impl MedicalInstitutionRecord {
             fee_table: fee_table,
             institution_code: institution_code,
             created_by: created_by,
+            extra: Vec::new(),
         }
     }
 
+    /// Serializes the record by `EscapePolicy::FullWidth`, which is lossy.
     pub fn to_code(&self) -> String {
-        format!("{},{},{},{},{},{}",
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        Ok(format!("{},{},{},{},{},{}{}",
             self.record_number().to_string(), // 51
-            self.name,
+            escape_field(&self.name, policy, 51, 1)?,
             self.prefecture.map(|v| v.to_code()).unwrap_or_default(),
             self.fee_table.map(|v| v.to_code()).unwrap_or_default(),
-            self.institution_code.as_ref().map(|s| s.clone()).unwrap_or_default(),
-            self.created_by.to_code()
-        )
+            escape_field(self.institution_code.as_deref().unwrap_or_default(), policy, 51, 4)?,
+            self.created_by.to_code(),
+            extra_code(&self.extra, policy, 51, 6)?
+        ))
     }
 }
 
@@ -1586,6 +2372,7 @@ impl Default for MedicalInstitutionRecord {
             fee_table: None,
             institution_code: None,
             created_by: RecordCreator::Unknown,
+            extra: Vec::new(),
         }
     }
 }
@@ -1594,31 +2381,34 @@ impl FromStr for MedicalInstitutionRecord {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         lazy_static! {
-            static ref RE: Regex = Regex::new(r"^(\d+),([^,]*),(\d{0,2}),(\d?),([^,]*),(\d)$").unwrap();
+            static ref RE: Regex = record_regex(51);
         }
         for cap in RE.captures_iter(s) {
             if (&cap[1]) == "51" {
                 return Ok(Self {
                     name: (&cap[2]).to_string(),
-                    prefecture: if (&cap[3]).is_empty() {None} else {Some((&cap[3]).parse()?)},
-                    fee_table: if (&cap[4]).is_empty() {None} else {Some((&cap[4]).parse()?)},
+                    prefecture: if cap[3].is_empty() {None} else {Some(parse_field(&cap, 3, 51)?)},
+                    fee_table: if cap[4].is_empty() {None} else {Some(parse_field(&cap, 4, 51)?)},
                     institution_code: if (&cap[5]).is_empty() {None} else {Some((&cap[5]).to_string())},
-                    created_by: (&cap[6]).parse()?,
+                    created_by: parse_field(&cap, 6, 51)?,
+                    extra: extra_fields(&cap),
                 })
             }
         }
-        Err(Error::InvalidRecordLine(
-            format!("Cannot convert str to MedicalInstitutionRecord, got \"{}\"", s)
-        ))
+        Err(record_line_error(s, 51))
     }
 }
 
 /// No 55. Physician record (処方－医師レコード)
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct PhysicianRecord {
     pub name: String, // 医師氏名
     pub specialty: Option<String>, // 診療科名
     pub created_by: RecordCreator, // レコード作成者
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub extra: Vec<String>, // 追加項目
 }
 
 impl PhysicianRecord {
@@ -1628,16 +2418,23 @@ impl PhysicianRecord {
             name: name,
             specialty: specialty,
             created_by: created_by,
+            extra: Vec::new(),
         }
     }
 
+    /// Serializes the record by `EscapePolicy::FullWidth`, which is lossy.
     pub fn to_code(&self) -> String {
-        format!("{},{},{},{}",
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        Ok(format!("{},{},{},{}{}",
             self.record_number().to_string(), // 55
-            self.name,
-            self.specialty.as_ref().map(|s| s.clone()).unwrap_or_default(),
-            self.created_by.to_code()
-        )
+            escape_field(&self.name, policy, 55, 1)?,
+            escape_field(self.specialty.as_deref().unwrap_or_default(), policy, 55, 2)?,
+            self.created_by.to_code(),
+            extra_code(&self.extra, policy, 55, 4)?
+        ))
     }
 }
 
@@ -1656,6 +2453,7 @@ impl Default for PhysicianRecord {
             name: "".to_string(),
             specialty: None,
             created_by: RecordCreator::Unknown,
+            extra: Vec::new(),
         }
     }
 }
@@ -1664,25 +2462,26 @@ impl FromStr for PhysicianRecord {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         lazy_static! {
-            static ref RE: Regex = Regex::new(r"^(\d+),([^,]*),([^,]*),(\d)$").unwrap();
+            static ref RE: Regex = record_regex(55);
         }
         for cap in RE.captures_iter(s) {
             if (&cap[1]) == "55" {
                 return Ok(Self {
                     name: (&cap[2]).to_string(),
                     specialty: if (&cap[3]).is_empty() {None} else {Some((&cap[3]).to_string())},
-                    created_by: (&cap[4]).parse()?,
+                    created_by: parse_field(&cap, 4, 55)?,
+                    extra: extra_fields(&cap),
                 })
             }
         }
-        Err(Error::InvalidRecordLine(
-            format!("Cannot convert str to PhysicianRecord, got \"{}\"", s)
-        ))
+        Err(record_line_error(s, 55))
     }
 }
 
 /// No 201. Drug record (薬品レコード)
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct DrugRecord {
     pub rp_number: u32, // RP番号
     pub name: String, // 薬品名称
@@ -1691,6 +2490,8 @@ pub struct DrugRecord {
     pub drug_code_type: DrugCodeType, // 薬品コード種別
     pub drug_code: Option<String>, // 薬品コード
     pub created_by: RecordCreator, // レコード作成者
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub extra: Vec<String>, // 追加項目
 }
 
 impl DrugRecord {
@@ -1705,20 +2506,27 @@ impl DrugRecord {
             drug_code_type: drug_code_type,
             drug_code: drug_code,
             created_by: created_by,
+            extra: Vec::new(),
         }
     }
 
+    /// Serializes the record by `EscapePolicy::FullWidth`, which is lossy.
     pub fn to_code(&self) -> String {
-        format!("{},{},{},{},{},{},{},{}",
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        Ok(format!("{},{},{},{},{},{},{},{}{}",
             self.record_number().to_string(), // 201
             self.rp_number,
-            self.name,
-            self.dosage,
-            self.unit,
+            escape_field(&self.name, policy, 201, 2)?,
+            escape_field(&self.dosage, policy, 201, 3)?,
+            escape_field(&self.unit, policy, 201, 4)?,
             self.drug_code_type.to_code(),
-            self.drug_code.as_ref().map(|s| s.clone()).unwrap_or_default(),
-            self.created_by.to_code()
-        )
+            escape_field(self.drug_code.as_deref().unwrap_or_default(), policy, 201, 6)?,
+            self.created_by.to_code(),
+            extra_code(&self.extra, policy, 201, 8)?
+        ))
     }
 
     pub fn to_block(self) -> DrugBlock {
@@ -1748,6 +2556,7 @@ impl Default for DrugRecord {
             drug_code_type: DrugCodeType::None,
             drug_code: None,
             created_by: RecordCreator::Unknown,
+            extra: Vec::new(),
         }
     }
 }
@@ -1756,33 +2565,36 @@ impl FromStr for DrugRecord {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         lazy_static! {
-            static ref RE: Regex = Regex::new(r"^(\d+),(\d+),([^,]*),([^,]*),([^,]*),(\d?),([^,]*),(\d)$").unwrap();
+            static ref RE: Regex = record_regex(201);
         }
         for cap in RE.captures_iter(s) {
             if (&cap[1]) == "201" {
                 return Ok(Self {
-                    rp_number: (&cap[2]).parse().map_err(Error::ParseIntError)?,
+                    rp_number: parse_field(&cap, 2, 201)?,
                     name: (&cap[3]).to_string(),
                     dosage: (&cap[4]).to_string(),
                     unit: (&cap[5]).to_string(),
-                    drug_code_type: (&cap[6]).parse()?,
+                    drug_code_type: parse_field(&cap, 6, 201)?,
                     drug_code: if (&cap[7]).is_empty() {None} else {Some((&cap[7]).to_string())},
-                    created_by: (&cap[8]).parse()?,
+                    created_by: parse_field(&cap, 8, 201)?,
+                    extra: extra_fields(&cap),
                 })
             }
         }
-        Err(Error::InvalidRecordLine(
-            format!("Cannot convert str to DrugRecord, got \"{}\"", s)
-        ))
+        Err(record_line_error(s, 201))
     }
 }
 
 /// No 281. Drug supplementary record (薬品補足レコード)
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct DrugSupplementaryRecord {
     pub rp_number: u32, // RP番号
     pub content: String, // 薬品補足情報
     pub created_by: RecordCreator, // レコード作成者
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub extra: Vec<String>, // 追加項目
 }
 
 impl DrugSupplementaryRecord {
@@ -1791,16 +2603,23 @@ impl DrugSupplementaryRecord {
             rp_number: rp_number,
             content: content,
             created_by: created_by,
+            extra: Vec::new(),
         }
     }
 
+    /// Serializes the record by `EscapePolicy::FullWidth`, which is lossy.
     pub fn to_code(&self) -> String {
-        format!("{},{},{},{}",
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        Ok(format!("{},{},{},{}{}",
             self.record_number().to_string(), // 281
             self.rp_number,
-            self.content,
-            self.created_by.to_code()
-        )
+            escape_field(&self.content, policy, 281, 2)?,
+            self.created_by.to_code(),
+            extra_code(&self.extra, policy, 281, 4)?
+        ))
     }
 }
 
@@ -1819,6 +2638,7 @@ impl Default for DrugSupplementaryRecord {
             rp_number: 1,
             content: "".to_string(),
             created_by: RecordCreator::Unknown,
+            extra: Vec::new(),
         }
     }
 }
@@ -1827,29 +2647,32 @@ impl FromStr for DrugSupplementaryRecord {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         lazy_static! {
-            static ref RE: Regex = Regex::new(r"^(\d+),(\d+),([^,]*),(\d)$").unwrap();
+            static ref RE: Regex = record_regex(281);
         }
         for cap in RE.captures_iter(s) {
             if (&cap[1]) == "281" {
                 return Ok(Self {
-                    rp_number: (&cap[2]).parse().map_err(Error::ParseIntError)?,
+                    rp_number: parse_field(&cap, 2, 281)?,
                     content: (&cap[3]).to_string(),
-                    created_by: (&cap[4]).parse()?,
+                    created_by: parse_field(&cap, 4, 281)?,
+                    extra: extra_fields(&cap),
                 })
             }
         }
-        Err(Error::InvalidRecordLine(
-            format!("Cannot convert str to DrugSupplementaryRecord, got \"{}\"", s)
-        ))
+        Err(record_line_error(s, 281))
     }
 }
 
 /// No 291. Drug notice record (薬品服用注意レコード)
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct DrugNoticeRecord {
     pub rp_number: u32, // RP番号
     pub content: String, // 内容
     pub created_by: RecordCreator, // レコード作成者
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub extra: Vec<String>, // 追加項目
 }
 
 impl DrugNoticeRecord {
@@ -1858,16 +2681,23 @@ impl DrugNoticeRecord {
             rp_number: rp_number,
             content: content,
             created_by: created_by,
+            extra: Vec::new(),
         }
     }
 
+    /// Serializes the record by `EscapePolicy::FullWidth`, which is lossy.
     pub fn to_code(&self) -> String {
-        format!("{},{},{},{}",
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        Ok(format!("{},{},{},{}{}",
             self.record_number().to_string(), // 291
             self.rp_number,
-            self.content,
-            self.created_by.to_code()
-        )
+            escape_field(&self.content, policy, 291, 2)?,
+            self.created_by.to_code(),
+            extra_code(&self.extra, policy, 291, 4)?
+        ))
     }
 }
 
@@ -1886,6 +2716,7 @@ impl Default for DrugNoticeRecord {
             rp_number: 1,
             content: "".to_string(),
             created_by: RecordCreator::Unknown,
+            extra: Vec::new(),
         }
     }
 }
@@ -1894,25 +2725,26 @@ impl FromStr for DrugNoticeRecord {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         lazy_static! {
-            static ref RE: Regex = Regex::new(r"^(\d+),(\d+),([^,]*),(\d)$").unwrap();
+            static ref RE: Regex = record_regex(291);
         }
         for cap in RE.captures_iter(s) {
             if (&cap[1]) == "291" {
                 return Ok(Self {
-                    rp_number: (&cap[2]).parse().map_err(Error::ParseIntError)?,
+                    rp_number: parse_field(&cap, 2, 291)?,
                     content: (&cap[3]).to_string(),
-                    created_by: (&cap[4]).parse()?,
+                    created_by: parse_field(&cap, 4, 291)?,
+                    extra: extra_fields(&cap),
                 })
             }
         }
-        Err(Error::InvalidRecordLine(
-            format!("Cannot convert str to DrugNoticeRecord, got \"{}\"", s)
-        ))
+        Err(record_line_error(s, 291))
     }
 }
 
 /// No 301. Usage record (用法レコード)
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct UsageRecord {
     pub rp_number: u32, // RP番号
     pub name: String, // 用法名称
@@ -1922,6 +2754,8 @@ pub struct UsageRecord {
     pub usage_code_type: Option<UsageCodeType>, // 用法コード種別
     pub usage_code: Option<String>, // 用法コード
     pub created_by: RecordCreator, // レコード作成者
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub extra: Vec<String>, // 追加項目
 }
 
 impl UsageRecord {
@@ -1938,21 +2772,28 @@ impl UsageRecord {
             usage_code_type: usage_code_type,
             usage_code: usage_code,
             created_by: created_by,
+            extra: Vec::new(),
         }
     }
 
+    /// Serializes the record by `EscapePolicy::FullWidth`, which is lossy.
     pub fn to_code(&self) -> String {
-        format!("{},{},{},{},{},{},{},{},{}",
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        Ok(format!("{},{},{},{},{},{},{},{},{}{}",
             self.record_number().to_string(), // 301
             self.rp_number,
-            self.name,
+            escape_field(&self.name, policy, 301, 2)?,
             self.quantity.map(|v| v.to_string()).unwrap_or_default(),
-            self.unit.as_ref().map(|s| s.clone()).unwrap_or_default(),
+            escape_field(self.unit.as_deref().unwrap_or_default(), policy, 301, 4)?,
             self.dosage_form.map(|v| v.to_code()).unwrap_or_default(),
             self.usage_code_type.map(|v| v.to_code()).unwrap_or_default(),
-            self.usage_code.as_ref().map(|s| s.clone()).unwrap_or_default(),
-            self.created_by.to_code()
-        )
+            escape_field(self.usage_code.as_deref().unwrap_or_default(), policy, 301, 7)?,
+            self.created_by.to_code(),
+            extra_code(&self.extra, policy, 301, 9)?
+        ))
     }
 }
 
@@ -1976,6 +2817,7 @@ impl Default for UsageRecord {
             usage_code_type: None,
             usage_code: None,
             created_by: RecordCreator::Unknown,
+            extra: Vec::new(),
         }
     }
 }
@@ -1984,35 +2826,38 @@ impl FromStr for UsageRecord {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         lazy_static! {
-            static ref RE: Regex = Regex::new(r"^(\d+),(\d+),([^,]*),(\d*),([^,]*),(\d*),(\d?),([^,]*),(\d)$").unwrap();
+            static ref RE: Regex = record_regex(301);
         }
         for cap in RE.captures_iter(s) {
             if (&cap[1]) == "301" {
                 return Ok(Self {
-                    rp_number: (&cap[2]).parse().map_err(Error::ParseIntError)?,
+                    rp_number: parse_field(&cap, 2, 301)?,
                     name: (&cap[3]).to_string(),
                     quantity: if (&cap[4]).is_empty() {None}
-                        else {Some((&cap[4]).parse().map_err(Error::ParseIntError)?)},
+                        else {Some(parse_field(&cap, 4, 301)?)},
                     unit: if (&cap[5]).is_empty() {None} else {Some((&cap[5]).to_string())},
-                    dosage_form: if (&cap[6]).is_empty() {None} else {Some((&cap[6]).parse()?)},
-                    usage_code_type: if (&cap[7]).is_empty() {None} else {Some((&cap[7]).parse()?)},
+                    dosage_form: if cap[6].is_empty() {None} else {Some(parse_field(&cap, 6, 301)?)},
+                    usage_code_type: if cap[7].is_empty() {None} else {Some(parse_field(&cap, 7, 301)?)},
                     usage_code: if (&cap[8]).is_empty() {None} else {Some((&cap[8]).to_string())},
-                    created_by: (&cap[9]).parse()?,
+                    created_by: parse_field(&cap, 9, 301)?,
+                    extra: extra_fields(&cap),
                 })
             }
         }
-        Err(Error::InvalidRecordLine(
-            format!("Cannot convert str to UsageRecord, got \"{}\"", s)
-        ))
+        Err(record_line_error(s, 301))
     }
 }
 
 /// No 311. Usage supplementary record (用法補足レコード)
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct UsageSupplementaryRecord {
     pub rp_number: u32, // RP番号
     pub content: String, // 用法補足情報
     pub created_by: RecordCreator, // レコード作成者
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub extra: Vec<String>, // 追加項目
 }
 
 impl UsageSupplementaryRecord {
@@ -2021,16 +2866,23 @@ impl UsageSupplementaryRecord {
             rp_number: rp_number,
             content: content,
             created_by: created_by,
+            extra: Vec::new(),
         }
     }
 
+    /// Serializes the record by `EscapePolicy::FullWidth`, which is lossy.
     pub fn to_code(&self) -> String {
-        format!("{},{},{},{}",
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        Ok(format!("{},{},{},{}{}",
             self.record_number().to_string(), // 311
             self.rp_number,
-            self.content,
-            self.created_by.to_code()
-        )
+            escape_field(&self.content, policy, 311, 2)?,
+            self.created_by.to_code(),
+            extra_code(&self.extra, policy, 311, 4)?
+        ))
     }
 }
 
@@ -2049,6 +2901,7 @@ impl Default for UsageSupplementaryRecord {
             rp_number: 1,
             content: "".to_string(),
             created_by: RecordCreator::Unknown,
+            extra: Vec::new(),
         }
     }
 }
@@ -2057,29 +2910,32 @@ impl FromStr for UsageSupplementaryRecord {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         lazy_static! {
-            static ref RE: Regex = Regex::new(r"^(\d+),(\d+),([^,]*),(\d)$").unwrap();
+            static ref RE: Regex = record_regex(311);
         }
         for cap in RE.captures_iter(s) {
             if (&cap[1]) == "311" {
                 return Ok(Self {
-                    rp_number: (&cap[2]).parse().map_err(Error::ParseIntError)?,
+                    rp_number: parse_field(&cap, 2, 311)?,
                     content: (&cap[3]).to_string(),
-                    created_by: (&cap[4]).parse()?,
+                    created_by: parse_field(&cap, 4, 311)?,
+                    extra: extra_fields(&cap),
                 })
             }
         }
-        Err(Error::InvalidRecordLine(
-            format!("Cannot convert str to UsageSupplementaryRecord, got \"{}\"", s)
-        ))
+        Err(record_line_error(s, 311))
     }
 }
 
 /// No 391. Rp notice record (処方服用注意レコード)
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct RpNoticeRecord {
     pub rp_number: u32, // RP番号
     pub content: String, // 内容
     pub created_by: RecordCreator, // レコード作成者
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub extra: Vec<String>, // 追加項目
 }
 
 impl RpNoticeRecord {
@@ -2088,16 +2944,23 @@ impl RpNoticeRecord {
             rp_number: rp_number,
             content: content,
             created_by: created_by,
+            extra: Vec::new(),
         }
     }
 
+    /// Serializes the record by `EscapePolicy::FullWidth`, which is lossy.
     pub fn to_code(&self) -> String {
-        format!("{},{},{},{}",
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        Ok(format!("{},{},{},{}{}",
             self.record_number().to_string(), // 391
             self.rp_number,
-            self.content,
-            self.created_by.to_code()
-        )
+            escape_field(&self.content, policy, 391, 2)?,
+            self.created_by.to_code(),
+            extra_code(&self.extra, policy, 391, 4)?
+        ))
     }
 }
 
@@ -2116,6 +2979,7 @@ impl Default for RpNoticeRecord {
             rp_number: 1,
             content: "".to_string(),
             created_by: RecordCreator::Unknown,
+            extra: Vec::new(),
         }
     }
 }
@@ -2124,28 +2988,31 @@ impl FromStr for RpNoticeRecord {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         lazy_static! {
-            static ref RE: Regex = Regex::new(r"^(\d+),(\d+),([^,]*),(\d)$").unwrap();
+            static ref RE: Regex = record_regex(391);
         }
         for cap in RE.captures_iter(s) {
             if (&cap[1]) == "391" {
                 return Ok(Self {
-                    rp_number: (&cap[2]).parse().map_err(Error::ParseIntError)?,
+                    rp_number: parse_field(&cap, 2, 391)?,
                     content: (&cap[3]).to_string(),
-                    created_by: (&cap[4]).parse()?,
+                    created_by: parse_field(&cap, 4, 391)?,
+                    extra: extra_fields(&cap),
                 })
             }
         }
-        Err(Error::InvalidRecordLine(
-            format!("Cannot convert str to RpNoticeRecord, got \"{}\"", s)
-        ))
+        Err(record_line_error(s, 391))
     }
 }
 
 /// No 401. Notice record (服用注意レコード)
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct NoticeRecord {
     pub content: String, // 内容
     pub created_by: RecordCreator, // レコード作成者
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub extra: Vec<String>, // 追加項目
 }
 
 impl NoticeRecord {
@@ -2153,15 +3020,22 @@ impl NoticeRecord {
         Self {
             content: content,
             created_by: created_by,
+            extra: Vec::new(),
         }
     }
 
+    /// Serializes the record by `EscapePolicy::FullWidth`, which is lossy.
     pub fn to_code(&self) -> String {
-        format!("{},{},{}",
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        Ok(format!("{},{},{}{}",
             self.record_number().to_string(), // 401
-            self.content,
-            self.created_by.to_code()
-        )
+            escape_field(&self.content, policy, 401, 1)?,
+            self.created_by.to_code(),
+            extra_code(&self.extra, policy, 401, 3)?
+        ))
     }
 }
 
@@ -2179,6 +3053,7 @@ impl Default for NoticeRecord {
         Self {
             content: "".to_string(),
             created_by: RecordCreator::Unknown,
+            extra: Vec::new(),
         }
     }
 }
@@ -2187,28 +3062,31 @@ impl FromStr for NoticeRecord {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         lazy_static! {
-            static ref RE: Regex = Regex::new(r"^(\d+),([^,]*),(\d)$").unwrap();
+            static ref RE: Regex = record_regex(401);
         }
         for cap in RE.captures_iter(s) {
             if (&cap[1]) == "401" {
                 return Ok(Self {
                     content: (&cap[2]).to_string(),
-                    created_by: (&cap[3]).parse()?,
+                    created_by: parse_field(&cap, 3, 401)?,
+                    extra: extra_fields(&cap),
                 })
             }
         }
-        Err(Error::InvalidRecordLine(
-            format!("Cannot convert str to NoticeRecord, got \"{}\"", s)
-        ))
+        Err(record_line_error(s, 401))
     }
 }
 
 /// No 411. Information provision record (医療機関等提供情報レコード)
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct InformationProvisionRecord {
     pub content: String, // 内容
     pub information_type: ProvidedInformationType, // 提供情報種別
     pub created_by: RecordCreator, // レコード作成者
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub extra: Vec<String>, // 追加項目
 }
 
 impl InformationProvisionRecord {
@@ -2218,16 +3096,23 @@ impl InformationProvisionRecord {
             content: content,
             information_type: information_type,
             created_by: created_by,
+            extra: Vec::new(),
         }
     }
 
+    /// Serializes the record by `EscapePolicy::FullWidth`, which is lossy.
     pub fn to_code(&self) -> String {
-        format!("{},{},{},{}",
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        Ok(format!("{},{},{},{}{}",
             self.record_number().to_string(), // 411
-            self.content,
+            escape_field(&self.content, policy, 411, 1)?,
             self.information_type.to_code(),
-            self.created_by.to_code()
-        )
+            self.created_by.to_code(),
+            extra_code(&self.extra, policy, 411, 4)?
+        ))
     }
 }
 
@@ -2246,6 +3131,7 @@ impl Default for InformationProvisionRecord {
             content: "".to_string(),
             information_type: ProvidedInformationType::Other,
             created_by: RecordCreator::Unknown,
+            extra: Vec::new(),
         }
     }
 }
@@ -2254,28 +3140,104 @@ impl FromStr for InformationProvisionRecord {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         lazy_static! {
-            static ref RE: Regex = Regex::new(r"^(\d+),([^,]*),(\d{1,2}),(\d)$").unwrap();
+            static ref RE: Regex = record_regex(411);
         }
         for cap in RE.captures_iter(s) {
             if (&cap[1]) == "411" {
                 return Ok(Self {
                     content: (&cap[2]).to_string(),
-                    information_type: (&cap[3]).parse()?,
-                    created_by: (&cap[4]).parse()?,
+                    information_type: parse_field(&cap, 3, 411)?,
+                    created_by: parse_field(&cap, 4, 411)?,
+                    extra: extra_fields(&cap),
                 })
             }
         }
-        Err(Error::InvalidRecordLine(
-            format!("Cannot convert str to InformationProvisionRecord, got \"{}\"", s)
+        Err(record_line_error(s, 411))
+    }
+}
+
+/// No 421. Remaining medicine record (残薬確認レコード)
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RemainingMedicineRecord {
+    pub content: String, // 残薬確認内容
+    pub created_by: RecordCreator, // レコード作成者
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub extra: Vec<String>, // 追加項目
+}
+
+impl RemainingMedicineRecord {
+    pub fn new(content: String, created_by: RecordCreator) -> Self {
+        Self {
+            content,
+            created_by,
+            extra: Vec::new(),
+        }
+    }
+
+    /// Serializes the record by `EscapePolicy::FullWidth`, which is lossy.
+    pub fn to_code(&self) -> String {
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        Ok(format!("{},{},{}{}",
+            self.record_number(), // 421
+            escape_field(&self.content, policy, 421, 1)?,
+            self.created_by.to_code(),
+            extra_code(&self.extra, policy, 421, 3)?
         ))
     }
 }
 
+impl Record for RemainingMedicineRecord {
+    fn record_number(&self) -> u32 {
+        421
+    }
+    fn cols(&self) -> u32 {
+        2
+    }
+}
+
+impl Default for RemainingMedicineRecord {
+    fn default() -> Self {
+        Self {
+            content: "".to_string(),
+            created_by: RecordCreator::Unknown,
+            extra: Vec::new(),
+        }
+    }
+}
+
+impl FromStr for RemainingMedicineRecord {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        lazy_static! {
+            static ref RE: Regex = record_regex(421);
+        }
+        for cap in RE.captures_iter(s) {
+            if &cap[1] == "421" {
+                return Ok(Self {
+                    content: cap[2].to_string(),
+                    created_by: parse_field(&cap, 3, 421)?,
+                    extra: extra_fields(&cap),
+                })
+            }
+        }
+        Err(record_line_error(s, 421))
+    }
+}
+
 /// No 501. Note record (備考レコード)
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct NoteRecord {
     pub content: String, // 備考情報
     pub created_by: RecordCreator, // レコード作成者
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub extra: Vec<String>, // 追加項目
 }
 
 impl NoteRecord {
@@ -2283,15 +3245,22 @@ impl NoteRecord {
         Self {
             content: content,
             created_by: created_by,
+            extra: Vec::new(),
         }
     }
 
+    /// Serializes the record by `EscapePolicy::FullWidth`, which is lossy.
     pub fn to_code(&self) -> String {
-        format!("{},{},{}",
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        Ok(format!("{},{},{}{}",
             self.record_number().to_string(), // 501
-            self.content,
-            self.created_by.to_code()
-        )
+            escape_field(&self.content, policy, 501, 1)?,
+            self.created_by.to_code(),
+            extra_code(&self.extra, policy, 501, 3)?
+        ))
     }
 }
 
@@ -2309,6 +3278,7 @@ impl Default for NoteRecord {
         Self {
             content: "".to_string(),
             created_by: RecordCreator::Unknown,
+            extra: Vec::new(),
         }
     }
 }
@@ -2317,27 +3287,30 @@ impl FromStr for NoteRecord {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         lazy_static! {
-            static ref RE: Regex = Regex::new(r"^(\d+),([^,]*),(\d)$").unwrap();
+            static ref RE: Regex = record_regex(501);
         }
         for cap in RE.captures_iter(s) {
             if (&cap[1]) == "501" {
                 return Ok(Self {
                     content: (&cap[2]).to_string(),
-                    created_by: (&cap[3]).parse()?,
+                    created_by: parse_field(&cap, 3, 501)?,
+                    extra: extra_fields(&cap),
                 })
             }
         }
-        Err(Error::InvalidRecordLine(
-            format!("Cannot convert str to NoteRecord, got \"{}\"", s)
-        ))
+        Err(record_line_error(s, 501))
     }
 }
 
 /// No 601. From patient record (患者等記入レコード)
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct FromPatientRecord {
     pub content: String, // 患者等記入情報
     pub created_at: Option<Date>, // 入力年月日
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub extra: Vec<String>, // 追加項目
 }
 
 impl FromPatientRecord {
@@ -2345,15 +3318,22 @@ impl FromPatientRecord {
         Self {
             content: content,
             created_at: created_at,
+            extra: Vec::new(),
         }
     }
 
+    /// Serializes the record by `EscapePolicy::FullWidth`, which is lossy.
     pub fn to_code(&self) -> String {
-        format!("{},{},{}",
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        Ok(format!("{},{},{}{}",
             self.record_number().to_string(), // 601
-            self.content,
+            escape_field(&self.content, policy, 601, 1)?,
             self.created_at.map(|v| v.to_code()).unwrap_or_default(),
-        )
+            extra_code(&self.extra, policy, 601, 3)?
+        ))
     }
 }
 
@@ -2371,6 +3351,7 @@ impl Default for FromPatientRecord {
         Self {
             content: "".to_string(),
             created_at: None,
+            extra: Vec::new(),
         }
     }
 }
@@ -2379,24 +3360,25 @@ impl FromStr for FromPatientRecord {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         lazy_static! {
-            static ref RE: Regex = Regex::new(r"^(\d+),([^,]*),((?:\d{8}|\w\d{6})?)$").unwrap();
+            static ref RE: Regex = record_regex(601);
         }
         for cap in RE.captures_iter(s) {
             if (&cap[1]) == "601" {
                 return Ok(Self {
                     content: (&cap[2]).to_string(),
-                    created_at: if (&cap[2]).is_empty() {None} else {Some((&cap[3]).parse()?)},
+                    created_at: if cap[3].is_empty() {None} else {Some(parse_field(&cap, 3, 601)?)},
+                    extra: extra_fields(&cap),
                 })
             }
         }
-        Err(Error::InvalidRecordLine(
-            format!("Cannot convert str to FromPatientRecord, got \"{}\"", s)
-        ))
+        Err(record_line_error(s, 601))
     }
 }
 
 /// No 701. Family pharmacist record (かかりつけ薬剤師レコード)
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct FamilyPharmacistRecord {
     pub name: String, // かかりつけ薬剤師氏名
     pub pharmacy_name: String, // 勤務先薬局名称
@@ -2404,6 +3386,8 @@ pub struct FamilyPharmacistRecord {
     pub start_date: Option<Date>, // 担当開始年月日
     pub end_date: Option<Date>, // 担当終了年月日
     pub created_by: RecordCreator, // レコード作成者
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub extra: Vec<String>, // 追加項目
 }
 
 impl FamilyPharmacistRecord {
@@ -2419,19 +3403,26 @@ impl FamilyPharmacistRecord {
             start_date: start_date,
             end_date: end_date,
             created_by: created_by,
+            extra: Vec::new(),
         }
     }
 
+    /// Serializes the record by `EscapePolicy::FullWidth`, which is lossy.
     pub fn to_code(&self) -> String {
-        format!("{},{},{},{},{},{},{}",
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        Ok(format!("{},{},{},{},{},{},{}{}",
             self.record_number().to_string(), // 701
-            self.name,
-            self.pharmacy_name,
-            self.contact_information,
+            escape_field(&self.name, policy, 701, 1)?,
+            escape_field(&self.pharmacy_name, policy, 701, 2)?,
+            escape_field(&self.contact_information, policy, 701, 3)?,
             self.start_date.map(|v| v.to_code()).unwrap_or_default(),
             self.end_date.map(|v| v.to_code()).unwrap_or_default(),
-            self.created_by.to_code()
-        )
+            self.created_by.to_code(),
+            extra_code(&self.extra, policy, 701, 7)?
+        ))
     }
 }
 
@@ -2453,6 +3444,7 @@ impl Default for FamilyPharmacistRecord {
             start_date: None,
             end_date: None,
             created_by: RecordCreator::Unknown,
+            extra: Vec::new(),
         }
     }
 }
@@ -2461,7 +3453,7 @@ impl FromStr for FamilyPharmacistRecord {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         lazy_static! {
-            static ref RE: Regex = Regex::new(r"^(\d+),([^,]*),([^,]*),([^,]*),((?:\d{8}|\w\d{6})?),((?:\d{8}|\w\d{6})?),(\d)$").unwrap();
+            static ref RE: Regex = record_regex(701);
         }
         for cap in RE.captures_iter(s) {
             if (&cap[1]) == "701" {
@@ -2469,23 +3461,229 @@ impl FromStr for FamilyPharmacistRecord {
                     name: (&cap[2]).to_string(),
                     pharmacy_name: (&cap[3]).to_string(),
                     contact_information: (&cap[4]).to_string(),
-                    start_date: if (&cap[5]).is_empty() {None} else {Some((&cap[5]).parse()?)},
-                    end_date: if (&cap[6]).is_empty() {None} else {Some((&cap[6]).parse()?)},
-                    created_by: (&cap[7]).parse()?,
+                    start_date: if cap[5].is_empty() {None} else {Some(parse_field(&cap, 5, 701)?)},
+                    end_date: if cap[6].is_empty() {None} else {Some(parse_field(&cap, 6, 701)?)},
+                    created_by: parse_field(&cap, 7, 701)?,
+                    extra: extra_fields(&cap),
                 })
             }
         }
-        Err(Error::InvalidRecordLine(
-            format!("Cannot convert str to FamilyPharmacistRecord, got \"{}\"", s)
+        Err(record_line_error(s, 701))
+    }
+}
+
+/// No 911. Split control record (分割制御レコード)
+///
+/// Written after the version record of each part when a notebook is split
+/// across several QR codes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SplitControlRecord {
+    pub data_id: String, // データID
+    pub total: u32, // 最大分割数
+    pub index: u32, // 分割番号
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub extra: Vec<String>, // 追加項目
+}
+
+impl SplitControlRecord {
+    pub fn new(data_id: String, total: u32, index: u32) -> Self {
+        Self {data_id, total, index, extra: Vec::new()}
+    }
+
+    /// Serializes the record by `EscapePolicy::FullWidth`, which is lossy.
+    pub fn to_code(&self) -> String {
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        Ok(format!("{},{},{},{}{}",
+            self.record_number(), // 911
+            escape_field(&self.data_id, policy, 911, 1)?,
+            self.total,
+            self.index,
+            extra_code(&self.extra, policy, 911, 4)?
         ))
     }
 }
 
+impl Record for SplitControlRecord {
+    fn record_number(&self) -> u32 {
+        911
+    }
+    fn cols(&self) -> u32 {
+        3
+    }
+}
+
+impl Default for SplitControlRecord {
+    fn default() -> Self {
+        Self {
+            data_id: "".to_string(),
+            total: 1,
+            index: 1,
+            extra: Vec::new(),
+        }
+    }
+}
+
+impl FromStr for SplitControlRecord {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        lazy_static! {
+            static ref RE: Regex = record_regex(911);
+        }
+        for cap in RE.captures_iter(s) {
+            if (&cap[1]) == "911" {
+                return Ok(Self {
+                    data_id: cap[2].to_string(),
+                    total: parse_field(&cap, 3, 911)?,
+                    index: parse_field(&cap, 4, 911)?,
+                    extra: extra_fields(&cap),
+                })
+            }
+        }
+        Err(record_line_error(s, 911))
+    }
+}
+
+/// Record whose number is not defined in the specification (未定義のレコード),
+/// such as one of a newer version or a vendor extension
+///
+/// `position` is the number of the other lines of the enclosing block or
+/// notebook which preceded the record when it was read, so that it is written
+/// back where it was. A record without one is written before the first known
+/// record of a greater number.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct UnknownRecord {
+    pub record_number: u32, // レコード番号
+    pub fields: Vec<String>, // 項目
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub position: Option<usize>, // 位置
+}
+
+impl UnknownRecord {
+    pub fn new(record_number: u32, fields: Vec<String>) -> Self {
+        Self {
+            record_number,
+            fields,
+            position: None,
+        }
+    }
+
+    /// Serializes the record by `EscapePolicy::FullWidth`, which is lossy.
+    pub fn to_code(&self) -> String {
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        Ok(format!("{}{}", self.record_number, extra_code(&self.fields, policy, self.record_number, 1)?))
+    }
+}
+
+impl Record for UnknownRecord {
+    fn record_number(&self) -> u32 {
+        self.record_number
+    }
+    fn cols(&self) -> u32 {
+        self.fields.len() as u32
+    }
+}
+
+impl FromStr for UnknownRecord {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        lazy_static! {
+            static ref RE: Regex = Regex::new(r"^(\d+)((?:,[^,]*)+)$").unwrap();
+        }
+        for cap in RE.captures_iter(s) {
+            let record_number: u32 = cap[1].parse()?;
+            if record_name(record_number).is_none() {
+                return Ok(Self::new(record_number, extra_fields(&cap)))
+            }
+        }
+        Err(Error::InvalidRecordLine(ParseError {
+            expected: "a record of a number not defined in the specification".to_string(),
+            found: s.to_string(),
+            .. Default::default()
+        }))
+    }
+}
+
+/// Returns whether a line is a record of a number not defined in the
+/// specification.
+pub(crate) fn is_unknown_record(line: &str) -> bool {
+    match line.split_once(',') {
+        Some((number, _)) => !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit())
+            && number.parse().is_ok_and(|n| record_name(n).is_none()),
+        None => false,
+    }
+}
+
+/// Unknown records waiting to be written among the other lines of a block or
+/// a notebook.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PendingRecords {
+    records: Vec<UnknownRecord>,
+    known_lines: usize,
+}
+
+impl PendingRecords {
+    pub(crate) fn new(records: &[UnknownRecord]) -> Self {
+        Self {
+            records: records.to_vec(),
+            known_lines: 0,
+        }
+    }
+
+    /// Takes the unknown records to be written before the next other line,
+    /// whose record number is `record_number`, and counts that line.
+    pub(crate) fn before(&mut self, record_number: u32) -> Vec<UnknownRecord> {
+        let known_lines = self.known_lines;
+        let (due, rest) = std::mem::take(&mut self.records).into_iter()
+            .partition(|r| r.position.map_or(r.record_number < record_number, |p| p <= known_lines));
+        self.records = rest;
+        self.known_lines += 1;
+        due
+    }
+
+    /// Takes the unknown records left after the last other line.
+    pub(crate) fn rest(&mut self) -> Vec<UnknownRecord> {
+        std::mem::take(&mut self.records)
+    }
+}
+
+/// Appends the lines of known records to `lines`, inserting the unknown
+/// records where they were read, or else in ascending order of record number
+/// as the specification requires for the known ones. `known` holds the record
+/// number of each line, where 0 keeps a line ahead of every unknown record.
+pub(crate) fn push_merged(lines: &mut Vec<String>, known: Vec<(u32, String)>,
+        unknown_records: &[UnknownRecord], policy: EscapePolicy) -> Result<(), Error> {
+    let mut pending = PendingRecords::new(unknown_records);
+    for (record_number, line) in known {
+        for record in pending.before(record_number) {
+            lines.push(record.to_code_with(policy)?);
+        }
+        lines.push(line);
+    }
+    for record in pending.rest() {
+        lines.push(record.to_code_with(policy)?);
+    }
+    Ok(())
+}
+
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct DrugBlock {
     pub drug: DrugRecord, // 薬品レコード
     pub drug_supplementary: Vec<DrugSupplementaryRecord>, // 薬品補足レコード
     pub drug_notice: Vec<DrugNoticeRecord>, // 薬品服用注意レコード
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub unknown_records: Vec<UnknownRecord>, // 未定義のレコード
 }
 
 impl DrugBlock {
@@ -2495,16 +3693,27 @@ impl DrugBlock {
             .. Default::default()
         }
     }
+    /// Serializes the block by `EscapePolicy::FullWidth`, which is lossy.
     pub fn to_code(&self) -> String {
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        Ok(self.code_lines(policy)?.join("\r\n"))
+    }
+
+    pub(crate) fn code_lines(&self, policy: EscapePolicy) -> Result<Vec<String>, Error> {
         let mut lines: Vec<String> = Vec::new();
-        lines.push(self.drug.to_code());
+        let mut known: Vec<(u32, String)> = Vec::new();
+        known.push((0, self.drug.to_code_with(policy)?));
         for record in &self.drug_supplementary {
-            lines.push(record.to_code());
+            known.push((record.record_number(), record.to_code_with(policy)?));
         }
         for record in &self.drug_notice {
-            lines.push(record.to_code());
+            known.push((record.record_number(), record.to_code_with(policy)?));
         }
-        lines.join("\r\n")
+        push_merged(&mut lines, known, &self.unknown_records, policy)?;
+        Ok(lines)
     }
 }
 
@@ -2514,6 +3723,7 @@ impl Default for DrugBlock {
             drug: DrugRecord::default(),
             drug_supplementary: Vec::new(),
             drug_notice: Vec::new(),
+            unknown_records: Vec::new(),
         }
     }
 }
@@ -2521,41 +3731,57 @@ impl Default for DrugBlock {
 impl FromStr for DrugBlock {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_lines(&numbered_lines(s))
+    }
+}
+
+impl FromLines for DrugBlock {
+    fn from_lines(lines: &[(usize, &str)]) -> Result<Self, Error> {
         let mut drug: Option<DrugRecord> = None;
         let mut drug_supplementary: Vec<DrugSupplementaryRecord> = Vec::new();
         let mut drug_notice: Vec<DrugNoticeRecord> = Vec::new();
-        for line in s.to_string().lines() {
-            if line.chars().count() >= 4 {
+        let mut unknown_records: Vec<UnknownRecord> = Vec::new();
+        for (i, &(n, line)) in lines.iter().filter(|(_, line)| !line.is_empty()).enumerate() {
+            if is_unknown_record(line) { // 未定義のレコード
+                if drug.is_none() {
+                    return Err(
+                        Error::GotUnexpectedRecordLine(
+                            ParseError::unexpected(n, line, "201 DrugRecord")
+                        )
+                    );
+                }
+                unknown_records.push(UnknownRecord {position: Some(i - unknown_records.len()), .. parse_line(line, n)?});
+            } else if line.chars().count() >= 4 {
                 let sep = line.char_indices().nth(4).unwrap().0;
                 if drug.is_none() {
                     if &line[..sep] == "201," { // 薬品レコード
-                        drug = Some(line.parse()?);
+                        drug = Some(parse_line(line, n)?);
                     } else {
                         return Err(
                             Error::GotUnexpectedRecordLine(
-                                format!("Expected 201 DrugRecord, got \"{}\".", line)
+                                ParseError::unexpected(n, line, "201 DrugRecord")
                             )
                         );
                     }
                 } else {
                     if &line[..sep] == "281," { // 薬品補足レコード
-                        drug_supplementary.push(line.parse()?);
+                        drug_supplementary.push(parse_line(line, n)?);
                     } else if &line[..sep] == "291," { // 薬品服用注意レコード
-                        drug_notice.push(line.parse()?);
+                        drug_notice.push(parse_line(line, n)?);
                     } else {
                         return Err(
                             Error::GotUnexpectedRecordLine(
-                                format!("Expected 281 or 291 record, got \"{}\".", line)
+                                ParseError::unexpected(n, line, "281 or 291 record")
                             )
                         );
                     }
                 }
-            } else if line == "" {
+            } else if line.is_empty() {
                 continue
             } else {
                 return Err(
                     Error::GotUnexpectedRecordLine(
-                        format!("Expected 201, 281 or 291 record, got \"{}\".", line)
+                        ParseError::unexpected(n, line, "201, 281 or 291 record")
                     )
                 );
             }
@@ -2565,19 +3791,24 @@ impl FromStr for DrugBlock {
                 drug: drug.unwrap(),
                 drug_supplementary: drug_supplementary,
                 drug_notice: drug_notice,
+                unknown_records,
             })
         } else {
-            Err(Error::MissingRequiredRecord(format!("DrugRecord is required.")))
+            Err(Error::MissingRequiredRecord(ParseError::missing_in(lines, "201 DrugRecord")))
         }
     }
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct RpBlock {
     pub drugs: Vec<DrugBlock>, // 薬品情報
     pub usage: UsageRecord, // 用法レコード
     pub usage_supplementary: Vec<UsageSupplementaryRecord>, // 用法補足レコード
     pub rp_notice: Vec<RpNoticeRecord>, // 処方服用注意レコード
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub unknown_records: Vec<UnknownRecord>, // 未定義のレコード
 }
 
 impl RpBlock {
@@ -2587,19 +3818,30 @@ impl RpBlock {
             .. Default::default()
         }
     }
+    /// Serializes the block by `EscapePolicy::FullWidth`, which is lossy.
     pub fn to_code(&self) -> String {
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        Ok(self.code_lines(policy)?.join("\r\n"))
+    }
+
+    pub(crate) fn code_lines(&self, policy: EscapePolicy) -> Result<Vec<String>, Error> {
         let mut lines: Vec<String> = Vec::new();
+        let mut known: Vec<(u32, String)> = Vec::new();
         for block in &self.drugs {
-            lines.push(block.to_code());
+            known.extend(block.code_lines(policy)?.into_iter().map(|line| (0, line)));
         }
-        lines.push(self.usage.to_code());
+        known.push((0, self.usage.to_code_with(policy)?));
         for record in &self.usage_supplementary {
-            lines.push(record.to_code());
+            known.push((record.record_number(), record.to_code_with(policy)?));
         }
         for record in &self.rp_notice {
-            lines.push(record.to_code());
+            known.push((record.record_number(), record.to_code_with(policy)?));
         }
-        lines.join("\r\n")
+        push_merged(&mut lines, known, &self.unknown_records, policy)?;
+        Ok(lines)
     }
 }
 
@@ -2610,6 +3852,7 @@ impl Default for RpBlock {
             usage: UsageRecord::default(),
             usage_supplementary: Vec::new(),
             rp_notice: Vec::new(),
+            unknown_records: Vec::new(),
         }
     }
 }
@@ -2617,92 +3860,116 @@ impl Default for RpBlock {
 impl FromStr for RpBlock {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_lines(&numbered_lines(s))
+    }
+}
+
+impl FromLines for RpBlock {
+    fn from_lines(lines: &[(usize, &str)]) -> Result<Self, Error> {
         let mut drug_blocks: Vec<DrugBlock> = Vec::new();
-        let mut temp_drug_block_string: Vec<String> = Vec::new();
+        let mut temp_drug_block: Vec<(usize, &str)> = Vec::new();
         let mut usage: Option<UsageRecord> = None;
         let mut usage_supplementary: Vec<UsageSupplementaryRecord> = Vec::new();
         let mut rp_notice: Vec<RpNoticeRecord> = Vec::new();
-        for line in s.to_string().lines() {
-            if line.chars().count() >= 4 {
+        let mut unknown_records: Vec<UnknownRecord> = Vec::new();
+        for (i, &(n, line)) in lines.iter().filter(|(_, line)| !line.is_empty()).enumerate() {
+            if is_unknown_record(line) { // 未定義のレコード
+                if usage.is_some() {
+                    unknown_records.push(UnknownRecord {position: Some(i - unknown_records.len()), .. parse_line(line, n)?});
+                } else if !temp_drug_block.is_empty() {
+                    temp_drug_block.push((n, line));
+                } else {
+                    return Err(
+                        Error::GotUnexpectedRecordLine(
+                            ParseError::unexpected(n, line, "201 DrugRecord line before this record")
+                        )
+                    );
+                }
+            } else if line.chars().count() >= 4 {
                 let sep = line.char_indices().nth(4).unwrap().0;
                 if usage.is_none() {
                     if &line[..sep] == "201," { // 薬品レコード
-                        if temp_drug_block_string.len() > 0 {
-                            drug_blocks.push(temp_drug_block_string.join("\r\n").parse()?);
-                            temp_drug_block_string = Vec::new();
+                        if !temp_drug_block.is_empty() {
+                            drug_blocks.push(DrugBlock::from_lines(&temp_drug_block)?);
+                            temp_drug_block = Vec::new();
                         }
-                        temp_drug_block_string.push(line.to_string());
+                        temp_drug_block.push((n, line));
                     } else if &line[..sep] == "281," || &line[..sep] == "291," { // 薬品補足 薬品服用注意レコード
-                        if temp_drug_block_string.len() == 0 {
+                        if temp_drug_block.is_empty() {
                             return Err(
                                 Error::GotUnexpectedRecordLine(
-                                    format!("201 DrugRecord line must exist before \"{}\"", line)
+                                    ParseError::unexpected(n, line, "201 DrugRecord line before this record")
                                 )
                             );
                         }
-                        temp_drug_block_string.push(line.to_string());
+                        temp_drug_block.push((n, line));
                     } else if &line[..sep] == "301," { // 用法レコード
-                        if temp_drug_block_string.len() > 0 {
-                            drug_blocks.push(temp_drug_block_string.join("\r\n").parse()?);
-                        } else if drug_blocks.len() == 0 {
+                        if !temp_drug_block.is_empty() {
+                            drug_blocks.push(DrugBlock::from_lines(&temp_drug_block)?);
+                        } else if drug_blocks.is_empty() {
                             return Err(
                                 Error::GotUnexpectedRecordLine(
-                                    format!("DrugBlock must exist before UsageRecord \"{}\"", line)
+                                    ParseError::unexpected(n, line, "DrugBlock before UsageRecord")
                                 )
                             );
                         }
-                        usage = Some(line.parse()?);
+                        usage = Some(parse_line(line, n)?);
                     } else {
                         return Err(
                             Error::GotUnexpectedRecordLine(
-                                format!("Expected DrugBlock or 301 UsageRecord, got \"{}\".", line)
+                                ParseError::unexpected(n, line, "DrugBlock or 301 UsageRecord")
                             )
                         );
                     }
                 } else {
                     if &line[..sep] == "311," { // 用法補足レコード
-                        usage_supplementary.push(line.parse()?);
+                        usage_supplementary.push(parse_line(line, n)?);
                     } else if &line[..sep] == "391," { // 処方服用注意レコード
-                        rp_notice.push(line.parse()?);
+                        rp_notice.push(parse_line(line, n)?);
                     } else {
                         return Err(
                             Error::GotUnexpectedRecordLine(
-                                format!("Expected 311 or 391 record, got \"{}\".", line)
+                                ParseError::unexpected(n, line, "311 or 391 record")
                             )
                         );
                     }
                 }
-            } else if line == "" {
+            } else if line.is_empty() {
                 continue
             } else {
                 return Err(
                     Error::GotUnexpectedRecordLine(
-                        format!("Expected 201, 281, 291, 301, 311, or 391 record, got \"{}\".", line)
+                        ParseError::unexpected(n, line, "201, 281, 291, 301, 311, or 391 record")
                     )
                 );
             }
         }
-        if drug_blocks.len() > 0 && usage.is_some() {
+        if !drug_blocks.is_empty() && usage.is_some() {
             Ok(Self {
                 drugs: drug_blocks,
                 usage: usage.unwrap(),
                 usage_supplementary: usage_supplementary,
                 rp_notice: rp_notice,
+                unknown_records,
             })
         } else {
-            if drug_blocks.len() == 0 {
-                Err(Error::MissingRequiredRecord(format!("DrugBlock is required.")))
+            if drug_blocks.is_empty() {
+                Err(Error::MissingRequiredRecord(ParseError::missing_in(lines, "DrugBlock")))
             } else {
-                Err(Error::MissingRequiredRecord(format!("UsageRecord is required.")))
+                Err(Error::MissingRequiredRecord(ParseError::missing_in(lines, "301 UsageRecord")))
             }
         }
     }
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct PrescriptionBlock {
     pub physician: Option<PhysicianRecord>, // 処方－医師レコード
     pub rps: Vec<RpBlock>, // RP情報
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub unknown_records: Vec<UnknownRecord>, // 未定義のレコード
 }
 
 impl PrescriptionBlock {
@@ -2711,15 +3978,28 @@ impl PrescriptionBlock {
             .. Default::default()
         }
     }
+    /// Serializes the block by `EscapePolicy::FullWidth`, which is lossy.
     pub fn to_code(&self) -> String {
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        Ok(self.code_lines(policy)?.join("\r\n"))
+    }
+
+    /// Unknown records of a prescription precede its RP blocks, since after
+    /// them they would be read as a part of the last RP.
+    pub(crate) fn code_lines(&self, policy: EscapePolicy) -> Result<Vec<String>, Error> {
         let mut lines: Vec<String> = Vec::new();
+        let mut known: Vec<(u32, String)> = Vec::new();
         if let Some(record) = &self.physician {
-            lines.push(record.to_code());
+            known.push((0, record.to_code_with(policy)?));
         }
         for block in &self.rps {
-            lines.push(block.to_code());
+            known.extend(block.code_lines(policy)?.into_iter().map(|line| (u32::MAX, line)));
         }
-        lines.join("\r\n")
+        push_merged(&mut lines, known, &self.unknown_records, policy)?;
+        Ok(lines)
     }
 }
 
@@ -2728,6 +4008,7 @@ impl Default for PrescriptionBlock {
         Self {
             physician: None,
             rps: Vec::new(),
+            unknown_records: Vec::new(),
         }
     }
 }
@@ -2735,71 +4016,87 @@ impl Default for PrescriptionBlock {
 impl FromStr for PrescriptionBlock {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_lines(&numbered_lines(s))
+    }
+}
+
+impl FromLines for PrescriptionBlock {
+    fn from_lines(lines: &[(usize, &str)]) -> Result<Self, Error> {
         let mut physician: Option<PhysicianRecord> = None;
         let mut rps: Vec<RpBlock> = Vec::new();
-        let mut temp_rp_block_string: Vec<String> = Vec::new();
+        let mut temp_rp_block: Vec<(usize, &str)> = Vec::new();
         let mut flag_usage_exists: bool = false;
-        for line in s.to_string().lines() {
-            if line.chars().count() >= 4 {
+        let mut unknown_records: Vec<UnknownRecord> = Vec::new();
+        for (i, &(n, line)) in lines.iter().filter(|(_, line)| !line.is_empty()).enumerate() {
+            if is_unknown_record(line) { // 未定義のレコード
+                if temp_rp_block.is_empty() && rps.is_empty() {
+                    unknown_records.push(UnknownRecord {position: Some(i - unknown_records.len()), .. parse_line(line, n)?});
+                } else {
+                    temp_rp_block.push((n, line));
+                }
+            } else if line.chars().count() >= 4 {
                 let sep3 = line.char_indices().nth(3).unwrap().0;
                 let sep4 = line.char_indices().nth(4).unwrap().0;
                 if &line[..sep3] == "55," { // 医師レコード
                     if physician.is_some() {
                         return Err(
                             Error::GotUnexpectedRecordLine(
-                                format!("Multiple PhysicianRecord lines are not allowed: \"{}\"", line)
+                                ParseError::unexpected(n, line, "a single PhysicianRecord")
                             )
                         );
-                    } else if rps.len() > 0 || temp_rp_block_string.len() > 0 {
+                    } else if !rps.is_empty() || !temp_rp_block.is_empty() {
                         return Err(
                             Error::GotUnexpectedRecordLine(
-                                format!("PhysicianRecord must exist before RpBlock")
+                                ParseError::unexpected(n, line, "PhysicianRecord before RpBlock")
                             )
                         );
                     } else {
-                        physician = Some(line.parse()?);
+                        physician = Some(parse_line(line, n)?);
                     }
                 } else if &line[..sep4] == "201," { // 薬品レコード
-                    if flag_usage_exists && temp_rp_block_string.len() > 0  {
-                        rps.push(temp_rp_block_string.join("\r\n").parse()?);
-                        temp_rp_block_string = Vec::new();
+                    if flag_usage_exists && !temp_rp_block.is_empty() {
+                        rps.push(RpBlock::from_lines(&temp_rp_block)?);
+                        temp_rp_block = Vec::new();
                         flag_usage_exists = false;
                     }
-                    temp_rp_block_string.push(line.to_string());
+                    temp_rp_block.push((n, line));
                 } else if &line[..sep4] == "301," { // 用法レコード
                     flag_usage_exists = true;
-                    temp_rp_block_string.push(line.to_string());
+                    temp_rp_block.push((n, line));
                 } else if &line[..sep4] == "281," || &line[..sep4] == "291,"
                         || &line[..sep4] == "311," || &line[..sep4] == "391," {
-                    temp_rp_block_string.push(line.to_string());
+                    temp_rp_block.push((n, line));
                 } else {
                     return Err(
                         Error::GotUnexpectedRecordLine(
-                            format!("Expected 311 or 391 record, got \"{}\".", line)
+                            ParseError::unexpected(n, line, "55 PhysicianRecord or RpBlock")
                         )
                     );
                 }
-            } else if line == "" {
+            } else if line.is_empty() {
                 continue
             } else {
                 return Err(
                     Error::GotUnexpectedRecordLine(
-                        format!("Expected 55 record or RpBlock, got \"{}\".", line)
+                        ParseError::unexpected(n, line, "55 record or RpBlock")
                     )
                 );
             }
         }
-        if temp_rp_block_string.len() > 0  {
-            rps.push(temp_rp_block_string.join("\r\n").parse()?);
+        if !temp_rp_block.is_empty() {
+            rps.push(RpBlock::from_lines(&temp_rp_block)?);
         }
         Ok(Self {
             physician: physician,
             rps: rps,
+            unknown_records,
         })
     }
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct DispensingInformationBlock {
     pub date: DateRecord, // 調剤等年月日レコード
     pub pharmacy: PharmacyRecord, // 調剤－医療機関等レコード
@@ -2810,8 +4107,11 @@ pub struct DispensingInformationBlock {
 
     pub notice: Option<NoticeRecord>, // 服用注意レコード
     pub information_provision: Option<InformationProvisionRecord>, //医療機関等提供情報レコード
+    pub remaining_medicine: Option<RemainingMedicineRecord>, // 残薬確認レコード
     pub note: Option<NoteRecord>, // 備考レコード
     pub from_patient: Option<FromPatientRecord>, // 患者等記入レコード
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub unknown_records: Vec<UnknownRecord>, // 未定義のレコード
 }
 
 impl DispensingInformationBlock {
@@ -2822,32 +4122,49 @@ impl DispensingInformationBlock {
             .. Default::default()
         }
     }
+    /// Serializes the block by `EscapePolicy::FullWidth`, which is lossy.
     pub fn to_code(&self) -> String {
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        Ok(self.code_lines(policy)?.join("\r\n"))
+    }
+
+    /// Unknown records numbered below 401 are written before the
+    /// prescriptions, since after them they would be read as a part of the
+    /// last prescription.
+    pub(crate) fn code_lines(&self, policy: EscapePolicy) -> Result<Vec<String>, Error> {
         let mut lines: Vec<String> = Vec::new();
-        lines.push(self.date.to_code());
-        lines.push(self.pharmacy.to_code());
+        let mut known: Vec<(u32, String)> = Vec::new();
+        known.push((0, self.date.to_code_with(policy)?));
+        known.push((self.pharmacy.record_number(), self.pharmacy.to_code_with(policy)?));
         if let Some(record) = &self.pharmacist {
-            lines.push(record.to_code());
+            known.push((record.record_number(), record.to_code_with(policy)?));
         }
         if let Some(record) = &self.medical_institute {
-            lines.push(record.to_code());
+            known.push((record.record_number(), record.to_code_with(policy)?));
         }
         for block in &self.prescriptions {
-            lines.push(block.to_code());
+            known.extend(block.code_lines(policy)?.into_iter().map(|line| (401, line)));
         }
         if let Some(record) = &self.notice {
-            lines.push(record.to_code());
+            known.push((record.record_number(), record.to_code_with(policy)?));
         }
         if let Some(record) = &self.information_provision {
-            lines.push(record.to_code());
+            known.push((record.record_number(), record.to_code_with(policy)?));
+        }
+        if let Some(record) = &self.remaining_medicine {
+            known.push((record.record_number(), record.to_code_with(policy)?));
         }
         if let Some(record) = &self.note {
-            lines.push(record.to_code());
+            known.push((record.record_number(), record.to_code_with(policy)?));
         }
         if let Some(record) = &self.from_patient {
-            lines.push(record.to_code());
+            known.push((record.record_number(), record.to_code_with(policy)?));
         }
-        lines.join("\r\n")
+        push_merged(&mut lines, known, &self.unknown_records, policy)?;
+        Ok(lines)
     }
 }
 
@@ -2863,8 +4180,11 @@ impl Default for DispensingInformationBlock {
 
             notice: None,
             information_provision: None,
+            remaining_medicine: None,
             note: None,
             from_patient: None,
+
+            unknown_records: Vec::new(),
         }
     }
 }
@@ -2872,91 +4192,107 @@ impl Default for DispensingInformationBlock {
 impl FromStr for DispensingInformationBlock {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_lines(&numbered_lines(s))
+    }
+}
+
+impl FromLines for DispensingInformationBlock {
+    fn from_lines(lines: &[(usize, &str)]) -> Result<Self, Error> {
         let mut date: Option<DateRecord> = None;
         let mut pharmacy: Option<PharmacyRecord> = None;
         let mut pharmacist: Option<PharmacistRecord> = None;
         let mut medical_institute: Option<MedicalInstitutionRecord> = None;
 
         let mut prescriptions: Vec<PrescriptionBlock> = Vec::new();
-        let mut temp_prescription_block_string: Vec<String> = Vec::new();
+        let mut temp_prescription_block: Vec<(usize, &str)> = Vec::new();
 
         let mut notice: Option<NoticeRecord> = None;
         let mut information_provision: Option<InformationProvisionRecord> = None;
+        let mut remaining_medicine: Option<RemainingMedicineRecord> = None;
         let mut note: Option<NoteRecord> = None;
         let mut from_patient: Option<FromPatientRecord> = None;
 
+        let mut unknown_records: Vec<UnknownRecord> = Vec::new();
+
         let mut cur_num: u32 = 0;
 
-        for line in s.to_string().lines() {
-            if line.chars().count() >= 4 {
+        for (i, &(n, line)) in lines.iter().filter(|(_, line)| !line.is_empty()).enumerate() {
+            if is_unknown_record(line) { // 未定義のレコード
+                let record: UnknownRecord = parse_line(line, n)?;
+                if cur_num == 55 && record.record_number < 401 {
+                    temp_prescription_block.push((n, line));
+                } else {
+                    unknown_records.push(UnknownRecord {position: Some(i - unknown_records.len()), .. record});
+                }
+            } else if line.chars().count() >= 4 {
                 let sep2 = line.char_indices().nth(2).unwrap().0;
                 let sep3 = line.char_indices().nth(3).unwrap().0;
                 let sep4 = line.char_indices().nth(4).unwrap().0;
                 if &line[..sep2] == "5," { // 調剤等年月日レコード
                     if date.is_none() {
-                        date = Some(line.parse()?);
+                        date = Some(parse_line(line, n)?);
                         cur_num = 5;
                     } else {
                         return Err(
                             Error::GotUnexpectedRecordLine(
-                                format!("Multiple DateRecord lines are not allowed: \"{}\"", line)
+                                ParseError::unexpected(n, line, "a single DateRecord")
                             )
                         );
                     }
                 } else if &line[..sep3] == "11," { // 調剤－医療機関等レコード
                     if cur_num < 11 {
                         if pharmacy.is_none() {
-                            pharmacy = Some(line.parse()?);
+                            pharmacy = Some(parse_line(line, n)?);
                             cur_num = 11;
                         } else {
                             return Err(
                                 Error::GotUnexpectedRecordLine(
-                                    format!("Multiple PharmacyRecord lines are not allowed: \"{}\"", line)
+                                    ParseError::unexpected(n, line, "a single PharmacyRecord")
                                 )
                             );
                         }
                     } else {
                         return Err(
                             Error::GotUnexpectedRecordLine(
-                                format!("Unexpected PharmacyRecord here: \"{}\"", line)
+                                ParseError::unexpected(n, line, "PharmacyRecord in ascending order of record number")
                             )
                         );
                     }
                 } else if &line[..sep3] == "15," { // 調剤－医師・薬剤師レコード
                     if cur_num < 15 {
                         if pharmacist.is_none() {
-                            pharmacist = Some(line.parse()?);
+                            pharmacist = Some(parse_line(line, n)?);
                             cur_num = 15;
                         } else {
                             return Err(
                                 Error::GotUnexpectedRecordLine(
-                                    format!("Multiple PharmacistRecord lines are not allowed: \"{}\"", line)
+                                    ParseError::unexpected(n, line, "a single PharmacistRecord")
                                 )
                             );
                         }
                     } else {
                         return Err(
                             Error::GotUnexpectedRecordLine(
-                                format!("Unexpected PharmacistRecord here: \"{}\"", line)
+                                ParseError::unexpected(n, line, "PharmacistRecord in ascending order of record number")
                             )
                         );
                     }
                 } else if &line[..sep3] == "51," { // 処方－医療機関レコード
                     if cur_num < 51 {
                         if medical_institute.is_none() {
-                            medical_institute = Some(line.parse()?);
+                            medical_institute = Some(parse_line(line, n)?);
                             cur_num = 51;
                         } else {
                             return Err(
                                 Error::GotUnexpectedRecordLine(
-                                    format!("Multiple MedicalInstituteRecord lines are not allowed: \"{}\"", line)
+                                    ParseError::unexpected(n, line, "a single MedicalInstituteRecord")
                                 )
                             );
                         }
                     } else {
                         return Err(
                             Error::GotUnexpectedRecordLine(
-                                format!("Unexpected MedicalInstituteRecord here: \"{}\"", line)
+                                ParseError::unexpected(n, line, "MedicalInstituteRecord in ascending order of record number")
                             )
                         );
                     }
@@ -2964,114 +4300,133 @@ impl FromStr for DispensingInformationBlock {
                         || &line[..sep4] == "281," || &line[..sep4] == "291,"
                         || &line[..sep4] == "301," || &line[..sep4] == "311," || &line[..sep4] == "391," {
                     if cur_num <= 55 {
-                        if &line[..sep3] == "55," && temp_prescription_block_string.len() > 0 {
-                            prescriptions.push(temp_prescription_block_string.join("\r\n").parse()?);
-                            temp_prescription_block_string = Vec::new();
+                        if &line[..sep3] == "55," && !temp_prescription_block.is_empty() {
+                            prescriptions.push(PrescriptionBlock::from_lines(&temp_prescription_block)?);
+                            temp_prescription_block = Vec::new();
                         }
-                        temp_prescription_block_string.push(line.to_string());
+                        temp_prescription_block.push((n, line));
                         cur_num = 55;
                     } else {
                         return Err(
                             Error::GotUnexpectedRecordLine(
-                                format!("Unexpected PrescriptionBlock here: \"{}\"", line)
+                                ParseError::unexpected(n, line, "PrescriptionBlock in ascending order of record number")
                             )
                         );
                     }
                 } else if &line[..sep4] == "401," { // 服用注意レコード
                     if cur_num < 401 {
                         if notice.is_none() {
-                            notice = Some(line.parse()?);
+                            notice = Some(parse_line(line, n)?);
                             cur_num = 401;
                         } else {
                             return Err(
                                 Error::GotUnexpectedRecordLine(
-                                    format!("Multiple NoticeRecord lines are not allowed: \"{}\"", line)
+                                    ParseError::unexpected(n, line, "a single NoticeRecord")
                                 )
                             );
                         }
                     } else {
                         return Err(
                             Error::GotUnexpectedRecordLine(
-                                format!("Unexpected NoticeRecord here: \"{}\"", line)
+                                ParseError::unexpected(n, line, "NoticeRecord in ascending order of record number")
                             )
                         );
                     }
                 } else if &line[..sep4] == "411," { // 医療機関等提供情報レコード
                     if cur_num < 411 {
                         if information_provision.is_none() {
-                            information_provision = Some(line.parse()?);
+                            information_provision = Some(parse_line(line, n)?);
                             cur_num = 411;
                         } else {
                             return Err(
                                 Error::GotUnexpectedRecordLine(
-                                    format!("Multiple InformationProvisionRecord lines are not allowed: \"{}\"", line)
+                                    ParseError::unexpected(n, line, "a single InformationProvisionRecord")
                                 )
                             );
                         }
                     } else {
                         return Err(
                             Error::GotUnexpectedRecordLine(
-                                format!("Unexpected InformationProvisionRecord here: \"{}\"", line)
+                                ParseError::unexpected(n, line, "InformationProvisionRecord in ascending order of record number")
+                            )
+                        );
+                    }
+                } else if &line[..sep4] == "421," { // 残薬確認レコード
+                    if cur_num < 421 {
+                        if remaining_medicine.is_none() {
+                            remaining_medicine = Some(parse_line(line, n)?);
+                            cur_num = 421;
+                        } else {
+                            return Err(
+                                Error::GotUnexpectedRecordLine(
+                                    ParseError::unexpected(n, line, "a single RemainingMedicineRecord")
+                                )
+                            );
+                        }
+                    } else {
+                        return Err(
+                            Error::GotUnexpectedRecordLine(
+                                ParseError::unexpected(n, line, "RemainingMedicineRecord in ascending order of record number")
                             )
                         );
                     }
                 } else if &line[..sep4] == "501," { // 備考レコード
                     if cur_num < 501 {
                         if note.is_none() {
-                            note = Some(line.parse()?);
+                            note = Some(parse_line(line, n)?);
                             cur_num = 501;
                         } else {
                             return Err(
                                 Error::GotUnexpectedRecordLine(
-                                    format!("Multiple NoteRecord lines are not allowed: \"{}\"", line)
+                                    ParseError::unexpected(n, line, "a single NoteRecord")
                                 )
                             );
                         }
                     } else {
                         return Err(
                             Error::GotUnexpectedRecordLine(
-                                format!("Unexpected NoteRecord here: \"{}\"", line)
+                                ParseError::unexpected(n, line, "NoteRecord in ascending order of record number")
                             )
                         );
                     }
                 } else if &line[..sep4] == "601," { // 患者記入レコード
                     if cur_num < 601 {
                         if from_patient.is_none() {
-                            from_patient = Some(line.parse()?);
+                            from_patient = Some(parse_line(line, n)?);
                             cur_num = 601;
                         } else {
                             return Err(
                                 Error::GotUnexpectedRecordLine(
-                                    format!("Multiple FromPatientRecord lines are not allowed: \"{}\"", line)
+                                    ParseError::unexpected(n, line, "a single FromPatientRecord")
                                 )
                             );
                         }
                     } else {
                         return Err(
                             Error::GotUnexpectedRecordLine(
-                                format!("Unexpected FromPatientRecord here: \"{}\"", line)
+                                ParseError::unexpected(n, line, "FromPatientRecord in ascending order of record number")
                             )
                         );
                     }
                 } else {
                     return Err(
                         Error::GotUnexpectedRecordLine(
-                            format!("Expected 5, 11, 15, 51, 55, 201~391, 401, 411, 501, or 601 record, got \"{}\".", line)
+                            ParseError::unexpected(n, line, "5, 11, 15, 51, 55, 201~391, 401, 411, 421, 501, or 601 record")
                         )
                     );
                 }
-            } else if line == "" {
+            } else if line.is_empty() {
                 continue
             } else {
                 return Err(
                     Error::GotUnexpectedRecordLine(
-                        format!("Expected record line of DispensingInformationBlock, got \"{}\".", line)
+                        ParseError::unexpected(n, line, "record line of DispensingInformationBlock")
                     )
                 );
             }
         }
-        if temp_prescription_block_string.len() > 0  {
-            prescriptions.push(temp_prescription_block_string.join("\r\n").parse()?);
+        if !temp_prescription_block.is_empty() {
+            prescriptions.push(PrescriptionBlock::from_lines(&temp_prescription_block)?);
         }
         if date.is_some() && pharmacy.is_some() {
             Ok(Self {
@@ -3082,26 +4437,33 @@ impl FromStr for DispensingInformationBlock {
                 prescriptions: prescriptions,
                 notice: notice,
                 information_provision: information_provision,
+                remaining_medicine,
                 note: note,
                 from_patient: from_patient,
+                unknown_records,
             })
         } else {
             if date.is_none() {
-                Err(Error::MissingRequiredRecord(format!("DateRecord is required.")))
+                Err(Error::MissingRequiredRecord(ParseError::missing_in(lines, "5 DateRecord")))
             } else {
-                Err(Error::MissingRequiredRecord(format!("PharmacyRecord is required.")))
+                Err(Error::MissingRequiredRecord(ParseError::missing_in(lines, "11 PharmacyRecord")))
             }
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct MedicineNotebook {
     pub version: VersionRecord, // バージョンレコード
+    pub split_control: Option<SplitControlRecord>, // 分割制御レコード
     pub patient: PatientRecord, // 患者情報レコード
     pub special_patient_notes: Vec<SpecialPatientNoteRecord>, // 患者特記レコード
     pub otc_drugs: Vec<OtcDrugRecord>, // 一般用医薬品服用レコード
     pub memos: Vec<MemoRecord>, // 手帳メモレコード
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub unknown_records: Vec<UnknownRecord>, // 未定義のレコード
 
     pub dispensing_information: Vec<DispensingInformationBlock>, // 調剤情報
 
@@ -3115,209 +4477,98 @@ impl MedicineNotebook {
             .. Default::default()
         }
     }
-    pub fn to_code(&self) -> String {
-        let mut lines: Vec<String> = Vec::new();
-        lines.push(self.version.to_code());
-        lines.push(self.patient.to_code());
-        for record in &self.special_patient_notes {
-            lines.push(record.to_code());
+
+    /// Returns the version record as written. Its number is raised to the
+    /// first version which defines every record of the notebook, e.g. to 7
+    /// for a remaining medicine record, so that the code is read back.
+    pub fn written_version(&self) -> VersionRecord {
+        let mut record_numbers: Vec<u32> = Vec::new();
+        if self.split_control.is_some() {
+            record_numbers.push(911);
         }
-        for record in &self.otc_drugs {
-            lines.push(record.to_code());
+        if !self.otc_drugs.is_empty() {
+            record_numbers.push(3);
         }
-        for record in &self.memos {
-            lines.push(record.to_code());
+        if !self.memos.is_empty() {
+            record_numbers.push(4);
         }
         for block in &self.dispensing_information {
-            lines.push(block.to_code());
+            if block.information_provision.is_some() {
+                record_numbers.push(411);
+            }
+            if block.remaining_medicine.is_some() {
+                record_numbers.push(421);
+            }
+            if block.from_patient.is_some() {
+                record_numbers.push(601);
+            }
+        }
+        if !self.family_pharmacist.is_empty() {
+            record_numbers.push(701);
+        }
+
+        let defines_all = |profile: &VersionProfile| record_numbers.iter().all(|&n| profile.columns(n).is_some());
+        if defines_all(&VersionProfile::for_version(self.version.number)) {
+            return self.version.clone();
+        }
+        let number = VersionProfile::all().iter()
+            .find(|profile| profile.version > self.version.number && defines_all(profile))
+            .map_or(self.version.number, |profile| profile.version);
+        VersionRecord {number, .. self.version.clone()}
+    }
+    /// Serializes the notebook, replacing commas and line breaks in the
+    /// fields with full-width characters, so that the result may not parse
+    /// back to the same notebook. Use `to_code_with(EscapePolicy::Reject)`,
+    /// as `MedicineNotebookWriter` does by default, to fail on them instead.
+    pub fn to_code(&self) -> String {
+        full_width(self.to_code_with(EscapePolicy::FullWidth))
+    }
+
+    /// Unknown records of the notebook without a position are written among
+    /// the header records, and the version record as `written_version`.
+    pub fn to_code_with(&self, policy: EscapePolicy) -> Result<String, Error> {
+        let mut known: Vec<(u32, String)> = Vec::new();
+        known.push((0, self.written_version().to_code_with(policy)?));
+        if let Some(record) = &self.split_control {
+            known.push((0, record.to_code_with(policy)?));
+        }
+        known.extend(header_lines(&self.patient, &self.special_patient_notes,
+            &self.otc_drugs, &self.memos, policy)?);
+        for block in &self.dispensing_information {
+            known.extend(block.code_lines(policy)?.into_iter().map(|line| (u32::MAX, line)));
         }
         for record in &self.family_pharmacist {
-            lines.push(record.to_code());
+            known.push((u32::MAX, record.to_code_with(policy)?));
         }
-        lines.join("\r\n")
+        let mut lines: Vec<String> = Vec::new();
+        push_merged(&mut lines, known, &self.unknown_records, policy)?;
+        Ok(lines.join("\r\n"))
     }
 }
 
-impl Default for MedicineNotebook {
-    fn default() -> Self {
-        Self {
-            version: VersionRecord::default(),
-            patient: PatientRecord::default(),
-            special_patient_notes: Vec::new(),
-            otc_drugs: Vec::new(),
-            memos: Vec::new(),
-
-            dispensing_information: Vec::new(),
-
-            family_pharmacist: Vec::new(),
-        }
+/// Returns the lines of the header records following the version and split
+/// control records, each with its record number.
+pub(crate) fn header_lines(patient: &PatientRecord, special_patient_notes: &[SpecialPatientNoteRecord],
+        otc_drugs: &[OtcDrugRecord], memos: &[MemoRecord],
+        policy: EscapePolicy) -> Result<Vec<(u32, String)>, Error> {
+    let mut known: Vec<(u32, String)> = Vec::new();
+    known.push((patient.record_number(), patient.to_code_with(policy)?));
+    for record in special_patient_notes {
+        known.push((record.record_number(), record.to_code_with(policy)?));
+    }
+    for record in otc_drugs {
+        known.push((record.record_number(), record.to_code_with(policy)?));
     }
+    for record in memos {
+        known.push((record.record_number(), record.to_code_with(policy)?));
+    }
+    Ok(known)
 }
 
 impl FromStr for MedicineNotebook {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut version: Option<VersionRecord> = None;
-        let mut patient: Option<PatientRecord> = None;
-        let mut special_patient_notes: Vec<SpecialPatientNoteRecord> = Vec::new();
-        let mut otc_drugs: Vec<OtcDrugRecord> = Vec::new();
-        let mut memos: Vec<MemoRecord> = Vec::new();
-
-        let mut dispensing_information: Vec<DispensingInformationBlock> = Vec::new();
-        let mut temp_disp_info_block_string: Vec<String> = Vec::new();
-
-        let mut family_pharmacist: Vec<FamilyPharmacistRecord> = Vec::new();
-
-        let mut cur_num: u32 = 0;
-
-        for line in s.to_string().lines() {
-            if line.chars().count() >= 4 {
-                let sep2 = line.char_indices().nth(2).unwrap().0;
-                let sep3 = line.char_indices().nth(3).unwrap().0;
-                let sep4 = line.char_indices().nth(4).unwrap().0;
-                let sep7 = line.char_indices().nth(7).unwrap().0;
-                if &line[..sep7] == "JAHISTC" { // バージョンレコード
-                    if cur_num == 0 {
-                        if version.is_none() {
-                            version = Some(line.parse()?);
-                            cur_num = 0;
-                        } else {
-                            return Err(
-                                Error::GotUnexpectedRecordLine(
-                                    format!("Multiple VersionRecord lines are not allowed: \"{}\"", line)
-                                )
-                            );
-                        }
-                    } else {
-                        return Err(
-                            Error::GotUnexpectedRecordLine(
-                                format!("Unexpected VersionRecord here: \"{}\"", line)
-                            )
-                        );
-                    }
-                } else if &line[..sep2] == "1," { // 患者情報レコード
-                    if cur_num < 1 {
-                        if patient.is_none() {
-                            patient = Some(line.parse()?);
-                            cur_num = 1;
-                        } else {
-                            return Err(
-                                Error::GotUnexpectedRecordLine(
-                                    format!("Multiple PatientRecord lines are not allowed: \"{}\"", line)
-                                )
-                            );
-                        }
-                    } else {
-                        return Err(
-                            Error::GotUnexpectedRecordLine(
-                                format!("Unexpected PatientRecord here: \"{}\"", line)
-                            )
-                        );
-                    }
-                } else if &line[..sep2] == "2," { // 患者特記レコード
-                    if cur_num <= 2 {
-                        special_patient_notes.push(line.parse()?);
-                        cur_num = 2;
-                    } else {
-                        return Err(
-                            Error::GotUnexpectedRecordLine(
-                                format!("Unexpected SpecialPatientNoteRecord here: \"{}\"", line)
-                            )
-                        );
-                    }
-                } else if &line[..sep2] == "3," { // 一般用医薬品服用レコード
-                    if cur_num <= 3 {
-                        otc_drugs.push(line.parse()?);
-                        cur_num = 3;
-                    } else {
-                        return Err(
-                            Error::GotUnexpectedRecordLine(
-                                format!("Unexpected OtcDrugRecord here: \"{}\"", line)
-                            )
-                        );
-                    }
-                } else if &line[..sep2] == "4," { // 手帳メモレコード
-                    if cur_num <= 4 {
-                        memos.push(line.parse()?);
-                        cur_num = 4;
-                    } else {
-                        return Err(
-                            Error::GotUnexpectedRecordLine(
-                                format!("Unexpected MemoRecord here: \"{}\"", line)
-                            )
-                        );
-                    }
-                } else if &line[..sep2] == "5," || &line[..sep3] == "11,"
-                        || &line[..sep3] == "15," || &line[..sep3] == "51,"
-                        || &line[..sep3] == "55," || &line[..sep4] == "201,"
-                        || &line[..sep4] == "281," || &line[..sep4] == "291,"
-                        || &line[..sep4] == "301," || &line[..sep4] == "311,"
-                        || &line[..sep4] == "391," || &line[..sep4] == "401,"
-                        || &line[..sep4] == "411," || &line[..sep4] == "501," || &line[..sep4] == "601," {
-                    if cur_num <= 5 {
-                        if &line[..sep2] == "5," && temp_disp_info_block_string.len() > 0 {
-                            dispensing_information.push(temp_disp_info_block_string.join("\r\n").parse()?);
-                            temp_disp_info_block_string = Vec::new();
-                        }
-                        temp_disp_info_block_string.push(line.to_string());
-                        cur_num = 5;
-                    } else {
-                        return Err(
-                            Error::GotUnexpectedRecordLine(
-                                format!("Unexpected DispensingInformationBlock here: \"{}\"", line)
-                            )
-                        );
-                    }
-                } else if &line[..sep4] == "701," { //かかりつけ薬剤師レコード
-                    if cur_num <= 701 {
-                        family_pharmacist.push(line.parse()?);
-                        cur_num = 701;
-                    } else {
-                        return Err(
-                            Error::GotUnexpectedRecordLine(
-                                format!("Unexpected FamilyPharmacistRecord here: \"{}\"", line)
-                            )
-                        );
-                    }
-                } else {
-                    return Err(
-                        Error::GotUnexpectedRecordLine(
-                            format!("Expected valid record line, got \"{}\".", line)
-                        )
-                    );
-                }
-            } else if line == "" {
-                continue
-            } else {
-                return Err(
-                    Error::GotUnexpectedRecordLine(
-                        format!("Expected valid record line, got \"{}\".", line)
-                    )
-                );
-            }
-        }
-        if temp_disp_info_block_string.len() > 0  {
-            dispensing_information.push(temp_disp_info_block_string.join("\r\n").parse()?);
-        }
-        if version.is_some() && patient.is_some() {
-            Ok(Self {
-                version: version.unwrap(),
-                patient: patient.unwrap(),
-                special_patient_notes: special_patient_notes,
-                otc_drugs: otc_drugs,
-                memos: memos,
-
-                dispensing_information: dispensing_information,
-
-                family_pharmacist: family_pharmacist,
-            })
-        } else {
-            if version.is_some() {
-                Err(Error::MissingRequiredRecord(format!("VersionRecord is required.")))
-            } else {
-                Err(Error::MissingRequiredRecord(format!("PatientRecord is required.")))
-            }
-        }
+        MedicineNotebookReader::new(s.as_bytes())?.into_notebook()
     }
 }
 
@@ -3438,4 +4689,75 @@ pub fn naivedate_to_wareki7(d: &chrono::NaiveDate) -> Result<String, Error> {
         return Err(Error::InvalidArgument);
     }
 }
-*/
\ No newline at end of file
+*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn creates_only_dates_which_exist() {
+        assert!(Date::new_seireki(2020, 2, 29).is_ok());
+        assert!(Date::new_seireki(2020, 2, 30).is_err());
+        assert!(Date::new_seireki(2021, 2, 29).is_err());
+        assert!(Date::new_seireki(2020, 13, 1).is_err());
+        assert!(Date::new_seireki_year_month(2020, 0).is_err());
+        assert!(Date::new_wareki(GengoYear::new('R', 2), 2, 29).is_ok());
+        assert!(Date::new_wareki(GengoYear::new('R', 2), 2, 30).is_err());
+        assert!(Date::new_wareki_year_month(GengoYear::new('R', 2), 13).is_err());
+    }
+
+    #[test]
+    fn creates_only_dates_in_their_era() {
+        assert!(Date::new_wareki(GengoYear::new('H', 31), 4, 30).is_ok());
+        assert_eq!(Date::new_wareki(GengoYear::new('H', 31), 5, 1), Err(Error::InvalidDate(
+            "平成31年5月1日 is after 平成 ended on 2019-04-30".to_string()
+        )));
+        assert!(Date::new_wareki(GengoYear::new('R', 1), 5, 1).is_ok());
+        assert_eq!(Date::new_wareki(GengoYear::new('R', 1), 4, 30), Err(Error::InvalidDate(
+            "令和元年4月30日 is before 令和 began on 2019-05-01".to_string()
+        )));
+        assert!(Date::new_wareki(GengoYear::new('R', 0), 5, 1).is_err());
+        assert!(Date::new_wareki(GengoYear::new('Q', 1), 5, 1).is_err());
+
+        // A month or a year is in its era if any day of it is.
+        assert!(Date::new_wareki_year_month(GengoYear::new('H', 31), 4).is_ok());
+        assert!(Date::new_wareki_year_month(GengoYear::new('H', 31), 5).is_err());
+        assert!(Date::new_wareki_year_month(GengoYear::new('R', 1), 4).is_err());
+        assert!(Date::new_wareki_year(GengoYear::new('H', 31)).is_ok());
+        assert!(Date::new_wareki_year(GengoYear::new('H', 32)).is_err());
+        assert_eq!(Date::new_wareki_year(GengoYear::new('R', 1)).unwrap().first_day(),
+            Ok(chrono::NaiveDate::from_ymd_opt(2019, 5, 1).unwrap()));
+    }
+
+    #[test]
+    fn reads_and_writes_months_and_years_in_otc_drugs_and_memos() {
+        let line = "3,ロキソニン,202001,R02,2";
+        let record: OtcDrugRecord = line.parse().unwrap();
+        assert_eq!(record.start_date, Some(Date::SeirekiYearMonth{year: 2020, month: 1}));
+        assert_eq!(record.end_date, Some(Date::WarekiYear{gengo_year: GengoYear::new('R', 2)}));
+        assert_eq!(record.to_code(), line);
+
+        let line = "3,ロキソニン,2019,R0201,2";
+        let record: OtcDrugRecord = line.parse().unwrap();
+        assert_eq!(record.start_date, Some(Date::SeirekiYear{year: 2019}));
+        assert_eq!(record.end_date, Some(Date::WarekiYearMonth{gengo_year: GengoYear::new('R', 2), month: 1}));
+        assert_eq!(record.to_code(), line);
+
+        let line = "4,メモです,R0205,2";
+        let record: MemoRecord = line.parse().unwrap();
+        assert_eq!(record.created_at, Some(Date::WarekiYearMonth{gengo_year: GengoYear::new('R', 2), month: 5}));
+        assert_eq!(record.to_code(), line);
+
+        let line = "4,メモです,2020,2";
+        let record: MemoRecord = line.parse().unwrap();
+        assert_eq!(record.created_at, Some(Date::SeirekiYear{year: 2020}));
+        assert_eq!(record.to_code(), line);
+
+        assert!("3,ロキソニン,20201,,2".parse::<OtcDrugRecord>().is_err());
+        assert!("4,メモです,202013,2".parse::<MemoRecord>().is_err());
+        assert!("4,メモです,H3205,2".parse::<MemoRecord>().is_err());
+        // Only the records of a patient take a month or a year.
+        assert!("5,202004,1".parse::<DateRecord>().is_err());
+    }
+}
@@ -1,2 +1,49 @@
 mod jahis;
+mod era;
+mod date_format;
+mod encoding;
+mod reader;
+mod writer;
+mod escape;
+mod profile;
+mod migration;
+mod split;
+mod validate;
+mod fields;
+mod prescription;
+mod uke;
+#[cfg(feature = "serde")]
+mod serialize;
+#[cfg(feature = "schema")]
+mod schema;
+#[cfg(feature = "fhir")]
+mod fhir;
+#[cfg(feature = "qr")]
+mod qr;
+#[cfg(feature = "qr-decode")]
+mod decode;
+#[cfg(feature = "qr-decode")]
+mod scan;
+#[cfg(test)]
+mod test_util;
 pub use jahis::*;
+pub use era::*;
+pub use date_format::*;
+pub use encoding::*;
+pub use reader::*;
+pub use writer::*;
+pub use escape::*;
+pub use profile::*;
+pub use migration::*;
+pub use split::*;
+pub use validate::*;
+pub use prescription::*;
+pub use uke::*;
+#[cfg(feature = "schema")]
+pub use schema::*;
+#[cfg(feature = "fhir")]
+pub use fhir::*;
+#[cfg(feature = "qr")]
+pub use qr::*;
+#[cfg(feature = "qr-decode")]
+pub use scan::*;
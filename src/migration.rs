@@ -0,0 +1,297 @@
+use std::fmt;
+use crate::jahis::*;
+use crate::escape::EscapePolicy;
+use crate::profile::{VersionProfile, current_columns};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// What a migration did to a record or a field
+pub enum MigrationAction {
+    Added, // 移行先で追加された (空のまま)
+    Defaulted(String), // 移行先で必須のため既定値を設定した
+    Dropped(String), // 移行先で定義されていないため削除した
+    Mapped{from: String, to: String}, // 移行先で定義されていないコード値を置き換えた
+}
+
+impl fmt::Display for MigrationAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Added => write!(f, "added"),
+            Self::Defaulted(value) => write!(f, "defaulted to \"{}\"", value),
+            Self::Dropped(value) => write!(f, "dropped \"{}\"", value),
+            Self::Mapped{from, to} => write!(f, "mapped \"{}\" to \"{}\"", from, to),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A change made to a record, or to one of its fields, by a migration
+pub struct MigrationChange {
+    pub record_number: u32, // レコード番号
+    pub field_name: Option<&'static str>, // 項目名 (None: レコード全体)
+    pub block: Option<usize>, // 調剤情報の番号 (0-based)
+    pub action: MigrationAction,
+}
+
+impl fmt::Display for MigrationChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(block) = self.block {
+            write!(f, "dispensing information {}: ", block + 1)?;
+        }
+        match self.field_name {
+            Some(field_name) => write!(f, "{} of record {} {}", field_name, self.record_number, self.action),
+            None => write!(f, "record {} {}", self.record_number, self.action),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+/// Report of the changes made by `migrate`
+pub struct MigrationReport {
+    pub from_version: u32,
+    pub to_version: u32,
+    pub changes: Vec<MigrationChange>,
+}
+
+impl MigrationReport {
+    /// Whether the migration lost any data.
+    pub fn is_lossy(&self) -> bool {
+        self.changes.iter().any(|c| matches!(c.action, MigrationAction::Dropped(_) | MigrationAction::Mapped{..}))
+    }
+}
+
+impl fmt::Display for MigrationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "JAHISTC{:>02} -> JAHISTC{:>02}", self.from_version, self.to_version)?;
+        for change in &self.changes {
+            write!(f, "\n{}", change)?;
+        }
+        Ok(())
+    }
+}
+
+/// Migrates a notebook to another version of the specification.
+///
+/// Records and fields which the target version does not define are dropped,
+/// code values it does not define are replaced with the `other` value of the
+/// code table, and the fields it adds are left empty, or set to the default
+/// value of the record when they are required. Every such change is listed
+/// in the report.
+///
+/// Fails with `Error::ForbiddenCharacter` if a field holds a comma or a line
+/// break, which could not be migrated without changing it.
+pub fn migrate(notebook: &MedicineNotebook, target_version: u32) -> Result<(MedicineNotebook, MigrationReport), Error> {
+    let source = VersionProfile::for_version(notebook.version.number);
+    let target = VersionProfile::for_version(target_version);
+    let mut report = MigrationReport {
+        from_version: source.version,
+        to_version: target.version,
+        changes: Vec::new(),
+    };
+
+    // The lines keep the current layout, so that they are read back as they are.
+    let version = VersionRecord {number: VersionProfile::latest().version, .. notebook.version.clone()};
+    let code = MedicineNotebook {version, .. notebook.clone()}.to_code_with(EscapePolicy::Reject)?;
+    let mut lines: Vec<String> = Vec::new();
+    let mut block: Option<usize> = None;
+    for line in code.split("\r\n") {
+        match line.split(',').next() {
+            Some("5") => block = Some(block.map_or(0, |b| b + 1)), // 調剤等年月日レコード
+            Some("701") => block = None, // かかりつけ薬剤師レコード
+            _ => {},
+        }
+        if let Some(line) = migrate_line(line, &source, &target, block, &mut report.changes) {
+            lines.push(line);
+        }
+    }
+
+    let mut migrated: MedicineNotebook = lines.join("\r\n").parse()?;
+    migrated.version.number = target.version;
+    Ok((migrated, report))
+}
+
+/// Migrates a record line of the current layout, returning `None` if the
+/// record is dropped.
+fn migrate_line(line: &str, source: &VersionProfile, target: &VersionProfile,
+        block: Option<usize>, changes: &mut Vec<MigrationChange>) -> Option<String> {
+    let mut cols: Vec<String> = line.split(',').map(String::from).collect();
+    let record_number: u32 = match cols[0].parse() {
+        Ok(n) if record_name(n).is_some() => n,
+        _ => return Some(line.to_string()),
+    };
+    let mut change = |field_index: Option<usize>, action: MigrationAction| {
+        changes.push(MigrationChange {
+            record_number,
+            field_name: field_index.and_then(|i| field_names(record_number)
+                .and_then(|names| names.get(i - 1).copied())),
+            block,
+            action,
+        });
+    };
+
+    let target_columns = match target.columns(record_number) {
+        Some(columns) => columns,
+        None => {
+            change(None, MigrationAction::Dropped(line.to_string()));
+            return None;
+        },
+    };
+    let source_columns = match source.columns(record_number) {
+        Some(columns) => columns,
+        None => {
+            change(None, MigrationAction::Added);
+            0
+        },
+    };
+
+    for i in 1..=current_columns(record_number).min(cols.len() - 1) {
+        if i > target_columns {
+            if !cols[i].is_empty() {
+                change(Some(i), MigrationAction::Dropped(std::mem::take(&mut cols[i])));
+            }
+            continue;
+        }
+        if i > source_columns && source_columns > 0 {
+            if cols[i].is_empty() && !is_optional_field(record_number, i) {
+                let value = default_field(record_number, i);
+                cols[i] = value.clone();
+                change(Some(i), MigrationAction::Defaulted(value));
+            } else {
+                change(Some(i), MigrationAction::Added);
+            }
+        }
+        if let Some(table) = target.code_table(record_number, i) {
+            if !cols[i].is_empty() && !table.values.contains(&cols[i].as_str()) {
+                let from = std::mem::replace(&mut cols[i], table.other.to_string());
+                change(Some(i), MigrationAction::Mapped {from, to: table.other.to_string()});
+                // 薬品コード種別 コードなし leaves no type for the 薬品コード.
+                if record_number == 201 && i == 5 && cols.len() > 6 && !cols[6].is_empty() {
+                    change(Some(6), MigrationAction::Dropped(std::mem::take(&mut cols[6])));
+                }
+            }
+        }
+    }
+    Some(cols.join(","))
+}
+
+/// Returns the value of a field in the default record.
+fn default_field(record_number: u32, field_index: usize) -> String {
+    let line = match record_number {
+        1 => PatientRecord::default().to_code(),
+        2 => SpecialPatientNoteRecord::default().to_code(),
+        3 => OtcDrugRecord::default().to_code(),
+        4 => MemoRecord::default().to_code(),
+        5 => DateRecord::default().to_code(),
+        11 => PharmacyRecord::default().to_code(),
+        15 => PharmacistRecord::default().to_code(),
+        51 => MedicalInstitutionRecord::default().to_code(),
+        55 => PhysicianRecord::default().to_code(),
+        201 => DrugRecord::default().to_code(),
+        281 => DrugSupplementaryRecord::default().to_code(),
+        291 => DrugNoticeRecord::default().to_code(),
+        301 => UsageRecord::default().to_code(),
+        311 => UsageSupplementaryRecord::default().to_code(),
+        391 => RpNoticeRecord::default().to_code(),
+        401 => NoticeRecord::default().to_code(),
+        411 => InformationProvisionRecord::default().to_code(),
+        421 => RemainingMedicineRecord::default().to_code(),
+        501 => NoteRecord::default().to_code(),
+        601 => FromPatientRecord::default().to_code(),
+        701 => FamilyPharmacistRecord::default().to_code(),
+        911 => SplitControlRecord::default().to_code(),
+        _ => String::new(),
+    };
+    line.split(',').nth(field_index).unwrap_or_default().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::CodeTable;
+
+    // Records which the first version lacks: a HOT code, 提供情報種別 30 and a 残薬確認レコード.
+    const SAMPLE: &str = "JAHISTC07,1\r\n\
+        1,山田太郎,1,19700101,,,,,,,ﾔﾏﾀﾞﾀﾛｳ\r\n\
+        4,メモです,,2\r\n\
+        5,20200401,1\r\n\
+        11,テスト薬局,13,4,1234567,1000001,東京都,03-1111-1111,1\r\n\
+        201,1,ロキソプロフェン錠60mg,1,錠,6,1234567890123,1\r\n\
+        301,1,1日3回毎食後,7,日分,1,1,,1\r\n\
+        411,提供情報,30,1\r\n\
+        421,残薬,1";
+
+    fn change(record_number: u32, field_name: Option<&'static str>, block: Option<usize>,
+            action: MigrationAction) -> MigrationChange {
+        MigrationChange {record_number, field_name, block, action}
+    }
+
+    #[test]
+    fn migrates_down_to_the_first_version() {
+        let notebook: MedicineNotebook = SAMPLE.parse().unwrap();
+        let (migrated, report) = migrate(&notebook, 1).unwrap();
+        assert_eq!(report.changes, vec![
+            change(1, Some("患者氏名カナ"), None, MigrationAction::Dropped("ﾔﾏﾀﾞﾀﾛｳ".to_string())),
+            change(4, None, None, MigrationAction::Dropped("4,メモです,,2".to_string())),
+            change(201, Some("薬品コード種別"), Some(0), MigrationAction::Mapped {
+                from: "6".to_string(),
+                to: "1".to_string(),
+            }),
+            change(201, Some("薬品コード"), Some(0), MigrationAction::Dropped("1234567890123".to_string())),
+            change(411, None, Some(0), MigrationAction::Dropped("411,提供情報,30,1".to_string())),
+            change(421, None, Some(0), MigrationAction::Dropped("421,残薬,1".to_string())),
+        ]);
+        assert!(report.is_lossy());
+        assert_eq!(migrated.version.number, 1);
+        assert_eq!(migrated.patient.name_in_kana, None);
+        assert!(migrated.memos.is_empty());
+        let drug = &migrated.dispensing_information[0].prescriptions[0].rps[0].drugs[0].drug;
+        assert_eq!(drug.drug_code_type, DrugCodeType::None);
+        assert_eq!(drug.drug_code, None);
+
+        let mut writer = crate::writer::MedicineNotebookWriter::new(Vec::new())
+            .version_profile(VersionProfile::for_version(1));
+        writer.write_notebook(&migrated).unwrap();
+        assert!(String::from_utf8(writer.finish().unwrap()).unwrap()
+            .contains("\r\n201,1,ロキソプロフェン錠60mg,1,錠,1,,1\r\n"));
+    }
+
+    #[test]
+    fn migrates_up_from_the_first_version() {
+        let notebook: MedicineNotebook = SAMPLE.parse().unwrap();
+        let (old, _) = migrate(&notebook, 1).unwrap();
+        let (migrated, report) = migrate(&old, 7).unwrap();
+        assert_eq!(report.changes, vec![
+            change(1, Some("患者氏名カナ"), None, MigrationAction::Added),
+        ]);
+        assert!(!report.is_lossy());
+        assert_eq!(report.to_string(), "JAHISTC01 -> JAHISTC07\n患者氏名カナ of record 1 added");
+        assert_eq!(migrated.version.number, 7);
+        assert_eq!(migrated.dispensing_information, old.dispensing_information);
+    }
+
+    #[test]
+    fn defaults_a_required_field_added_by_the_target() {
+        // A layout of 医療機関等レコード without レコード作成者, and without 提供情報種別 30.
+        let source = VersionProfile {
+            version: 1,
+            records: &[(11, 7), (411, 3)],
+            code_tables: &[],
+        };
+        let target = VersionProfile {
+            version: 2,
+            records: &[(11, 8), (411, 3)],
+            code_tables: &[CodeTable {record_number: 411, field_index: 2, values: &["31", "99"], other: "99"}],
+        };
+        let mut changes = Vec::new();
+        assert_eq!(migrate_line("11,テスト薬局,13,4,1234567,,,,", &source, &target, Some(0), &mut changes),
+            Some(format!("11,テスト薬局,13,4,1234567,,,,{}", default_field(11, 8))));
+        assert_eq!(migrate_line("411,提供情報,30,1", &source, &target, Some(0), &mut changes),
+            Some("411,提供情報,99,1".to_string()));
+        assert_eq!(changes, vec![
+            change(11, Some("レコード作成者"), Some(0), MigrationAction::Defaulted(default_field(11, 8))),
+            change(411, Some("提供情報種別"), Some(0), MigrationAction::Mapped {
+                from: "30".to_string(),
+                to: "99".to_string(),
+            }),
+        ]);
+    }
+}
@@ -0,0 +1,418 @@
+// JAHIS 院外処方箋2次元シンボル記録条件規約
+// https://www.jahis.jp/standard/contents_type=33
+
+use std::str::FromStr;
+use std::convert::TryFrom;
+use lazy_static::lazy_static;
+use regex::Regex;
+use crate::jahis::*;
+use crate::encoding::TextEncoding;
+use crate::fields::Fields;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A drug of an RP of a prescription (薬品レコード)
+///
+/// A drug code of a type which a notebook does not have, such as 一般名コード,
+/// is kept in `drug_code` with `DrugCodeType::None`, and is left out of the
+/// drug record of a notebook, where the name identifies the drug.
+pub struct PrescriptionDrug {
+    pub index: u32, // RP内連番
+    pub drug_code_type: DrugCodeType, // 薬品コード種別
+    pub drug_code: Option<String>, // 薬品コード
+    pub name: String, // 薬品名称
+    pub amount: String, // 分量
+    pub unit: String, // 単位名
+    pub supplementary: Vec<String>, // 薬品補足情報
+}
+
+impl PrescriptionDrug {
+    /// The drug code for a drug record of a notebook, which has none for
+    /// a code of a type it does not have.
+    pub fn notebook_drug_code(&self) -> Option<String> {
+        self.drug_code.clone().filter(|_| self.drug_code_type != DrugCodeType::None)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// An RP of a prescription, made of its prescription, usage and drug records
+pub struct PrescriptionRp {
+    pub rp_number: u32, // RP番号
+    pub dosage_form: Option<DosageForm>, // 剤形区分
+    pub dosage_form_name: Option<String>, // 剤形名称
+    pub quantity: Option<u32>, // 調剤数量
+    pub usage_code_type: Option<UsageCodeType>, // 用法コード種別
+    pub usage_code: Option<String>, // 用法コード
+    pub usage_name: String, // 用法名称
+    pub times_per_day: Option<u32>, // 1日回数
+    pub usage_supplementary: Vec<String>, // 用法補足情報
+    pub drugs: Vec<PrescriptionDrug>, // 薬品
+}
+
+impl PrescriptionRp {
+    fn new(rp_number: u32) -> Self {
+        Self {
+            rp_number,
+            dosage_form: None,
+            dosage_form_name: None,
+            quantity: None,
+            usage_code_type: None,
+            usage_code: None,
+            usage_name: "".to_string(),
+            times_per_day: None,
+            usage_supplementary: Vec::new(),
+            drugs: Vec::new(),
+        }
+    }
+
+    pub fn to_rp_block(&self) -> RpBlock {
+        let rp_number = self.rp_number;
+        let creator = RecordCreator::MedicalExpert;
+        let drugs = self.drugs.iter().map(|drug| DrugBlock {
+            drug_supplementary: drug.supplementary.iter()
+                .map(|content| DrugSupplementaryRecord::new(rp_number, content.clone(), creator))
+                .collect(),
+            .. DrugBlock::new(DrugRecord::new(rp_number, drug.name.clone(), drug.amount.clone(),
+                drug.unit.clone(), drug.drug_code_type, drug.notebook_drug_code(), creator))
+        }).collect();
+        RpBlock {
+            drugs,
+            usage_supplementary: self.usage_supplementary.iter()
+                .map(|content| UsageSupplementaryRecord::new(rp_number, content.clone(), creator))
+                .collect(),
+            .. RpBlock::new(UsageRecord::new(rp_number, self.usage_name.clone(), self.quantity,
+                quantity_unit(self.dosage_form), self.dosage_form, self.usage_code_type, self.usage_code.clone(), creator))
+        }
+    }
+}
+
+/// Unit of the quantity of an RP in a notebook, which is days for a drug
+/// taken every day and times for a drug taken when needed.
+pub(crate) fn quantity_unit(dosage_form: Option<DosageForm>) -> Option<String> {
+    match dosage_form? {
+        DosageForm::OralAdministration | DosageForm::Drop | DosageForm::Infusodecoction
+            | DosageForm::Decoction => Some("日分".to_string()),
+        DosageForm::Potion => Some("回分".to_string()),
+        _ => None,
+    }
+}
+
+/// Maps a 剤形区分 of a prescription to that of a notebook, whose codes are
+/// numbered differently.
+fn prescription_dosage_form(code: &str) -> Option<DosageForm> {
+    match code.parse::<u32>().ok()? {
+        1 => Some(DosageForm::OralAdministration), // 内服
+        2 => Some(DosageForm::Potion), // 頓服
+        3 => Some(DosageForm::ExternalUse), // 外用
+        4 => Some(DosageForm::Drop), // 内服滴剤
+        5 => Some(DosageForm::Injection), // 注射
+        6 => Some(DosageForm::Material), // 医療材料
+        9 => Some(DosageForm::Other), // 不明
+        _ => None,
+    }
+}
+
+/// An outpatient prescription (院外処方箋) read from the data of its 2D
+/// symbol.
+///
+/// The records of the medical institution, the physician, the patient, the
+/// date of issue and the RPs are read into fields; the others, such as the
+/// insurance records, are kept in `other_records`. A drug code of a type
+/// other than those of a notebook, such as 一般名コード, is kept with
+/// `DrugCodeType::None`, see `PrescriptionDrug`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutpatientPrescription {
+    pub version: u32, // バージョン (JAHISn)
+    pub fee_table: Option<FeeTable>, // 医療機関コード種別
+    pub institution_code: Option<String>, // 医療機関コード
+    pub prefecture: Option<Prefecture>, // 医療機関都道府県
+    pub institution_name: String, // 医療機関名称
+    pub zip_code: Option<String>, // 医療機関郵便番号
+    pub address: Option<String>, // 医療機関所在地
+    pub telephone: Option<String>, // 医療機関電話番号
+    pub department: Option<String>, // 診療科名称
+    pub physician_name: Option<String>, // 医師漢字氏名
+    pub physician_name_in_kana: Option<String>, // 医師カナ氏名
+    pub patient_name: Option<String>, // 患者漢字氏名
+    pub patient_name_in_kana: Option<String>, // 患者カナ氏名
+    pub gender: Option<Gender>, // 患者性別
+    pub day_of_birth: Option<Date>, // 患者生年月日
+    pub issued_at: Option<Date>, // 処方箋交付年月日
+    pub rps: Vec<PrescriptionRp>, // RP
+    pub other_records: Vec<UnknownRecord>, // 読み込まないレコード
+}
+
+impl OutpatientPrescription {
+    pub fn from_bytes(bytes: &[u8], encoding: TextEncoding) -> Result<Self, Error> {
+        encoding.decode(bytes)?.parse()
+    }
+
+    pub fn from_sjis_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        Self::from_bytes(bytes, TextEncoding::ShiftJis)
+    }
+
+    pub fn to_medical_institution_record(&self) -> MedicalInstitutionRecord {
+        MedicalInstitutionRecord::new(self.institution_name.clone(), self.prefecture, self.fee_table,
+            self.institution_code.clone(), RecordCreator::MedicalExpert)
+    }
+
+    pub fn to_physician_record(&self) -> Option<PhysicianRecord> {
+        self.physician_name.as_ref().map(|name|
+            PhysicianRecord::new(name.clone(), self.department.clone(), RecordCreator::MedicalExpert))
+    }
+
+    /// Converts the prescription to a prescription of a notebook, whose
+    /// RPs keep their RP numbers.
+    pub fn to_prescription_block(&self) -> PrescriptionBlock {
+        PrescriptionBlock {
+            physician: self.to_physician_record(),
+            rps: self.rps.iter().map(|rp| rp.to_rp_block()).collect(),
+            .. PrescriptionBlock::default()
+        }
+    }
+
+    /// Starts a dispensing information block of a notebook from the
+    /// prescription, with the medical institution which issued it.
+    pub fn to_dispensing_information_block(&self, date: DateRecord, pharmacy: PharmacyRecord) -> DispensingInformationBlock {
+        DispensingInformationBlock {
+            medical_institute: Some(self.to_medical_institution_record()),
+            prescriptions: vec![self.to_prescription_block()],
+            .. DispensingInformationBlock::new(date, pharmacy)
+        }
+    }
+
+    fn rp_mut(&mut self, rp_number: u32) -> &mut PrescriptionRp {
+        match self.rps.iter().position(|rp| rp.rp_number == rp_number) {
+            Some(i) => &mut self.rps[i],
+            None => {
+                self.rps.push(PrescriptionRp::new(rp_number));
+                self.rps.last_mut().unwrap()
+            },
+        }
+    }
+}
+
+impl FromStr for OutpatientPrescription {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        lazy_static! {
+            static ref RE_VERSION: Regex = Regex::new(r"^JAHIS(\d+)$").unwrap();
+        }
+        let mut lines = s.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+        let version = match lines.next() {
+            Some((i, line)) => match RE_VERSION.captures(line.trim()) {
+                Some(cap) => cap[1].parse()?,
+                None => return Err(Error::InvalidRecordLine(ParseError {
+                    line: Some(i + 1),
+                    expected: "version record JAHISn".to_string(),
+                    found: line.to_string(),
+                    .. Default::default()
+                })),
+            },
+            None => return Err(Error::MissingRequiredRecord(ParseError {
+                expected: "version record JAHISn".to_string(),
+                .. Default::default()
+            })),
+        };
+        let mut prescription = OutpatientPrescription {
+            version,
+            fee_table: None,
+            institution_code: None,
+            prefecture: None,
+            institution_name: "".to_string(),
+            zip_code: None,
+            address: None,
+            telephone: None,
+            department: None,
+            physician_name: None,
+            physician_name_in_kana: None,
+            patient_name: None,
+            patient_name_in_kana: None,
+            gender: None,
+            day_of_birth: None,
+            issued_at: None,
+            rps: Vec::new(),
+            other_records: Vec::new(),
+        };
+
+        for (i, line) in lines {
+            let line = line.trim_end_matches('\r');
+            let cols: Vec<&str> = line.split(',').collect();
+            let record_number: u32 = cols[0].parse().map_err(|_| Error::InvalidRecordLine(ParseError {
+                line: Some(i + 1),
+                expected: "a prescription record number".to_string(),
+                found: line.to_string(),
+                .. Default::default()
+            }))?;
+            let f = Fields::new(i + 1, "prescription", line);
+            match record_number {
+                1 => {
+                    prescription.fee_table = f.parse(1, "医療機関コード種別")?;
+                    prescription.institution_code = f.get(2);
+                    prescription.prefecture = f.parse::<u32>(3, "医療機関都道府県")?
+                        .map(Prefecture::try_from).transpose()?;
+                    prescription.institution_name = f.get(4).unwrap_or_default();
+                },
+                2 => {
+                    prescription.zip_code = f.get(1);
+                    prescription.address = f.get(2);
+                },
+                3 => prescription.telephone = f.get(1),
+                4 => prescription.department = f.get(3),
+                5 => {
+                    prescription.physician_name_in_kana = f.get(2);
+                    prescription.physician_name = f.get(3);
+                },
+                11 => {
+                    prescription.patient_name = f.get(2);
+                    prescription.patient_name_in_kana = f.get(3);
+                },
+                12 => prescription.gender = f.parse(1, "患者性別")?,
+                13 => prescription.day_of_birth = f.parse(1, "患者生年月日")?,
+                51 => prescription.issued_at = f.parse(1, "処方箋交付年月日")?,
+                101 => {
+                    let dosage_form = f.parse_with(2, "剤形区分", |code| prescription_dosage_form(code).ok_or(()))?;
+                    let dosage_form_name = f.get(3);
+                    let quantity = f.parse(4, "調剤数量")?;
+                    let rp = prescription.rp_mut(f.require(1, "RP番号")?);
+                    rp.dosage_form = dosage_form;
+                    rp.dosage_form_name = dosage_form_name;
+                    rp.quantity = quantity;
+                },
+                111 => {
+                    let usage_code_type: Option<UsageCodeType> = f.parse(2, "用法コード種別")?;
+                    let usage_code = f.get(3).filter(|_| usage_code_type.is_some_and(|t| t != UsageCodeType::None));
+                    let usage_name = f.get(4).unwrap_or_default();
+                    let times_per_day = f.parse(5, "1日回数")?;
+                    let rp = prescription.rp_mut(f.require(1, "RP番号")?);
+                    rp.usage_code_type = usage_code_type;
+                    rp.usage_code = usage_code;
+                    rp.usage_name = usage_name;
+                    rp.times_per_day = times_per_day;
+                },
+                181 => {
+                    let content = f.get(3);
+                    let rp = prescription.rp_mut(f.require(1, "RP番号")?);
+                    rp.usage_supplementary.extend(content);
+                },
+                201 => {
+                    // 6 is 一般名コード here, unlike HOTコード in a notebook
+                    let drug_code_type = match f.get(4).as_deref() {
+                        Some(t @ ("1" | "2" | "3" | "4")) => t.parse()?,
+                        _ => DrugCodeType::None,
+                    };
+                    let drug = PrescriptionDrug {
+                        index: f.require(2, "RP内連番")?,
+                        drug_code_type,
+                        drug_code: f.get(5),
+                        name: f.get(6).unwrap_or_default(),
+                        amount: f.get(7).unwrap_or_default(),
+                        unit: f.get(9).unwrap_or_default(),
+                        supplementary: Vec::new(),
+                    };
+                    prescription.rp_mut(f.require(1, "RP番号")?).drugs.push(drug);
+                },
+                281 => {
+                    let index: u32 = f.require(2, "RP内連番")?;
+                    let content = f.get(4);
+                    let rp = prescription.rp_mut(f.require(1, "RP番号")?);
+                    match rp.drugs.iter_mut().find(|drug| drug.index == index) {
+                        Some(drug) => drug.supplementary.extend(content),
+                        None => return Err(Error::GotUnexpectedRecordLine(ParseError {
+                            line: Some(i + 1),
+                            expected: "prescription record 281 after the drug record 201 it supplements".to_string(),
+                            found: line.to_string(),
+                            .. Default::default()
+                        })),
+                    }
+                },
+                n => prescription.other_records.push(
+                    UnknownRecord::new(n, f.cols[1..].iter().map(|s| s.to_string()).collect())
+                ),
+            }
+        }
+        Ok(prescription)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "JAHIS7\r\n\
+        1,1,1234567,13,テスト病院\r\n\
+        2,1000001,東京都千代田区\r\n\
+        4,1,01,内科\r\n\
+        5,,ｲｼ ｲﾁﾛｳ,医師一郎\r\n\
+        11,,山田太郎,ﾔﾏﾀﾞ ﾀﾛｳ\r\n\
+        12,1\r\n\
+        13,19700101\r\n\
+        51,20240401\r\n\
+        101,1,1,,7\r\n\
+        111,1,1,1013044400000000,1日3回毎食後,3\r\n\
+        201,1,1,1,2,620098801,ロキソプロフェンナトリウム錠60mg,1,1,錠\r\n\
+        201,1,2,1,6,1149019F1ZZZ,【般】ロキソプロフェンNa錠60mg,1,1,錠\r\n\
+        281,1,2,1,後発品に変更可\r\n\
+        101,2,2,,5\r\n\
+        111,2,1,,疼痛時,\r\n\
+        201,2,1,1,2,620098801,ロキソプロフェンナトリウム錠60mg,1,1,錠\r\n\
+        101,3,3,,1\r\n\
+        111,3,1,,1日2回朝夕,2\r\n\
+        201,3,1,1,2,620000001,湿布薬,1,1,枚\r\n\
+        21,1,06130011";
+
+    #[test]
+    fn reads_a_prescription() {
+        let prescription: OutpatientPrescription = SAMPLE.parse().unwrap();
+        assert_eq!(prescription.version, 7);
+        assert_eq!(prescription.institution_name, "テスト病院");
+        assert_eq!(prescription.physician_name.as_deref(), Some("医師一郎"));
+        assert_eq!(prescription.issued_at, Some(Date::new_seireki(2024, 4, 1).unwrap()));
+        assert_eq!(prescription.other_records.len(), 1);
+
+        let rp = &prescription.rps[0];
+        assert_eq!((rp.dosage_form, rp.quantity, rp.times_per_day), (Some(DosageForm::OralAdministration), Some(7), Some(3)));
+        assert_eq!(rp.drugs[0].drug_code_type, DrugCodeType::Receipt);
+        assert_eq!(rp.drugs[0].drug_code.as_deref(), Some("620098801"));
+        assert_eq!(rp.drugs[1].supplementary, vec!["後発品に変更可"]);
+
+        let dosage_forms: Vec<Option<DosageForm>> = prescription.rps.iter().map(|rp| rp.dosage_form).collect();
+        assert_eq!(dosage_forms, vec![Some(DosageForm::OralAdministration), Some(DosageForm::Potion), Some(DosageForm::ExternalUse)]);
+        let units: Vec<Option<String>> = prescription.to_prescription_block().rps.iter()
+            .map(|rp| rp.usage.unit.clone())
+            .collect();
+        assert_eq!(units, vec![Some("日分".to_string()), Some("回分".to_string()), None]);
+    }
+
+    #[test]
+    fn keeps_a_drug_code_of_a_type_a_notebook_does_not_have() {
+        let prescription: OutpatientPrescription = SAMPLE.parse().unwrap();
+        let drug = &prescription.rps[0].drugs[1];
+        assert_eq!(drug.drug_code_type, DrugCodeType::None);
+        assert_eq!(drug.drug_code.as_deref(), Some("1149019F1ZZZ"));
+        assert_eq!(drug.notebook_drug_code(), None);
+
+        let rp = prescription.to_prescription_block().rps.remove(0);
+        assert_eq!(rp.drugs[0].drug.drug_code.as_deref(), Some("620098801"));
+        assert_eq!(rp.drugs[1].drug.drug_code, None);
+        assert_eq!(rp.drugs[1].drug.name, "【般】ロキソプロフェンNa錠60mg");
+    }
+
+    #[test]
+    fn reports_the_field_which_cannot_be_read() {
+        let s = SAMPLE.replace("101,1,1,,7", "101,1,X,,7");
+        match s.parse::<OutpatientPrescription>() {
+            Err(Error::InvalidRecordLine(e)) => {
+                assert_eq!((e.line, e.field_index, e.field_name), (Some(10), Some(2), Some("剤形区分")));
+                assert_eq!(e.found, "X");
+                assert_eq!(e.to_string(), "line 10: field 2 (剤形区分): expected a valid value of prescription record 101, found \"X\"");
+            },
+            result => panic!("{:?}", result),
+        }
+
+        let s = SAMPLE.replace("201,1,2,1,6", "201,1,,1,6");
+        match s.parse::<OutpatientPrescription>() {
+            Err(Error::InvalidRecordLine(e)) => assert_eq!((e.line, e.field_index, e.field_name), (Some(13), Some(2), Some("RP内連番"))),
+            result => panic!("{:?}", result),
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use std::borrow::Cow;
+use crate::jahis::*;
+
+/// Layout of notebooks written against a version of the specification,
+/// identified by the number of the version record (JAHISTCnn).
+///
+/// A profile lists the records defined in the version with the number of
+/// their fields, and the code tables of coded fields. Reading maps
+/// a line of an older layout to the current one, leaving the fields added
+/// since empty; writing maps it back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionProfile {
+    pub version: u32, // バージョン番号
+    pub records: &'static [(u32, usize)], // レコード番号, 項目数
+    pub code_tables: &'static [CodeTable], // コード表
+}
+
+/// Code values allowed in a coded field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodeTable {
+    pub record_number: u32, // レコード番号
+    pub field_index: usize, // 項目番号 (1-based)
+    pub values: &'static [&'static str], // コード値
+    pub other: &'static str, // 定義されていない値の移行先 (その他, コードなし)
+}
+
+const RECORDS_01: &[(u32, usize)] = &[
+    (1, 9), (2, 3), (5, 2), (11, 8), (15, 3), (51, 5), (55, 3),
+    (201, 7), (281, 3), (291, 3), (301, 8), (311, 3), (391, 3), (401, 2), (501, 2),
+];
+
+// 一般用医薬品服用レコード, 手帳メモレコード, 患者等記入レコード, 分割制御レコード
+const RECORDS_02: &[(u32, usize)] = &[
+    (1, 9), (2, 3), (3, 4), (4, 3), (5, 2), (11, 8), (15, 3), (51, 5), (55, 3),
+    (201, 7), (281, 3), (291, 3), (301, 8), (311, 3), (391, 3), (401, 2), (501, 2), (601, 2),
+    (911, 3),
+];
+
+// かかりつけ薬剤師レコード
+const RECORDS_04: &[(u32, usize)] = &[
+    (1, 9), (2, 3), (3, 4), (4, 3), (5, 2), (11, 8), (15, 3), (51, 5), (55, 3),
+    (201, 7), (281, 3), (291, 3), (301, 8), (311, 3), (391, 3), (401, 2), (501, 2), (601, 2),
+    (701, 6), (911, 3),
+];
+
+// 医療機関等提供情報レコード
+const RECORDS_05: &[(u32, usize)] = &[
+    (1, 9), (2, 3), (3, 4), (4, 3), (5, 2), (11, 8), (15, 3), (51, 5), (55, 3),
+    (201, 7), (281, 3), (291, 3), (301, 8), (311, 3), (391, 3), (401, 2), (411, 3), (501, 2), (601, 2),
+    (701, 6), (911, 3),
+];
+
+// 患者氏名カナ
+const RECORDS_06: &[(u32, usize)] = &[
+    (1, 10), (2, 3), (3, 4), (4, 3), (5, 2), (11, 8), (15, 3), (51, 5), (55, 3),
+    (201, 7), (281, 3), (291, 3), (301, 8), (311, 3), (391, 3), (401, 2), (411, 3), (501, 2), (601, 2),
+    (701, 6), (911, 3),
+];
+
+// 残薬確認レコード
+const RECORDS_07: &[(u32, usize)] = &[
+    (1, 10), (2, 3), (3, 4), (4, 3), (5, 2), (11, 8), (15, 3), (51, 5), (55, 3),
+    (201, 7), (281, 3), (291, 3), (301, 8), (311, 3), (391, 3), (401, 2), (411, 3), (421, 2), (501, 2),
+    (601, 2), (701, 6), (911, 3),
+];
+
+const SPECIAL_PATIENT_NOTE_CATEGORIES: CodeTable = CodeTable { // 患者特記種別
+    record_number: 2, field_index: 1, values: &["1", "2", "3", "9"], other: "9",
+};
+
+const DRUG_CODE_TYPES_01: CodeTable = CodeTable { // 薬品コード種別
+    record_number: 201, field_index: 5, values: &["1", "2", "3", "4"], other: "1",
+};
+
+// HOTコード
+const DRUG_CODE_TYPES_02: CodeTable = CodeTable { // 薬品コード種別
+    record_number: 201, field_index: 5, values: &["1", "2", "3", "4", "6"], other: "1",
+};
+
+const USAGE_CODE_TYPES: CodeTable = CodeTable { // 用法コード種別
+    record_number: 301, field_index: 6, values: &["1", "2"], other: "1",
+};
+
+const PROVIDED_INFORMATION_TYPES: CodeTable = CodeTable { // 提供情報種別
+    record_number: 411, field_index: 2, values: &["30", "31", "99"], other: "99",
+};
+
+const CODE_TABLES_01: &[CodeTable] = &[
+    SPECIAL_PATIENT_NOTE_CATEGORIES, DRUG_CODE_TYPES_01, USAGE_CODE_TYPES,
+];
+
+const CODE_TABLES_02: &[CodeTable] = &[
+    SPECIAL_PATIENT_NOTE_CATEGORIES, DRUG_CODE_TYPES_02, USAGE_CODE_TYPES,
+];
+
+const CODE_TABLES_05: &[CodeTable] = &[
+    SPECIAL_PATIENT_NOTE_CATEGORIES, DRUG_CODE_TYPES_02, USAGE_CODE_TYPES, PROVIDED_INFORMATION_TYPES,
+];
+
+const PROFILES: [VersionProfile; 6] = [
+    VersionProfile {version: 1, records: RECORDS_01, code_tables: CODE_TABLES_01},
+    VersionProfile {version: 2, records: RECORDS_02, code_tables: CODE_TABLES_02},
+    VersionProfile {version: 4, records: RECORDS_04, code_tables: CODE_TABLES_02},
+    VersionProfile {version: 5, records: RECORDS_05, code_tables: CODE_TABLES_05},
+    VersionProfile {version: 6, records: RECORDS_06, code_tables: CODE_TABLES_05},
+    VersionProfile {version: 7, records: RECORDS_07, code_tables: CODE_TABLES_05},
+];
+
+impl VersionProfile {
+    /// Profiles of the versions known to this library, in ascending order.
+    pub fn all() -> &'static [Self] {
+        &PROFILES
+    }
+
+    /// The profile of the layout this library reads and writes by default.
+    pub fn latest() -> Self {
+        PROFILES[PROFILES.len() - 1]
+    }
+
+    /// Returns the profile of a version, which has the layout of the closest
+    /// version not newer than it, or of the oldest one.
+    pub fn for_version(version: u32) -> Self {
+        let profile = PROFILES.iter()
+            .rev()
+            .find(|p| p.version <= version)
+            .copied()
+            .unwrap_or(PROFILES[0]);
+        Self {version, .. profile}
+    }
+
+    /// Number of the fields following the record number, if the record is
+    /// defined in this version.
+    pub fn columns(&self, record_number: u32) -> Option<usize> {
+        self.records.iter()
+            .find(|(n, _)| *n == record_number)
+            .map(|(_, cols)| *cols)
+    }
+
+    /// Maps a record line of this version to the current layout. Records of
+    /// numbers unknown to the library are left as they are.
+    pub fn read_line<'a>(&self, line: &'a str) -> Result<Cow<'a, str>, Error> {
+        let (record_number, cols) = match self.split(line) {
+            Some(split) => split,
+            None => return Ok(Cow::Borrowed(line)),
+        };
+        let error = |expected: String, field_index: Option<usize>, found: &str| {
+            Error::InvalidRecordLine(ParseError {
+                record_number: Some(record_number),
+                field_index,
+                field_name: field_index.and_then(|i| field_names(record_number)
+                    .and_then(|names| names.get(i - 1).copied())),
+                expected,
+                found: found.to_string(),
+                .. Default::default()
+            })
+        };
+        let columns = match self.columns(record_number) {
+            Some(columns) => columns,
+            None => return Err(error(
+                format!("a record defined in JAHISTC{:>02}", self.version), None, line
+            )),
+        };
+        self.check_code_values(record_number, &cols)
+            .map_err(|i| error(
+                format!("a code value defined in JAHISTC{:>02}", self.version), Some(i), cols[i]
+            ))?;
+        let current = current_columns(record_number);
+        if columns >= current || cols.len() - 1 < columns {
+            return Ok(Cow::Borrowed(line));
+        }
+        let mut mapped: Vec<&str> = cols[..=columns].to_vec();
+        mapped.resize(current + 1, "");
+        mapped.extend(&cols[columns + 1..]);
+        Ok(Cow::Owned(mapped.join(",")))
+    }
+
+    /// Maps an error found in a line read by `read_line` back to the line as
+    /// it was written: the field index of an extra column is shifted back
+    /// over the fields padded by `read_line`, and a whole line found is
+    /// replaced with the original one.
+    pub fn restore_error(&self, error: Error, original: &str, mapped: &str) -> Error {
+        let restore = |e: ParseError| {
+            let record_number = match e.record_number {
+                Some(n) => n,
+                None => return e,
+            };
+            let columns = self.columns(record_number).unwrap_or(0);
+            let added = current_columns(record_number).saturating_sub(columns);
+            let mut e = ParseError {
+                found: if e.found == mapped {original.to_string()} else {e.found},
+                .. e
+            };
+            match e.field_index {
+                Some(i) if i > columns && i <= columns + added => {
+                    e.expected = format!("{} (not in JAHISTC{:>02}, so left empty)", e.expected, self.version);
+                },
+                Some(i) if i > columns + added => {
+                    e.field_index = Some(i - added);
+                    e.field_name = None;
+                },
+                _ => {},
+            }
+            e
+        };
+        match error {
+            Error::InvalidRecordLine(e) => Error::InvalidRecordLine(restore(e)),
+            Error::GotUnexpectedRecordLine(e) => Error::GotUnexpectedRecordLine(restore(e)),
+            Error::MissingRequiredRecord(e) => Error::MissingRequiredRecord(restore(e)),
+            e => e,
+        }
+    }
+
+    /// Maps a record line of the current layout to this version, failing if
+    /// a record or a non-empty field is not defined in this version.
+    pub fn write_line<'a>(&self, line: &'a str) -> Result<Cow<'a, str>, Error> {
+        let (record_number, cols) = match self.split(line) {
+            Some(split) => split,
+            None => return Ok(Cow::Borrowed(line)),
+        };
+        let columns = self.columns(record_number)
+            .ok_or(Error::NotInVersion {version: self.version, record_number, field_name: None})?;
+        let current = current_columns(record_number);
+        if let Err(i) = self.check_code_values(record_number, &cols) {
+            return Err(Error::NotInVersion {
+                version: self.version,
+                record_number,
+                field_name: field_names(record_number).and_then(|names| names.get(i - 1).copied()),
+            });
+        }
+        if columns >= current {
+            return Ok(Cow::Borrowed(line));
+        }
+        if let Some(i) = (columns + 1..=current).find(|&i| cols.get(i).is_some_and(|c| !c.is_empty())) {
+            return Err(Error::NotInVersion {
+                version: self.version,
+                record_number,
+                field_name: field_names(record_number).and_then(|names| names.get(i - 1).copied()),
+            });
+        }
+        let mut mapped: Vec<&str> = cols[..=columns].to_vec();
+        mapped.extend(cols.iter().skip(current + 1));
+        Ok(Cow::Owned(mapped.join(",")))
+    }
+
+    /// Splits a line of a record known to the library into its columns.
+    fn split<'a>(&self, line: &'a str) -> Option<(u32, Vec<&'a str>)> {
+        let cols: Vec<&str> = line.split(',').collect();
+        let record_number: u32 = cols[0].parse().ok()?;
+        record_name(record_number)?;
+        Some((record_number, cols))
+    }
+
+    /// Returns the code table of a field, if it is a coded field.
+    pub fn code_table(&self, record_number: u32, field_index: usize) -> Option<&CodeTable> {
+        self.code_tables.iter()
+            .find(|t| t.record_number == record_number && t.field_index == field_index)
+    }
+
+    /// Returns the index of the first field holding a code value which is
+    /// not defined in this version.
+    fn check_code_values(&self, record_number: u32, cols: &[&str]) -> Result<(), usize> {
+        for table in self.code_tables.iter().filter(|t| t.record_number == record_number) {
+            match cols.get(table.field_index) {
+                Some(value) if !value.is_empty() && !table.values.contains(value) => return Err(table.field_index),
+                _ => {},
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for VersionProfile {
+    fn default() -> Self {
+        Self::latest()
+    }
+}
+
+/// Number of the fields of a record in the current layout.
+pub(crate) fn current_columns(record_number: u32) -> usize {
+    field_names(record_number).map_or(0, |names| names.len())
+}
@@ -0,0 +1,300 @@
+use std::fmt;
+use std::str::FromStr;
+use qrcode::{EcLevel, QrCode, Version};
+use qrcode::bits::Bits;
+use crate::jahis::*;
+use crate::encoding::TextEncoding;
+use crate::escape::EscapePolicy;
+use crate::split::split_code;
+
+/// Width of the quiet zone around a symbol, in modules.
+const QUIET_ZONE: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+/// Error correction level of a QR code
+pub enum ErrorCorrectionLevel {
+    Low, // L (7%)
+    #[default]
+    Medium, // M (15%)
+    Quartile, // Q (25%)
+    High, // H (30%)
+}
+
+impl ErrorCorrectionLevel {
+    pub fn to_code(&self) -> String {
+        self.to_string()
+    }
+
+    fn ec_level(&self) -> EcLevel {
+        match *self {
+            Self::Low => EcLevel::L,
+            Self::Medium => EcLevel::M,
+            Self::Quartile => EcLevel::Q,
+            Self::High => EcLevel::H,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCorrectionLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Low => write!(f, "L"),
+            Self::Medium => write!(f, "M"),
+            Self::Quartile => write!(f, "Q"),
+            Self::High => write!(f, "H"),
+        }
+    }
+}
+
+impl FromStr for ErrorCorrectionLevel {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "L" | "l" => Ok(Self::Low),
+            "M" | "m" => Ok(Self::Medium),
+            "Q" | "q" => Ok(Self::Quartile),
+            "H" | "h" => Ok(Self::High),
+            _ => Err(Error::InvalidArgument(
+                format!("Cannot convert str to ErrorCorrectionLevel, got \"{}\"", s)
+            )),
+        }
+    }
+}
+
+/// One QR code holding the whole code of a notebook or a part of it.
+#[derive(Debug, Clone)]
+pub struct QrSymbol {
+    version: u8,
+    width: usize,
+    modules: Vec<bool>,
+    payload: Vec<u8>,
+}
+
+impl QrSymbol {
+    /// Shift_JIS bytes encoded in the symbol.
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Version (size) of the symbol, from 1 to 40.
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Number of modules on each side, without the quiet zone.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn is_dark(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.width + x]
+    }
+
+    /// Renders the symbol as an SVG document, drawing each module as a
+    /// square of `module_size` pixels inside a quiet zone of four modules.
+    pub fn to_svg(&self, module_size: u32) -> String {
+        let size = (self.width + QUIET_ZONE * 2) * module_size as usize;
+        let mut path = String::new();
+        for y in 0..self.width {
+            for x in (0..self.width).filter(|&x| self.is_dark(x, y)) {
+                path.push_str(&format!("M{},{}h1v1h-1z", x + QUIET_ZONE, y + QUIET_ZONE));
+            }
+        }
+        format!(concat!(
+            r#"<?xml version="1.0" standalone="yes"?>"#,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" version="1.1" width="{size}" height="{size}" viewBox="0 0 {modules} {modules}" shape-rendering="crispEdges">"#,
+            r#"<rect width="100%" height="100%" fill="white"/>"#,
+            r#"<path fill="black" d="{path}"/></svg>"#),
+            size = size, modules = self.width + QUIET_ZONE * 2, path = path)
+    }
+
+    /// Renders the symbol as a black and white PNG image, drawing each
+    /// module as a square of `module_size` pixels inside a quiet zone of
+    /// four modules.
+    pub fn to_png(&self, module_size: u32) -> Result<Vec<u8>, Error> {
+        let module_size = module_size.max(1) as usize;
+        let size = (self.width() + QUIET_ZONE * 2) * module_size;
+        let row_bytes = size.div_ceil(8);
+        let mut data = vec![0xFF; row_bytes * size];
+        for y in 0..self.width() {
+            for x in 0..self.width() {
+                if !self.is_dark(x, y) {
+                    continue;
+                }
+                for py in (y + QUIET_ZONE) * module_size..(y + QUIET_ZONE + 1) * module_size {
+                    for px in (x + QUIET_ZONE) * module_size..(x + QUIET_ZONE + 1) * module_size {
+                        data[py * row_bytes + px / 8] &= !(0x80 >> (px % 8));
+                    }
+                }
+            }
+        }
+
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, size as u32, size as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::One);
+        encoder.write_header()
+            .and_then(|mut writer| writer.write_image_data(&data))
+            .map_err(|e| Error::IoError(e.to_string()))?;
+        Ok(png)
+    }
+}
+
+/// Encodes notebooks into QR codes in byte mode with Shift_JIS text.
+///
+/// The smallest version which holds the data is used for each symbol. When
+/// the data does not fit in `max_version`, it is split into several symbols
+/// with split control records (911) sharing `data_id`, which defaults to the
+/// current local time as YYYYMMDDhhmmss.
+#[derive(Debug, Clone)]
+pub struct QrEncoder {
+    error_correction: ErrorCorrectionLevel,
+    max_version: u8,
+    data_id: Option<String>,
+}
+
+impl QrEncoder {
+    pub fn new() -> Self {
+        Self {
+            error_correction: ErrorCorrectionLevel::default(),
+            max_version: 40,
+            data_id: None,
+        }
+    }
+
+    pub fn error_correction(mut self, error_correction: ErrorCorrectionLevel) -> Self {
+        self.error_correction = error_correction;
+        self
+    }
+
+    /// Sets the largest version of a symbol, from 1 to 40.
+    pub fn max_version(mut self, max_version: u8) -> Self {
+        self.max_version = max_version.clamp(1, 40);
+        self
+    }
+
+    pub fn data_id(mut self, data_id: &str) -> Self {
+        self.data_id = Some(data_id.to_string());
+        self
+    }
+
+    /// Number of bytes a symbol of `max_version` holds in byte mode.
+    pub fn capacity(&self) -> usize {
+        let version = Version::Normal(self.max_version as i16);
+        let bits = Bits::new(version).max_len(self.error_correction.ec_level()).unwrap_or(0);
+        let char_count_bits = if self.max_version <= 9 { 8 } else { 16 };
+        bits.saturating_sub(4 + char_count_bits) / 8
+    }
+
+    pub fn encode_notebook(&self, notebook: &MedicineNotebook) -> Result<Vec<QrSymbol>, Error> {
+        // Encoding the whole notebook first reports the record and the field
+        // of a character which Shift_JIS cannot represent.
+        notebook.to_sjis_bytes()?;
+        let data_id = match &self.data_id {
+            Some(data_id) => data_id.clone(),
+            None => chrono::Local::now().format("%Y%m%d%H%M%S").to_string(),
+        };
+        let code = notebook.to_code_with(EscapePolicy::Reject)?;
+        split_code(&code, &data_id, self.capacity(), TextEncoding::ShiftJis)?
+            .iter()
+            .map(|part| self.encode_bytes(TextEncoding::ShiftJis.encode(part)?.into_owned()))
+            .collect()
+    }
+
+    /// Encodes one dispensing information block together with the version
+    /// and the patient records of the notebook it belongs to.
+    pub fn encode_block(&self, version: &VersionRecord, patient: &PatientRecord,
+            block: &DispensingInformationBlock) -> Result<Vec<QrSymbol>, Error> {
+        let notebook = MedicineNotebook {
+            version: version.clone(),
+            patient: patient.clone(),
+            dispensing_information: vec![block.clone()],
+            .. Default::default()
+        };
+        self.encode_notebook(&notebook)
+    }
+
+    fn encode_bytes(&self, payload: Vec<u8>) -> Result<QrSymbol, Error> {
+        let ec_level = self.error_correction.ec_level();
+        for v in 1..=self.max_version as i16 {
+            let mut bits = Bits::new(Version::Normal(v));
+            if bits.push_byte_data(&payload).and_then(|_| bits.push_terminator(ec_level)).is_err() {
+                continue;
+            }
+            let code = QrCode::with_bits(bits, ec_level)
+                .map_err(|e| Error::InvalidArgument(format!("Cannot make a QR code: {}", e)))?;
+            return Ok(QrSymbol {
+                version: v as u8,
+                width: code.width(),
+                modules: code.into_colors().into_iter().map(|c| c == qrcode::Color::Dark).collect(),
+                payload,
+            });
+        }
+        Err(Error::InvalidArgument(
+            format!("Cannot fit {} bytes in a QR code of version {}", payload.len(), self.max_version)
+        ))
+    }
+}
+
+impl Default for QrEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MedicineNotebook {
+    /// Encodes the notebook into QR codes with the default settings of
+    /// `QrEncoder`.
+    pub fn to_qr_symbols(&self) -> Result<Vec<QrSymbol>, Error> {
+        QrEncoder::new().encode_notebook(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holds_as_many_bytes_as_the_capacity() {
+        for (version, level, bytes) in [
+            (1, ErrorCorrectionLevel::Low, 17),
+            (1, ErrorCorrectionLevel::High, 7),
+            (9, ErrorCorrectionLevel::Medium, 180),
+            (10, ErrorCorrectionLevel::Medium, 213),
+            (40, ErrorCorrectionLevel::Low, 2953),
+            (40, ErrorCorrectionLevel::Medium, 2331),
+        ] {
+            let encoder = QrEncoder::new().max_version(version).error_correction(level);
+            assert_eq!(encoder.capacity(), bytes, "{}-{}", version, level);
+            let symbol = encoder.encode_bytes(vec![b'A'; bytes]).unwrap();
+            assert_eq!(symbol.version(), version);
+            assert!(encoder.encode_bytes(vec![b'A'; bytes + 1]).is_err());
+        }
+        assert_eq!(QrEncoder::new().max_version(0).capacity(), QrEncoder::new().max_version(1).capacity());
+    }
+
+    #[test]
+    fn renders_a_symbol_with_its_quiet_zone() {
+        let symbol = QrEncoder::new().encode_bytes(b"JAHISTC07,1".to_vec()).unwrap();
+        assert_eq!(symbol.version(), 1);
+        assert_eq!(symbol.width(), 21);
+        assert!(symbol.is_dark(0, 0));
+
+        let svg = symbol.to_svg(4);
+        assert!(svg.contains(r#"width="116" height="116" viewBox="0 0 29 29""#), "{}", svg);
+        assert!(svg.contains(r#"d="M4,4h1v1h-1z"#), "{}", svg);
+
+        for (module_size, size) in [(3, 87), (0, 29)] {
+            let png = symbol.to_png(module_size).unwrap();
+            let mut reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+            let mut data = vec![0; reader.output_buffer_size()];
+            let info = reader.next_frame(&mut data).unwrap();
+            assert_eq!((info.width, info.height), (size, size));
+            let module_size = module_size.max(1) as usize;
+            let pixel = |x: usize, y: usize| data[y * info.line_size + x / 8] & (0x80 >> (x % 8)) != 0;
+            assert!(pixel(0, 0));
+            assert!(!pixel(QUIET_ZONE * module_size, QUIET_ZONE * module_size));
+            assert!(pixel(QUIET_ZONE * module_size - 1, QUIET_ZONE * module_size));
+        }
+    }
+}
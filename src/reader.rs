@@ -0,0 +1,860 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::BufRead;
+use crate::jahis::*;
+use crate::encoding::TextEncoding;
+use crate::escape::EscapePolicy;
+use crate::profile::VersionProfile;
+
+/// Record numbers that belong to a dispensing information block (調剤情報)
+const DISPENSING_INFORMATION_RECORDS: [u32; 16] = [
+    5, 11, 15, 51, 55, 201, 281, 291, 301, 311, 391, 401, 411, 421, 501, 601,
+];
+
+/// Lines of a dispensing information block and their line numbers
+type BlockLines = Vec<(usize, String)>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LineKind {
+    Empty,
+    Version,
+    SplitControl,
+    Header(u32),
+    DispensingInformation(u32),
+    FamilyPharmacist,
+    Unknown(u32),
+    Invalid,
+}
+
+impl LineKind {
+    fn of(line: &str) -> Self {
+        if line.is_empty() {
+            return Self::Empty;
+        }
+        if line.starts_with("JAHISTC") {
+            return Self::Version;
+        }
+        let number: u32 = match line.split(',').next().map(|s| s.parse()) {
+            Some(Ok(n)) if line.contains(',') => n,
+            _ => return Self::Invalid,
+        };
+        match number {
+            1..=4 => Self::Header(number),
+            701 => Self::FamilyPharmacist,
+            911 => Self::SplitControl,
+            n if DISPENSING_INFORMATION_RECORDS.contains(&n) => Self::DispensingInformation(n),
+            n => Self::Unknown(n),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+/// Options of `MedicineNotebookReader`
+pub struct ReaderOptions {
+    pub encoding: TextEncoding, // 文字コード
+    pub lenient: bool, // 読み込めない行を読み飛ばして続行する
+    pub escape_policy: EscapePolicy, // 改行文字の扱い
+}
+
+impl ReaderOptions {
+    pub fn new(encoding: TextEncoding, lenient: bool) -> Self {
+        Self {encoding, lenient, .. Default::default()}
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A problem found in lenient mode, with the text skipped because of it
+pub struct Diagnostic {
+    pub line: Option<usize>, // 1-based line number
+    pub content: String, // 読み飛ばした行
+    pub error: Error,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.error)?;
+        if !self.content.is_empty() {
+            write!(f, " (skipped \"{}\")", self.content)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct HeaderState {
+    version: bool,
+    split_control: bool,
+    patient: bool,
+    cur_num: u32,
+}
+
+/// A streaming reader of a medicine notebook.
+///
+/// The header records (version, patient, special patient notes, OTC drugs
+/// and memos) are read when the reader is created. Dispensing information
+/// blocks are then parsed one at a time by iterating over the reader, so
+/// that only a single block is held in memory. Family pharmacist records,
+/// which follow the last block, are available from `family_pharmacist()`
+/// once the iteration has finished.
+///
+/// Once the version record has been read, each line is read in the layout
+/// of its version as described by `VersionProfile`.
+///
+/// Records of numbers not defined in the specification are kept with the
+/// block they appear in, or with the notebook when they appear in the header
+/// or after the family pharmacist records.
+///
+/// In lenient mode, lines which cannot be parsed or are out of place are
+/// skipped and reported as diagnostics instead of stopping the reader. A
+/// dispensing information block without its date or pharmacy record is
+/// skipped as a whole.
+#[derive(Debug)]
+pub struct MedicineNotebookReader<R> {
+    inner: R,
+    buf: Vec<u8>,
+    options: ReaderOptions,
+    line_number: usize,
+    peeked: Option<(usize, String)>,
+    finished: bool,
+    diagnostics: Vec<Diagnostic>,
+    profile: Option<VersionProfile>,
+    originals: HashMap<usize, String>, // 旧レイアウトから変換した行の元の内容
+    known_lines: usize, // 読み込んだ未定義のレコード以外の行数
+
+    version: VersionRecord,
+    split_control: Option<SplitControlRecord>,
+    patient: PatientRecord,
+    special_patient_notes: Vec<SpecialPatientNoteRecord>,
+    otc_drugs: Vec<OtcDrugRecord>,
+    memos: Vec<MemoRecord>,
+    unknown_records: Vec<UnknownRecord>,
+    family_pharmacist: Vec<FamilyPharmacistRecord>,
+}
+
+impl<R: BufRead> MedicineNotebookReader<R> {
+    /// Creates a reader of UTF-8 text and parses the header records of the notebook.
+    pub fn new(inner: R) -> Result<Self, Error> {
+        Self::with_options(inner, ReaderOptions::default())
+    }
+
+    /// Creates a reader of text in the given encoding and parses the header
+    /// records of the notebook.
+    pub fn with_encoding(inner: R, encoding: TextEncoding) -> Result<Self, Error> {
+        Self::with_options(inner, ReaderOptions::new(encoding, false))
+    }
+
+    /// Creates a reader with the given options and parses the header records
+    /// of the notebook.
+    pub fn with_options(inner: R, options: ReaderOptions) -> Result<Self, Error> {
+        let mut reader = Self {
+            inner,
+            buf: Vec::new(),
+            options,
+            line_number: 0,
+            peeked: None,
+            finished: false,
+            diagnostics: Vec::new(),
+            profile: None,
+            originals: HashMap::new(),
+            known_lines: 0,
+
+            version: VersionRecord::default(),
+            split_control: None,
+            patient: PatientRecord::default(),
+            special_patient_notes: Vec::new(),
+            otc_drugs: Vec::new(),
+            memos: Vec::new(),
+            unknown_records: Vec::new(),
+            family_pharmacist: Vec::new(),
+        };
+        reader.read_header()?;
+        Ok(reader)
+    }
+
+    pub fn version(&self) -> &VersionRecord {
+        &self.version
+    }
+
+    /// The split control record, if the notebook is a part of split data.
+    pub fn split_control(&self) -> Option<&SplitControlRecord> {
+        self.split_control.as_ref()
+    }
+
+    /// The profile of the version the notebook is read in.
+    pub fn profile(&self) -> VersionProfile {
+        self.profile.unwrap_or_default()
+    }
+
+    pub fn patient(&self) -> &PatientRecord {
+        &self.patient
+    }
+
+    pub fn special_patient_notes(&self) -> &[SpecialPatientNoteRecord] {
+        &self.special_patient_notes
+    }
+
+    pub fn otc_drugs(&self) -> &[OtcDrugRecord] {
+        &self.otc_drugs
+    }
+
+    pub fn memos(&self) -> &[MemoRecord] {
+        &self.memos
+    }
+
+    /// Unknown records of the notebook read so far; complete once the
+    /// iteration over the dispensing information blocks has finished.
+    pub fn unknown_records(&self) -> &[UnknownRecord] {
+        &self.unknown_records
+    }
+
+    /// Family pharmacist records read so far; complete once the iteration
+    /// over the dispensing information blocks has finished.
+    pub fn family_pharmacist(&self) -> &[FamilyPharmacistRecord] {
+        &self.family_pharmacist
+    }
+
+    /// Diagnostics collected so far in lenient mode.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Reads the remaining blocks and collects the whole notebook.
+    pub fn into_notebook(self) -> Result<MedicineNotebook, Error> {
+        self.into_notebook_with_diagnostics().map(|(notebook, _)| notebook)
+    }
+
+    /// Reads the remaining blocks and collects the whole notebook along with
+    /// the diagnostics of lenient mode.
+    pub fn into_notebook_with_diagnostics(mut self) -> Result<(MedicineNotebook, Vec<Diagnostic>), Error> {
+        let mut dispensing_information: Vec<DispensingInformationBlock> = Vec::new();
+        for block in &mut self {
+            dispensing_information.push(block?);
+        }
+        let notebook = MedicineNotebook {
+            version: self.version,
+            split_control: self.split_control,
+            patient: self.patient,
+            special_patient_notes: self.special_patient_notes,
+            otc_drugs: self.otc_drugs,
+            memos: self.memos,
+            unknown_records: self.unknown_records,
+
+            dispensing_information,
+
+            family_pharmacist: self.family_pharmacist,
+        };
+        Ok((notebook, self.diagnostics))
+    }
+
+    /// Returns the error in strict mode, or records it along with the
+    /// skipped content in lenient mode. A line mapped from an older layout
+    /// is reported as it was written.
+    fn recover(&mut self, error: Error, line: Option<usize>, content: String) -> Result<(), Error> {
+        let (error, content) = self.restore(error, line, content);
+        if self.options.lenient {
+            self.diagnostics.push(Diagnostic {line, content, error});
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+
+    fn restore(&self, error: Error, line: Option<usize>, content: String) -> (Error, String) {
+        let n = match error.parse_error().and_then(|e| e.line).or(line) {
+            Some(n) => n,
+            None => return (error, content),
+        };
+        let (original, profile) = match (self.originals.get(&n), self.profile) {
+            (Some(original), Some(profile)) => (original, profile),
+            _ => return (error, content),
+        };
+        let mapped = profile.read_line(original).map(|l| l.into_owned()).unwrap_or_default();
+        let error = profile.restore_error(error, original, &mapped);
+        let content = content.split("\r\n")
+            .map(|l| if l == mapped {original.as_str()} else {l})
+            .collect::<Vec<&str>>()
+            .join("\r\n");
+        (error, content)
+    }
+
+    /// Reads the next line along with its 1-based line number.
+    fn read_line(&mut self) -> Result<Option<(usize, String)>, Error> {
+        if let Some(line) = self.peeked.take() {
+            return Ok(Some(line));
+        }
+        loop {
+            self.buf.clear();
+            let n = self.inner.read_until(b'\n', &mut self.buf)
+                .map_err(|e| Error::IoError(e.to_string()))?;
+            if n == 0 {
+                return Ok(None);
+            }
+            self.line_number += 1;
+            if self.buf.ends_with(b"\n") {
+                self.buf.pop();
+                if self.buf.ends_with(b"\r") {
+                    self.buf.pop();
+                }
+            }
+            let error = match self.options.encoding.decode(&self.buf) {
+                Ok(s) => {
+                    let line = self.escape_line(s.into_owned());
+                    let profile = match self.profile {
+                        Some(profile) => profile,
+                        None => return Ok(Some((self.line_number, line))),
+                    };
+                    match profile.read_line(&line) {
+                        Ok(Cow::Borrowed(_)) => return Ok(Some((self.line_number, line))),
+                        Ok(Cow::Owned(mapped)) => {
+                            self.originals.insert(self.line_number, line);
+                            return Ok(Some((self.line_number, mapped)));
+                        },
+                        Err(e) => {
+                            self.recover(e.at_line(self.line_number), Some(self.line_number), line)?;
+                            continue;
+                        },
+                    }
+                },
+                Err(Error::EncodingError(s)) => Error::EncodingError(
+                    format!("{} at line {}", s, self.line_number)
+                ),
+                Err(e) => e,
+            };
+            let content = self.options.encoding.decode_lossy(&self.buf).into_owned();
+            self.recover(error, Some(self.line_number), content)?;
+        }
+    }
+
+    /// A carriage return left inside a line is a line break within a field;
+    /// it is rejected by the record parsers, or replaced with a full-width
+    /// space as `EscapePolicy::FullWidth` would write it.
+    fn escape_line(&self, line: String) -> String {
+        if self.options.escape_policy == EscapePolicy::FullWidth && line.contains('\r') {
+            line.replace('\r', "　")
+        } else {
+            line
+        }
+    }
+
+    fn read_header(&mut self) -> Result<(), Error> {
+        let mut state = HeaderState::default();
+        while let Some((n, line)) = self.read_line()? {
+            let kind = LineKind::of(&line);
+            if let LineKind::DispensingInformation(_) | LineKind::FamilyPharmacist = kind {
+                self.peeked = Some((n, line));
+                break;
+            }
+            if let Err(e) = self.read_header_line(kind, n, &line, &mut state) {
+                self.recover(e, Some(n), line)?;
+            }
+        }
+
+        if !state.version {
+            let e = Error::MissingRequiredRecord(ParseError {
+                expected: "VersionRecord".to_string(),
+                .. Default::default()
+            });
+            self.recover(e, None, String::new())?;
+        }
+        if !state.patient {
+            let e = Error::MissingRequiredRecord(ParseError {
+                expected: "1 PatientRecord".to_string(),
+                .. Default::default()
+            });
+            self.recover(e, None, String::new())?;
+        }
+        Ok(())
+    }
+
+    fn read_header_line(&mut self, kind: LineKind, n: usize, line: &str,
+                state: &mut HeaderState) -> Result<(), Error> {
+        match kind {
+            LineKind::Empty => {},
+            LineKind::Version => { // バージョンレコード
+                if state.version {
+                    return Err(
+                        Error::GotUnexpectedRecordLine(
+                            ParseError::unexpected(n, line, "a single VersionRecord")
+                        )
+                    );
+                } else if state.cur_num > 0 || state.split_control {
+                    return Err(
+                        Error::GotUnexpectedRecordLine(
+                            ParseError::unexpected(n, line, "VersionRecord in ascending order of record number")
+                        )
+                    );
+                }
+                self.version = parse_line(line, n)?;
+                self.profile = Some(VersionProfile::for_version(self.version.number));
+                state.version = true;
+                self.known_lines += 1;
+            },
+            LineKind::SplitControl => { // 分割制御レコード
+                if state.split_control {
+                    return Err(
+                        Error::GotUnexpectedRecordLine(
+                            ParseError::unexpected(n, line, "a single SplitControlRecord")
+                        )
+                    );
+                } else if !state.version || state.cur_num > 0 {
+                    return Err(
+                        Error::GotUnexpectedRecordLine(
+                            ParseError::unexpected(n, line, "SplitControlRecord right after VersionRecord")
+                        )
+                    );
+                }
+                self.split_control = Some(parse_line(line, n)?);
+                state.split_control = true;
+                self.known_lines += 1;
+            },
+            LineKind::Header(1) => { // 患者情報レコード
+                if state.patient {
+                    return Err(
+                        Error::GotUnexpectedRecordLine(
+                            ParseError::unexpected(n, line, "a single PatientRecord")
+                        )
+                    );
+                } else if state.cur_num > 0 {
+                    return Err(
+                        Error::GotUnexpectedRecordLine(
+                            ParseError::unexpected(n, line, "PatientRecord in ascending order of record number")
+                        )
+                    );
+                }
+                self.patient = parse_line(line, n)?;
+                state.patient = true;
+                state.cur_num = 1;
+                self.known_lines += 1;
+            },
+            LineKind::Header(number) => {
+                if state.cur_num > number {
+                    return Err(
+                        Error::GotUnexpectedRecordLine(
+                            ParseError::unexpected(
+                                n, line, &format!("{} in ascending order of record number",
+                                    record_name(number).unwrap_or_default()))
+                        )
+                    );
+                }
+                match number {
+                    2 => self.special_patient_notes.push(parse_line(line, n)?), // 患者特記レコード
+                    3 => self.otc_drugs.push(parse_line(line, n)?), // 一般用医薬品服用レコード
+                    _ => self.memos.push(parse_line(line, n)?), // 手帳メモレコード
+                }
+                state.cur_num = number;
+                self.known_lines += 1;
+            },
+            LineKind::Unknown(_) => { // 未定義のレコード
+                let record: UnknownRecord = parse_line(line, n)?;
+                self.unknown_records.push(UnknownRecord {position: Some(self.known_lines), .. record});
+            },
+            LineKind::DispensingInformation(_) | LineKind::FamilyPharmacist => {
+                return Err(Error::Unreachable(
+                    format!("Unreachable code in read_header_line, got \"{}\"", line)
+                ));
+            },
+            LineKind::Invalid => {
+                return Err(
+                    Error::GotUnexpectedRecordLine(
+                        ParseError::unexpected(n, line, "valid record line")
+                    )
+                );
+            },
+        }
+        Ok(())
+    }
+
+    fn read_block(&mut self) -> Result<Option<DispensingInformationBlock>, Error> {
+        while let Some(lines) = self.read_block_lines()? {
+            if let Some(block) = self.parse_block_lines(lines)? {
+                return Ok(Some(block));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Collects the lines of the next dispensing information block, reading
+    /// the family pharmacist records on the way.
+    fn read_block_lines(&mut self) -> Result<Option<BlockLines>, Error> {
+        let peeked = self.peeked.as_ref().map(|(n, _)| *n);
+        self.originals.retain(|&n, _| Some(n) == peeked);
+        let mut lines: BlockLines = Vec::new();
+        while let Some((n, line)) = self.read_line()? {
+            let error = match LineKind::of(&line) {
+                LineKind::Empty => continue,
+                LineKind::DispensingInformation(number) => {
+                    if self.family_pharmacist.is_empty() {
+                        if number == 5 && !lines.is_empty() { // 調剤等年月日レコード
+                            self.peeked = Some((n, line));
+                            break;
+                        }
+                        lines.push((n, line));
+                        continue;
+                    }
+                    Error::GotUnexpectedRecordLine(
+                        ParseError::unexpected(n, &line, "DispensingInformationBlock in ascending order of record number")
+                    )
+                },
+                LineKind::FamilyPharmacist => { //かかりつけ薬剤師レコード
+                    if !lines.is_empty() {
+                        self.peeked = Some((n, line));
+                        break;
+                    }
+                    match parse_line(&line, n) {
+                        Ok(record) => {
+                            self.family_pharmacist.push(record);
+                            self.known_lines += 1;
+                            continue;
+                        },
+                        Err(e) => e,
+                    }
+                },
+                LineKind::Unknown(_) => { // 未定義のレコード
+                    if self.family_pharmacist.is_empty() && !lines.is_empty() {
+                        lines.push((n, line));
+                        continue;
+                    }
+                    match parse_line(&line, n) {
+                        Ok(record) => {
+                            self.unknown_records.push(UnknownRecord {position: Some(self.known_lines), .. record});
+                            continue;
+                        },
+                        Err(e) => e,
+                    }
+                },
+                LineKind::Version => {
+                    Error::GotUnexpectedRecordLine(
+                        ParseError::unexpected(n, &line, "VersionRecord in ascending order of record number")
+                    )
+                },
+                LineKind::SplitControl => {
+                    Error::GotUnexpectedRecordLine(
+                        ParseError::unexpected(n, &line, "SplitControlRecord right after VersionRecord")
+                    )
+                },
+                LineKind::Header(number) => {
+                    Error::GotUnexpectedRecordLine(
+                        ParseError::unexpected(
+                            n, &line, &format!("{} in ascending order of record number",
+                                record_name(number).unwrap_or_default()))
+                    )
+                },
+                LineKind::Invalid => {
+                    Error::GotUnexpectedRecordLine(
+                        ParseError::unexpected(n, &line, "valid record line")
+                    )
+                },
+            };
+            self.recover(error, Some(n), line)?;
+        }
+        if lines.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(lines))
+        }
+    }
+
+    /// Parses the lines of a block. In lenient mode the line an error points
+    /// at is skipped and the rest is parsed again, while an error which
+    /// concerns the whole block skips the block.
+    fn parse_block_lines(&mut self, mut lines: BlockLines)
+            -> Result<Option<DispensingInformationBlock>, Error> {
+        while !lines.is_empty() {
+            let numbered: Vec<(usize, &str)> = lines.iter().map(|(n, line)| (*n, line.as_str())).collect();
+            let error = match DispensingInformationBlock::from_lines(&numbered) {
+                Ok(block) => {
+                    self.known_lines += lines.len();
+                    return Ok(Some(block));
+                },
+                Err(e) => e,
+            };
+            let position = error.parse_error()
+                .and_then(|e| e.line)
+                .and_then(|n| lines.iter().position(|&(m, _)| m == n));
+            let skip_line = match (&error, position) {
+                (Error::MissingRequiredRecord(_), Some(i)) => i > 0,
+                (Error::InvalidRecordLine(_), Some(_))
+                    | (Error::GotUnexpectedRecordLine(_), Some(_)) => true,
+                _ => false,
+            };
+            match position {
+                Some(i) if skip_line => {
+                    let (n, content) = lines.remove(i);
+                    self.recover(error, Some(n), content)?;
+                },
+                _ => {
+                    let n = lines.first().map(|(n, _)| *n);
+                    let content: Vec<String> = lines.into_iter().map(|(_, line)| line).collect();
+                    self.recover(error, n, content.join("\r\n"))?;
+                    return Ok(None);
+                },
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<R: BufRead> Iterator for MedicineNotebookReader<R> {
+    type Item = Result<DispensingInformationBlock, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        match self.read_block() {
+            Ok(Some(block)) => Some(Ok(block)),
+            Ok(None) => {
+                self.finished = true;
+                None
+            },
+            Err(e) => {
+                self.finished = true;
+                Some(Err(e))
+            },
+        }
+    }
+}
+
+impl MedicineNotebook {
+    /// Parses as much of a notebook as possible, skipping what cannot be
+    /// parsed and reporting it as diagnostics.
+    pub fn parse_lenient(s: &str) -> (Self, Vec<Diagnostic>) {
+        let options = ReaderOptions::new(TextEncoding::Utf8, true);
+        match MedicineNotebookReader::with_options(s.as_bytes(), options)
+                .and_then(|reader| reader.into_notebook_with_diagnostics()) {
+            Ok(result) => result,
+            Err(error) => (Self::default(), vec![Diagnostic {line: None, content: String::new(), error}]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer::MedicineNotebookWriter;
+
+    const SAMPLE: &str = "JAHISTC06,1\r\n\
+        1,山田太郎,1,19700101,1000001,東京都千代田区,03-0000-0000,,A,60.5,ﾔﾏﾀﾞﾀﾛｳ\r\n\
+        2,1,卵アレルギー,1\r\n\
+        3,ロキソニン,20200101,20200201,2\r\n\
+        4,メモです,20200301,2\r\n\
+        5,20200401,1\r\n\
+        11,テスト薬局,13,4,1234567,1000001,東京都,03-1111-1111,1\r\n\
+        15,薬剤師花子,03-1111-1111,1\r\n\
+        51,テスト病院,13,1,7654321,1\r\n\
+        55,医師一郎,内科,1\r\n\
+        201,1,ロキソプロフェン錠60mg,1,錠,2,620098801,1\r\n\
+        281,1,補足,1\r\n\
+        291,1,注意,1\r\n\
+        301,1,1日3回毎食後,7,日分,1,1,,1\r\n\
+        311,1,用法補足,1\r\n\
+        391,1,処方注意,1\r\n\
+        201,2,ムコスタ錠100mg,3,錠,4,2329021F1021,1\r\n\
+        301,2,1日3回毎食後,7,日分,1,,,1\r\n\
+        401,服用注意,1\r\n\
+        411,提供情報,30,1\r\n\
+        501,備考,1\r\n\
+        601,患者記入,20200402\r\n\
+        5,R020501,1\r\n\
+        11,テスト薬局2,,,,,,,1\r\n\
+        701,かかりつけ,テスト薬局,03,20200101,,1";
+
+    #[test]
+    fn reads_blocks_one_at_a_time() {
+        let mut reader = MedicineNotebookReader::new(SAMPLE.as_bytes()).unwrap();
+        assert_eq!(reader.version().number, 6);
+        assert_eq!(reader.patient().name, "山田太郎");
+        assert_eq!(reader.memos().len(), 1);
+        assert!(reader.family_pharmacist().is_empty());
+
+        let first = reader.next().unwrap().unwrap();
+        assert_eq!(first.prescriptions[0].rps.len(), 2);
+        let second = reader.next().unwrap().unwrap();
+        assert_eq!(second.date.created_at.to_code(), "R020501");
+        assert!(reader.next().is_none());
+        assert_eq!(reader.family_pharmacist().len(), 1);
+    }
+
+    #[test]
+    fn round_trips_through_to_code() {
+        let notebook: MedicineNotebook = SAMPLE.parse().unwrap();
+        let code = notebook.to_code_with(EscapePolicy::Reject).unwrap();
+        assert_eq!(code, SAMPLE);
+        let reparsed: MedicineNotebook = code.parse().unwrap();
+        assert_eq!(reparsed, notebook);
+    }
+
+    #[test]
+    fn writes_unknown_records_back_where_they_were_read() {
+        let s = "JAHISTC07,1\r\n\
+            950,ヘッダ前\r\n\
+            1,山田太郎,1,19700101,1000001,東京都千代田区,03-0000-0000,,A,60.5,ﾔﾏﾀﾞﾀﾛｳ,追加\r\n\
+            4,メモです,20200301,2\r\n\
+            940,メモ後,,\r\n\
+            5,20200401,1,追加1,追加2\r\n\
+            810,日付後\r\n\
+            11,テスト薬局,13,4,1234567,1000001,東京都,03-1111-1111,1\r\n\
+            55,医師一郎,内科,1\r\n\
+            201,1,ロキソプロフェン錠60mg,1,錠,2,620098801,1,追加\r\n\
+            250,薬品後\r\n\
+            291,1,注意,1\r\n\
+            301,1,1日3回毎食後,7,日分,1,1,,1\r\n\
+            230,用法後\r\n\
+            391,1,処方注意,1\r\n\
+            450,RPの間\r\n\
+            201,2,ムコスタ錠100mg,3,錠,4,2329021F1021,1\r\n\
+            301,2,1日3回毎食後,7,日分,1,,,1\r\n\
+            401,服用注意,1,追加\r\n\
+            960,服用注意後\r\n\
+            501,備考,1\r\n\
+            5,R020501,1\r\n\
+            11,テスト薬局2,,,,,,,1\r\n\
+            701,かかりつけ,テスト薬局,03,20200101,,1\r\n\
+            990,末尾";
+        let notebook: MedicineNotebook = s.parse().unwrap();
+        let block = &notebook.dispensing_information[0];
+        assert_eq!(notebook.unknown_records.len(), 3);
+        assert_eq!(block.unknown_records.len(), 3);
+        assert_eq!(block.prescriptions[0].rps[0].drugs[0].unknown_records.len(), 1);
+        assert_eq!(block.prescriptions[0].rps[0].unknown_records.len(), 1);
+        assert_eq!(notebook.patient.extra, vec!["追加"]);
+        assert_eq!(block.date.extra, vec!["追加1", "追加2"]);
+
+        assert_eq!(notebook.to_code_with(EscapePolicy::Reject).unwrap(), s);
+        let mut writer = MedicineNotebookWriter::new(Vec::new());
+        writer.write_notebook(&notebook).unwrap();
+        assert_eq!(writer.finish().unwrap(), s.as_bytes());
+        let mut writer = MedicineNotebookWriter::new(Vec::new());
+        writer.write_header(&notebook.version, &notebook.patient, &notebook.special_patient_notes,
+            &notebook.otc_drugs, &notebook.memos, &notebook.unknown_records).unwrap();
+        for block in &notebook.dispensing_information {
+            writer.write_block(block).unwrap();
+        }
+        for record in &notebook.family_pharmacist {
+            writer.write_family_pharmacist(record).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), s.as_bytes());
+    }
+
+    #[test]
+    fn strict_mode_stops_at_a_bad_field() {
+        let s = SAMPLE.replace("201,2,ムコスタ錠100mg,3,錠,4,", "201,2,ムコスタ錠100mg,3,錠,x,");
+        let e = MedicineNotebookReader::new(s.as_bytes()).unwrap()
+            .into_notebook()
+            .unwrap_err();
+        let parse_error = e.parse_error().unwrap();
+        assert!(matches!(e, Error::InvalidRecordLine(_)));
+        assert_eq!(parse_error.line, Some(17));
+        assert_eq!(parse_error.record_number, Some(201));
+        assert_eq!(parse_error.field_index, Some(5));
+        assert_eq!(parse_error.field_name, Some("薬品コード種別"));
+        assert_eq!(parse_error.found, "x");
+    }
+
+    #[test]
+    fn lenient_mode_skips_bad_lines() {
+        let s = SAMPLE.replace("4,メモです,20200301,2", "4,メモです,2020-03-01,2");
+        let (notebook, diagnostics) = MedicineNotebook::parse_lenient(&s);
+        assert!(notebook.memos.is_empty());
+        assert_eq!(notebook.dispensing_information.len(), 2);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, Some(5));
+        assert_eq!(diagnostics[0].content, "4,メモです,2020-03-01,2");
+        let parse_error = diagnostics[0].error.parse_error().unwrap();
+        assert_eq!(parse_error.field_index, Some(2));
+        assert_eq!(parse_error.field_name, Some("メモ入力年月日"));
+
+        let strict = MedicineNotebookReader::new(s.as_bytes()).unwrap_err();
+        assert_eq!(strict, diagnostics[0].error);
+    }
+
+    #[test]
+    fn lenient_mode_skips_a_block_without_its_pharmacy() {
+        let s = SAMPLE.replace("11,テスト薬局2,,,,,,,1\r\n", "");
+        let (notebook, diagnostics) = MedicineNotebook::parse_lenient(&s);
+        assert_eq!(notebook.dispensing_information.len(), 1);
+        assert_eq!(notebook.family_pharmacist.len(), 1);
+        assert_eq!(diagnostics.len(), 1);
+        assert!(matches!(diagnostics[0].error, Error::MissingRequiredRecord(_)));
+        assert_eq!(diagnostics[0].line, Some(23));
+    }
+
+    #[test]
+    fn reports_the_line_of_an_older_layout_as_written() {
+        let s = "JAHISTC05,1\r\n1,山田,9,19700101,,,,,A,,X\r\n1,山田,1,19700101,,,,,A,,X\r\n";
+        let e = MedicineNotebookReader::new(s.as_bytes()).unwrap_err();
+        assert_eq!(e.parse_error().unwrap().found, "9");
+
+        let s = s.replace(",9,", ",1,");
+        let e = MedicineNotebookReader::new(s.as_bytes()).unwrap_err();
+        assert_eq!(e.parse_error().unwrap().found, "1,山田,1,19700101,,,,,A,,X");
+    }
+
+    #[test]
+    fn places_remaining_medicine_and_split_control_records() {
+        let s = "JAHISTC07,1\r\n\
+            911,ID0001,2,1\r\n\
+            1,山田太郎,1,19700101,,,,,,,\r\n\
+            5,20200401,1\r\n\
+            11,テスト薬局,13,4,1234567,1000001,東京都,03-1111-1111,1\r\n\
+            201,1,ロキソプロフェン錠60mg,1,錠,2,620098801,1\r\n\
+            301,1,1日3回毎食後,7,日分,1,1,,1\r\n\
+            411,提供情報,30,1\r\n\
+            421,残薬,1\r\n\
+            501,備考,1";
+        let notebook: MedicineNotebook = s.parse().unwrap();
+        assert_eq!(notebook.split_control, Some(SplitControlRecord::new("ID0001".to_string(), 2, 1)));
+        let block = &notebook.dispensing_information[0];
+        assert_eq!(block.remaining_medicine, Some(RemainingMedicineRecord::new("残薬".to_string(), RecordCreator::MedicalExpert)));
+        assert_eq!(notebook.to_code_with(EscapePolicy::Reject).unwrap(), s);
+
+        let expect_unexpected = |s: &str, line: usize, expected: &str| {
+            let e = s.parse::<MedicineNotebook>().unwrap_err();
+            assert!(matches!(e, Error::GotUnexpectedRecordLine(_)), "{:?}", e);
+            assert_eq!(e.parse_error().unwrap().line, Some(line));
+            assert_eq!(e.parse_error().unwrap().expected, expected);
+        };
+        expect_unexpected(&s.replace("421,残薬,1\r\n501,備考,1", "501,備考,1\r\n421,残薬,1"),
+            10, "RemainingMedicineRecord in ascending order of record number");
+        expect_unexpected(&s.replace("421,残薬,1", "421,残薬,1\r\n421,残薬,1"),
+            10, "RemainingMedicineRecord in ascending order of record number");
+        expect_unexpected(&s.replace("411,提供情報,30,1\r\n421,残薬,1", "421,残薬,1\r\n411,提供情報,30,1"),
+            9, "InformationProvisionRecord in ascending order of record number");
+        expect_unexpected(&s.replace("911,ID0001,2,1\r\n1,山田太郎,1,19700101,,,,,,,", "1,山田太郎,1,19700101,,,,,,,\r\n911,ID0001,2,1"),
+            3, "SplitControlRecord right after VersionRecord");
+        expect_unexpected(&s.replace("911,ID0001,2,1", "911,ID0001,2,1\r\n911,ID0001,2,2"),
+            3, "a single SplitControlRecord");
+        expect_unexpected(&s.replace("JAHISTC07,1\r\n911,ID0001,2,1", "911,ID0001,2,1\r\nJAHISTC07,1"),
+            1, "SplitControlRecord right after VersionRecord");
+        expect_unexpected(&s.replace("501,備考,1", "501,備考,1\r\n911,ID0001,2,2"),
+            11, "SplitControlRecord right after VersionRecord");
+    }
+
+    #[test]
+    fn reads_back_a_remaining_medicine_record_added_in_code() {
+        let mut block = DispensingInformationBlock::new(
+            DateRecord::new(Date::new_seireki(2020, 4, 1).unwrap(), RecordCreator::MedicalExpert),
+            PharmacyRecord {name: "テスト薬局".to_string(), .. Default::default()},
+        );
+        block.remaining_medicine = Some(RemainingMedicineRecord::new("残薬あり".to_string(), RecordCreator::MedicalExpert));
+        let mut notebook = MedicineNotebook::new(PatientRecord {name: "山田太郎".to_string(), .. Default::default()});
+        notebook.dispensing_information.push(block);
+        assert_eq!(notebook.version.number, 6);
+        assert_eq!(notebook.written_version().number, 7);
+
+        let code = notebook.to_code();
+        assert!(code.starts_with("JAHISTC07,1\r\n"), "{}", code);
+        assert!(code.ends_with("\r\n421,残薬あり,1"), "{}", code);
+        let read: MedicineNotebook = code.parse().unwrap();
+        assert_eq!(read.version.number, 7);
+        assert_eq!(read.dispensing_information, notebook.dispensing_information);
+
+        let mut writer = MedicineNotebookWriter::new(Vec::new());
+        writer.write_notebook(&notebook).unwrap();
+        assert_eq!(String::from_utf8(writer.finish().unwrap()).unwrap(), code);
+
+        notebook.dispensing_information[0].remaining_medicine = None;
+        assert!(notebook.to_code().starts_with("JAHISTC06,1\r\n"));
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use crate::jahis::*;
+use crate::encoding::TextEncoding;
+use crate::split::SplitAssembler;
+use crate::decode::{decode_image, DecodedSymbol};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Result of decoding the QR codes of one image
+pub struct ImageDiagnostic {
+    pub image: usize, // 画像の番号 (0-based)
+    pub decoded: usize, // 読み取ったQRコードの数
+    pub errors: Vec<Error>, // 読み取れなかったQRコードやデータのエラー
+}
+
+impl fmt::Display for ImageDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "image {}: decoded {} QR code(s)", self.image + 1, self.decoded)?;
+        for e in &self.errors {
+            write!(f, "; {}", e)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads notebooks from images of their QR codes.
+///
+/// Every QR code found in each PNG or JPEG image is decoded, and the
+/// payloads are reassembled into one notebook, whether it is split with
+/// split control records (911) or with the structured append of QR codes.
+/// The codes may appear in any order and in any of the images, and an image
+/// which cannot be read does not prevent reading the others.
+#[derive(Debug, Clone, Default)]
+pub struct QrScanner {
+    assembler: SplitAssembler,
+    structured_append: BTreeMap<(u8, u8), BTreeMap<u8, Vec<u8>>>, // (parity, total) -> parts
+    diagnostics: Vec<ImageDiagnostic>,
+}
+
+impl QrScanner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes the QR codes of an image.
+    pub fn scan_image(&mut self, image: &[u8]) -> &ImageDiagnostic {
+        let mut diagnostic = ImageDiagnostic {
+            image: self.diagnostics.len(),
+            decoded: 0,
+            errors: Vec::new(),
+        };
+        match decode_image(image) {
+            Ok((symbols, errors)) => {
+                diagnostic.decoded = symbols.len();
+                diagnostic.errors = errors;
+                for symbol in symbols {
+                    if let Err(e) = self.add_symbol(symbol) {
+                        diagnostic.errors.push(e);
+                    }
+                }
+            },
+            Err(e) => diagnostic.errors.push(e),
+        }
+        self.diagnostics.push(diagnostic);
+        self.diagnostics.last().unwrap()
+    }
+
+    fn add_symbol(&mut self, symbol: DecodedSymbol) -> Result<(), Error> {
+        let payload = match symbol.structured_append {
+            Some(sa) => {
+                if sa.index >= sa.total {
+                    return Err(Error::InvalidArgument(
+                        format!("Cannot use symbol {} of {} symbols of structured append", sa.index + 1, sa.total)
+                    ));
+                }
+                // A set is told apart by its parity and its number of symbols,
+                // and is removed once it is complete, so that a later set with
+                // the same ones starts anew.
+                let key = (sa.parity, sa.total);
+                let parts = self.structured_append.entry(key).or_default();
+                parts.insert(sa.index, symbol.payload);
+                if parts.len() < sa.total as usize {
+                    return Ok(());
+                }
+                let parts = self.structured_append.remove(&key).unwrap_or_default();
+                parts.into_values().flatten().collect()
+            },
+            None => symbol.payload,
+        };
+        let text = TextEncoding::ShiftJis.decode(&payload)
+            .or_else(|_| TextEncoding::Utf8.decode(&payload))?;
+        self.assembler.add(&text)
+    }
+
+    pub fn diagnostics(&self) -> &[ImageDiagnostic] {
+        &self.diagnostics
+    }
+
+    /// Parts of the notebook read so far.
+    pub fn assembler(&self) -> &SplitAssembler {
+        &self.assembler
+    }
+
+    pub fn into_notebook(self) -> Result<MedicineNotebook, Error> {
+        self.into_notebook_with_diagnostics().0
+    }
+
+    pub fn into_notebook_with_diagnostics(self) -> (Result<MedicineNotebook, Error>, Vec<ImageDiagnostic>) {
+        let result = match self.structured_append.iter().next() {
+            Some(((parity, total), parts)) => Err(Error::MissingSplitParts {
+                data_id: format!("structured append {:02X}", parity),
+                missing: (0..*total).filter(|i| !parts.contains_key(i)).map(|i| i as u32 + 1).collect(),
+            }),
+            None => self.assembler.into_notebook(),
+        };
+        (result, self.diagnostics)
+    }
+}
+
+impl MedicineNotebook {
+    /// Reads a notebook from PNG or JPEG images of its QR codes, returning
+    /// the diagnostics of each image along with it.
+    pub fn from_qr_images<I, B>(images: I) -> (Result<Self, Error>, Vec<ImageDiagnostic>)
+            where I: IntoIterator<Item = B>, B: AsRef<[u8]> {
+        let mut scanner = QrScanner::new();
+        for image in images {
+            scanner.scan_image(image.as_ref());
+        }
+        scanner.into_notebook_with_diagnostics()
+    }
+}
+
+#[cfg(all(test, feature = "qr"))]
+mod tests {
+    use super::*;
+    use crate::decode::StructuredAppend;
+    use crate::QrEncoder;
+    use crate::test_util::SAMPLE;
+
+    #[test]
+    fn reads_split_symbols_in_any_order() {
+        let notebook: MedicineNotebook = SAMPLE.parse().unwrap();
+        let symbols = QrEncoder::new().max_version(8).data_id("20200501000000").encode_notebook(&notebook).unwrap();
+        assert!(symbols.len() > 2);
+        let images: Vec<Vec<u8>> = symbols.iter().rev().map(|s| s.to_png(3).unwrap()).collect();
+
+        let (result, diagnostics) = MedicineNotebook::from_qr_images(&images);
+        assert!(diagnostics.iter().all(|d| d.decoded == 1 && d.errors.is_empty()), "{:?}", diagnostics);
+        assert_eq!(result.unwrap().to_code(), notebook.to_code());
+
+        let (result, _) = MedicineNotebook::from_qr_images(&images[1..]);
+        assert!(matches!(result, Err(Error::MissingSplitParts {..})));
+    }
+
+    #[test]
+    fn joins_structured_append_across_split_parts() {
+        let notebook: MedicineNotebook = SAMPLE.parse().unwrap();
+        let symbols = QrEncoder::new().max_version(8).data_id("20200501000000").encode_notebook(&notebook).unwrap();
+
+        // Each part of the 911 split is carried by two symbols of structured append.
+        let mut scanner = QrScanner::new();
+        for symbol in &symbols {
+            let payload = symbol.payload();
+            let parity = payload.iter().fold(0, |p, b| p ^ b);
+            let (first, second) = payload.split_at(payload.len() / 2);
+            for (index, part) in [(1, second), (0, first)] {
+                scanner.add_symbol(DecodedSymbol {
+                    payload: part.to_vec(),
+                    structured_append: Some(StructuredAppend {index, total: 2, parity}),
+                }).unwrap();
+            }
+        }
+        assert_eq!(scanner.into_notebook().unwrap().to_code(), notebook.to_code());
+
+        let mut scanner = QrScanner::new();
+        scanner.add_symbol(DecodedSymbol {
+            payload: symbols[0].payload().to_vec(),
+            structured_append: Some(StructuredAppend {index: 0, total: 2, parity: 0}),
+        }).unwrap();
+        match scanner.into_notebook() {
+            Err(Error::MissingSplitParts {missing, ..}) => assert_eq!(missing, vec![2]),
+            result => panic!("{:?}", result.map(|n| n.to_code())),
+        }
+    }
+
+    #[test]
+    fn tells_apart_sets_of_structured_append_with_the_same_parity() {
+        let notebook: MedicineNotebook = SAMPLE.parse().unwrap();
+        let symbols = QrEncoder::new().max_version(8).data_id("20200501000000").encode_notebook(&notebook).unwrap();
+        assert!(symbols.len() >= 3);
+        let parts = |payload: &[u8], total: u8| -> Vec<DecodedSymbol> {
+            let size = payload.len().div_ceil(total as usize);
+            payload.chunks(size).enumerate().map(|(index, part)| DecodedSymbol {
+                payload: part.to_vec(),
+                structured_append: Some(StructuredAppend {index: index as u8, total, parity: 0x42}),
+            }).collect()
+        };
+
+        // The sets of the first two parts are interleaved, and the third one
+        // follows the first with the same parity and number of symbols.
+        let mut scanner = QrScanner::new();
+        let first = parts(symbols[0].payload(), 2);
+        let second = parts(symbols[1].payload(), 3);
+        for symbol in first.into_iter().zip(second.iter().cloned()).flat_map(|(a, b)| [a, b]) {
+            scanner.add_symbol(symbol).unwrap();
+        }
+        scanner.add_symbol(second[2].clone()).unwrap();
+        for symbol in parts(symbols[2].payload(), 2) {
+            scanner.add_symbol(symbol).unwrap();
+        }
+        for symbol in &symbols[3..] {
+            scanner.add_symbol(DecodedSymbol {payload: symbol.payload().to_vec(), structured_append: None}).unwrap();
+        }
+        assert!(scanner.structured_append.is_empty());
+        assert_eq!(scanner.into_notebook().unwrap().to_code(), notebook.to_code());
+
+        let mut scanner = QrScanner::new();
+        let result = scanner.add_symbol(DecodedSymbol {
+            payload: symbols[0].payload().to_vec(),
+            structured_append: Some(StructuredAppend {index: 2, total: 2, parity: 0x42}),
+        });
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+        assert!(scanner.structured_append.is_empty());
+    }
+}
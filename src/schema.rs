@@ -0,0 +1,185 @@
+use std::borrow::Cow;
+use schemars::{JsonSchema, Schema, SchemaGenerator, json_schema};
+use schemars::generate::SchemaSettings;
+use crate::jahis::*;
+
+/// Describes a code table as the names its values are serialized as, each
+/// with its JAHIS code in `x-jahis-code` and its Japanese name in `title`.
+macro_rules! code_table_schema {
+    ($name:ident, $description:expr, [$($variant:ident),* $(,)?]) => {
+        impl JsonSchema for $name {
+            fn schema_name() -> Cow<'static, str> {
+                stringify!($name).into()
+            }
+
+            fn json_schema(_: &mut SchemaGenerator) -> Schema {
+                let values: Vec<serde_json::Value> = vec![$(
+                    serde_json::json!({
+                        "const": serde_json::to_value($name::$variant).unwrap(),
+                        "title": $name::$variant.to_string(),
+                        "x-jahis-code": $name::$variant.to_code(),
+                    }),
+                )*];
+                json_schema!({
+                    "type": "string",
+                    "description": $description,
+                    "oneOf": values,
+                })
+            }
+        }
+    };
+}
+
+code_table_schema!(Prefecture, "Appended Table 2: Prefecture Code", [
+    Hokkaido, Aomori, Iwate, Miyagi, Akita, Yamagata, Fukushima, Ibaraki, Tochigi, Gumma,
+    Saitama, Chiba, Tokyo, Kanagawa, Niigata, Toyama, Ishikawa, Fukui, Yamanashi, Nagano,
+    Gifu, Shizuoka, Aichi, Mie, Shiga, Kyoto, Osaka, Hyogo, Nara, Wakayama,
+    Tottori, Shimane, Okayama, Hiroshima, Yamaguchi, Tokushima, Kagawa, Ehime, Kochi, Fukuoka,
+    Saga, Nagasaki, Kumamoto, Oita, Miyazaki, Kagoshima, Okinawa,
+]);
+code_table_schema!(FeeTable, "Appended Table 3: Type of Medical Fee Table", [
+    Medicine, Dentistry, Pharmacy,
+]);
+code_table_schema!(DosageForm, "Appended Table 4: Type of dosage form", [
+    OralAdministration, Drop, Potion, Injection, ExternalUse, Infusodecoction, Decoction,
+    Material, Other,
+]);
+code_table_schema!(RecordCreator, "Creator of a record (レコード作成者)", [
+    MedicalExpert, Patient, Other, Unknown,
+]);
+code_table_schema!(OutputCategory, "Output category (出力区分)", [
+    ToPatient, FromPatinet,
+]);
+code_table_schema!(Gender, "Gender of the patient (患者性別)", [
+    Male, Female,
+]);
+code_table_schema!(SpecialPatientNoteCategory, "Category of a special patient note (患者特記種別)", [
+    Allergy, AdverseEvent, PastHistory, Other,
+]);
+code_table_schema!(DrugCodeType, "Type of drug code (薬品コード種別)", [
+    None, Receipt, Mhlw, Yj, Hot,
+]);
+code_table_schema!(UsageCodeType, "Type of usage code (用法コード種別)", [
+    None, Jami,
+]);
+code_table_schema!(ProvidedInformationType, "Type of provided information (提供情報種別)", [
+    AdverseEventInHospital, PostDischargeCare, Other,
+]);
+
+impl JsonSchema for Date {
+    fn schema_name() -> Cow<'static, str> {
+        "Date".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "type": "string",
+            "description": "Date as written in the record: YYYYMMDD in seireki or GYYMMDD in wareki, \
+                where G is the code of the era, e.g. \"20200501\" or \"R020501\". A date of an OTC \
+                drug or a memo may be known only to the month (YYYYMM, GYYMM) or to the year (YYYY, GYY).",
+            "pattern": r"^(\d{4}(\d{2}(\d{2})?)?|[A-Z]\d{2}(\d{2}(\d{2})?)?)$",
+        })
+    }
+}
+
+impl JsonSchema for GengoYear {
+    fn schema_name() -> Cow<'static, str> {
+        "GengoYear".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "type": "string",
+            "description": "Year of an era as GYY, where G is the code of the era, e.g. \"R02\"",
+            "pattern": r"^[A-Z]\d{2,}$",
+        })
+    }
+}
+
+/// JSON Schema (draft 2020-12) of a `MedicineNotebook` serialized with the
+/// `serde` feature, with every block and record in `$defs`.
+pub fn notebook_json_schema() -> serde_json::Value {
+    SchemaGenerator::new(SchemaSettings::draft2020_12())
+        .into_root_schema_for::<MedicineNotebook>()
+        .to_value()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    // Every kind of record, for a schema to cover.
+    const SAMPLE: &str = "JAHISTC07,1\r\n\
+        1,山田太郎,1,19700101,1000001,東京都千代田区,03-0000-0000,,A,60.5,ﾔﾏﾀﾞﾀﾛｳ,追加\r\n\
+        2,1,卵アレルギー,1\r\n\
+        3,ロキソニン,202001,R02,2\r\n\
+        4,メモです,,2\r\n\
+        950,未定義\r\n\
+        5,R020401,1\r\n\
+        11,テスト薬局,13,4,1234567,1000001,東京都,03-1111-1111,1\r\n\
+        15,薬剤師花子,03-1111-1111,1\r\n\
+        51,テスト病院,13,1,7654321,1\r\n\
+        55,医師一郎,内科,1\r\n\
+        201,1,ロキソプロフェン錠60mg,1,錠,2,620098801,1\r\n\
+        281,1,補足,1\r\n\
+        291,1,注意,1\r\n\
+        301,1,1日3回毎食後,7,日分,1,1,,1\r\n\
+        311,1,用法補足,1\r\n\
+        391,1,処方注意,1\r\n\
+        401,服用注意,1\r\n\
+        411,提供情報,30,1\r\n\
+        421,残薬,1\r\n\
+        501,備考,1\r\n\
+        601,患者記入,20200402\r\n\
+        701,かかりつけ,テスト薬局,03,20200101,,1";
+
+    /// Checks that the values of a code table in the schema are exactly the
+    /// names its variants are serialized as.
+    macro_rules! assert_code_table {
+        ($schema:expr, $name:ident) => {{
+            let consts: Vec<serde_json::Value> = $schema["$defs"][stringify!($name)]["oneOf"]
+                .as_array()
+                .expect(stringify!($name))
+                .iter()
+                .map(|value| value["const"].clone())
+                .collect();
+            for value in &consts {
+                let _: $name = serde_json::from_value(value.clone()).expect(&value.to_string());
+            }
+            let variants: Vec<serde_json::Value> = (0..100)
+                .filter_map(|n| $name::try_from(n).ok())
+                .map(|variant| serde_json::to_value(variant).unwrap())
+                .collect();
+            assert_eq!(consts, variants, stringify!($name));
+        }};
+    }
+
+    #[test]
+    fn lists_every_variant_of_the_code_tables() {
+        let schema = notebook_json_schema();
+        assert_code_table!(schema, Prefecture);
+        assert_code_table!(schema, FeeTable);
+        assert_code_table!(schema, DosageForm);
+        assert_code_table!(schema, RecordCreator);
+        assert_code_table!(schema, OutputCategory);
+        assert_code_table!(schema, Gender);
+        assert_code_table!(schema, SpecialPatientNoteCategory);
+        assert_code_table!(schema, DrugCodeType);
+        assert_code_table!(schema, UsageCodeType);
+        assert_code_table!(schema, ProvidedInformationType);
+    }
+
+    #[test]
+    fn accepts_a_serialized_notebook() {
+        let notebook: MedicineNotebook = SAMPLE.parse().unwrap();
+        let instance = serde_json::to_value(&notebook).unwrap();
+        let validator = jsonschema::validator_for(&notebook_json_schema()).unwrap();
+        let errors: Vec<String> = validator.iter_errors(&instance).map(|e| format!("{} at {}", e, e.instance_path)).collect();
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let mut invalid = instance.clone();
+        invalid["patient"]["gender"] = serde_json::json!("Unknown");
+        assert!(!validator.is_valid(&invalid));
+    }
+}
@@ -0,0 +1,75 @@
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use crate::jahis::*;
+
+impl Serialize for Date {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_code())
+    }
+}
+
+impl<'de> Deserialize<'de> for Date {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for GengoYear {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_code())
+    }
+}
+
+impl<'de> Deserialize<'de> for GengoYear {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "JAHISTC07,2,追加\r\n\
+        1,山田太郎,2,S450101,1000001,東京都千代田区,03-0000-0000,,A,60.5,ﾔﾏﾀﾞﾀﾛｳ\r\n\
+        2,1,卵アレルギー,1\r\n\
+        3,ロキソニン,202001,R02,2\r\n\
+        4,メモです,2020,2,追加1,追加2\r\n\
+        5,R020401,1\r\n\
+        11,テスト薬局,13,4,1234567,1000001,東京都,03-1111-1111,1\r\n\
+        201,1,ロキソプロフェン錠60mg,1,錠,2,620098801,1\r\n\
+        301,1,1日3回毎食後,7,日分,1,1,,1\r\n\
+        950,未定義";
+
+    #[test]
+    fn round_trips_through_json() {
+        let notebook: MedicineNotebook = SAMPLE.parse().unwrap();
+        let json = serde_json::to_string(&notebook).unwrap();
+        let deserialized: MedicineNotebook = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, notebook);
+        assert_eq!(deserialized.to_code(), SAMPLE);
+
+        let value = serde_json::to_value(&notebook).unwrap();
+        assert_eq!(value["version"]["output_category"], "FromPatient");
+        assert_eq!(value["patient"]["day_of_birth"], "S450101");
+        assert_eq!(value["otc_drugs"][0]["start_date"], "202001");
+        assert_eq!(value["otc_drugs"][0]["end_date"], "R02");
+        assert_eq!(value["memos"][0]["created_at"], "2020");
+        assert_eq!(value["memos"][0]["extra"], serde_json::json!(["追加1", "追加2"]));
+        assert!(value["patient"].get("extra").is_none());
+        assert!(value.get("unknown_records").is_none());
+        assert_eq!(value["dispensing_information"][0]["unknown_records"][0]["position"], 4);
+    }
+
+    #[test]
+    fn reads_json_without_empty_lists_and_with_the_old_name() {
+        let notebook: MedicineNotebook = SAMPLE.parse().unwrap();
+        let mut value = serde_json::to_value(&notebook).unwrap();
+        value["version"]["output_category"] = serde_json::json!("FromPatinet");
+        value["memos"][0].as_object_mut().unwrap().remove("extra");
+        let deserialized: MedicineNotebook = serde_json::from_value(value).unwrap();
+        assert_eq!(deserialized.version.output_category, OutputCategory::FromPatinet);
+        assert!(deserialized.memos[0].extra.is_empty());
+    }
+}
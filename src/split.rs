@@ -0,0 +1,305 @@
+use std::collections::BTreeMap;
+use crate::jahis::*;
+use crate::encoding::TextEncoding;
+use crate::escape::EscapePolicy;
+
+/// Splits the code of a notebook into parts which are at most `max_bytes`
+/// long in `encoding`, e.g. to fit the capacity of a QR code.
+///
+/// Each part starts with the version record followed by a split control
+/// record (911) holding `data_id`, the number of parts and its own number,
+/// and the records are never split in the middle. A split control record
+/// already in the code is replaced. A code which fits in `max_bytes` is
+/// returned as the only part as it is.
+pub fn split_code(code: &str, data_id: &str, max_bytes: usize, encoding: TextEncoding) -> Result<Vec<String>, Error> {
+    if encoding.encode(code)?.len() <= max_bytes {
+        return Ok(vec![code.to_string()]);
+    }
+    let mut lines = code.lines();
+    let version = lines.next().unwrap_or_default();
+    let body: Vec<&str> = lines.filter(|line| !line.starts_with("911,")).collect();
+
+    // The length of the split control record depends on the number of parts,
+    // so pack again until the number of digits does not change.
+    let mut total = 2;
+    loop {
+        let control = SplitControlRecord::new(data_id.to_string(), total, total);
+        let header = format!("{}\r\n{}", version, control.to_code_with(EscapePolicy::Reject)?);
+        let parts = pack(&body, encoding.encode(&header)?.len(), max_bytes, encoding)?;
+        let n = parts.len() as u32;
+        if n.to_string().len() == total.to_string().len() {
+            return parts.iter().enumerate().map(|(i, lines)| {
+                let control = SplitControlRecord::new(data_id.to_string(), n, i as u32 + 1);
+                Ok(format!("{}\r\n{}\r\n{}",
+                    version, control.to_code_with(EscapePolicy::Reject)?, lines.join("\r\n")))
+            }).collect();
+        }
+        total = n;
+    }
+}
+
+/// Packs lines into groups which fit in `max_bytes` after a header of
+/// `header_len` bytes, counting a CRLF before each line.
+fn pack<'a>(lines: &[&'a str], header_len: usize, max_bytes: usize, encoding: TextEncoding) -> Result<Vec<Vec<&'a str>>, Error> {
+    let mut parts: Vec<Vec<&str>> = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    let mut len = header_len;
+    for line in lines {
+        let line_len = encoding.encode(line)?.len() + 2;
+        if header_len + line_len > max_bytes {
+            return Err(Error::InvalidArgument(
+                format!("Cannot fit a record line in {} bytes, got \"{}\"", max_bytes, line)
+            ));
+        }
+        if len + line_len > max_bytes {
+            parts.push(std::mem::take(&mut current));
+            len = header_len;
+        }
+        current.push(line);
+        len += line_len;
+    }
+    if !current.is_empty() || parts.is_empty() {
+        parts.push(current);
+    }
+    Ok(parts)
+}
+
+/// Reassembles the parts of a split notebook, given in any order.
+///
+/// A part given twice is accepted when both copies are the same, as happens
+/// when the same QR code is scanned again, and is listed in `duplicates`.
+/// A part without a split control record is taken as the whole notebook.
+#[derive(Debug, Clone, Default)]
+pub struct SplitAssembler {
+    version: Option<String>,
+    data_id: Option<String>,
+    total: u32,
+    parts: BTreeMap<u32, String>,
+    duplicates: Vec<u32>,
+}
+
+impl SplitAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a part; an error is returned if it does not belong to the same
+    /// data as the parts already added.
+    pub fn add(&mut self, part: &str) -> Result<(), Error> {
+        let mut lines = part.lines();
+        let version = lines.next().unwrap_or_default();
+        let mut body: Vec<&str> = lines.collect();
+        let control = match body.first() {
+            Some(line) if line.starts_with("911,") => {
+                let control: SplitControlRecord = parse_line(line, 2)?;
+                body.remove(0);
+                control
+            },
+            _ => SplitControlRecord::default(),
+        };
+        if control.total == 0 || control.index == 0 || control.index > control.total {
+            return Err(Error::InvalidArgument(
+                format!("Cannot use part {} of {} parts", control.index, control.total)
+            ));
+        }
+
+        match &self.data_id {
+            Some(data_id) if *data_id != control.data_id || self.total != control.total =>
+                return Err(Error::InvalidArgument(
+                    format!("Cannot join a part of split data \"{}\" of {} parts with data \"{}\" of {} parts",
+                        control.data_id, control.total, data_id, self.total)
+                )),
+            Some(_) => {},
+            None => {
+                self.data_id = Some(control.data_id.clone());
+                self.total = control.total;
+            },
+        }
+        match &self.version {
+            Some(v) if v != version => return Err(Error::InvalidArgument(
+                format!("Cannot join a part of version \"{}\" with version \"{}\"", version, v)
+            )),
+            Some(_) => {},
+            None => self.version = Some(version.to_string()),
+        }
+
+        let body = body.join("\r\n");
+        match self.parts.get(&control.index) {
+            Some(existing) if *existing == body => self.duplicates.push(control.index),
+            Some(_) => return Err(Error::DuplicateSplitPart {
+                data_id: control.data_id,
+                index: control.index,
+            }),
+            None => {
+                self.parts.insert(control.index, body);
+            },
+        }
+        Ok(())
+    }
+
+    /// Data ID of the parts added so far; it is empty for a notebook which
+    /// is not split.
+    pub fn data_id(&self) -> Option<&str> {
+        self.data_id.as_deref()
+    }
+
+    /// Number of parts, or 0 before any part is added.
+    pub fn total(&self) -> u32 {
+        self.total
+    }
+
+    /// Numbers of the parts which have not been added yet.
+    pub fn missing(&self) -> Vec<u32> {
+        (1..=self.total).filter(|i| !self.parts.contains_key(i)).collect()
+    }
+
+    /// Numbers of the parts which were added more than once.
+    pub fn duplicates(&self) -> &[u32] {
+        &self.duplicates
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.total > 0 && self.missing().is_empty()
+    }
+
+    /// Joins the parts into the code of the whole notebook.
+    pub fn to_code(&self) -> Result<String, Error> {
+        if !self.is_complete() {
+            return Err(Error::MissingSplitParts {
+                data_id: self.data_id.clone().unwrap_or_default(),
+                missing: if self.total == 0 { vec![1] } else { self.missing() },
+            });
+        }
+        let mut lines = vec![self.version.as_deref().unwrap_or_default()];
+        lines.extend(self.parts.values().filter(|body| !body.is_empty()).map(String::as_str));
+        Ok(lines.join("\r\n"))
+    }
+
+    pub fn into_notebook(self) -> Result<MedicineNotebook, Error> {
+        self.to_code()?.parse()
+    }
+}
+
+impl MedicineNotebook {
+    /// Reassembles a notebook from the parts of its code, given in any order.
+    pub fn from_split_codes<I, S>(parts: I) -> Result<Self, Error>
+            where I: IntoIterator<Item = S>, S: AsRef<str> {
+        let mut assembler = SplitAssembler::new();
+        for part in parts {
+            assembler.add(part.as_ref())?;
+        }
+        assembler.into_notebook()
+    }
+
+    /// Splits the code into parts of at most `max_bytes` bytes in `encoding`.
+    /// A field containing a comma or a line break is an error.
+    pub fn to_split_codes(&self, data_id: &str, max_bytes: usize, encoding: TextEncoding) -> Result<Vec<String>, Error> {
+        split_code(&self.to_code_with(EscapePolicy::Reject)?, data_id, max_bytes, encoding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::SAMPLE;
+
+    fn assemble<'a>(parts: impl IntoIterator<Item = &'a String>) -> Result<SplitAssembler, Error> {
+        let mut assembler = SplitAssembler::new();
+        for part in parts {
+            assembler.add(part)?;
+        }
+        Ok(assembler)
+    }
+
+    #[test]
+    fn splits_into_parts_which_fit() {
+        let notebook: MedicineNotebook = SAMPLE.parse().unwrap();
+        for encoding in [TextEncoding::Utf8, TextEncoding::ShiftJis] {
+            let parts = notebook.to_split_codes("ID0001", 160, encoding).unwrap();
+            assert!(parts.len() > 2);
+            for (i, part) in parts.iter().enumerate() {
+                assert!(encoding.encode(part).unwrap().len() <= 160, "{}", part);
+                let control = format!("JAHISTC07,1\r\n911,ID0001,{},{}\r\n", parts.len(), i + 1);
+                assert!(part.starts_with(&control), "{}", part);
+            }
+            assert_eq!(MedicineNotebook::from_split_codes(parts.iter().rev()).unwrap(), notebook);
+        }
+
+        let parts = notebook.to_split_codes("ID0001", SAMPLE.len(), TextEncoding::Utf8).unwrap();
+        assert_eq!(parts, vec![SAMPLE.to_string()]);
+        assert!(notebook.to_split_codes("ID0001", 60, TextEncoding::Utf8).is_err());
+    }
+
+    #[test]
+    fn counts_the_digits_of_ten_parts_or_more() {
+        // Each memo line is 20 bytes with its CRLF, so that a part holds two
+        // of them after a split control record of "2,2", but only one after
+        // one of two-digit numbers.
+        let mut code = "JAHISTC07,1\r\n1,YAMADA,1,19700101,,,,,,,".to_string();
+        for i in 0..20 {
+            code.push_str(&format!("\r\n4,memo{:>09},,2", i));
+        }
+        let parts = split_code(&code, "ID", 63, TextEncoding::Utf8).unwrap();
+        assert_eq!(parts.len(), 21);
+        for (i, part) in parts.iter().enumerate() {
+            assert!(part.len() <= 63, "{}", part);
+            assert!(part.starts_with(&format!("JAHISTC07,1\r\n911,ID,21,{}\r\n", i + 1)), "{}", part);
+        }
+        assert_eq!(assemble(&parts).unwrap().to_code().unwrap(), code);
+    }
+
+    #[test]
+    fn accepts_a_part_scanned_twice() {
+        let notebook: MedicineNotebook = SAMPLE.parse().unwrap();
+        let parts = notebook.to_split_codes("ID0001", 160, TextEncoding::Utf8).unwrap();
+        let mut assembler = assemble(&parts).unwrap();
+        assembler.add(&parts[1]).unwrap();
+        assert_eq!(assembler.duplicates(), &[2]);
+        assert_eq!(assembler.into_notebook().unwrap(), notebook);
+
+        let mut assembler = assemble(&parts).unwrap();
+        let changed = format!("{}\r\n401,服用注意,1", parts[1]);
+        assert_eq!(assembler.add(&changed), Err(Error::DuplicateSplitPart {
+            data_id: "ID0001".to_string(),
+            index: 2,
+        }));
+    }
+
+    #[test]
+    fn rejects_parts_of_other_data() {
+        let notebook: MedicineNotebook = SAMPLE.parse().unwrap();
+        let parts = notebook.to_split_codes("ID0001", 160, TextEncoding::Utf8).unwrap();
+        let other = notebook.to_split_codes("ID0002", 160, TextEncoding::Utf8).unwrap();
+        let mut assembler = assemble(&parts[..1]).unwrap();
+        assert!(matches!(assembler.add(&other[1]), Err(Error::InvalidArgument(_))));
+        let more = notebook.to_split_codes("ID0001", 300, TextEncoding::Utf8).unwrap();
+        assert_ne!(more.len(), parts.len());
+        assert!(matches!(assembler.add(&more[1]), Err(Error::InvalidArgument(_))));
+        assert_eq!(assembler.data_id(), Some("ID0001"));
+        assert_eq!(assembler.total(), parts.len() as u32);
+    }
+
+    #[test]
+    fn lists_the_missing_parts() {
+        let notebook: MedicineNotebook = SAMPLE.parse().unwrap();
+        let parts = notebook.to_split_codes("ID0001", 160, TextEncoding::Utf8).unwrap();
+        let mut assembler = SplitAssembler::new();
+        assert!(assembler.missing().is_empty());
+        assert!(!assembler.is_complete());
+
+        assembler.add(&parts[1]).unwrap();
+        let missing: Vec<u32> = (1..=parts.len() as u32).filter(|&i| i != 2).collect();
+        assert_eq!(assembler.missing(), missing);
+        assert!(!assembler.is_complete());
+        assert_eq!(assembler.to_code(), Err(Error::MissingSplitParts {
+            data_id: "ID0001".to_string(),
+            missing,
+        }));
+
+        for part in &parts {
+            assembler.add(part).unwrap();
+        }
+        assert!(assembler.missing().is_empty());
+        assert!(assembler.is_complete());
+    }
+}
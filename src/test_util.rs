@@ -0,0 +1,16 @@
+//! Data shared by the tests of several modules.
+
+/// A notebook of a patient with a memo and two dispensing blocks of one RP
+/// each, the first one with the レセプト電算 code of its drug and the second
+/// one with the YJ code.
+pub(crate) const SAMPLE: &str = "JAHISTC07,1\r\n\
+    1,山田太郎,1,19700101,1000001,東京都千代田区,03-0000-0000,,A,60.5,ﾔﾏﾀﾞﾀﾛｳ\r\n\
+    4,メモです,,2\r\n\
+    5,20200401,1\r\n\
+    11,テスト薬局,13,4,1234567,1000001,東京都,03-1111-1111,1\r\n\
+    201,1,ロキソプロフェン錠60mg,1,錠,2,620098801,1\r\n\
+    301,1,1日3回毎食後,7,日分,1,1,,1\r\n\
+    5,20200501,1\r\n\
+    11,テスト薬局,13,4,1234567,1000001,東京都,03-1111-1111,1\r\n\
+    201,1,ムコスタ錠100mg,3,錠,4,2329021F1021,1\r\n\
+    301,1,1日3回毎食後,7,日分,1,,,1";
@@ -0,0 +1,403 @@
+// 電子レセプト (レセプト電算処理システム) 記録条件仕様 調剤用
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use crate::jahis::*;
+use crate::encoding::TextEncoding;
+use crate::fields::Fields;
+use crate::prescription::quantity_unit;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A line of a UKE file which `UkeImporter` could not fully represent
+pub struct UkeImportIssue {
+    pub line: usize, // 1-based line number
+    pub message: String,
+}
+
+impl fmt::Display for UkeImportIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+/// Report of what `UkeImporter::import` left out of the notebooks
+pub struct UkeImportReport {
+    pub issues: Vec<UkeImportIssue>,
+}
+
+impl UkeImportReport {
+    /// Whether the whole file is represented in the notebooks.
+    pub fn is_complete(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    fn push(&mut self, line: usize, message: String) {
+        self.issues.push(UkeImportIssue {line, message});
+    }
+}
+
+impl fmt::Display for UkeImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} issues", self.issues.len())?;
+        for issue in &self.issues {
+            write!(f, "\n{}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a UKE date, GYYMMDD where G is the number of the era (1: 明治,
+/// 2: 大正, 3: 昭和, 4: 平成, 5: 令和), or YYYYMMDD.
+pub fn parse_uke_date(s: &str) -> Result<Date, Error> {
+    let invalid = || Error::InvalidArgument(format!("Cannot convert str to UKE date, got \"{}\"", s));
+    if !s.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    match s.len() {
+        7 => {
+            let code = match &s[0..1] {
+                "1" => 'M',
+                "2" => 'T',
+                "3" => 'S',
+                "4" => 'H',
+                "5" => 'R',
+                _ => return Err(invalid()),
+            };
+            Date::new_wareki(GengoYear::new(code, s[1..3].parse()?), s[3..5].parse()?, s[5..7].parse()?)
+        },
+        8 => Date::new_seireki(s[0..4].parse()?, s[4..6].parse()?, s[6..8].parse()?),
+        _ => Err(invalid()),
+    }
+}
+
+/// Maps a 剤形コード of UKE to the 剤形区分 of a notebook. The codes of both
+/// tables are numbered alike, but UKE may write them with a leading zero.
+fn uke_dosage_form(code: &str) -> Option<DosageForm> {
+    match code.parse::<u32>().ok()? {
+        1 => Some(DosageForm::OralAdministration), // 内服
+        2 => Some(DosageForm::Drop), // 内滴
+        3 => Some(DosageForm::Potion), // 屯服
+        4 => Some(DosageForm::Injection), // 注射
+        5 => Some(DosageForm::ExternalUse), // 外用
+        6 => Some(DosageForm::Infusodecoction), // 浸煎
+        7 => Some(DosageForm::Decoction), // 湯
+        9 => Some(DosageForm::Material), // 材料
+        10 => Some(DosageForm::Other), // その他
+        _ => None,
+    }
+}
+
+/// A 剤 of a レセプト, with its drugs and the days it was dispensed
+struct Preparation {
+    number: u32, // 剤番号
+    line: usize,
+    usage: UsageRecord,
+    drugs: Vec<DrugRecord>,
+    dates: Vec<Date>,
+}
+
+/// A patient of the file and the dispensing information of the レセプト read
+/// so far
+struct Patient {
+    notebook: MedicineNotebook,
+    preparations: Vec<Preparation>,
+}
+
+impl Patient {
+    /// Moves the 剤 of the current レセプト into the dispensing information
+    /// blocks of their days.
+    fn flush(&mut self, pharmacy: &PharmacyRecord, report: &mut UkeImportReport) {
+        for preparation in self.preparations.drain(..) {
+            if preparation.dates.is_empty() {
+                report.push(preparation.line, format!("剤 {} has no dispensing date and was left out", preparation.number));
+                continue;
+            }
+            for date in preparation.dates {
+                let blocks = &mut self.notebook.dispensing_information;
+                let i = match blocks.iter().position(|b| b.date.created_at == date && &b.pharmacy == pharmacy) {
+                    Some(i) => i,
+                    None => {
+                        let mut block = DispensingInformationBlock::new(
+                            DateRecord::new(date, RecordCreator::MedicalExpert), pharmacy.clone());
+                        block.prescriptions.push(PrescriptionBlock::new());
+                        blocks.push(block);
+                        blocks.len() - 1
+                    },
+                };
+                let prescription = &mut blocks[i].prescriptions[0];
+                let rp_number = prescription.rps.len() as u32 + 1;
+                let mut rp = RpBlock::new(UsageRecord {rp_number, .. preparation.usage.clone()});
+                rp.drugs = preparation.drugs.iter()
+                    .map(|drug| DrugBlock::new(DrugRecord {rp_number, .. drug.clone()}))
+                    .collect();
+                prescription.rps.push(rp);
+            }
+        }
+    }
+}
+
+/// Builds medicine notebooks from a 調剤レセプト file (UKE) of レセプト電算処理
+/// システム.
+///
+/// A UKE file has no names of drugs and usages, only their レセプト電算
+/// codes, so the names are looked up in the tables given by `drug` and
+/// `usage`, e.g. from 医薬品マスター.
+///
+/// The fields read are:
+///
+/// - IR (薬局): 2 都道府県, 3 点数表, 4 薬局コード, 6 薬局名称, 9 電話番号
+/// - RE (レセプト共通): 4 氏名, 5 男女区分, 6 生年月日
+/// - SH (処方基本): 1 剤番号, 2 用法コード, 3 用法名称, 4 剤形コード, 5 調剤数量
+///   (a 剤形コード not in the 剤形区分 of a notebook is reported and left out)
+/// - CZ (調剤): 1 剤番号, 2 調剤年月日
+/// - IY (医薬品): 1 剤番号, 2 医薬品コード, 3 使用量
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct UkeImporter {
+    drugs: HashMap<String, (String, String)>, // 医薬品コード -> (名称, 単位)
+    usages: HashMap<String, String>, // 用法コード -> 名称
+}
+
+impl UkeImporter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the name and the unit of a drug code.
+    pub fn drug(mut self, code: &str, name: &str, unit: &str) -> Self {
+        self.drugs.insert(code.to_string(), (name.to_string(), unit.to_string()));
+        self
+    }
+
+    /// Sets the name of a usage code.
+    pub fn usage(mut self, code: &str, name: &str) -> Self {
+        self.usages.insert(code.to_string(), name.to_string());
+        self
+    }
+
+    pub fn import_bytes(&self, bytes: &[u8], encoding: TextEncoding) -> Result<(Vec<MedicineNotebook>, UkeImportReport), Error> {
+        self.import(&encoding.decode(bytes)?)
+    }
+
+    /// Reads a UKE file, which is usually in Shift_JIS.
+    pub fn import_sjis_bytes(&self, bytes: &[u8]) -> Result<(Vec<MedicineNotebook>, UkeImportReport), Error> {
+        self.import_bytes(bytes, TextEncoding::ShiftJis)
+    }
+
+    /// Builds one notebook for each patient of the file, with a dispensing
+    /// information block for each day a drug was dispensed to them.
+    ///
+    /// The レセプト of a patient are merged by the name, the gender and the
+    /// date of birth. Each 剤 becomes an RP, whose drugs have their
+    /// レセプト電算 codes; a code missing from the tables keeps the code as
+    /// its name and is listed in the report, as are the lines which cannot
+    /// be placed, such as a 剤 without a dispensing date. The insurance,
+    /// cost and comment records are skipped.
+    ///
+    /// Fails on a line which cannot be read, or on a patient before the
+    /// pharmacy record.
+    pub fn import(&self, s: &str) -> Result<(Vec<MedicineNotebook>, UkeImportReport), Error> {
+        let mut report = UkeImportReport::default();
+        let mut pharmacy: Option<PharmacyRecord> = None;
+        let mut patients: Vec<Patient> = Vec::new();
+        let mut current: Option<usize> = None;
+
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim_end_matches(['\r', '\x1a']);
+            if line.is_empty() {
+                continue;
+            }
+            let f = Fields::new(i + 1, "UKE", line);
+            match f.cols[0] {
+                "IR" => {
+                    if let (Some(n), Some(pharmacy)) = (current.take(), &pharmacy) {
+                        patients[n].flush(pharmacy, &mut report);
+                    }
+                    pharmacy = Some(PharmacyRecord::new(
+                        f.get(6).unwrap_or_default(),
+                        f.parse::<u32>(2, "都道府県")?.map(Prefecture::try_from).transpose()?,
+                        f.parse(3, "点数表")?,
+                        f.get(4),
+                        None,
+                        None,
+                        f.get(9),
+                        RecordCreator::MedicalExpert,
+                    ));
+                },
+                "RE" => {
+                    let pharmacy = pharmacy.as_ref().ok_or_else(|| Error::MissingRequiredRecord(ParseError {
+                        line: Some(i + 1),
+                        expected: "UKE record IR before record RE".to_string(),
+                        found: line.to_string(),
+                        .. Default::default()
+                    }))?;
+                    if let Some(n) = current.take() {
+                        patients[n].flush(pharmacy, &mut report);
+                    }
+                    let patient = PatientRecord::new(
+                        f.require(4, "氏名")?,
+                        f.require(5, "男女区分")?,
+                        f.parse_with(6, "生年月日", parse_uke_date)?.ok_or_else(|| f.missing(6, "生年月日"))?,
+                    );
+                    let n = match patients.iter().position(|p| {
+                        let q = &p.notebook.patient;
+                        q.name == patient.name && q.gender == patient.gender && q.day_of_birth == patient.day_of_birth
+                    }) {
+                        Some(n) => n,
+                        None => {
+                            patients.push(Patient {notebook: MedicineNotebook::new(patient), preparations: Vec::new()});
+                            patients.len() - 1
+                        },
+                    };
+                    current = Some(n);
+                },
+                "SH" => {
+                    let patient = match current {
+                        Some(n) => &mut patients[n],
+                        None => {
+                            report.push(i + 1, "record SH outside a レセプト was left out".to_string());
+                            continue;
+                        },
+                    };
+                    let number: u32 = f.require(1, "剤番号")?;
+                    let usage_code = f.get(2);
+                    let name = match f.get(3).or_else(|| usage_code.as_ref().and_then(|code| self.usages.get(code)).cloned()) {
+                        Some(name) => name,
+                        None => {
+                            match &usage_code {
+                                Some(code) => report.push(i + 1, format!("no name of usage code {:?}", code)),
+                                None => report.push(i + 1, format!("剤 {} has no usage", number)),
+                            }
+                            usage_code.unwrap_or_default()
+                        },
+                    };
+                    let dosage_form = match f.get(4) {
+                        Some(code) => {
+                            let dosage_form = uke_dosage_form(&code);
+                            if dosage_form.is_none() {
+                                report.push(i + 1, format!("剤 {} has the unknown 剤形コード {:?}, which was left out", number, code));
+                            }
+                            dosage_form
+                        },
+                        None => None,
+                    };
+                    let usage = UsageRecord::new(number, name, f.parse(5, "調剤数量")?,
+                        quantity_unit(dosage_form), dosage_form, None, None, RecordCreator::MedicalExpert);
+                    patient.preparations.push(Preparation {number, line: i + 1, usage, drugs: Vec::new(), dates: Vec::new()});
+                },
+                "CZ" | "IY" => {
+                    let number: u32 = f.require(1, "剤番号")?;
+                    let preparation = match current.and_then(|n| patients[n].preparations.iter_mut().find(|p| p.number == number)) {
+                        Some(preparation) => preparation,
+                        None => {
+                            report.push(i + 1, format!("record {} of 剤 {} without its record SH was left out", f.cols[0], number));
+                            continue;
+                        },
+                    };
+                    if f.cols[0] == "CZ" {
+                        let date = f.parse_with(2, "調剤年月日", parse_uke_date)?.ok_or_else(|| f.missing(2, "調剤年月日"))?;
+                        if !preparation.dates.contains(&date) {
+                            preparation.dates.push(date);
+                        }
+                    } else {
+                        let code: String = f.require(2, "医薬品コード")?;
+                        let (name, unit) = match self.drugs.get(&code) {
+                            Some(drug) => drug.clone(),
+                            None => {
+                                report.push(i + 1, format!("no name of drug code {:?}", code));
+                                (code.clone(), "".to_string())
+                            },
+                        };
+                        preparation.drugs.push(DrugRecord::new(number, name, f.get(3).unwrap_or_default(), unit,
+                            DrugCodeType::Receipt, Some(code), RecordCreator::MedicalExpert));
+                    }
+                },
+                "HO" | "KO" | "KH" | "SN" | "JD" | "MF" | "TO" | "CO" | "KI" | "TK" | "SJ" | "GO" => {},
+                id => report.push(i + 1, format!("unsupported record {} was left out", id)),
+            }
+        }
+        if let (Some(n), Some(pharmacy)) = (current, &pharmacy) {
+            patients[n].flush(pharmacy, &mut report);
+        }
+
+        let notebooks = patients.into_iter().map(|p| {
+            let mut notebook = p.notebook;
+            notebook.dispensing_information.sort_by_key(|b| b.date.created_at);
+            notebook
+        }).collect();
+        Ok((notebooks, report))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "IR,0,13,4,1234567,,テスト薬局,,,03-1111-1111\r\n\
+        RE,1,4127,506,山田太郎,1,3450101\r\n\
+        HO,06130011,12345,67\r\n\
+        SH,1,1013044400000000,,01,7\r\n\
+        CZ,1,5060401\r\n\
+        IY,1,620098801,3\r\n\
+        RE,2,4127,505,山田太郎,1,3450101\r\n\
+        SH,1,1013044400000000,,1,14\r\n\
+        CZ,1,5060501\r\n\
+        IY,1,620098801,3\r\n\
+        IY,1,620000000,1\r\n\
+        RE,3,4127,506,佐藤花子,2,4010201\r\n\
+        SH,1,9999999999999999,,12,5\r\n\
+        IY,1,620098801,1\r\n\
+        GO,3,100\x1a";
+
+    #[test]
+    fn imports_a_notebook_for_each_patient() {
+        let importer = UkeImporter::new()
+            .drug("620098801", "ロキソプロフェンナトリウム錠60mg", "錠")
+            .usage("1013044400000000", "1日3回毎食後");
+        let (notebooks, report) = importer.import(SAMPLE).unwrap();
+
+        assert_eq!(notebooks.len(), 2);
+        let notebook = &notebooks[0];
+        assert_eq!(notebook.patient.name, "山田太郎");
+        assert_eq!(notebook.patient.day_of_birth, Date::new_wareki(GengoYear::Showa(45), 1, 1).unwrap());
+        let dates: Vec<Date> = notebook.dispensing_information.iter().map(|b| b.date.created_at).collect();
+        assert_eq!(dates, vec![Date::new_wareki(GengoYear::Reiwa(6), 4, 1).unwrap(), Date::new_wareki(GengoYear::Reiwa(6), 5, 1).unwrap()]);
+        assert!(notebook.dispensing_information.iter().all(|b| b.pharmacy.name == "テスト薬局"));
+
+        let rp = &notebook.dispensing_information[0].prescriptions[0].rps[0];
+        assert_eq!(rp.usage.name, "1日3回毎食後");
+        assert_eq!(rp.usage.dosage_form, Some(DosageForm::OralAdministration));
+        assert_eq!(rp.usage.unit.as_deref(), Some("日分"));
+        assert_eq!(rp.drugs[0].drug.name, "ロキソプロフェンナトリウム錠60mg");
+        assert_eq!(rp.drugs[0].drug.drug_code.as_deref(), Some("620098801"));
+
+        // A drug code missing from the table keeps the code as its name.
+        let rp = &notebook.dispensing_information[1].prescriptions[0].rps[0];
+        assert_eq!(rp.drugs[1].drug.name, "620000000");
+
+        // The 剤 without a dispensing date is left out.
+        assert_eq!(notebooks[1].patient.name, "佐藤花子");
+        assert!(notebooks[1].dispensing_information.is_empty());
+
+        let issues: Vec<String> = report.issues.iter().map(|i| i.to_string()).collect();
+        assert_eq!(issues, vec![
+            "line 11: no name of drug code \"620000000\"",
+            "line 13: no name of usage code \"9999999999999999\"",
+            "line 13: 剤 1 has the unknown 剤形コード \"12\", which was left out",
+            "line 13: 剤 1 has no dispensing date and was left out",
+        ]);
+    }
+
+    #[test]
+    fn reports_the_field_which_cannot_be_read() {
+        let s = SAMPLE.replace("山田太郎,1,3450101", "山田太郎,1,3451301");
+        match UkeImporter::new().import(&s) {
+            Err(Error::InvalidRecordLine(e)) => {
+                assert_eq!((e.line, e.field_index, e.field_name), (Some(2), Some(6), Some("生年月日")));
+                assert_eq!(e.to_string(), "line 2: field 6 (生年月日): expected a valid value of UKE record RE, found \"3451301\"");
+            },
+            result => panic!("{:?}", result.map(|(_, report)| report)),
+        }
+    }
+}
@@ -0,0 +1,625 @@
+use std::fmt;
+use std::str::FromStr;
+use crate::jahis::*;
+use crate::encoding::field_length_errors;
+use crate::escape::{EscapePolicy, FORBIDDEN_CHARACTERS};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+/// Severity of a validation finding
+pub enum Severity {
+    Warning, // 警告
+    Error, // エラー
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Warning => write!(f, "warning"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// Rules checked by `MedicineNotebook::validate`
+pub enum ValidationRule {
+    InvalidDate, // 存在しない年月日
+    DateOrder, // 終了年月日が開始年月日より前
+    EmptyRp, // 薬品レコードのないRP
+    RpNumberMismatch, // RP内のRP番号の不一致
+    DuplicateRpNumber, // RP番号の重複
+    NonSequentialRpNumber, // RP番号が連番でない
+    InvalidSplitControl, // 分割番号が最大分割数の範囲外
+    ForbiddenCharacter, // カンマや改行を含む項目
+    FieldTooLong, // 最大バイト数を超える項目
+}
+
+impl ValidationRule {
+    /// Rule ID, which stays the same across releases.
+    pub fn to_code(&self) -> String {
+        self.to_string()
+    }
+
+    pub fn severity(&self) -> Severity {
+        match *self {
+            Self::NonSequentialRpNumber | Self::ForbiddenCharacter => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl fmt::Display for ValidationRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::InvalidDate => write!(f, "invalid-date"),
+            Self::DateOrder => write!(f, "date-order"),
+            Self::EmptyRp => write!(f, "empty-rp"),
+            Self::RpNumberMismatch => write!(f, "rp-number-mismatch"),
+            Self::DuplicateRpNumber => write!(f, "duplicate-rp-number"),
+            Self::NonSequentialRpNumber => write!(f, "non-sequential-rp-number"),
+            Self::InvalidSplitControl => write!(f, "invalid-split-control"),
+            Self::ForbiddenCharacter => write!(f, "forbidden-character"),
+            Self::FieldTooLong => write!(f, "field-too-long"),
+        }
+    }
+}
+
+impl FromStr for ValidationRule {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "invalid-date" => Ok(Self::InvalidDate),
+            "date-order" => Ok(Self::DateOrder),
+            "empty-rp" => Ok(Self::EmptyRp),
+            "rp-number-mismatch" => Ok(Self::RpNumberMismatch),
+            "duplicate-rp-number" => Ok(Self::DuplicateRpNumber),
+            "non-sequential-rp-number" => Ok(Self::NonSequentialRpNumber),
+            "invalid-split-control" => Ok(Self::InvalidSplitControl),
+            "forbidden-character" => Ok(Self::ForbiddenCharacter),
+            "field-too-long" => Ok(Self::FieldTooLong),
+            _ => Err(Error::InvalidArgument(
+                format!("Cannot convert str to ValidationRule, got \"{}\"", s)
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+/// Where a finding is in the notebook; the indices are 0-based
+pub struct Location {
+    pub block: Option<usize>, // 調剤情報
+    pub prescription: Option<usize>, // 処方
+    pub rp: Option<usize>, // RP
+    pub record_number: Option<u32>, // レコード番号
+    pub field_name: Option<&'static str>, // 項目名
+}
+
+impl Location {
+    fn record(record_number: u32, field_name: Option<&'static str>) -> Self {
+        Self {record_number: Some(record_number), field_name, .. Default::default()}
+    }
+
+    fn in_block(mut self, block: usize) -> Self {
+        self.block = Some(block);
+        self
+    }
+
+    fn in_rp(mut self, prescription: usize, rp: usize) -> Self {
+        self.prescription = Some(prescription);
+        self.rp = Some(rp);
+        self
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts: Vec<String> = Vec::new();
+        if let Some(block) = self.block {
+            parts.push(format!("dispensing information {}", block + 1));
+        }
+        if let Some(prescription) = self.prescription {
+            parts.push(format!("prescription {}", prescription + 1));
+        }
+        if let Some(rp) = self.rp {
+            parts.push(format!("RP {}", rp + 1));
+        }
+        match (self.field_name, self.record_number) {
+            (Some(field_name), Some(record_number)) => parts.push(format!("{} of record {}", field_name, record_number)),
+            (None, Some(record_number)) => parts.push(format!("record {}", record_number)),
+            _ => {},
+        }
+        if parts.is_empty() {
+            write!(f, "notebook")
+        } else {
+            write!(f, "{}", parts.join(", "))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A problem found by `MedicineNotebook::validate`
+pub struct Finding {
+    pub rule: ValidationRule,
+    pub severity: Severity,
+    pub location: Location,
+    pub message: String,
+}
+
+impl Finding {
+    fn new(rule: ValidationRule, location: Location, message: String) -> Self {
+        Self {rule, severity: rule.severity(), location, message}
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} [{}] {}: {}", self.severity, self.rule, self.location, self.message)
+    }
+}
+
+impl MedicineNotebook {
+    /// Checks the relationships between the records which parsing does not
+    /// check, such as the RP numbers of an RP and the order of dates.
+    ///
+    /// The notebook does not need to have been parsed, so a notebook built in
+    /// code can be checked before it is written.
+    pub fn validate(&self) -> Vec<Finding> {
+        let mut findings: Vec<Finding> = Vec::new();
+
+        if let Some(record) = &self.split_control {
+            if record.total == 0 || record.index == 0 || record.index > record.total {
+                findings.push(Finding::new(
+                    ValidationRule::InvalidSplitControl,
+                    Location::record(911, Some("分割番号")),
+                    format!("part {} is out of {} parts", record.index, record.total),
+                ));
+            }
+        }
+
+        check_date(&mut findings, &self.patient.day_of_birth, Location::record(1, Some("患者生年月日")), false);
+        for record in &self.otc_drugs {
+            check_period(&mut findings, record.start_date.as_ref(), record.end_date.as_ref(),
+                Location::record(3, None), "服用開始年月日", "服用終了年月日", true);
+        }
+        for record in &self.memos {
+            if let Some(date) = &record.created_at {
+                check_date(&mut findings, date, Location::record(4, Some("メモ入力年月日")), true);
+            }
+        }
+
+        for (i, block) in self.dispensing_information.iter().enumerate() {
+            check_block(&mut findings, block, i);
+        }
+
+        for record in &self.family_pharmacist {
+            check_period(&mut findings, record.start_date.as_ref(), record.end_date.as_ref(),
+                Location::record(701, None), "担当開始年月日", "担当終了年月日", false);
+        }
+
+        check_characters(&mut findings, self);
+        findings.extend(self.check_field_lengths());
+        findings
+    }
+
+    /// Reports every field longer than its limit in Shift_JIS bytes, as
+    /// written by `to_code`.
+    pub fn check_field_lengths(&self) -> Vec<Finding> {
+        let policy = EscapePolicy::FullWidth;
+        let mut lines: Vec<(Option<usize>, String)> = Vec::new();
+        if let Some(record) = &self.split_control {
+            lines.push((None, full_width(record.to_code_with(policy))));
+        }
+        let header = full_width(header_lines(&self.patient, &self.special_patient_notes, &self.otc_drugs,
+            &self.memos, policy));
+        lines.extend(header.into_iter().map(|(_, line)| (None, line)));
+        for (i, block) in self.dispensing_information.iter().enumerate() {
+            let block_lines = full_width(block.code_lines(policy));
+            lines.extend(block_lines.into_iter().map(|line| (Some(i), line)));
+        }
+        lines.extend(self.family_pharmacist.iter().map(|record| (None, record.to_code())));
+
+        let mut findings: Vec<Finding> = Vec::new();
+        for (block, line) in lines {
+            for e in field_length_errors(&line) {
+                if let Error::FieldTooLong {record_number, field_name, max_bytes, bytes} = e {
+                    findings.push(Finding::new(
+                        ValidationRule::FieldTooLong,
+                        Location {block, .. Location::record(record_number, Some(field_name))},
+                        format!("{} bytes is over the limit of {} bytes", bytes, max_bytes),
+                    ));
+                }
+            }
+        }
+        findings
+    }
+}
+
+fn check_block(findings: &mut Vec<Finding>, block: &DispensingInformationBlock, i: usize) {
+    check_date(findings, &block.date.created_at, Location::record(5, Some("調剤等年月日")).in_block(i), false);
+    if let Some(date) = block.from_patient.as_ref().and_then(|r| r.created_at.as_ref()) {
+        check_date(findings, date, Location::record(601, Some("入力年月日")).in_block(i), false);
+    }
+
+    for (j, prescription) in block.prescriptions.iter().enumerate() {
+        let mut previous: Option<u32> = None;
+        let mut seen: Vec<u32> = Vec::new();
+        for (k, rp) in prescription.rps.iter().enumerate() {
+            let rp_number = rp.usage.rp_number;
+            let location = |record_number: u32| {
+                Location::record(record_number, Some("RP番号")).in_block(i).in_rp(j, k)
+            };
+
+            if rp.drugs.is_empty() {
+                findings.push(Finding::new(
+                    ValidationRule::EmptyRp, location(301),
+                    format!("RP {} has no drug record", rp_number),
+                ));
+            }
+            let mut numbers: Vec<(u32, u32)> = Vec::new();
+            for drug in &rp.drugs {
+                numbers.push((201, drug.drug.rp_number));
+                numbers.extend(drug.drug_supplementary.iter().map(|r| (281, r.rp_number)));
+                numbers.extend(drug.drug_notice.iter().map(|r| (291, r.rp_number)));
+            }
+            numbers.extend(rp.usage_supplementary.iter().map(|r| (311, r.rp_number)));
+            numbers.extend(rp.rp_notice.iter().map(|r| (391, r.rp_number)));
+            for (record_number, n) in numbers.into_iter().filter(|&(_, n)| n != rp_number) {
+                findings.push(Finding::new(
+                    ValidationRule::RpNumberMismatch, location(record_number),
+                    format!("RP number {} differs from {} of the usage record", n, rp_number),
+                ));
+            }
+
+            if seen.contains(&rp_number) {
+                findings.push(Finding::new(
+                    ValidationRule::DuplicateRpNumber, location(301),
+                    format!("RP number {} is used more than once", rp_number),
+                ));
+            } else if previous.is_some_and(|p| rp_number != p + 1) {
+                findings.push(Finding::new(
+                    ValidationRule::NonSequentialRpNumber, location(301),
+                    format!("RP number {} does not follow {}", rp_number, previous.unwrap_or_default()),
+                ));
+            }
+            seen.push(rp_number);
+            previous = Some(rp_number);
+        }
+    }
+}
+
+/// Checks that a date exists, and that its day is known unless `partial`,
+/// returning the first and the last days it may be.
+fn check_date(findings: &mut Vec<Finding>, date: &Date, location: Location, partial: bool)
+        -> Option<(chrono::NaiveDate, chrono::NaiveDate)> {
+    let result = if partial {
+        date.first_day().and_then(|first| Ok((first, date.last_day()?)))
+    } else {
+        date.try_to_naivedate().map(|day| (day, day))
+    };
+    match result {
+        Ok(period) => Some(period),
+        Err(e) => {
+            let reason = match e {
+                Error::InvalidDate(reason) => reason,
+                e => e.to_string(),
+            };
+            findings.push(Finding::new(
+                ValidationRule::InvalidDate, location,
+                format!("{} is not a valid date: {}", date.to_code(), reason),
+            ));
+            None
+        },
+    }
+}
+
+/// Checks that the end of a period does not come before its start. A date
+/// known only to the month or the year is before another only if all of
+/// its days are.
+fn check_period(findings: &mut Vec<Finding>, start: Option<&Date>, end: Option<&Date>,
+        location: Location, start_name: &'static str, end_name: &'static str, partial: bool) {
+    let start_period = start.and_then(|date| check_date(findings, date, Location {field_name: Some(start_name), .. location.clone()}, partial));
+    let end_period = end.and_then(|date| check_date(findings, date, Location {field_name: Some(end_name), .. location.clone()}, partial));
+    if let (Some((first, _)), Some((_, last)), Some(start), Some(end)) = (start_period, end_period, start, end) {
+        if last < first {
+            findings.push(Finding::new(
+                ValidationRule::DateOrder, Location {field_name: Some(end_name), .. location},
+                format!("{} comes before {} {}", end, start_name, start),
+            ));
+        }
+    }
+}
+
+/// A free-text field, which may be optional
+trait TextValue {
+    fn text(&self) -> &str;
+}
+
+impl TextValue for String {
+    fn text(&self) -> &str {
+        self
+    }
+}
+
+impl TextValue for Option<String> {
+    fn text(&self) -> &str {
+        self.as_deref().unwrap_or_default()
+    }
+}
+
+/// A record whose free-text fields can be checked for forbidden characters
+trait TextFields {
+    /// Returns the record number and the free-text fields by their 1-based
+    /// indices, followed by the extra columns.
+    fn text_fields(&self) -> (u32, Vec<(usize, &str)>);
+}
+
+/// Implements `TextFields` for records, given the record number, the index of
+/// each free-text field and the index of the first extra column.
+macro_rules! text_fields {
+    ($($record:ty: $record_number:literal, [$($index:literal => $field:ident),*], $extra:literal;)*) => {
+        $(
+            impl TextFields for $record {
+                fn text_fields(&self) -> (u32, Vec<(usize, &str)>) {
+                    let mut fields: Vec<(usize, &str)> = vec![$(($index, self.$field.text())),*];
+                    fields.extend(self.extra.iter().enumerate().map(|(i, col)| ($extra + i, col.as_str())));
+                    ($record_number, fields)
+                }
+            }
+        )*
+    };
+}
+
+text_fields! {
+    VersionRecord: 0, [], 2;
+    PatientRecord: 1, [1 => name, 4 => zip_code, 5 => address, 6 => telephone,
+        7 => emergency_contact_information, 8 => blood_type, 10 => name_in_kana], 11;
+    SpecialPatientNoteRecord: 2, [2 => content], 4;
+    OtcDrugRecord: 3, [1 => drug_name], 5;
+    MemoRecord: 4, [1 => content], 4;
+    DateRecord: 5, [], 3;
+    PharmacyRecord: 11, [1 => name, 4 => institution_code, 5 => zip_code, 6 => address, 7 => telephone], 9;
+    PharmacistRecord: 15, [1 => name, 2 => contact_information], 4;
+    MedicalInstitutionRecord: 51, [1 => name, 4 => institution_code], 6;
+    PhysicianRecord: 55, [1 => name, 2 => specialty], 4;
+    DrugRecord: 201, [2 => name, 3 => dosage, 4 => unit, 6 => drug_code], 8;
+    DrugSupplementaryRecord: 281, [2 => content], 4;
+    DrugNoticeRecord: 291, [2 => content], 4;
+    UsageRecord: 301, [2 => name, 4 => unit, 7 => usage_code], 9;
+    UsageSupplementaryRecord: 311, [2 => content], 4;
+    RpNoticeRecord: 391, [2 => content], 4;
+    NoticeRecord: 401, [1 => content], 3;
+    InformationProvisionRecord: 411, [1 => content], 4;
+    RemainingMedicineRecord: 421, [1 => content], 3;
+    NoteRecord: 501, [1 => content], 3;
+    FromPatientRecord: 601, [1 => content], 3;
+    FamilyPharmacistRecord: 701, [1 => name, 2 => pharmacy_name, 3 => contact_information], 7;
+    SplitControlRecord: 911, [1 => data_id], 4;
+}
+
+impl TextFields for UnknownRecord {
+    fn text_fields(&self) -> (u32, Vec<(usize, &str)>) {
+        (self.record_number, self.fields.iter().enumerate().map(|(i, col)| (i + 1, col.as_str())).collect())
+    }
+}
+
+/// Returns the records of a dispensing information block.
+fn block_records(block: &DispensingInformationBlock) -> Vec<&dyn TextFields> {
+    let mut records: Vec<&dyn TextFields> = vec![&block.date, &block.pharmacy];
+    records.extend(block.pharmacist.iter().map(|r| r as &dyn TextFields));
+    records.extend(block.medical_institute.iter().map(|r| r as &dyn TextFields));
+    for prescription in &block.prescriptions {
+        records.extend(prescription.physician.iter().map(|r| r as &dyn TextFields));
+        records.extend(prescription.unknown_records.iter().map(|r| r as &dyn TextFields));
+        for rp in &prescription.rps {
+            for drug in &rp.drugs {
+                records.push(&drug.drug);
+                records.extend(drug.drug_supplementary.iter().map(|r| r as &dyn TextFields));
+                records.extend(drug.drug_notice.iter().map(|r| r as &dyn TextFields));
+                records.extend(drug.unknown_records.iter().map(|r| r as &dyn TextFields));
+            }
+            records.push(&rp.usage);
+            records.extend(rp.usage_supplementary.iter().map(|r| r as &dyn TextFields));
+            records.extend(rp.rp_notice.iter().map(|r| r as &dyn TextFields));
+            records.extend(rp.unknown_records.iter().map(|r| r as &dyn TextFields));
+        }
+    }
+    records.extend(block.notice.iter().map(|r| r as &dyn TextFields));
+    records.extend(block.information_provision.iter().map(|r| r as &dyn TextFields));
+    records.extend(block.remaining_medicine.iter().map(|r| r as &dyn TextFields));
+    records.extend(block.note.iter().map(|r| r as &dyn TextFields));
+    records.extend(block.from_patient.iter().map(|r| r as &dyn TextFields));
+    records.extend(block.unknown_records.iter().map(|r| r as &dyn TextFields));
+    records
+}
+
+/// Reports every field which holds a comma or a line break, which cannot be
+/// written as it is.
+fn check_characters(findings: &mut Vec<Finding>, notebook: &MedicineNotebook) {
+    let mut records: Vec<(Option<usize>, &dyn TextFields)> = vec![(None, &notebook.version)];
+    records.extend(notebook.split_control.iter().map(|r| (None, r as &dyn TextFields)));
+    records.push((None, &notebook.patient));
+    records.extend(notebook.special_patient_notes.iter().map(|r| (None, r as &dyn TextFields)));
+    records.extend(notebook.otc_drugs.iter().map(|r| (None, r as &dyn TextFields)));
+    records.extend(notebook.memos.iter().map(|r| (None, r as &dyn TextFields)));
+    records.extend(notebook.unknown_records.iter().map(|r| (None, r as &dyn TextFields)));
+    for (i, block) in notebook.dispensing_information.iter().enumerate() {
+        records.extend(block_records(block).into_iter().map(|r| (Some(i), r)));
+    }
+    records.extend(notebook.family_pharmacist.iter().map(|r| (None, r as &dyn TextFields)));
+
+    for (block, record) in records {
+        let (record_number, fields) = record.text_fields();
+        for (field_index, value) in fields {
+            let mut characters: Vec<String> = Vec::new();
+            for character in value.chars().filter(|c| FORBIDDEN_CHARACTERS.contains(c)) {
+                let character = format!("{:?}", character);
+                if !characters.contains(&character) {
+                    characters.push(character);
+                }
+            }
+            if characters.is_empty() {
+                continue;
+            }
+            let field_name = field_names(record_number)
+                .and_then(|names| names.get(field_index - 1).copied())
+                .unwrap_or("追加項目");
+            findings.push(Finding::new(
+                ValidationRule::ForbiddenCharacter,
+                Location {block, .. Location::record(record_number, Some(field_name))},
+                format!("{} {} not allowed in a field", characters.join(", "),
+                    if characters.len() > 1 {"are"} else {"is"}),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Unlike the shared sample, an OTC drug, a dated memo, and two RPs in one block.
+    const SAMPLE: &str = "JAHISTC07,1\r\n\
+        1,山田太郎,1,19700101,1000001,東京都千代田区,03-0000-0000,,A,60.5,ﾔﾏﾀﾞﾀﾛｳ\r\n\
+        3,ロキソニン,202001,20200201,2\r\n\
+        4,メモです,20200301,2\r\n\
+        5,20200401,1\r\n\
+        11,テスト薬局,13,4,1234567,1000001,東京都,03-1111-1111,1\r\n\
+        201,1,ロキソプロフェン錠60mg,1,錠,2,620098801,1\r\n\
+        301,1,1日3回毎食後,7,日分,1,1,,1\r\n\
+        201,2,ムコスタ錠100mg,3,錠,4,2329021F1021,1\r\n\
+        301,2,1日3回毎食後,7,日分,1,,,1";
+
+    fn sample() -> MedicineNotebook {
+        SAMPLE.parse().unwrap()
+    }
+
+    fn rps(notebook: &mut MedicineNotebook) -> &mut Vec<RpBlock> {
+        &mut notebook.dispensing_information[0].prescriptions[0].rps
+    }
+
+    #[test]
+    fn finds_nothing_in_a_valid_notebook() {
+        assert_eq!(sample().validate(), vec![]);
+    }
+
+    #[test]
+    fn reports_invalid_dates() {
+        let mut notebook = sample();
+        notebook.patient.day_of_birth = Date::Seireki{year: 1970, month: 2, day: 30};
+        notebook.memos[0].created_at = Some(Date::SeirekiYearMonth{year: 2020, month: 13});
+        let findings = notebook.validate();
+        assert_eq!(findings.len(), 2);
+        assert!(findings.iter().all(|f| f.rule == ValidationRule::InvalidDate && f.severity == Severity::Error));
+        assert_eq!(findings[0].location, Location::record(1, Some("患者生年月日")));
+        assert!(findings[0].message.starts_with("19700230 is not a valid date"), "{}", findings[0].message);
+        assert_eq!(findings[1].location, Location::record(4, Some("メモ入力年月日")));
+    }
+
+    #[test]
+    fn reports_a_period_ending_before_it_starts() {
+        let mut notebook = sample();
+        notebook.otc_drugs[0].end_date = Some(Date::new_seireki(2020, 1, 31).unwrap());
+        assert_eq!(notebook.validate(), vec![]);
+
+        notebook.otc_drugs[0].end_date = Some(Date::new_seireki(2019, 12, 31).unwrap());
+        let findings = notebook.validate();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].rule, ValidationRule::DateOrder);
+        assert_eq!(findings[0].location, Location::record(3, Some("服用終了年月日")));
+        assert_eq!(findings[0].message, "2019年12月31日 comes before 服用開始年月日 2020年1月");
+    }
+
+    #[test]
+    fn reports_an_rp_without_drugs() {
+        let mut notebook = sample();
+        let mut rp = rps(&mut notebook)[1].clone();
+        rp.usage.rp_number = 3;
+        rp.drugs.clear();
+        rps(&mut notebook).push(rp);
+        let findings = notebook.validate();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].rule, ValidationRule::EmptyRp);
+        assert_eq!(findings[0].location, Location::record(301, Some("RP番号")).in_block(0).in_rp(0, 2));
+    }
+
+    #[test]
+    fn reports_rp_numbers_which_differ_within_an_rp() {
+        let mut notebook = sample();
+        rps(&mut notebook)[0].drugs[0].drug.rp_number = 5;
+        let findings = notebook.validate();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].rule, ValidationRule::RpNumberMismatch);
+        assert_eq!(findings[0].location, Location::record(201, Some("RP番号")).in_block(0).in_rp(0, 0));
+        assert_eq!(findings[0].message, "RP number 5 differs from 1 of the usage record");
+    }
+
+    #[test]
+    fn reports_duplicate_and_non_sequential_rp_numbers() {
+        let mut notebook = sample();
+        for rp in rps(&mut notebook) {
+            rp.usage.rp_number = 1;
+            rp.drugs[0].drug.rp_number = 1;
+        }
+        let findings = notebook.validate();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].rule, ValidationRule::DuplicateRpNumber);
+        assert_eq!(findings[0].location.rp, Some(1));
+
+        for rp in rps(&mut notebook).iter_mut().skip(1) {
+            rp.usage.rp_number = 3;
+            rp.drugs[0].drug.rp_number = 3;
+        }
+        let findings = notebook.validate();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].rule, ValidationRule::NonSequentialRpNumber);
+        assert_eq!(findings[0].severity, Severity::Warning);
+        assert_eq!(findings[0].message, "RP number 3 does not follow 1");
+    }
+
+    #[test]
+    fn reports_a_split_part_out_of_range() {
+        let mut notebook = sample();
+        notebook.split_control = Some(SplitControlRecord::new("ID".to_string(), 2, 2));
+        assert_eq!(notebook.validate(), vec![]);
+
+        notebook.split_control = Some(SplitControlRecord::new("ID".to_string(), 2, 3));
+        let findings = notebook.validate();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].rule, ValidationRule::InvalidSplitControl);
+        assert_eq!(findings[0].location, Location::record(911, Some("分割番号")));
+        assert_eq!(findings[0].message, "part 3 is out of 2 parts");
+    }
+
+    #[test]
+    fn reports_commas_and_line_breaks() {
+        let mut notebook = sample();
+        notebook.memos[0].content = "一行目\r\n二行目,三行目\n".to_string();
+        rps(&mut notebook)[1].drugs[0].drug.extra = vec!["追加,項目".to_string()];
+        let findings = notebook.validate();
+        assert_eq!(findings.len(), 2);
+        assert!(findings.iter().all(|f| f.rule == ValidationRule::ForbiddenCharacter && f.severity == Severity::Warning));
+        assert_eq!(findings[0].location, Location::record(4, Some("手帳メモ情報")));
+        assert_eq!(findings[0].message, r"'\r', '\n', ',' are not allowed in a field");
+        assert_eq!(findings[1].location, Location::record(201, Some("追加項目")).in_block(0));
+        assert_eq!(findings[1].message, "',' is not allowed in a field");
+    }
+
+    #[test]
+    fn reports_fields_over_their_limit() {
+        let mut notebook = sample();
+        notebook.patient.name = "山".repeat(20);
+        assert_eq!(notebook.validate(), vec![]);
+        assert_eq!(notebook.check_field_lengths(), vec![]);
+
+        notebook.patient.name.push('a');
+        rps(&mut notebook)[0].drugs[0].drug.unit = "錠".repeat(11);
+        let findings = notebook.validate();
+        assert_eq!(findings.len(), 2);
+        assert!(findings.iter().all(|f| f.rule == ValidationRule::FieldTooLong));
+        assert_eq!(findings[0].location, Location::record(1, Some("患者氏名")));
+        assert_eq!(findings[0].message, "41 bytes is over the limit of 40 bytes");
+        assert_eq!(findings[1].location, Location::record(201, Some("単位名")).in_block(0));
+        assert_eq!(findings[1].message, "22 bytes is over the limit of 20 bytes");
+        assert_eq!(notebook.check_field_lengths(), findings);
+    }
+}
@@ -0,0 +1,302 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use crate::jahis::*;
+use crate::encoding::{TextEncoding, field_length_errors};
+use crate::escape::EscapePolicy;
+use crate::profile::VersionProfile;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+/// Line terminator written between record lines. A bare carriage return is
+/// not one, as the reader takes it for a line break within a field.
+pub enum LineTerminator {
+    #[default]
+    CrLf, // "\r\n"
+    Lf, // "\n"
+}
+
+impl LineTerminator {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Self::CrLf => "\r\n",
+            Self::Lf => "\n",
+        }
+    }
+}
+
+impl fmt::Display for LineTerminator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::CrLf => write!(f, "CRLF"),
+            Self::Lf => write!(f, "LF"),
+        }
+    }
+}
+
+impl FromStr for LineTerminator {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "\r\n" | "CRLF" | "crlf" => Ok(Self::CrLf),
+            "\n" | "LF" | "lf" => Ok(Self::Lf),
+            _ => Err(Error::InvalidArgument(
+                format!("Cannot convert str to LineTerminator, got \"{:?}\"", s)
+            )),
+        }
+    }
+}
+
+/// A streaming writer of a medicine notebook.
+///
+/// Record lines are encoded and written to the underlying `io::Write` one
+/// at a time, so a notebook can be serialized without building its whole
+/// code in memory. Either write a complete notebook with `write_notebook`,
+/// or write the header with `write_header`, append dispensing information
+/// blocks with `write_block` and finish with the family pharmacist records.
+///
+/// By default, a field containing a comma or a line break is rejected with
+/// an error naming the field, so that whatever is written parses back to
+/// the same values; `escape_policy` can replace them with full-width
+/// characters instead.
+///
+/// With `version_profile`, the notebook is written in the layout of an older
+/// version for readers which have not been upgraded. The version record then
+/// holds the number of that version, and a record or a non-empty field which
+/// the version does not define is an error.
+#[derive(Debug)]
+pub struct MedicineNotebookWriter<W: Write> {
+    inner: W,
+    line_terminator: LineTerminator,
+    trailing_newline: bool,
+    encoding: TextEncoding,
+    escape_policy: EscapePolicy,
+    profile: Option<VersionProfile>,
+    enforce_field_lengths: bool,
+    lines_written: usize,
+    pending: PendingRecords,
+}
+
+impl<W: Write> MedicineNotebookWriter<W> {
+    /// Creates a writer emitting UTF-8 with CRLF line terminators and no
+    /// trailing newline, which is the same output as `to_code` unless a field
+    /// contains a comma or a line break.
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            line_terminator: LineTerminator::default(),
+            trailing_newline: false,
+            encoding: TextEncoding::default(),
+            escape_policy: EscapePolicy::default(),
+            profile: None,
+            enforce_field_lengths: false,
+            lines_written: 0,
+            pending: PendingRecords::default(),
+        }
+    }
+
+    pub fn line_terminator(mut self, line_terminator: LineTerminator) -> Self {
+        self.line_terminator = line_terminator;
+        self
+    }
+
+    pub fn trailing_newline(mut self, trailing_newline: bool) -> Self {
+        self.trailing_newline = trailing_newline;
+        self
+    }
+
+    pub fn encoding(mut self, encoding: TextEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    pub fn escape_policy(mut self, escape_policy: EscapePolicy) -> Self {
+        self.escape_policy = escape_policy;
+        self
+    }
+
+    pub fn version_profile(mut self, profile: VersionProfile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Refuses to write a field longer than its limit in Shift_JIS bytes,
+    /// as given by `field_max_bytes`, whatever the encoding of the output.
+    pub fn enforce_field_lengths(mut self, enforce_field_lengths: bool) -> Self {
+        self.enforce_field_lengths = enforce_field_lengths;
+        self
+    }
+
+    /// Number of record lines written so far.
+    pub fn lines_written(&self) -> usize {
+        self.lines_written
+    }
+
+    /// Writes a whole notebook. Its unknown records are written where they
+    /// were read, the rest of them by `finish`. Without `version_profile`,
+    /// the version record is written as `MedicineNotebook::written_version`.
+    pub fn write_notebook(&mut self, notebook: &MedicineNotebook) -> Result<(), Error> {
+        self.pending = PendingRecords::new(&notebook.unknown_records);
+        self.write_version(&notebook.written_version())?;
+        if let Some(record) = &notebook.split_control {
+            self.write_known(0, &record.to_code_with(self.escape_policy)?)?;
+        }
+        self.write_header_records(
+            &notebook.patient,
+            &notebook.special_patient_notes,
+            &notebook.otc_drugs,
+            &notebook.memos,
+        )?;
+        for block in &notebook.dispensing_information {
+            self.write_block(block)?;
+        }
+        for record in &notebook.family_pharmacist {
+            self.write_family_pharmacist(record)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the header of a notebook. The unknown records of the notebook
+    /// are written where they were read, the rest of them by `finish`.
+    pub fn write_header(&mut self, version: &VersionRecord, patient: &PatientRecord,
+                special_patient_notes: &[SpecialPatientNoteRecord],
+                otc_drugs: &[OtcDrugRecord], memos: &[MemoRecord],
+                unknown_records: &[UnknownRecord]) -> Result<(), Error> {
+        self.pending = PendingRecords::new(unknown_records);
+        self.write_version(version)?;
+        self.write_header_records(patient, special_patient_notes, otc_drugs, memos)
+    }
+
+    fn write_version(&mut self, version: &VersionRecord) -> Result<(), Error> {
+        let version = match self.profile {
+            Some(profile) => VersionRecord {number: profile.version, .. version.clone()},
+            None => version.clone(),
+        };
+        self.write_known(0, &version.to_code_with(self.escape_policy)?)
+    }
+
+    fn write_header_records(&mut self, patient: &PatientRecord,
+                special_patient_notes: &[SpecialPatientNoteRecord],
+                otc_drugs: &[OtcDrugRecord], memos: &[MemoRecord]) -> Result<(), Error> {
+        for (record_number, line) in header_lines(patient, special_patient_notes, otc_drugs, memos,
+                self.escape_policy)? {
+            self.write_known(record_number, &line)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_block(&mut self, block: &DispensingInformationBlock) -> Result<(), Error> {
+        for line in block.code_lines(self.escape_policy)? {
+            self.write_known(u32::MAX, &line)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_family_pharmacist(&mut self, record: &FamilyPharmacistRecord) -> Result<(), Error> {
+        self.write_known(u32::MAX, &record.to_code_with(self.escape_policy)?)
+    }
+
+    /// Writes the remaining unknown records of the notebook and the trailing
+    /// newline if requested, flushes, and returns the underlying writer.
+    pub fn finish(mut self) -> Result<W, Error> {
+        for record in self.pending.rest() {
+            self.write_line(&record.to_code_with(self.escape_policy)?)?;
+        }
+        if self.trailing_newline && self.lines_written > 0 {
+            let terminator = self.encoding.encode(self.line_terminator.as_str())?;
+            self.inner.write_all(&terminator).map_err(|e| Error::IoError(e.to_string()))?;
+        }
+        self.inner.flush().map_err(|e| Error::IoError(e.to_string()))?;
+        Ok(self.inner)
+    }
+
+    /// Writes a line other than an unknown record of the notebook, preceded by
+    /// the unknown records due before it.
+    fn write_known(&mut self, record_number: u32, line: &str) -> Result<(), Error> {
+        for record in self.pending.before(record_number) {
+            self.write_line(&record.to_code_with(self.escape_policy)?)?;
+        }
+        self.write_line(line)
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), Error> {
+        // The limits are of the current layout, so the line is checked before it is mapped.
+        if self.enforce_field_lengths {
+            if let Some(e) = field_length_errors(line).into_iter().next() {
+                return Err(e);
+            }
+        }
+        let line = match self.profile {
+            Some(profile) => profile.write_line(line)?,
+            None => Cow::Borrowed(line),
+        };
+        let bytes = self.encoding.encode_record_line(&line)?;
+        if self.lines_written > 0 {
+            let terminator = self.encoding.encode(self.line_terminator.as_str())?;
+            self.inner.write_all(&terminator).map_err(|e| Error::IoError(e.to_string()))?;
+        }
+        self.inner.write_all(&bytes).map_err(|e| Error::IoError(e.to_string()))?;
+        self.lines_written += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::SAMPLE;
+
+    fn write(notebook: &MedicineNotebook, mut writer: MedicineNotebookWriter<Vec<u8>>) -> Result<String, Error> {
+        writer.write_notebook(notebook)?;
+        Ok(String::from_utf8(writer.finish()?).unwrap())
+    }
+
+    #[test]
+    fn writes_each_line_terminator() {
+        let notebook: MedicineNotebook = SAMPLE.parse().unwrap();
+        for terminator in [LineTerminator::CrLf, LineTerminator::Lf] {
+            let expected = SAMPLE.replace("\r\n", terminator.as_str());
+            let code = write(&notebook, MedicineNotebookWriter::new(Vec::new()).line_terminator(terminator)).unwrap();
+            assert_eq!(code, expected);
+            assert_eq!(code.parse::<MedicineNotebook>().unwrap(), notebook);
+
+            let code = write(&notebook, MedicineNotebookWriter::new(Vec::new())
+                .line_terminator(terminator)
+                .trailing_newline(true)).unwrap();
+            assert_eq!(code, format!("{}{}", expected, terminator.as_str()));
+            assert_eq!(code.parse::<MedicineNotebook>().unwrap(), notebook);
+        }
+    }
+
+    #[test]
+    fn parses_line_terminators_by_name() {
+        assert_eq!("CRLF".parse::<LineTerminator>().unwrap(), LineTerminator::CrLf);
+        assert_eq!("\n".parse::<LineTerminator>().unwrap(), LineTerminator::Lf);
+        assert!("CR".parse::<LineTerminator>().is_err());
+    }
+
+    #[test]
+    fn enforces_field_lengths_up_to_the_limit() {
+        let mut notebook: MedicineNotebook = SAMPLE.parse().unwrap();
+        notebook.patient.name = "山".repeat(20);
+        let code = write(&notebook, MedicineNotebookWriter::new(Vec::new()).enforce_field_lengths(true)).unwrap();
+        assert_eq!(code, notebook.to_code());
+
+        notebook.patient.name.push('A');
+        assert_eq!(write(&notebook, MedicineNotebookWriter::new(Vec::new()).enforce_field_lengths(true)),
+            Err(Error::FieldTooLong {record_number: 1, field_name: "患者氏名", max_bytes: 40, bytes: 41}));
+        assert!(write(&notebook, MedicineNotebookWriter::new(Vec::new())).is_ok());
+    }
+
+    #[test]
+    fn enforces_field_lengths_of_the_current_layout_for_an_older_version() {
+        let mut notebook: MedicineNotebook = SAMPLE.parse().unwrap();
+        notebook.patient.name_in_kana = None;
+        notebook.patient.extra = vec!["追加".repeat(25)];
+        let code = write(&notebook, MedicineNotebookWriter::new(Vec::new())
+            .version_profile(VersionProfile::for_version(5))
+            .enforce_field_lengths(true)).unwrap();
+        assert!(code.contains(&format!("\r\n1,山田太郎,1,19700101,1000001,東京都千代田区,03-0000-0000,,A,60.5,{}\r\n",
+            "追加".repeat(25))));
+    }
+}